@@ -0,0 +1,324 @@
+/* Copyright (C) 2022 Antmicro
+ * 
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * 
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ * 
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Writing routing information of site types as BBA blobs.
+//!
+//! BBA is the text format compiled into binary chip databases by nextpnr's `bbasm`.
+//! Each blob holds routing information of a single site type and references no labels
+//! outside of itself, so that blobs of different site types can be loaded independently.
+//!
+//! The blob starts with the `site_type_routing` record:
+//! * `str` - name of the site type
+//! * `u32`, `ref` - number of pins and a list of their names (`str`), indexed by pin ids
+//! * `u32`, `ref` - number of pin pairs and a list of pair records, sorted by pins
+//! * `u32`, `ref` - number of edge usage records and a list of them, sorted by pins
+//!   (empty unless edge usage is counted, see `BruteRouter::with_edge_usage`)
+//!
+//! A pair record consists of:
+//! * `u32`, `u32` - ids of the source and sink pins
+//! * `u32`, `ref` - number of cubes of the _requires_ formula and a list of cube records
+//! * `u32`, `ref` - number of cubes of the _implies_ formula and a list of cube records
+//!
+//! A cube record is a number of terms (`u32`) and a reference to the list of terms.
+//! A term is a pair of `u32` values: its kind (see `TERM_VAR` and friends) and the id
+//! of the constrained port (`0` for constant terms).
+//!
+//! An edge usage record is three `u32` values: ids of the pins at both ends of an edge
+//! of the routing graph and the number of routes crossing it.
+//!
+//! Blobs of all the routed site types can also be written into a single file with
+//! `CombinedBbaWriter`. Labels of each blob are then prefixed with `st<SITE_TYPE_ID>_`
+//! and the file ends with the `site_types` record:
+//! * `u32`, `ref` - number of site types and a list of site type records
+//!
+//! A site type record is the id of the site type (`u32`) and a reference to the
+//! `site_type_routing` record of its blob.
+
+use std::fmt::Write;
+
+use crate::ic_loader::archdef::Root as Device;
+use crate::logic_formula::{DNFCube, FormulaTerm};
+use crate::router::SitePinId;
+use crate::router::site_brute_router::{BruteRouter, ConstrainingElement, RoutingInfo};
+use crate::strings::GlobalStringsCtx;
+
+#[cfg(test)]
+mod tests;
+
+pub const TERM_VAR: u32 = 0;
+pub const TERM_NEG_VAR: u32 = 1;
+pub const TERM_TRUE: u32 = 2;
+pub const TERM_FALSE: u32 = 3;
+
+/// Label of the record at the beginning of each blob.
+pub const ROOT_LABEL: &str = "site_type_routing";
+
+/// Label of the record listing site types of a combined file.
+pub const INDEX_LABEL: &str = "site_types";
+
+const DEVICE_HASH_COMMENT: &str = "pre // Device hash: ";
+const PIN_RANGE_COMMENT: &str = "pre // Pin range: ";
+
+/// Emits BBA directives. Labels (and references to them) get `label_prefix` prepended.
+struct BbaWriter {
+    out: String,
+    label_prefix: String,
+}
+
+impl BbaWriter {
+    fn new(label_prefix: String) -> Self {
+        Self { out: String::new(), label_prefix }
+    }
+
+    fn directive(&mut self, directive: &str, value: impl std::fmt::Display, comment: &str) {
+        if comment.is_empty() {
+            writeln!(self.out, "{} {}", directive, value).unwrap();
+        } else {
+            writeln!(self.out, "{} {} {}", directive, value, comment).unwrap();
+        }
+    }
+
+    fn label(&mut self, label: &str) {
+        let label = format!("{}{}", self.label_prefix, label);
+        self.directive("label", label, "");
+    }
+
+    fn ref_(&mut self, label: &str, comment: &str) {
+        let label = format!("{}{}", self.label_prefix, label);
+        self.directive("ref", label, comment);
+    }
+
+    fn u32(&mut self, value: u32, comment: &str) {
+        self.directive("u32", value, comment);
+    }
+
+    fn str(&mut self, value: &str, comment: &str) -> Result<(), String> {
+        /* `|` delimits strings and can't be escaped */
+        if value.contains('|') {
+            return Err(format!("String {} can't be stored in BBA, as it contains |", value));
+        }
+        self.directive("str", format!("|{}|", value), comment);
+        Ok(())
+    }
+}
+
+fn term_record(term: &FormulaTerm<ConstrainingElement>) -> (u32, u32) {
+    match term {
+        FormulaTerm::Var(ConstrainingElement::Port(port)) => (TERM_VAR, *port),
+        FormulaTerm::NegVar(ConstrainingElement::Port(port)) => (TERM_NEG_VAR, *port),
+        FormulaTerm::True => (TERM_TRUE, 0),
+        FormulaTerm::False => (TERM_FALSE, 0),
+    }
+}
+
+fn write_formula_cubes(
+    bba: &mut BbaWriter,
+    label: &str,
+    form: &[DNFCube<ConstrainingElement>]
+) {
+    bba.label(label);
+    for (cube_idx, cube) in form.iter().enumerate() {
+        bba.u32(cube.terms.len() as u32, "term count");
+        bba.ref_(&format!("{}_{}", label, cube_idx), "terms");
+    }
+    for (cube_idx, cube) in form.iter().enumerate() {
+        bba.label(&format!("{}_{}", label, cube_idx));
+        for term in &cube.terms {
+            let (kind, port) = term_record(term);
+            bba.u32(kind, "kind");
+            bba.u32(port, "port");
+        }
+    }
+}
+
+/// Writes a BBA blob holding routing information of a single site type.
+/// 
+/// # Arguments
+/// * `device` - device the router was created for
+/// * `router` - router of the site type
+/// * `routing_info` - results of routing the site type with `router`
+/// * `device_hash` - hash of the device file, recorded in a comment (see `read_device_hash`)
+/// 
+/// Routing information of a slice of source pins has the slice recorded in a comment as
+/// well (see `read_pin_range`).
+/// 
+/// # Return
+/// The blob, or an error if a name can't be represented in BBA
+pub fn export_site_routing_bba<'d, A>(
+    device: &Device<'d>,
+    router: &BruteRouter<A>,
+    routing_info: &RoutingInfo,
+    device_hash: Option<&str>
+)
+    -> Result<String, String>
+where
+    A: Default + Clone + std::fmt::Debug + 'static
+{
+    site_routing_blob(device, router, routing_info, device_hash, String::new())
+}
+
+fn site_routing_blob<'d, A>(
+    device: &Device<'d>,
+    router: &BruteRouter<A>,
+    routing_info: &RoutingInfo,
+    device_hash: Option<&str>,
+    label_prefix: String
+)
+    -> Result<String, String>
+where
+    A: Default + Clone + std::fmt::Debug + 'static
+{
+    let st = device.get_site_type_list().unwrap().get(router.get_st_id());
+    let st_name = device.ic_str(st.get_name());
+    let pin_count = router.get_graph().node_count();
+
+    let mut pairs: Vec<_> = routing_info.pin_to_pin_routing.iter().collect();
+    pairs.sort_by_key(|(pair, _)| **pair);
+    let mut edge_usage: Vec<_> = routing_info.edge_usage.iter().collect();
+    edge_usage.sort_by_key(|(edge, _)| **edge);
+
+    let mut bba = BbaWriter::new(label_prefix);
+    writeln!(bba.out, "pre // Site routing of site type {}", st_name).unwrap();
+    if let Some(device_hash) = device_hash {
+        writeln!(bba.out, "{}{}", DEVICE_HASH_COMMENT, device_hash).unwrap();
+    }
+    if let Some(pin_range) = &routing_info.pin_range {
+        writeln!(bba.out, "{}{}..{}", PIN_RANGE_COMMENT, pin_range.start, pin_range.end).unwrap();
+    }
+    bba.out.push_str("push site_routing\n");
+
+    bba.label(ROOT_LABEL);
+    bba.str(&st_name, "site type")?;
+    bba.u32(pin_count as u32, "pin count");
+    bba.ref_("pin_names", "");
+    bba.u32(pairs.len() as u32, "pair count");
+    bba.ref_("pairs", "");
+    bba.u32(edge_usage.len() as u32, "edge usage count");
+    bba.ref_("edge_usage", "");
+
+    let gsctx = GlobalStringsCtx::hold();
+    bba.label("pin_names");
+    for pin in 0 .. pin_count {
+        bba.str(&router.get_pin_name(device, &gsctx, SitePinId::from(pin)).to_string(), "")?;
+    }
+
+    bba.label("pairs");
+    for (idx, ((from, to), ppri)) in pairs.iter().enumerate() {
+        bba.u32(from.index() as u32, "from");
+        bba.u32(to.index() as u32, "to");
+        bba.u32(ppri.requires.len() as u32, "requires cube count");
+        bba.ref_(&format!("pair_{}_requires", idx), "");
+        bba.u32(ppri.implies.len() as u32, "implies cube count");
+        bba.ref_(&format!("pair_{}_implies", idx), "");
+    }
+    for (idx, (_, ppri)) in pairs.iter().enumerate() {
+        write_formula_cubes(&mut bba, &format!("pair_{}_requires", idx), &ppri.requires);
+        write_formula_cubes(&mut bba, &format!("pair_{}_implies", idx), &ppri.implies);
+    }
+
+    bba.label("edge_usage");
+    for ((from, to), count) in edge_usage {
+        bba.u32(from.index() as u32, "from");
+        bba.u32(to.index() as u32, "to");
+        bba.u32(*count as u32, "route count");
+    }
+
+    bba.out.push_str("pop\n");
+    Ok(bba.out)
+}
+
+/// Writes routing information of multiple site types into a single BBA file, see the
+/// module documentation for the layout.
+pub struct CombinedBbaWriter<W> where W: std::io::Write {
+    out: W,
+    site_types: Vec<u32>,
+}
+
+impl<W> CombinedBbaWriter<W> where W: std::io::Write {
+    /// Starts the file, recording `device_hash` the way `export_site_routing_bba` does.
+    pub fn new(mut out: W, device_hash: Option<&str>) -> std::io::Result<Self> {
+        if let Some(device_hash) = device_hash {
+            writeln!(out, "{}{}", DEVICE_HASH_COMMENT, device_hash)?;
+        }
+        Ok(Self { out, site_types: Vec::new() })
+    }
+
+    /// Creates the blob of a site type. It has to be written with `write_blob`.
+    /// 
+    /// # Return
+    /// The blob, or an error if a name can't be represented in BBA
+    pub fn blob<'d, A>(
+        &self,
+        device: &Device<'d>,
+        router: &BruteRouter<A>,
+        routing_info: &RoutingInfo
+    )
+        -> Result<(u32, String), String>
+    where
+        A: Default + Clone + std::fmt::Debug + 'static
+    {
+        let st_id = router.get_st_id();
+        let blob = site_routing_blob(
+            device,
+            router,
+            routing_info,
+            None,
+            format!("st{}_", st_id)
+        )?;
+        Ok((st_id, blob))
+    }
+
+    /// Appends a blob created with `blob`.
+    pub fn write_blob(&mut self, (st_id, blob): (u32, String)) -> std::io::Result<()> {
+        self.out.write_all(blob.as_bytes())?;
+        self.site_types.push(st_id);
+        Ok(())
+    }
+
+    /// Writes the `site_types` record and flushes the file.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        let mut bba = BbaWriter::new(String::new());
+        bba.out.push_str("push site_routing\n");
+        bba.label(INDEX_LABEL);
+        bba.u32(self.site_types.len() as u32, "site type count");
+        bba.ref_("site_type_list", "");
+        bba.label("site_type_list");
+        for st_id in &self.site_types {
+            bba.u32(*st_id, "site type");
+            bba.ref_(&format!("st{}_{}", st_id, ROOT_LABEL), "");
+        }
+        bba.out.push_str("pop\n");
+        self.out.write_all(bba.out.as_bytes())?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Gets the device hash recorded in a blob written by `export_site_routing_bba`.
+pub fn read_device_hash(bba: &str) -> Option<&str> {
+    bba.lines()
+        .take_while(|line| line.starts_with("pre "))
+        .find_map(|line| line.strip_prefix(DEVICE_HASH_COMMENT))
+}
+
+/// Gets the slice of source pins recorded in a blob written by `export_site_routing_bba`.
+/// `None` if routing information of all the source pins was written.
+pub fn read_pin_range(bba: &str) -> Option<std::ops::Range<usize>> {
+    let (start, end) = bba.lines()
+        .take_while(|line| line.starts_with("pre "))
+        .find_map(|line| line.strip_prefix(PIN_RANGE_COMMENT))?
+        .split_once("..")?;
+    Some(start.parse().ok()? .. end.parse().ok()?)
+}
@@ -0,0 +1,231 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+use std::collections::HashMap;
+
+use super::*;
+use crate::test_utils::*;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Item {
+    U32(u32),
+    Ref(String),
+    Str(String),
+}
+
+/* Groups values of a blob by the labels preceding them */
+fn parse_bba(bba: &str) -> HashMap<String, Vec<Item>> {
+    let mut labels = HashMap::new();
+    let mut current: Option<String> = None;
+    for line in bba.lines() {
+        let (directive, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let value = rest.split(' ').next().unwrap();
+        let item = match directive {
+            "pre" | "push" | "pop" => continue,
+            "label" => {
+                assert!(!labels.contains_key(value), "Label {} is duplicated", value);
+                labels.insert(value.to_string(), Vec::new());
+                current = Some(value.to_string());
+                continue;
+            },
+            "u32" => Item::U32(value.parse().unwrap()),
+            "ref" => Item::Ref(value.to_string()),
+            "str" => Item::Str(rest[1 ..].split('|').next().unwrap().to_string()),
+            _ => panic!("Unexpected directive {}", directive),
+        };
+        labels.get_mut(current.as_ref().unwrap()).unwrap().push(item);
+    }
+
+    /* The blob must be self-contained */
+    for items in labels.values() {
+        for item in items {
+            if let Item::Ref(label) = item {
+                assert!(labels.contains_key(label), "Label {} is not defined", label);
+            }
+        }
+    }
+    labels
+}
+
+fn u32_at(items: &[Item], idx: usize) -> u32 {
+    match &items[idx] {
+        Item::U32(value) => *value,
+        item => panic!("Expected u32, got {:?}", item),
+    }
+}
+
+fn ref_at<'i>(labels: &'i HashMap<String, Vec<Item>>, items: &[Item], idx: usize)
+    -> &'i [Item]
+{
+    match &items[idx] {
+        Item::Ref(label) => &labels[label],
+        item => panic!("Expected ref, got {:?}", item),
+    }
+}
+
+fn read_formula(labels: &HashMap<String, Vec<Item>>, cubes: &[Item], count: u32)
+    -> Vec<Vec<(u32, u32)>>
+{
+    assert_eq!(cubes.len(), 2 * count as usize);
+    cubes.chunks(2)
+        .map(|cube| {
+            let terms = ref_at(labels, cube, 1);
+            assert_eq!(terms.len(), 2 * u32_at(cube, 0) as usize);
+            terms.chunks(2).map(|term| (u32_at(term, 0), u32_at(term, 1))).collect()
+        })
+        .collect()
+}
+
+fn expected_formula(form: &[DNFCube<ConstrainingElement>]) -> Vec<Vec<(u32, u32)>> {
+    form.iter()
+        .map(|cube| cube.terms.iter().map(term_record).collect())
+        .collect()
+}
+
+#[test]
+fn test_per_site_blobs() {
+    let msg = mux_site().site_type(buf_site_type()).build();
+    let device = fixture_root(&msg);
+
+    for (st_id, st_name) in [(0, "MUX_SITE"), (1, "BUF_SITE")] {
+        let router = BruteRouter::<()>::new(&device, st_id, false);
+        let info = router.route_all(true);
+        let labels = parse_bba(&export_site_routing_bba(&device, &router, &info, None).unwrap());
+
+        let root = &labels[ROOT_LABEL];
+        assert_eq!(root[0], Item::Str(st_name.into()));
+
+        let pin_names = ref_at(&labels, root, 2);
+        assert_eq!(u32_at(root, 1) as usize, router.get_graph().node_count());
+        let gsctx = GlobalStringsCtx::hold();
+        for (pin, name) in pin_names.iter().enumerate() {
+            let expected = router.get_pin_name(&device, &gsctx, SitePinId::from(pin)).to_string();
+            assert_eq!(*name, Item::Str(expected));
+        }
+
+        let pairs = ref_at(&labels, root, 4);
+        assert_eq!(u32_at(root, 3) as usize, info.pin_to_pin_routing.len());
+        assert!(!pairs.is_empty());
+        let mut previous = None;
+        for pair in pairs.chunks(6) {
+            let from = SitePinId::from(u32_at(pair, 0) as usize);
+            let to = SitePinId::from(u32_at(pair, 1) as usize);
+            assert!(previous < Some((from, to)));
+            previous = Some((from, to));
+
+            let ppri = &info.pin_to_pin_routing[&(from, to)];
+            let requires = read_formula(&labels, ref_at(&labels, pair, 3), u32_at(pair, 2));
+            let implies = read_formula(&labels, ref_at(&labels, pair, 5), u32_at(pair, 4));
+            assert_eq!(requires, expected_formula(&ppri.requires));
+            assert_eq!(implies, expected_formula(&ppri.implies));
+        }
+
+        assert_eq!(u32_at(root, 5), 0);
+        assert!(ref_at(&labels, root, 6).is_empty());
+    }
+}
+
+#[test]
+fn test_edge_usage_records() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false).with_edge_usage(true);
+    let info = router.route_all(true);
+    assert!(!info.edge_usage.is_empty());
+
+    let labels = parse_bba(&export_site_routing_bba(&device, &router, &info, None).unwrap());
+    let root = &labels[ROOT_LABEL];
+    let records = ref_at(&labels, root, 6);
+    assert_eq!(u32_at(root, 5) as usize, info.edge_usage.len());
+    assert_eq!(records.len(), 3 * info.edge_usage.len());
+    let mut previous = None;
+    for record in records.chunks(3) {
+        let edge = (
+            SitePinId::from(u32_at(record, 0) as usize),
+            SitePinId::from(u32_at(record, 1) as usize),
+        );
+        assert!(previous < Some(edge));
+        previous = Some(edge);
+        assert_eq!(u32_at(record, 2) as usize, info.edge_usage[&edge]);
+    }
+}
+
+#[test]
+fn test_device_hash_comment() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let info = router.route_all(true);
+
+    let bba = export_site_routing_bba(&device, &router, &info, Some("0123abcd")).unwrap();
+    assert_eq!(read_device_hash(&bba), Some("0123abcd"));
+    parse_bba(&bba);
+
+    let bba = export_site_routing_bba(&device, &router, &info, None).unwrap();
+    assert_eq!(read_device_hash(&bba), None);
+}
+
+#[test]
+fn test_pin_range_comment() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let slice = router.route_pin_range(1 .. 3, true);
+    let bba = export_site_routing_bba(&device, &router, &slice, None).unwrap();
+    assert_eq!(read_pin_range(&bba), Some(1 .. 3));
+    parse_bba(&bba);
+
+    let bba = export_site_routing_bba(&device, &router, &router.route_all(true), None).unwrap();
+    assert_eq!(read_pin_range(&bba), None);
+}
+
+#[test]
+fn test_combined_file() {
+    let msg = mux_site().site_type(buf_site_type()).build();
+    let device = fixture_root(&msg);
+
+    let mut combined = CombinedBbaWriter::new(Vec::new(), Some("0123abcd")).unwrap();
+    let mut infos = Vec::new();
+    for st_id in [1, 0] {
+        let router = BruteRouter::<()>::new(&device, st_id, false);
+        let info = router.route_all(true);
+        let blob = combined.blob(&device, &router, &info).unwrap();
+        combined.write_blob(blob).unwrap();
+        infos.push((st_id, info));
+    }
+    let bba = String::from_utf8(combined.finish().unwrap()).unwrap();
+    assert_eq!(read_device_hash(&bba), Some("0123abcd"));
+
+    /* Labels of the blobs don't clash and the index points at their roots */
+    let labels = parse_bba(&bba);
+    let index = &labels[INDEX_LABEL];
+    assert_eq!(u32_at(index, 0), 2);
+    let site_types = ref_at(&labels, index, 1);
+    for (record, (st_id, info)) in site_types.chunks(2).zip(&infos) {
+        assert_eq!(u32_at(record, 0), *st_id);
+        let root = ref_at(&labels, record, 1);
+        assert_eq!(u32_at(root, 3) as usize, info.pin_to_pin_routing.len());
+    }
+}
+
+#[test]
+fn test_unrepresentable_string() {
+    let mut bba = BbaWriter::new(String::new());
+    assert!(bba.str("A|B", "").is_err());
+    assert!(bba.out.is_empty());
+    assert!(bba.str("A_B", "").is_ok());
+}
@@ -13,14 +13,69 @@
  * limitations under the License.
  */
 
+#[cfg(test)]
+mod tests;
+
 pub trait IcStr<'a> {
+    /// Gets a string of the device by its id.
+    /// 
+    /// # Panics
+    /// Panics if there's no string with such id, which means the device is corrupt.
+    /// Use `try_ic_str` to handle that gracefully.
     fn ic_str(&self, id: u32) -> &'a str;
+
+    /// Same as `ic_str`, but returns `None` if there's no string with such id.
+    fn try_ic_str(&self, id: u32) -> Option<&'a str>;
 }
 
 impl<'a> IcStr<'a> for crate::ic_loader::archdef::Root<'a> {
     fn ic_str(&self, id: u32) -> &'a str {
-        self.get_str_list().unwrap().get(id).unwrap()
+        self.try_ic_str(id).unwrap_or_else(|| panic!(
+            "String id {} is out of range of the string list of {} strings",
+            id,
+            self.get_str_list().map(|list| list.len()).unwrap_or(0)
+        ))
+    }
+
+    fn try_ic_str(&self, id: u32) -> Option<&'a str> {
+        let list = self.get_str_list().ok()?;
+        if id >= list.len() {
+            return None;
+        }
+        list.get(id).ok()
+    }
+}
+
+/// Matches `name` against a pattern, in which `*` stands for any sequence of characters
+/// and `?` for any single character.
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    /* Backtracks to the last `*`, letting it consume one more character */
+    let (mut p, mut n) = (0, 0);
+    let mut last_star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                last_star = Some((p, n));
+                p += 1;
+            },
+            Some(c) if (*c == '?') || (*c == name[n]) => {
+                p += 1;
+                n += 1;
+            },
+            _ => match last_star {
+                Some((star_p, star_n)) => {
+                    last_star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                },
+                None => return false,
+            },
+        }
     }
+    pattern[p ..].iter().all(|c| *c == '*')
 }
 
 /* Splits a range into `slices` possibly even ranges  */
@@ -33,7 +88,7 @@ pub fn split_range_nicely(range: std::ops::Range<usize>, slices: usize)
     let left = len - total;
     
     (0 .. slices)
-        .scan((0, left), move |(current_idx, left), _| {
+        .scan((range.start, left), move |(current_idx, left), _| {
             let my_len = if *left > 0 {
                 *left -= 1;
                 split_sz + 1
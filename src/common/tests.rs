@@ -0,0 +1,40 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+use super::*;
+use crate::test_utils::*;
+
+#[test]
+fn test_try_ic_str_out_of_range() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let len = device.get_str_list().unwrap().len();
+
+    assert!(len > 0);
+    assert_eq!(device.try_ic_str(0), Some(device.ic_str(0)));
+    assert_eq!(device.try_ic_str(len), None);
+    assert_eq!(device.try_ic_str(u32::MAX), None);
+}
+
+#[test]
+#[should_panic(expected = "is out of range of the string list")]
+fn test_ic_str_out_of_range_panics() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let len = device.get_str_list().unwrap().len();
+
+    device.ic_str(len);
+}
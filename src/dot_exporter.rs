@@ -16,12 +16,14 @@
 use std::collections::HashMap;
 use std::borrow::Borrow;
 use crate::ic_loader::archdef::Root as Device;
-use crate::router::{PinDir, BELInfo, ResourceNameRef};
+use crate::router::{PinDir, BELInfo, ResourceName};
 use crate::router::site_brute_router::{
     RoutingGraph,
+    RoutingGraphEdge,
     RoutingGraphNodeKind
 };
 use crate::strings::GlobalStringsCtx;
+use crate::graph_exporter::GraphExporter;
 
 pub struct SiteRoutingGraphDotExporter<'d, G, B, P> where 
     G: Borrow<RoutingGraph>,
@@ -31,6 +33,8 @@ pub struct SiteRoutingGraphDotExporter<'d, G, B, P> where
     graph: G,
     bels: B,
     tile_belpin_idx_to_bel_pin: P,
+    sorted: bool,
+    edge_kind_colors: bool,
     _d: std::marker::PhantomData<&'d ()>,
 }
 
@@ -40,14 +44,60 @@ impl<'d, G, B, P> SiteRoutingGraphDotExporter<'d, G, B, P> where
     P: Borrow<Vec<(usize, usize)>>
 {
     pub fn new(graph: G, bels: B, tile_belpin_idx_to_bel_pin: P) -> Self {
-        Self { graph, bels, tile_belpin_idx_to_bel_pin, _d: Default::default() }
+        Self {
+            graph,
+            bels,
+            tile_belpin_idx_to_bel_pin,
+            sorted: false,
+            edge_kind_colors: false,
+            _d: Default::default()
+        }
+    }
+
+    /// Emit BEL clusters and pins within them sorted by name. This makes the output
+    /// deterministic, so that diagrams of the same site can be diffed.
+    pub fn with_sorted_clusters(mut self, sorted: bool) -> Self {
+        self.sorted = sorted;
+        self
+    }
+
+    /// Color edges by their kind: site wires (fixed connectivity), pseudo-pips (routing
+    /// choices) and connections synthesized for virtual constant networks.
+    pub fn with_edge_kind_colors(mut self, colors: bool) -> Self {
+        self.edge_kind_colors = colors;
+        self
+    }
+
+    fn pin_name<F>(&self, resolve: &F, pin_idx: usize) -> String where
+        F: Fn(&ResourceName) -> String
+    {
+        let (bel_idx, bel_pin_idx) = self.tile_belpin_idx_to_bel_pin.borrow()[pin_idx];
+        resolve(&self.bels.borrow()[bel_idx].pins[bel_pin_idx].name)
     }
 
     pub fn export_dot(&self, device: &Device<'d>, name: &str) -> String {
+        self.render(name, |resource_name| {
+            resource_name.get(device, &GlobalStringsCtx::hold()).to_string()
+        })
+    }
+
+    /// Same as `export_dot`, but doesn't require access to the device. All BEL and pin
+    /// names have to be virtual (see `BruteRouter::with_detached_names`). Unlike the
+    /// device, the exporter can be used from any thread.
+    pub fn export_detached_dot(&self, name: &str) -> String {
+        self.render(name, |resource_name| match resource_name {
+            ResourceName::Virtual(id) =>
+                GlobalStringsCtx::hold().get_global_string(*id).to_string(),
+            ResourceName::DeviceResources(_) =>
+                panic!("Name is not detached from the device"),
+        })
+    }
+
+    fn render<F>(&self, name: &str, resolve: F) -> String where
+        F: Fn(&ResourceName) -> String
+    {
         let mut bel_subgraphs = HashMap::new();
 
-        let gsctx = GlobalStringsCtx::hold();
-    
         /* Group pins of the same BELs into subgraphs */
         for node_idx in 0 .. self.graph.borrow().node_count() {
             let node = self.graph.borrow().get_node(node_idx);
@@ -58,8 +108,7 @@ impl<'d, G, B, P> SiteRoutingGraphDotExporter<'d, G, B, P> where
                 RoutingGraphNodeKind::FreePort => unreachable!(),
             };
 
-            let bel_name: ResourceNameRef =
-                self.bels.borrow()[bel_idx].name.get(device, &gsctx);
+            let bel_name = resolve(&self.bels.borrow()[bel_idx].name);
     
             let bucket = bel_subgraphs.entry(bel_name)
                 .or_insert_with(|| BELSubGraph::default());
@@ -72,6 +121,16 @@ impl<'d, G, B, P> SiteRoutingGraphDotExporter<'d, G, B, P> where
     
             bucket.pins.push(node_idx);
         }
+
+        let mut bel_subgraphs: Vec<_> = bel_subgraphs.into_iter().collect();
+        if self.sorted {
+            bel_subgraphs.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (_, bel_subgraph) in &mut bel_subgraphs {
+                bel_subgraph.pins.sort_by_cached_key(|pin_idx| {
+                    (self.pin_name(&resolve, *pin_idx), *pin_idx)
+                });
+            }
+        }
         
         /* Write DOT */
         let mut dot = "# DOT Graph generated by NISP\n\n".to_string();
@@ -88,13 +147,7 @@ impl<'d, G, B, P> SiteRoutingGraphDotExporter<'d, G, B, P> where
             );
     
             for pin_idx in &bel_subgraph.pins {
-                let (bel_idx, bel_pin_idx) =
-                    self.tile_belpin_idx_to_bel_pin.borrow()[*pin_idx];
-                let bel = &self.bels.borrow()[bel_idx];
-
-                let gsctx = GlobalStringsCtx::hold();
-
-                let pin_name = bel.pins[bel_pin_idx].name.get(device, &gsctx);
+                let pin_name = self.pin_name(&resolve, *pin_idx);
             
                 dot += &format!(
                     "        {} [label=\"{}\", color={}];\n",
@@ -112,7 +165,17 @@ impl<'d, G, B, P> SiteRoutingGraphDotExporter<'d, G, B, P> where
     
         for from in 0 .. self.graph.borrow().node_count() {
             for to in self.graph.borrow().edges_from(from) {
-                dot += &format!("    {} -> {};\n", from, to);
+                if !self.edge_kind_colors {
+                    dot += &format!("    {} -> {};\n", from, to);
+                    continue;
+                }
+                let color = match self.graph.borrow().get_edge(from, to) {
+                    RoutingGraphEdge::SiteWire => "\"#404040\"",
+                    RoutingGraphEdge::PseudoPip => "\"#a347c4\"",
+                    RoutingGraphEdge::VirtualConst => "\"#e07b39\"",
+                    RoutingGraphEdge::None => unreachable!(),
+                };
+                dot += &format!("    {} -> {} [color={}];\n", from, to, color);
             }
         }
     
@@ -122,6 +185,16 @@ impl<'d, G, B, P> SiteRoutingGraphDotExporter<'d, G, B, P> where
     }
 }
 
+impl<'d, G, B, P> GraphExporter<'d> for SiteRoutingGraphDotExporter<'d, G, B, P> where 
+    G: Borrow<RoutingGraph>,
+    B: Borrow<Vec<BELInfo>>,
+    P: Borrow<Vec<(usize, usize)>>
+{
+    fn export_graph(&self, device: &Device<'d>, name: &str) -> String {
+        self.export_dot(device, name)
+    }
+}
+
 enum BELSubGrapgBELCategory {
     NoRouting,
     Routing,
@@ -17,7 +17,7 @@
 use std::path::{Path, PathBuf};
 use std::fs::File;
 use std::collections::HashSet;
-use std::io::Write;
+use std::io::{BufWriter, Write};
 use std::collections::HashMap;
 
 use serde::Serialize;
@@ -38,12 +38,29 @@ impl AsBytes for str {
     }
 }
 
+impl AsBytes for Vec<u8> {
+    fn as_bytes<'s>(&'s self) -> &'s [u8] {
+        self
+    }
+}
+
 impl AsBytes for [u8] {
     fn as_bytes<'s>(&'s self) -> &'s [u8] {
         self
     }
 }
 
+/* Creates a file along with its missing parent directories */
+pub(crate) fn create_file(path: &Path) -> std::io::Result<File> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).map_err(|e| std::io::Error::new(
+            e.kind(),
+            format!("Couldn't create output directory {}: {}", dir.display(), e)
+        ))?;
+    }
+    File::create(path)
+}
+
 #[derive(Default)]
 struct ExportChecker {
     export: HashSet<String>,
@@ -51,6 +68,19 @@ struct ExportChecker {
 }
 
 impl ExportChecker {
+    /* Names given on the command line, `:all` selects everything */
+    fn from_args(arg_list: &Option<Vec<String>>) -> Self {
+        let mut checker = Self::default();
+        for arg in arg_list.iter().flatten() {
+            if arg == ":all" {
+                checker.export_all = true;
+            } else {
+                checker.export.insert(arg.clone());
+            }
+        }
+        checker
+    }
+
     fn should_export(&self, name: &str) -> bool {
         if self.export_all || self.export.contains(name) {
             return true;
@@ -76,19 +106,34 @@ pub struct MultiFileExporter {
 
 impl MultiFileExporter {
     pub fn new(arg_list: &Option<Vec<String>>, prefix: String, suffix: String) -> Self {
-        let mut export_all = false;
-        let mut export = HashSet::new();
-        if let Some(args) = arg_list {
-            for arg in args {
-                if arg == ":all" {
-                    export_all = true;
-                } else {
-                    export.insert(arg.clone());
-                }
-            }
-        }
+        Self { prefix, suffix, checker: ExportChecker::from_args(arg_list) }
+    }
+
+    pub fn should_export(&self, name: &str) -> bool {
+        self.checker.should_export(name)
+    }
+
+    /// Gets path of the file written for `name`.
+    pub fn path(&self, name: &str) -> PathBuf {
+        Path::new(&self.prefix).join(Path::new(&(name.to_string() + &self.suffix)))
+    }
+
+    /// Writes a file for `name`, regardless of whether it was requested. Each file is
+    /// written independently, so this can be called from multiple threads at once.
+    /// 
+    /// The data is written to a temporary file first, which then replaces the file for
+    /// `name`. An interrupted export thus never leaves a partially written file behind
+    /// to be taken for a complete one (see `--resume`).
+    pub fn export<D>(&self, name: &str, data: D) -> std::io::Result<()> where D: AsBytes {
+        let path = self.path(name);
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
 
-        Self { prefix, suffix, checker: ExportChecker { export, export_all } }
+        let mut file = create_file(&tmp_path)?;
+        file.write_all(data.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &path)
     }
 }
 
@@ -99,11 +144,7 @@ impl<D> Exporter<D> for MultiFileExporter where D: AsBytes {
         F: FnOnce() -> D + 's
     {
         if self.checker.should_export(name) {
-            let data = exporter();
-            let path = Path::new(&self.prefix)
-                .join(Path::new(&(name.to_string() + &self.suffix)));
-            let mut file = File::create(path)?;
-            return file.write(data.as_bytes()).map(|_| ());
+            return self.export(name, exporter());
         }
         Ok(())
     }
@@ -121,22 +162,10 @@ pub struct CompoundJsonExporter<D> where D: Serialize {
 
 impl<D> CompoundJsonExporter<D> where D: Serialize {
     pub fn new(arg_list: &Option<Vec<String>>, filename: PathBuf) -> Self {
-        let mut export_all = false;
-        let mut export = HashSet::new();
-        if let Some(args) = arg_list {
-            for arg in args {
-                if arg == ":all" {
-                    export_all = true;
-                } else {
-                    export.insert(arg.clone());
-                }
-            }
-        }
-
         Self {
             filename,
             data: HashMap::new(),
-            checker: ExportChecker { export, export_all }
+            checker: ExportChecker::from_args(arg_list)
         }
     }
 }
@@ -156,7 +185,143 @@ impl<D> Exporter<D> for CompoundJsonExporter<D> where D: Serialize {
 
     fn flush(&mut self) -> std::io::Result<()> {
         let data = serde_json::to_string_pretty(&self.data).unwrap();
-        let mut file = File::create(&self.filename)?;
+        let mut file = create_file(&self.filename)?;
         return file.write(data.as_bytes()).map(|_| ());
     }
 }
+
+/// Writes the same JSON object as `CompoundJsonExporter`, but serializes entries as soon
+/// as they are exported, instead of keeping all of them in memory until `flush`.
+pub struct StreamingJsonExporter {
+    filename: PathBuf,
+    file: Option<BufWriter<File>>,
+    checker: ExportChecker,
+}
+
+impl StreamingJsonExporter {
+    pub fn new(arg_list: &Option<Vec<String>>, filename: PathBuf) -> Self {
+        Self {
+            filename,
+            file: None,
+            checker: ExportChecker::from_args(arg_list)
+        }
+    }
+}
+
+impl<D> Exporter<D> for StreamingJsonExporter where D: Serialize {
+    fn ignore_or_export<'s, F>(&'s mut self, name: &str, exporter: F)
+        -> std::io::Result<()>
+    where
+        F: FnOnce() -> D + 's
+    {
+        if !self.checker.should_export(name) {
+            return Ok(());
+        }
+
+        let data = exporter();
+        if let Some(file) = &mut self.file {
+            file.write_all(b",\n")?;
+        } else {
+            let mut file = BufWriter::new(create_file(&self.filename)?);
+            file.write_all(b"{\n")?;
+            self.file = Some(file);
+        }
+        let file = self.file.as_mut().unwrap();
+        serde_json::to_writer(&mut *file, name)?;
+        file.write_all(b": ")?;
+        serde_json::to_writer_pretty(&mut *file, &data)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.file.take() {
+            Some(mut file) => {
+                file.write_all(b"\n}")?;
+                file.flush()
+            },
+            None => create_file(&self.filename)?.write_all(b"{}"),
+        }
+    }
+}
+
+/// Writes newline-delimited JSON: one line per exported entry, holding its name as
+/// `site_type` along with the fields of the exported data. Lines are written as soon as
+/// entries are exported and each of them can be parsed on its own.
+pub struct JsonLinesExporter {
+    filename: PathBuf,
+    file: Option<BufWriter<File>>,
+    checker: ExportChecker,
+}
+
+impl JsonLinesExporter {
+    pub fn new(arg_list: &Option<Vec<String>>, filename: PathBuf) -> Self {
+        Self {
+            filename,
+            file: None,
+            checker: ExportChecker::from_args(arg_list)
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonLine<'d, D> where D: Serialize {
+    site_type: &'d str,
+    #[serde(flatten)]
+    data: &'d D,
+}
+
+impl<D> Exporter<D> for JsonLinesExporter where D: Serialize {
+    fn ignore_or_export<'s, F>(&'s mut self, name: &str, exporter: F)
+        -> std::io::Result<()>
+    where
+        F: FnOnce() -> D + 's
+    {
+        if !self.checker.should_export(name) {
+            return Ok(());
+        }
+
+        let data = exporter();
+        if self.file.is_none() {
+            self.file = Some(BufWriter::new(create_file(&self.filename)?));
+        }
+        let file = self.file.as_mut().unwrap();
+        serde_json::to_writer(&mut *file, &JsonLine { site_type: name, data: &data })?;
+        file.write_all(b"\n")
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.file.take() {
+            Some(mut file) => file.flush(),
+            None => create_file(&self.filename).map(|_| ()),
+        }
+    }
+}
+
+/// JSON exporter with the export strategy chosen at runtime.
+pub enum JsonExporter<D> where D: Serialize {
+    Compound(CompoundJsonExporter<D>),
+    Streaming(StreamingJsonExporter),
+    Lines(JsonLinesExporter),
+}
+
+impl<D> Exporter<D> for JsonExporter<D> where D: Serialize {
+    fn ignore_or_export<'s, F>(&'s mut self, name: &str, exporter: F)
+        -> std::io::Result<()>
+    where
+        F: FnOnce() -> D + 's
+    {
+        match self {
+            Self::Compound(e) => e.ignore_or_export(name, exporter),
+            Self::Streaming(e) => e.ignore_or_export(name, exporter),
+            Self::Lines(e) => e.ignore_or_export(name, exporter),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Compound(e) => e.flush(),
+            Self::Streaming(e) => <StreamingJsonExporter as Exporter<D>>::flush(e),
+            Self::Lines(e) => <JsonLinesExporter as Exporter<D>>::flush(e),
+        }
+    }
+}
@@ -0,0 +1,26 @@
+/* Copyright (C) 2022 Antmicro
+ * 
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * 
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ * 
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::ic_loader::archdef::Root as Device;
+
+/// Common interface of exporters rendering site routing graphs into text formats.
+pub trait GraphExporter<'d> {
+    /// Renders the graph into a string.
+    /// 
+    /// # Arguments
+    /// * `device` - `DeviceResources::Device` root
+    /// * `name` - name of the graph, usually the name of the site type
+    fn export_graph(&self, device: &Device<'d>, name: &str) -> String;
+}
@@ -0,0 +1,114 @@
+/* Copyright (C) 2022 Antmicro
+ * 
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * 
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ * 
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::borrow::Borrow;
+use crate::graph_exporter::GraphExporter;
+use crate::ic_loader::archdef::Root as Device;
+use crate::router::{PinDir, BELInfo};
+use crate::router::site_brute_router::RoutingGraph;
+use crate::strings::GlobalStringsCtx;
+
+#[cfg(test)]
+mod tests;
+
+pub struct SiteRoutingGraphGraphMLExporter<'d, G, B, P> where 
+    G: Borrow<RoutingGraph>,
+    B: Borrow<Vec<BELInfo>>,
+    P: Borrow<Vec<(usize, usize)>>
+{
+    graph: G,
+    bels: B,
+    tile_belpin_idx_to_bel_pin: P,
+    _d: std::marker::PhantomData<&'d ()>,
+}
+
+fn escape_xml(s: &str) -> String {
+    s.chars().fold(String::with_capacity(s.len()), |mut escaped, ch| {
+        match ch {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&apos;",
+            _ => escaped.push(ch),
+        }
+        escaped
+    })
+}
+
+impl<'d, G, B, P> SiteRoutingGraphGraphMLExporter<'d, G, B, P> where 
+    G: Borrow<RoutingGraph>,
+    B: Borrow<Vec<BELInfo>>,
+    P: Borrow<Vec<(usize, usize)>>
+{
+    pub fn new(graph: G, bels: B, tile_belpin_idx_to_bel_pin: P) -> Self {
+        Self { graph, bels, tile_belpin_idx_to_bel_pin, _d: Default::default() }
+    }
+
+    pub fn export_graphml(&self, device: &Device<'d>, name: &str) -> String {
+        let gsctx = GlobalStringsCtx::hold();
+        let graph = self.graph.borrow();
+
+        let mut xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string();
+        xml += "<!-- GraphML generated by NISP -->\n";
+        xml += "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n";
+        xml += "  <key id=\"bel\" for=\"node\" attr.name=\"bel\" attr.type=\"string\"/>\n";
+        xml += "  <key id=\"pin\" for=\"node\" attr.name=\"pin\" attr.type=\"string\"/>\n";
+        xml += "  <key id=\"dir\" for=\"node\" attr.name=\"dir\" attr.type=\"string\"/>\n";
+        xml += &format!(
+            "  <graph id=\"{}\" edgedefault=\"directed\">\n",
+            escape_xml(name)
+        );
+
+        for node_idx in 0 .. graph.node_count() {
+            let (bel_idx, bel_pin_idx) = self.tile_belpin_idx_to_bel_pin.borrow()[node_idx];
+            let bel = &self.bels.borrow()[bel_idx];
+            let bel_name = bel.name.get(device, &gsctx);
+            let pin_name = bel.pins[bel_pin_idx].name.get(device, &gsctx);
+            let dir = match graph.get_node(node_idx).dir {
+                PinDir::Input => "input",
+                PinDir::Output => "output",
+                PinDir::Inout => "inout",
+            };
+
+            xml += &format!("    <node id=\"n{}\">\n", node_idx);
+            xml += &format!("      <data key=\"bel\">{}</data>\n", escape_xml(&bel_name));
+            xml += &format!("      <data key=\"pin\">{}</data>\n", escape_xml(&pin_name));
+            xml += &format!("      <data key=\"dir\">{}</data>\n", dir);
+            xml += "    </node>\n";
+        }
+
+        for from in 0 .. graph.node_count() {
+            for to in graph.edges_from(from) {
+                xml += &format!("    <edge source=\"n{}\" target=\"n{}\"/>\n", from, to);
+            }
+        }
+
+        xml += "  </graph>\n";
+        xml += "</graphml>\n";
+
+        xml
+    }
+}
+
+impl<'d, G, B, P> GraphExporter<'d> for SiteRoutingGraphGraphMLExporter<'d, G, B, P> where 
+    G: Borrow<RoutingGraph>,
+    B: Borrow<Vec<BELInfo>>,
+    P: Borrow<Vec<(usize, usize)>>
+{
+    fn export_graph(&self, device: &Device<'d>, name: &str) -> String {
+        self.export_graphml(device, name)
+    }
+}
@@ -0,0 +1,71 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+use crate::router::site_brute_router::BruteRouter;
+use crate::test_utils::*;
+
+/// Checks that every opened tag gets closed in the right order.
+/// Returns the number of tags with a given name.
+fn check_tags_balanced(xml: &str, count_tag: &str) -> usize {
+    let mut stack = Vec::new();
+    let mut count = 0;
+    let mut rest = xml;
+
+    while let Some(start) = rest.find('<') {
+        let end = rest[start ..].find('>').expect("Unterminated tag") + start;
+        let tag = &rest[start + 1 .. end];
+        rest = &rest[end + 1 ..];
+
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+        if let Some(closing) = tag.strip_prefix('/') {
+            assert_eq!(stack.pop(), Some(closing.to_string()));
+            continue;
+        }
+
+        let name = tag.split_whitespace().next().unwrap().trim_end_matches('/');
+        if name == count_tag {
+            count += 1;
+        }
+        if !tag.ends_with('/') {
+            stack.push(name.to_string());
+        }
+    }
+
+    assert!(stack.is_empty(), "Unclosed tags: {:?}", stack);
+    count
+}
+
+#[test]
+fn test_graphml_well_formed() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let graph = router.get_graph();
+
+    let xml = router.create_graphml_exporter().export_graphml(&device, "MUX_SITE");
+
+    let edge_count: usize = (0 .. graph.node_count())
+        .map(|node| graph.edges_from(node).count())
+        .sum();
+
+    assert_eq!(check_tags_balanced(&xml, "node"), graph.node_count());
+    assert_eq!(check_tags_balanced(&xml, "edge"), edge_count);
+    assert!(xml.contains("<data key=\"bel\">MUX</data>"));
+    assert!(xml.contains("<data key=\"pin\">I0</data>"));
+    assert!(xml.contains("<data key=\"dir\">output</data>"));
+}
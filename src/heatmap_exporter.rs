@@ -0,0 +1,56 @@
+/* Copyright (C) 2022 Antmicro
+ * 
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * 
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ * 
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Rendering routability of site types as images.
+//!
+//! Routing graphs of dense sites are unreadable when drawn, so instead the routability
+//! matrix gets rendered as a grayscale image, with one pixel per pin pair.
+
+use image::{GrayImage, ImageEncoder, Luma};
+use image::codecs::png::PngEncoder;
+
+use crate::router::site_brute_router::RoutingInfo;
+
+#[cfg(test)]
+mod tests;
+
+const ROUTABLE: Luma<u8> = Luma([255]);
+
+/// Renders a routability matrix. The image is `pin_count` × `pin_count` pixels. Pixel in
+/// column `j` of row `i` is white if pin `j` can be reached from pin `i` and black
+/// otherwise.
+/// 
+/// # Arguments
+/// * `pin_count` - number of nodes in the routing graph
+/// * `routing_info` - routing results of the site type
+pub fn routability_heatmap(pin_count: usize, routing_info: &RoutingInfo) -> GrayImage {
+    let mut image = GrayImage::new(pin_count as u32, pin_count as u32);
+    for (from, to) in routing_info.pin_to_pin_routing.keys() {
+        image.put_pixel(to.0 as u32, from.0 as u32, ROUTABLE);
+    }
+    image
+}
+
+/// Encodes an image as PNG.
+pub fn encode_png(image: &GrayImage) -> image::ImageResult<Vec<u8>> {
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(
+        image.as_raw(),
+        image.width(),
+        image.height(),
+        image::ColorType::L8
+    )?;
+    Ok(png)
+}
@@ -0,0 +1,38 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+use super::*;
+use crate::router::site_brute_router::BruteRouter;
+use crate::test_utils::*;
+
+#[test]
+fn test_heatmap_dimensions() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let info = router.route_all(true);
+    let node_count = router.get_graph().node_count();
+
+    let png = encode_png(&routability_heatmap(node_count, &info)).unwrap();
+    let image = image::load_from_memory(&png).unwrap().into_luma8();
+
+    assert_eq!(image.dimensions(), (node_count as u32, node_count as u32));
+
+    let a = router.get_pin_id(&device, "A", "A").unwrap();
+    let d = router.get_pin_id(&device, "FF", "D").unwrap();
+    assert_eq!(image.get_pixel(d.0 as u32, a.0 as u32), &ROUTABLE);
+    assert_eq!(image.get_pixel(a.0 as u32, d.0 as u32), &Luma([0]));
+}
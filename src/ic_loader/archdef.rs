@@ -29,7 +29,7 @@ pub type DeviceBuilder = capnp::message::TypedBuilder::<
     DeviceResources_capnp::device::Owned
 >;
 pub type Root<'a> = DeviceResources_capnp::device::Reader<'a>;
-//type RootBuilder<'a> = archdef::DeviceResources_capnp::device::Builder<'a>;
+pub type RootBuilder<'a> = DeviceResources_capnp::device::Builder<'a>;
 pub type TileReader<'a> = DeviceResources_capnp::device::tile::Reader<'a>;
 pub type TileTypeReader<'a> = DeviceResources_capnp::device::tile_type::Reader<'a>;
 pub type SiteTypeReader<'a> = DeviceResources_capnp::device::site_type::Reader<'a>;
@@ -40,6 +40,62 @@ pub type SiteTypeReader<'a> = DeviceResources_capnp::device::site_type::Reader<'
 
 pub type WireReader<'a> = DeviceResources_capnp::device::wire::Reader<'a>;
 
+/// Checks whether the device contains the fields required by NISP. A device produced
+/// with an incompatible schema version can be read successfully, but will be missing
+/// those, which would otherwise lead to a panic much later.
+pub fn validate_root<'a>(root: &Root<'a>) -> Result<(), OpenWriteError> {
+    let missing = |what: &str| Err(OpenWriteError::MalformedDevice(format!(
+        "Device has no {}. Is it a valid fpga-interchange device file of a compatible \
+        schema version?",
+        what
+    )));
+
+    if !root.has_str_list() {
+        return missing("string list");
+    }
+    if !root.has_site_type_list() {
+        return missing("site type list");
+    }
+    if !root.has_constants() {
+        return missing("constants");
+    }
+    if root.get_str_list().is_err() || root.get_site_type_list().is_err() {
+        return Err(OpenWriteError::MalformedDevice(
+            "Device lists can't be read".into()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Header metadata of a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub site_type_count: u32,
+    pub tile_type_count: u32,
+    pub string_count: u32,
+    /// Whether the device lists BEL pins of site types acting as constant sources
+    pub has_constant_site_sources: bool,
+}
+
+impl DeviceInfo {
+    /// Gathers metadata of a device. The device is expected to have passed
+    /// `validate_root`.
+    pub fn gather<'a>(root: &Root<'a>) -> Self {
+        Self {
+            name: root.get_name().unwrap_or("").to_string(),
+            site_type_count: root.get_site_type_list().map(|l| l.len()).unwrap_or(0),
+            tile_type_count: root.get_tile_type_list().map(|l| l.len()).unwrap_or(0),
+            string_count: root.get_str_list().map(|l| l.len()).unwrap_or(0),
+            has_constant_site_sources: root.get_constants()
+                .and_then(|constants| constants.get_site_sources())
+                .map(|sources| sources.len() != 0)
+                .unwrap_or(false),
+        }
+    }
+}
+
 pub fn make_builder<'a>(root: DeviceResources_capnp::device::Reader<'a>) -> DeviceBuilder {
     let mut builder = DeviceBuilder::new_default();
     builder.set_root(root.clone()).unwrap();
@@ -63,3 +119,41 @@ pub fn write<P>(path: P, builder: DeviceBuilder, opts: WriteOpts)
 
     Ok(())
 }
+
+/// Loads a device file, lets `edit` modify it and writes the result to another file.
+/// The format of the source file is detected (see `detect_format`), the destination
+/// is written according to `opts`. Useful for deriving test fixtures from real devices.
+/// 
+/// # Arguments
+/// * `src` - device file to load
+/// * `dst` - file to write the modified device to. May be the same as `src`.
+/// * `opts` - options for writing `dst`
+/// * `edit` - closure modifying the device
+pub fn edit_and_write<P, Q, F>(src: P, dst: Q, opts: WriteOpts, edit: F)
+    -> Result<(), OpenWriteError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    F: FnOnce(&mut RootBuilder<'_>)
+{
+    let raw = match detect_format(&src)? {
+        FileFormat::Raw => true,
+        FileFormat::Gzip => false,
+        FileFormat::Zstd => return Err(OpenWriteError::CantOpenFile(
+            "Editing zstd-compressed device files is not supported".into()
+        )),
+    };
+
+    /* The source gets released before writing, as it might be overwritten */
+    let mut builder = {
+        let msg = open(src, OpenOpts { raw })?;
+        let root = msg.get_archdef_root()
+            .map_err(|e| OpenWriteError::CapnProtoError(format!("{:?}", e)))?;
+        make_builder(root)
+    };
+    let mut root = builder.get_root()
+        .map_err(|e| OpenWriteError::CapnProtoError(format!("{:?}", e)))?;
+    edit(&mut root);
+
+    write(dst, builder, opts)
+}
@@ -34,16 +34,18 @@ pub mod PhysicalNetlist_capnp {
     include_interchange_capnp!("PhysicalNetlist_capnp.rs");
 }
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Read};
 use memmap2::Mmap;
 use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, Clone)]
 pub enum OpenWriteError {
     CantOpenFile(String),
-    CapnProtoError(String)
+    CapnProtoError(String),
+    MalformedDevice(String),
 }
 
 const CPNP_MSG_MAXSIZE: usize = usize::MAX; // 4GiB
@@ -83,10 +85,113 @@ impl<S> MsgReader for capnp::message::Reader<S> where
     }
 }
 
+fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .map(|path| path.starts_with("http://") || path.starts_with("https://"))
+        .unwrap_or(false)
+}
+
+/* Gets a path to a local copy of the file, fetching it first if `path` is an URL */
+fn local_path(path: &Path) -> Result<PathBuf, OpenWriteError> {
+    #[cfg(feature = "remote")]
+    if is_url(path) {
+        return remote::fetch(path.to_str().unwrap());
+    }
+
+    #[cfg(not(feature = "remote"))]
+    if is_url(path) {
+        return Err(OpenWriteError::CantOpenFile(
+            "Opening files from URLs requires building with `remote` feature".into()
+        ));
+    }
+
+    Ok(path.to_path_buf())
+}
+
+/// Compression format of a device file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Raw,
+    Gzip,
+    Zstd,
+}
+
+impl std::fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FileFormat::Raw => "raw",
+            FileFormat::Gzip => "gzip",
+            FileFormat::Zstd => "zstd",
+        })
+    }
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Detects the format of a device file by its magic number. Files which are neither
+/// gzip nor zstd compressed are assumed to be raw Cap'n Proto messages.
+pub fn detect_format<P>(path: P) -> Result<FileFormat, OpenWriteError> where
+    P: AsRef<Path>
+{
+    let path = local_path(path.as_ref())?;
+    let mut magic = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(ZSTD_MAGIC.len() as u64).read_to_end(&mut magic))
+        .map_err(|e| OpenWriteError::CantOpenFile(format!("{:?}", e)))?;
+
+    Ok(if magic.starts_with(GZIP_MAGIC) {
+        FileFormat::Gzip
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        FileFormat::Zstd
+    } else {
+        FileFormat::Raw
+    })
+}
+
+/* Passes read data through, feeding it to a hasher along the way */
+struct HashingReader<R> {
+    inner: R,
+    hasher: Option<Sha256>,
+}
+
+impl<R> Read for HashingReader<R> where R: Read {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[.. len]);
+        }
+        Ok(len)
+    }
+}
+
+/// Opens a device file. With the `remote` feature enabled, `path` can also be
+/// an HTTP(S) URL (see `remote::fetch`).
 pub fn open<P>(path: P, opts: OpenOpts) -> Result<Box<dyn MsgReader>, OpenWriteError> where
     P: AsRef<Path>,
 
 {
+    open_impl(path.as_ref(), opts, false).map(|(reader, _)| reader)
+}
+
+/// Same as `open`, but also computes SHA-256 hash of the device file (as stored,
+/// ie. before decompression) while reading it.
+/// 
+/// # Return
+/// The message reader and the hash as a lowercase hex string
+pub fn open_with_hash<P>(path: P, opts: OpenOpts)
+    -> Result<(Box<dyn MsgReader>, String), OpenWriteError>
+where
+    P: AsRef<Path>
+{
+    open_impl(path.as_ref(), opts, true).map(|(reader, hash)| (reader, hash.unwrap()))
+}
+
+fn open_impl(path: &Path, opts: OpenOpts, hash: bool)
+    -> Result<(Box<dyn MsgReader>, Option<String>), OpenWriteError>
+{
+    let path = local_path(path)?;
+
     let archdef_file = File::open(path)
         .map_err(|e| OpenWriteError::CantOpenFile(format!("{:?}", e)))?;
     
@@ -103,28 +208,48 @@ pub fn open<P>(path: P, opts: OpenOpts) -> Result<Box<dyn MsgReader>, OpenWriteE
      * IMPORTANT: In order to use RAW mode, you must decompress the fpga-interchange
      * device file using gzip.
      */
-    let reader: Box<dyn MsgReader> = if opts.raw {
+    let (reader, hash): (Box<dyn MsgReader>, _) = if opts.raw {
         /* UNSAFE DUE TO A POTENTIAL UB WHEN A FILE IS CHANGED! */
         let mmapped = unsafe { Mmap::map(&archdef_file) }
             .map_err(|e| OpenWriteError::CantOpenFile(format!("mmap failed: {:?}", e)))?;
+        let hash = hash.then(|| format!("{:x}", Sha256::digest(&mmapped[..])));
         let segments = capnp::serialize::BufferSegments::new(mmapped, reader_opts)
             .map_err(|e| OpenWriteError::CapnProtoError(format!("failed to create buffer segments: {:?}", e)))?;
-        Box::new(capnp::message::Reader::new(segments, reader_opts))
+        (Box::new(capnp::message::Reader::new(segments, reader_opts)), hash)
     } else {
-        let d = BufReader::new(GzDecoder::new(archdef_file));
+        let hashing_file = HashingReader {
+            inner: archdef_file,
+            hasher: hash.then(Sha256::new),
+        };
+        let mut d = BufReader::new(GzDecoder::new(hashing_file));
     
         let reader = capnp::serialize::read_message(
-            d,
+            &mut d,
             capnp::message::ReaderOptions {
                 traversal_limit_in_words: Some(CPNP_MSG_MAXSIZE),
                 .. capnp::message::DEFAULT_READER_OPTIONS
             }
         ).map_err(|e| OpenWriteError::CapnProtoError(format!("{:?}", e)))?;
-        Box::new(reader)
+
+        /* The decoder doesn't need to read the whole file, but the hash has to cover it */
+        let mut hashing_file = d.into_inner().into_inner();
+        let hash = match hashing_file.hasher.is_some() {
+            true => {
+                std::io::copy(&mut hashing_file, &mut std::io::sink())
+                    .map_err(|e| OpenWriteError::CantOpenFile(format!("{:?}", e)))?;
+                hashing_file.hasher.take().map(|hasher| format!("{:x}", hasher.finalize()))
+            },
+            false => None,
+        };
+        (Box::new(reader), hash)
     };
     
-    Ok(reader)
+    Ok((reader, hash))
 }
 
 pub mod archdef;
 pub mod logical_netlist;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,80 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Fetching device files over HTTP(S).
+//!
+//! Downloaded files are cached in `NISP_CACHE_DIR` (or a `nisp-cache` directory within
+//! the system's temporary directory) and reused by subsequent runs.
+
+use std::fs::File;
+use std::path::PathBuf;
+use sha2::{Digest, Sha256};
+
+use super::OpenWriteError;
+
+fn cache_dir() -> PathBuf {
+    match std::env::var("NISP_CACHE_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => std::env::temp_dir().join("nisp-cache"),
+    }
+}
+
+/* Different URLs can end with the same file name, so the name is prefixed with a hash
+ * of the whole URL. The query and fragment are dropped first, as they don't name the
+ * file (eg. access tokens of signed URLs). */
+fn cached_file_name(url: &str) -> String {
+    let url = url.split(|c| (c == '?') || (c == '#')).next().unwrap();
+    let hash = Sha256::digest(url.as_bytes());
+
+    let file_name = url.rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty())
+        .unwrap_or("device");
+
+    format!("{:x}-{}", hash, file_name)
+}
+
+/// Downloads a file, unless it's already present in the cache.
+///
+/// # Return
+/// Path to the cached file
+pub fn fetch(url: &str) -> Result<PathBuf, OpenWriteError> {
+    let cant_fetch = |what: String| OpenWriteError::CantOpenFile(
+        format!("Can't fetch {}: {}", url, what)
+    );
+
+    let dir = cache_dir();
+    let path = dir.join(cached_file_name(url));
+    if path.exists() {
+        return Ok(path);
+    }
+
+    std::fs::create_dir_all(&dir).map_err(|e| cant_fetch(format!("{:?}", e)))?;
+
+    let response = ureq::get(url).call().map_err(|e| cant_fetch(format!("{}", e)))?;
+
+    /* Download to a temporary file first, so that an interrupted download doesn't
+     * leave a corrupted file in the cache */
+    let partial_path = path.with_extension(format!("part{}", std::process::id()));
+    let mut file = File::create(&partial_path)
+        .map_err(|e| cant_fetch(format!("{:?}", e)))?;
+    std::io::copy(&mut response.into_reader(), &mut file)
+        .map_err(|e| cant_fetch(format!("{:?}", e)))?;
+    drop(file);
+
+    std::fs::rename(&partial_path, &path).map_err(|e| cant_fetch(format!("{:?}", e)))?;
+
+    Ok(path)
+}
@@ -0,0 +1,174 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+use super::*;
+use crate::test_utils::*;
+
+#[test]
+fn test_validate_empty_device() {
+    let mut message = capnp::message::Builder::new_default();
+    message.init_root::<DeviceResources_capnp::device::Builder>();
+    let root = fixture_root(&message);
+
+    match archdef::validate_root(&root) {
+        Err(OpenWriteError::MalformedDevice(msg)) => assert!(msg.contains("string list")),
+        _ => panic!("Empty device passed validation"),
+    }
+}
+
+#[test]
+fn test_validate_fixture_device() {
+    let message = DeviceFixture::new("test_device")
+        .site_type(SiteTypeFixture::new("EMPTY"))
+        .build();
+    let root = fixture_root(&message);
+
+    assert!(archdef::validate_root(&root).is_ok());
+}
+
+#[test]
+fn test_detect_format() {
+    let path = std::env::temp_dir()
+        .join(format!("nisp-detect-format-{}", std::process::id()));
+
+    let cases: [(&[u8], FileFormat); 3] = [
+        (&[0x1f, 0x8b, 0x08, 0x00], FileFormat::Gzip),
+        (&[0x28, 0xb5, 0x2f, 0xfd, 0x00], FileFormat::Zstd),
+        (&[0x00, 0x00], FileFormat::Raw),
+    ];
+    for (data, format) in cases {
+        std::fs::write(&path, data).unwrap();
+        assert_eq!(detect_format(&path).unwrap(), format);
+    }
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_open_with_hash() {
+    use sha2::{Digest, Sha256};
+
+    let message = DeviceFixture::new("hashed_device")
+        .site_type(SiteTypeFixture::new("EMPTY"))
+        .build();
+    let dir = std::env::temp_dir();
+    let gzip_path = dir.join(format!("nisp-hash-{}.device", std::process::id()));
+    let raw_path = dir.join(format!("nisp-hash-{}.raw", std::process::id()));
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    capnp::serialize::write_message(&mut encoder, &message).unwrap();
+    std::fs::write(&gzip_path, encoder.finish().unwrap()).unwrap();
+    let mut raw = Vec::new();
+    capnp::serialize::write_message(&mut raw, &message).unwrap();
+    std::fs::write(&raw_path, raw).unwrap();
+
+    for (path, raw) in [(&gzip_path, false), (&raw_path, true)] {
+        let expected = format!("{:x}", Sha256::digest(std::fs::read(path).unwrap()));
+        let (msg, hash) = open_with_hash(path, OpenOpts { raw }).unwrap();
+        assert_eq!(hash, expected);
+        assert_eq!(msg.get_archdef_root().unwrap().get_name().unwrap(), "hashed_device");
+    }
+
+    std::fs::remove_file(&gzip_path).unwrap();
+    std::fs::remove_file(&raw_path).unwrap();
+}
+
+#[cfg(feature = "remote")]
+#[test]
+fn test_open_remote_device() {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    let message = DeviceFixture::new("remote_device")
+        .site_type(SiteTypeFixture::new("EMPTY"))
+        .build();
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    capnp::serialize::write_message(&mut encoder, &message).unwrap();
+    let body = encoder.finish().unwrap();
+
+    /* Serve a single request */
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let len = stream.read(&mut buf).unwrap();
+            if len == 0 { break; }
+            request.extend_from_slice(&buf[.. len]);
+        }
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        ).unwrap();
+        stream.write_all(&body).unwrap();
+    });
+
+    let cache_dir = std::env::temp_dir()
+        .join(format!("nisp-cache-test-{}", std::process::id()));
+    std::env::set_var("NISP_CACHE_DIR", &cache_dir);
+
+    let url = format!("http://127.0.0.1:{}/fixture.device", port);
+    let reader = open(&url, OpenOpts::default()).unwrap();
+    server.join().unwrap();
+
+    let root = reader.get_archdef_root().unwrap();
+    assert_eq!(root.get_name().unwrap(), "remote_device");
+
+    /* The server is gone, so this can succeed only thanks to the cache */
+    let reader = open(&url, OpenOpts::default()).unwrap();
+    assert_eq!(reader.get_archdef_root().unwrap().get_name().unwrap(), "remote_device");
+
+    /* The query and fragment don't change the cached file */
+    let reader = open(&format!("{}?token=1#top", url), OpenOpts::default()).unwrap();
+    assert_eq!(reader.get_archdef_root().unwrap().get_name().unwrap(), "remote_device");
+    let cached: Vec<_> = std::fs::read_dir(&cache_dir).unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(cached.len(), 1);
+    assert!(cached[0].ends_with("-fixture.device"), "{:?}", cached);
+
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+}
+
+#[test]
+fn test_edit_and_write() {
+    let message = DeviceFixture::new("original_device")
+        .site_type(SiteTypeFixture::new("EMPTY"))
+        .build();
+    let dir = std::env::temp_dir();
+    let src_path = dir.join(format!("nisp-edit-src-{}.device", std::process::id()));
+    let dst_path = dir.join(format!("nisp-edit-dst-{}.device", std::process::id()));
+    let mut raw = Vec::new();
+    capnp::serialize::write_message(&mut raw, &message).unwrap();
+    std::fs::write(&src_path, raw).unwrap();
+
+    let opts = WriteOpts { raw: false, compresion_level: 1 };
+    archdef::edit_and_write(&src_path, &dst_path, opts, |device| {
+        device.set_name("edited_device");
+    }).unwrap();
+
+    assert_eq!(detect_format(&dst_path).unwrap(), FileFormat::Gzip);
+    let msg = open(&dst_path, OpenOpts { raw: false }).unwrap();
+    let root = msg.get_archdef_root().unwrap();
+    assert_eq!(root.get_name().unwrap(), "edited_device");
+    assert_eq!(root.get_site_type_list().unwrap().len(), 1);
+
+    std::fs::remove_file(&src_path).unwrap();
+    std::fs::remove_file(&dst_path).unwrap();
+}
@@ -29,7 +29,15 @@
 //! * `logic_formula` - Utilities for handling and preocessing boolean logic formulas
 //! * `router` - Routing of FPGA resources
 //! * `exporter` - Exporting data into files and serialization
+//! * `graph_exporter` - Common interface of routing graph exporters
 //! * `dot_exporter` - Writing graphviz _.dot_ files
+//! * `graphml_exporter` - Writing _.graphml_ files
+//! * `bba_exporter` - Writing routing information as BBA blobs
+//! * `physnet_exporter` - Writing routes as FPGA-Interchange physical netlists
+//! * `routability` - High-level entry point for querying site routability
+//! * `heatmap_exporter` - Rendering routability matrices as PNG images
+//! * `profiling` - Recording flamegraphs of processing phases
+//! * `test_utils` - Synthetic device fixtures for tests (`test-utils` feature)
 //! 
 //! ## Common nomenclature / Glossary
 //! 
@@ -59,6 +67,7 @@
 
 #[macro_use]
 extern crate lazy_static;
+#[cfg(feature = "serialize")]
 #[macro_use]
 extern crate serde;
 
@@ -66,10 +75,22 @@ extern crate serde;
 pub mod include_path;
 #[macro_use]
 pub mod log;
+#[macro_use]
+pub mod profiling;
 pub mod common;
 pub mod strings;
 pub mod ic_loader;
 pub mod logic_formula;
 pub mod router;
+#[cfg(feature = "serialize")]
 pub mod exporter;
+pub mod graph_exporter;
 pub mod dot_exporter;
+pub mod graphml_exporter;
+pub mod bba_exporter;
+pub mod physnet_exporter;
+pub mod routability;
+#[cfg(feature = "image")]
+pub mod heatmap_exporter;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
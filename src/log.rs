@@ -14,14 +14,17 @@
  */
 
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 lazy_static! {
-    pub static ref DBG_LOG_LEVEL: usize = {
+    /* Initialized from the environment, can be changed at runtime with `set_log_level` */
+    pub static ref DBG_LOG_LEVEL: AtomicUsize = {
         use std::env;
 
-        match env::var("NISP_DBG_LOG_LEVEL") {
+        AtomicUsize::new(match env::var("NISP_DBG_LOG_LEVEL") {
             Ok(lvl) => usize::from_str_radix(&lvl, 10).unwrap(),
             Err(_) => 0,            
-        }
+        })
     };
 
     pub static ref DBG_PRINT_CODE_INFO: usize = {
@@ -40,6 +43,21 @@ pub const DBG_INFO: usize = 2;
 pub const DBG_EXTRA1: usize = 3;
 pub const DBG_EXTRA2: usize = 4;
 
+/// Gets the current log level.
+pub fn log_level() -> usize {
+    DBG_LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Checks whether messages of level `lvl` get printed.
+pub fn log_enabled(lvl: usize) -> bool {
+    log_level() >= lvl
+}
+
+/// Changes the log level, overriding the one set with `NISP_DBG_LOG_LEVEL`.
+pub fn set_log_level(lvl: usize) {
+    DBG_LOG_LEVEL.store(lvl, Ordering::Relaxed);
+}
+
 pub const LOG_LVL_STR: &'static [&'static str] = &[
     /* 0 */ "CRITICAL",
     /* 1 */ "WARNING",
@@ -48,15 +66,16 @@ pub const LOG_LVL_STR: &'static [&'static str] = &[
     /* 4 */ "EXTRA INFO"
 ];
 
-#[cfg(debug_assertions)]
+/* Messages are filtered at runtime in all builds, so that `-v` works in release builds
+ * too. Checking the level is a single atomic load, cheap enough for the routing loops. */
 macro_rules! dbg_log {
     ($lvl:expr, $fmt:literal $(, $v:expr )*) => {
         let lvl = crate::log::LOG_LVL_STR.len().min($lvl);
-        if *crate::log::DBG_LOG_LEVEL >= lvl {
+        if crate::log::log_enabled(lvl) {
             if *crate::log::DBG_PRINT_CODE_INFO != 0 {
                 dbg!(
                     concat!("{}: ", $fmt),
-                    $fmt, LOG_LVL_STR[lvl] $(, &$v )*
+                    $fmt, crate::log::LOG_LVL_STR[lvl] $(, &$v )*
                 );
             } else {
                 eprintln!(
@@ -67,10 +86,3 @@ macro_rules! dbg_log {
         }
     }
 }
-
-#[cfg(not(debug_assertions))]
-macro_rules! dbg_log {
-    ($lvl:expr, $fmt:literal $(, $v:expr )*) => {
-        /* NOP */
-    };
-}
\ No newline at end of file
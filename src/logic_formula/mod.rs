@@ -19,6 +19,7 @@
  */
 
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 
 mod intersperse;
 #[cfg(test)]
@@ -28,7 +29,7 @@ use self::intersperse::*;
 #[allow(unused)]
 use crate::log::*;
 
-#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum FormulaTerm<Id> where Id: Ord + Eq {
     Var(Id),
     NegVar(Id),
@@ -85,6 +86,16 @@ impl<Id> PartialEq for FormulaTerm<Id> where Id: Ord + Eq {
 
 impl<Id> Eq for FormulaTerm<Id> where Id: Ord + Eq {}
 
+impl<Id> std::hash::Hash for FormulaTerm<Id> where Id: Ord + Eq + std::hash::Hash {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::Var(v) | Self::NegVar(v) => v.hash(state),
+            Self::True | Self::False => (),
+        }
+    }
+}
+
 /* The order goes like:
  *   ⊥ < ... x < y < ¬y < z < ... < ⊤
  * So False goes first (to allow quickly determining that the entire conjunction
@@ -138,11 +149,64 @@ impl<Id> Clone for FormulaTerm<Id> where Id: Ord + Eq + Clone {
 }
 
 /// Represents a conjunction group (aka. "cube") in DNF boolean formula
-#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
 pub struct DNFCube<Id> where Id: Ord + Eq {
     pub terms: Vec<FormulaTerm<Id>>
 }
 
+#[cfg(feature = "serialize")]
+#[derive(Deserialize)]
+struct RawCube<Id> where Id: Ord + Eq {
+    terms: Vec<FormulaTerm<Id>>
+}
+
+#[cfg(feature = "serialize")]
+fn terms_are_sorted<Id>(terms: &[FormulaTerm<Id>]) -> bool where Id: Ord + Eq {
+    terms.windows(2).all(|w| w[0] < w[1])
+}
+
+/// Terms of deserialized cubes get sorted if needed, the same way `DNFCube::add_term`
+/// does it (see `AssumeSortedCube` for skipping that).
+#[cfg(feature = "serialize")]
+impl<'de, Id> serde::Deserialize<'de> for DNFCube<Id> where
+    Id: Ord + Eq + serde::Deserialize<'de>
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where
+        D: serde::Deserializer<'de>
+    {
+        let raw = RawCube::<Id>::deserialize(deserializer)?;
+        if terms_are_sorted(&raw.terms) {
+            return Ok(Self { terms: raw.terms });
+        }
+        let mut cube = Self::new();
+        for term in raw.terms {
+            cube.add_term(term);
+        }
+        Ok(cube)
+    }
+}
+
+/// A `DNFCube` deserialized trusting the order of its terms.
+///
+/// Meant for reading back NISP's own output, which always has sorted terms. Debug builds
+/// still check the order.
+#[cfg(feature = "serialize")]
+pub struct AssumeSortedCube<Id>(pub DNFCube<Id>) where Id: Ord + Eq;
+
+#[cfg(feature = "serialize")]
+impl<'de, Id> serde::Deserialize<'de> for AssumeSortedCube<Id> where
+    Id: Ord + Eq + serde::Deserialize<'de>
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where
+        D: serde::Deserializer<'de>
+    {
+        let raw = RawCube::<Id>::deserialize(deserializer)?;
+        debug_assert!(terms_are_sorted(&raw.terms), "Deserialized cube has unsorted terms");
+        Ok(Self(DNFCube { terms: raw.terms }))
+    }
+}
+
 impl<Id> DNFCube<Id> where Id: Ord + Eq {
     pub fn new() -> Self {
         Self { terms: Vec::new() }
@@ -416,7 +480,44 @@ impl<Id> Clone for DNFCube<Id> where Id: Ord + Eq + Clone {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+impl<Id> DNFCube<Id> where Id: Ord + Eq + Clone {
+    /* Yields terms of this cube along with a flag telling whether the other cube contains
+     * them too. Both cubes need to be sorted, so a single pass over them is enough. */
+    fn merge_walk<'s>(&'s self, other: &'s Self)
+        -> impl Iterator<Item = (&'s FormulaTerm<Id>, bool)> + 's
+    {
+        let mut other_terms = other.terms.iter().peekable();
+        self.terms.iter().map(move |term| {
+            while other_terms.next_if(|other_term| *other_term < term).is_some() {}
+            (term, other_terms.peek().map_or(false, |other_term| *other_term == term))
+        })
+    }
+
+    /// Returns a cube made of literals present in both cubes (`a ∧ b` and `b ∧ c` have
+    /// `b` in common). Terms of both cubes need to be sorted, as kept by `add_term`.
+    pub fn common_literals(&self, other: &Self) -> Self {
+        Self {
+            terms: self.merge_walk(other)
+                .filter(|(_, common)| *common)
+                .map(|(term, _)| term.clone())
+                .collect(),
+        }
+    }
+
+    /// Returns a cube made of literals of this cube which are absent from the other one
+    /// (`a ∧ b` without `b ∧ c` leaves `a`). Terms of both cubes need to be sorted, as
+    /// kept by `add_term`.
+    pub fn difference(&self, other: &Self) -> Self {
+        Self {
+            terms: self.merge_walk(other)
+                .filter(|(_, common)| !*common)
+                .map(|(term, _)| term.clone())
+                .collect(),
+        }
+    }
+}
+
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct DNFForm<Id> where Id: Ord + Eq {
     pub cubes: Vec<DNFCube<Id>>,
 }
@@ -446,9 +547,109 @@ impl<Id> DNFForm<Id> where Id: Ord + Eq {
     pub fn num_cubes(&self) -> usize {
         self.cubes.len()
     }
+
+    /// Total number of terms in all cubes. A better measure of the formula's complexity
+    /// than the number of cubes.
+    pub fn num_terms(&self) -> usize {
+        self.cubes.iter().map(DNFCube::len).sum()
+    }
+
+    /// Number of terms in the longest cube, or `0` if the formula has no cubes.
+    pub fn max_cube_len(&self) -> usize {
+        self.cubes.iter().map(DNFCube::len).max().unwrap_or(0)
+    }
+
+    /// Creates a canonical representation of the formula, suitable for hashing
+    pub fn canonical(&self) -> CanonicalDNFForm<Id> where Id: Clone {
+        CanonicalDNFForm::new(&self.cubes)
+    }
+
+    /// Assigns values to variables. Terms which become true are removed from their
+    /// cubes and cubes with terms which become false are removed from the formula.
+    /// A cube left without terms makes the whole formula true.
+    /// 
+    /// # Arguments
+    /// * `value_of` - value of a variable, or `None` to leave the variable free
+    pub fn substitute<F>(self, value_of: F) -> Self where
+        F: Fn(&Id) -> Option<bool>
+    {
+        let mut cubes = Vec::with_capacity(self.cubes.len());
+        'cube_loop: for cube in self.cubes {
+            let mut terms = Vec::with_capacity(cube.terms.len());
+            for term in cube.terms {
+                let value = match &term {
+                    FormulaTerm::Var(v) => value_of(v),
+                    FormulaTerm::NegVar(v) => value_of(v).map(|value| !value),
+                    FormulaTerm::True | FormulaTerm::False => None,
+                };
+                match value {
+                    Some(true) => (),
+                    Some(false) => continue 'cube_loop,
+                    None => terms.push(term),
+                }
+            }
+            /* p ∨ ⊤ ≡ ⊤ */
+            if terms.is_empty() {
+                return Self { cubes: vec![DNFCube::new()] };
+            }
+            /* Removing terms keeps them sorted */
+            let cube = DNFCube { terms };
+            if !cubes.contains(&cube) {
+                cubes.push(cube);
+            }
+        }
+        Self { cubes }
+    }
+}
+
+/// Canonical representation of a DNF formula - terms of its cubes and the cubes
+/// themselves are sorted and deduplicated. Formulas consisting of the same cubes have
+/// equal canonical representations, regardless of the order of the cubes and their terms.
+/// Unlike `DNFForm`'s `PartialEq`, comparing and hashing these is cheap, which makes them
+/// suitable as `HashMap` keys. They don't account for logically equivalent formulas
+/// made of different cubes though, so optimized formulas should be used for best results.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct CanonicalDNFForm<Id> where Id: Ord + Eq {
+    cubes: Vec<DNFCube<Id>>,
+}
+
+impl<Id> CanonicalDNFForm<Id> where Id: Ord + Eq {
+    /* Cubes created with `add_term` are already sorted, but the ones created directly
+     * out of terms don't have to be */
+    fn from_cubes(mut cubes: Vec<DNFCube<Id>>) -> Self {
+        for cube in &mut cubes {
+            cube.terms.sort();
+            cube.terms.dedup();
+        }
+        cubes.sort_by(|a, b| a.terms.cmp(&b.terms));
+        cubes.dedup();
+        Self { cubes }
+    }
+
+    pub fn cubes(&self) -> &[DNFCube<Id>] {
+        &self.cubes
+    }
+
+    pub fn into_form(self) -> DNFForm<Id> {
+        DNFForm { cubes: self.cubes }
+    }
+}
+
+impl<Id> CanonicalDNFForm<Id> where Id: Ord + Eq + Clone {
+    pub fn new(cubes: &[DNFCube<Id>]) -> Self {
+        Self::from_cubes(cubes.to_vec())
+    }
+}
+
+/// Canonicalizes a formula without copying its cubes
+impl<Id> From<DNFForm<Id>> for CanonicalDNFForm<Id> where Id: Ord + Eq {
+    fn from(form: DNFForm<Id>) -> Self {
+        Self::from_cubes(form.cubes)
+    }
 }
 
 pub trait MergableDNFForm<Id> where
+    Self: Sized,
     DNFCube<Id>: std::fmt::Debug,
     FormulaTerm<Id>: std::fmt::Debug,
     Id: Ord + Eq
@@ -457,8 +658,17 @@ pub trait MergableDNFForm<Id> where
     fn add_cube(self, cube: DNFCube<Id>) -> Self;
     fn disjunct_opt(self, other: Self) -> Self;
     fn disjunct(self, other: Self) -> Self;
+    fn conjunct(self, other: Self) -> Self;
     fn conjunct_term(self, term: &FormulaTerm<Id>) -> Self;
     fn conjunct_term_with(self, at: usize, term: FormulaTerm<Id>) -> Self;
+    /// Adds a term to the most recently added cube. Panics if there are no cubes.
+    fn conjunct_term_with_last(self, term: FormulaTerm<Id>) -> Self;
+    /// Performs the first reduction step of `optimize`. Cubes reduced away are not kept
+    /// for the following steps, use `FormReducer` to step through all of `optimize`.
+    /// 
+    /// # Return
+    /// The formula after the reduction and whether any reduction was applied
+    fn reduce_step(self) -> (Self, bool);
     fn optimize(self) -> Self;
 }
 
@@ -520,6 +730,24 @@ impl<Id> MergableDNFForm<Id> for DNFForm<Id> where
             .fold(self, |me, cube| me.add_cube(cube))
     }
 
+    /* (a ∨ b) ∧ (c ∨ d) ≡ (a ∧ c) ∨ (a ∧ d) ∨ (b ∧ c) ∨ (b ∧ d)
+     * Complexity: O(cubes * other cubes * terms) */
+    fn conjunct(self, other: Self) -> Self {
+        let mut result = DNFForm::new();
+        for my_cube in &self.cubes {
+            for other_cube in &other.cubes {
+                let mut cube = my_cube.clone();
+                for term in &other_cube.terms {
+                    cube.add_term(term.clone());
+                }
+                if !cube.is_false_const() {
+                    result.cubes.push(cube);
+                }
+            }
+        }
+        result
+    }
+
     fn conjunct_term(mut self, term: &FormulaTerm<Id>) -> Self {
         for cube in &mut self.cubes {
             cube.add_term(term.clone());
@@ -537,62 +765,288 @@ impl<Id> MergableDNFForm<Id> for DNFForm<Id> where
         self
     }
 
-    fn optimize(mut self) -> Self {
-        /* Wrapped in DNFForm only for debugging purposes */
-        let mut facts = DNFForm { cubes: Vec::new() };
+    fn conjunct_term_with_last(self, term: FormulaTerm<Id>) -> Self {
+        match self.cubes.len() {
+            0 => panic!("Form has no cubes to conjunct a term with"),
+            len => self.conjunct_term_with(len - 1, term),
+        }
+    }
 
+    fn reduce_step(self) -> (Self, bool) {
+        let (reducer, reduced) = FormReducer::new(self).reduce_step();
+        (reducer.into_form(), reduced)
+    }
+
+    fn optimize(self) -> Self {
         dbg_log!(DBG_EXTRA1, "+---------------------------------------------------------+");
         dbg_log!(DBG_EXTRA1, ">>>>>>>>>>>> Optimising formula {:?}", &self);
         dbg_log!(DBG_EXTRA1, "+---------------------------------------------------------+");
-        
-        'outer: loop {
-            dbg_log!(DBG_EXTRA1, ">>>>> Form: {:?}, Other facts: {:?}", self, facts);
-            let mut reduction = None;
-            let facts_iter = self.cubes.iter().chain(facts.cubes.iter());
-            'fact_loop: for (fact_idx, fact) in facts_iter.enumerate() {
-                for (cube_idx, cube) in self.cubes.iter().enumerate() {
-                    match cube.try_to_reduce_disjunction(&fact) {
-                        Some(new_cube) => {
-                            if new_cube.terms != cube.terms {
-                                reduction = Some((new_cube, cube_idx, fact_idx));
-                                break 'fact_loop;
-                            }
-                        },
-                        None => (),
-                    }
-                }
+
+        let mut reducer = FormReducer::new(self);
+        loop {
+            let (next, reduced) = reducer.reduce_step();
+            reducer = next;
+            if !reduced {
+                return reducer.into_form();
             }
-            if let Some((new_cube, at, of)) = reduction {
-                #[allow(unused)]
-                { /* Literally just debug code */
-                    let of_cube = if of < self.cubes.len() {
-                        &self.cubes[of]
-                    } else {
-                        &facts.cubes[of - self.cubes.len()]
-                    };
-                    
-                    dbg_log!(
-                        DBG_EXTRA1, ">>>>> {:?}, {:?} => {:?}",
-                        self.cubes[at],
-                        of_cube,
-                        new_cube
-                    );
-                }
+        }
+    }
+}
+
+/// Steps through `MergableDNFForm::optimize`, one reduction at a time. Cubes reduced away
+/// are kept as facts for the following steps, so stepping until no reduction applies
+/// gives the same formula as `optimize`.
+pub struct FormReducer<Id>(IncrementalReducer<Id>) where Id: Ord + Eq;
+
+impl<Id> FormReducer<Id> where
+    DNFCube<Id>: std::fmt::Debug,
+    FormulaTerm<Id>: std::fmt::Debug,
+    Id: Ord + Eq + Clone
+{
+    pub fn new(mut form: DNFForm<Id>) -> Self {
+        /* Start from a fixed order of cubes, so that the result does not depend on the
+         * order in which the cubes were added */
+        form.cubes.sort_by(|a, b| a.terms.cmp(&b.terms));
+        Self(IncrementalReducer::new(form.cubes))
+    }
+
+    /// Performs a single reduction.
+    /// 
+    /// # Return
+    /// The reducer after the reduction and whether any reduction was applied
+    pub fn reduce_step(mut self) -> (Self, bool) {
+        let reduced = self.0.step();
+        (self, reduced)
+    }
+
+    /// Gets the formula reduced so far.
+    pub fn form(&self) -> DNFForm<Id> {
+        DNFForm { cubes: self.0.formula.iter().map(|id| self.0.cubes[*id].clone()).collect() }
+    }
+
+    pub fn into_form(self) -> DNFForm<Id> {
+        self.0.into_form()
+    }
+}
+
+/* A reduction of `cube` by `fact`, both identified by their ids in `IncrementalReducer` */
+struct ReductionCandidate<Id> where Id: Ord + Eq {
+    fact: usize,
+    cube: usize,
+    reduced: DNFCube<Id>,
+}
 
-                facts.cubes.push(new_cube.clone());
-                self.cubes.push(new_cube);
-                let old_cube = self.cubes.swap_remove(at);
-                facts.cubes.push(old_cube);
-                /* XXX: of > self.cubes.len() when of is a fact outside of the formula */
-                if of < self.cubes.len() {
-                    self.cubes.remove(of);
+/* Greedy reduction of `optimize`. Each step applies the reduction that removes the most
+ * terms, without rescanning every fact × cube pair. Cubes never change once created, so
+ * reductions between them are computed once and bucketed by the number of terms they
+ * remove. A step only computes reductions involving the cube it creates. */
+struct IncrementalReducer<Id> where Id: Ord + Eq {
+    /* All cubes seen so far, indexed by their ids */
+    cubes: Vec<DNFCube<Id>>,
+    /* Ids of cubes of the formula, in order */
+    formula: Vec<usize>,
+    /* Ids of facts outside of the formula, in the order they were added */
+    facts: Vec<usize>,
+    formula_pos: Vec<Option<usize>>,
+    first_fact_pos: Vec<Option<usize>>,
+    candidates: BTreeMap<usize, Vec<ReductionCandidate<Id>>>,
+}
+
+impl<Id> IncrementalReducer<Id> where
+    DNFCube<Id>: std::fmt::Debug,
+    FormulaTerm<Id>: std::fmt::Debug,
+    Id: Ord + Eq + Clone
+{
+    fn new(cubes: Vec<DNFCube<Id>>) -> Self {
+        let count = cubes.len();
+        let mut reducer = Self {
+            cubes,
+            formula: (0 .. count).collect(),
+            facts: Vec::new(),
+            formula_pos: (0 .. count).map(Some).collect(),
+            first_fact_pos: vec![None; count],
+            candidates: BTreeMap::new(),
+        };
+        for fact in 0 .. count {
+            for cube in 0 .. count {
+                reducer.add_candidate(fact, cube);
+            }
+        }
+        reducer
+    }
+
+    fn is_fact(&self, id: usize) -> bool {
+        self.formula_pos[id].is_some() || self.first_fact_pos[id].is_some()
+    }
+
+    /* Position of the first occurrence of a fact in the list of all facts, which starts
+     * with the cubes of the formula */
+    fn fact_pos(&self, id: usize) -> usize {
+        match self.formula_pos[id] {
+            Some(pos) => pos,
+            None => self.formula.len() + self.first_fact_pos[id].unwrap(),
+        }
+    }
+
+    fn add_candidate(&mut self, fact: usize, cube: usize) {
+        let reduced = match self.cubes[cube].try_to_reduce_disjunction(&self.cubes[fact]) {
+            Some(reduced) if reduced.terms != self.cubes[cube].terms => reduced,
+            _ => return,
+        };
+        let removed = self.cubes[cube].terms.len().saturating_sub(reduced.terms.len());
+        self.candidates.entry(removed)
+            .or_default()
+            .push(ReductionCandidate { fact, cube, reduced });
+    }
+
+    /* Picks the reduction removing the most terms, on ties the one with the lowest
+     * fact position, then the lowest cube position */
+    fn pick(&mut self) -> Option<ReductionCandidate<Id>> {
+        loop {
+            let removed = *self.candidates.keys().next_back()?;
+            let mut bucket = self.candidates.remove(&removed).unwrap();
+            bucket.retain(|c| self.formula_pos[c.cube].is_some() && self.is_fact(c.fact));
+
+            let best = bucket.iter()
+                .enumerate()
+                .min_by_key(|(_, c)| (self.fact_pos(c.fact), self.formula_pos[c.cube]))
+                .map(|(idx, _)| idx);
+            if let Some(best) = best {
+                let candidate = bucket.swap_remove(best);
+                if !bucket.is_empty() {
+                    self.candidates.insert(removed, bucket);
                 }
-            } else {
-                break 'outer;
+                return Some(candidate);
             }
         }
+    }
 
-        self
+    fn step(&mut self) -> bool {
+        let ReductionCandidate { fact: of, cube: at, reduced } = match self.pick() {
+            Some(candidate) => candidate,
+            None => return false,
+        };
+
+        dbg_log!(
+            DBG_EXTRA1, ">>>>> {:?}, {:?} => {:?}",
+            self.cubes[at],
+            self.cubes[of],
+            reduced
+        );
+
+        /* The reduced cube takes the place of `at`, which becomes a fact */
+        let new = self.cubes.len();
+        self.cubes.push(reduced);
+        self.first_fact_pos.push(Some(self.facts.len()));
+        self.facts.push(new);
+        let at_pos = self.formula_pos[at].take().unwrap();
+        self.formula[at_pos] = new;
+        self.formula_pos.push(Some(at_pos));
+        if self.first_fact_pos[at].is_none() {
+            self.first_fact_pos[at] = Some(self.facts.len());
+        }
+        self.facts.push(at);
+
+        if let Some(of_pos) = self.formula_pos[of].take() {
+            self.formula.remove(of_pos);
+            for (pos, id) in self.formula.iter().enumerate().skip(of_pos) {
+                self.formula_pos[*id] = Some(pos);
+            }
+        }
+
+        for fact in 0 .. self.cubes.len() {
+            if self.is_fact(fact) {
+                self.add_candidate(fact, new);
+            }
+        }
+        for pos in 0 .. self.formula.len() {
+            self.add_candidate(new, self.formula[pos]);
+        }
+
+        true
+    }
+
+    fn into_form(self) -> DNFForm<Id> {
+        let mut cubes: Vec<_> = self.cubes.into_iter().map(Some).collect();
+        DNFForm {
+            cubes: self.formula.iter().map(|id| cubes[*id].take().unwrap()).collect(),
+        }
+    }
+}
+
+/* Symbols used for rendering formulas as strings */
+struct MathSymbols {
+    and: &'static str,
+    or: &'static str,
+    not: &'static str,
+    top: &'static str,
+    bottom: &'static str,
+}
+
+const UNICODE_SYMBOLS: MathSymbols = MathSymbols {
+    and: " ∧ ",
+    or: " ∨ ",
+    not: "¬",
+    top: "⊤",
+    bottom: "⊥",
+};
+
+const LATEX_SYMBOLS: MathSymbols = MathSymbols {
+    and: " \\land ",
+    or: " \\lor ",
+    not: "\\lnot ",
+    top: "\\top",
+    bottom: "\\bot",
+};
+
+impl<Id> DNFForm<Id> where Id: Ord + Eq {
+    /// Renders the formula as an infix string using `∧`, `∨` and `¬`. Cubes consisting
+    /// of multiple terms are parenthesized if there's more than one cube.
+    /// 
+    /// # Arguments
+    /// * `name_fn` - maps variable identifiers to their names
+    pub fn to_math_string<F, S>(&self, name_fn: F) -> String where
+        F: FnMut(&Id) -> S,
+        S: std::fmt::Display
+    {
+        self.render(name_fn, &UNICODE_SYMBOLS)
+    }
+
+    /// Same as `to_math_string`, but uses LaTeX commands: `\lor`, `\land` and `\lnot`.
+    pub fn to_latex<F, S>(&self, name_fn: F) -> String where
+        F: FnMut(&Id) -> S,
+        S: std::fmt::Display
+    {
+        self.render(name_fn, &LATEX_SYMBOLS)
+    }
+
+    fn render<F, S>(&self, mut name_fn: F, symbols: &MathSymbols) -> String where
+        F: FnMut(&Id) -> S,
+        S: std::fmt::Display
+    {
+        if self.cubes.is_empty() {
+            return symbols.bottom.to_string();
+        }
+
+        let mut term_str = |term: &FormulaTerm<Id>| match term {
+            FormulaTerm::Var(id) => name_fn(id).to_string(),
+            FormulaTerm::NegVar(id) => format!("{}{}", symbols.not, name_fn(id)),
+            FormulaTerm::True => symbols.top.to_string(),
+            FormulaTerm::False => symbols.bottom.to_string(),
+        };
+
+        let cube_count = self.cubes.len();
+        self.cubes.iter()
+            .map(|cube| {
+                let terms: Vec<_> = cube.terms.iter().map(&mut term_str).collect();
+                match (cube_count, terms.len()) {
+                    (_, 0) => symbols.top.to_string(),
+                    (_, 1) | (1, _) => terms.join(symbols.and),
+                    _ => format!("({})", terms.join(symbols.and)),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(symbols.or)
     }
 }
 
@@ -627,7 +1081,9 @@ impl<Id> Clone for DNFForm<Id> where Id: Ord + Eq + Clone {
     }
 }
 
-/* WARNING: This is slow. */
+/* WARNING: This is slow.
+ * Formulas equal in this sense can consist of different cubes, so there's no `Hash`
+ * consistent with it. Use `CanonicalDNFForm` for hashing. */
 impl<Id> PartialEq for DNFForm<Id> where Id: Ord + Eq {
     fn eq(&self, other: &Self) -> bool {
         self.is_subformula_of(other)
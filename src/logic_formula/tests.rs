@@ -17,7 +17,7 @@
 use super::*;
 
 #[allow(dead_code)]
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 enum TestVar {
     A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, W, V, X, Y, Z,
 }
@@ -241,6 +241,36 @@ fn test_double_reduction_of_complementaries() {
     assert_eq!(form1, expected);
 }
 
+#[test]
+fn test_stepping_double_reduction_of_complementaries() {
+    let form = DNFForm::new()
+        .add_cube(DNFCube { terms: vec![NegVar(X), NegVar(Y), Var(Z)] }) /* 1 */
+        .add_cube(DNFCube { terms: vec![Var(X), Var(Y), Var(Z)] })       /* 2 */
+        .add_cube(DNFCube { terms: vec![NegVar(X), Var(Y), Var(Z)] });   /* 3 */
+
+    /* G[2] ∨ G[3] ≡ y ∧ z */
+    let (reducer, reduced) = FormReducer::new(form.clone()).reduce_step();
+    assert!(reduced);
+    let first = vec![
+        DNFCube { terms: vec![Var(Y), Var(Z)] },
+        DNFCube { terms: vec![NegVar(X), NegVar(Y), Var(Z)] },
+    ];
+    assert_eq!(reducer.form().cubes, first);
+    assert_eq!(form.reduce_step(), (DNFForm { cubes: first }, true));
+
+    /* G[1] ∨ G[3] ≡ ¬x ∧ z, G[3] is no longer a part of the formula, but it's a fact */
+    let (reducer, reduced) = reducer.reduce_step();
+    assert!(reduced);
+    assert_eq!(reducer.form().cubes, vec![
+        DNFCube { terms: vec![Var(Y), Var(Z)] },
+        DNFCube { terms: vec![NegVar(X), Var(Z)] },
+    ]);
+
+    let (reducer, reduced) = reducer.reduce_step();
+    assert!(!reduced);
+    assert_eq!(reducer.into_form().cubes.len(), 2);
+}
+
 #[test]
 fn test_quadruple_reduction_of_complementaries() {
     let form1 = DNFForm::new()
@@ -275,3 +305,269 @@ fn test_quadruple_reduction_of_complementaries() {
     assert_eq!(form1, expected);
 }
 
+
+#[test]
+fn test_canonical_form_ignores_cube_order() {
+    use std::collections::HashSet;
+
+    let form1 = DNFForm::new()
+        .add_cube(DNFCube { terms: vec![Var(X), NegVar(Y)] })
+        .add_cube(DNFCube { terms: vec![Var(Z)] });
+    
+    let form2 = DNFForm::new()
+        .add_cube(DNFCube { terms: vec![Var(Z)] })
+        .add_cube(DNFCube { terms: vec![Var(X), NegVar(Y)] })
+        .add_cube(DNFCube { terms: vec![Var(Z)] });
+
+    let form3 = DNFForm::new()
+        .add_cube(DNFCube { terms: vec![Var(X), Var(Y)] })
+        .add_cube(DNFCube { terms: vec![Var(Z)] });
+
+    assert_eq!(form1.canonical(), form2.canonical());
+    assert_ne!(form1.canonical(), form3.canonical());
+
+    let set: HashSet<_> = [&form1, &form2, &form3].iter()
+        .map(|form| form.canonical())
+        .collect();
+    assert_eq!(set.len(), 2);
+}
+
+#[test]
+fn test_canonical_form_hash() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let hash = |form: CanonicalDNFForm<TestVar>| {
+        let mut hasher = DefaultHasher::new();
+        form.hash(&mut hasher);
+        hasher.finish()
+    };
+
+    /* Same cubes in different order, with terms out of order */
+    let form1 = DNFForm {
+        cubes: vec![
+            DNFCube { terms: vec![NegVar(Y), Var(X)] },
+            DNFCube { terms: vec![Var(Z)] },
+        ]
+    };
+    let form2 = DNFForm {
+        cubes: vec![
+            DNFCube { terms: vec![Var(Z)] },
+            DNFCube { terms: vec![Var(X), NegVar(Y)] },
+        ]
+    };
+    assert_eq!(form1.canonical(), CanonicalDNFForm::from(form2.clone()));
+    assert_eq!(hash(form1.into()), hash(form2.canonical()));
+}
+
+#[test]
+fn test_conjunction_of_forms() {
+    let form1 = DNFForm::new()
+        .add_cube(DNFCube { terms: vec![Var(A)] })
+        .add_cube(DNFCube { terms: vec![NegVar(B)] });
+    
+    let form2 = DNFForm::new()
+        .add_cube(DNFCube { terms: vec![Var(B)] })
+        .add_cube(DNFCube { terms: vec![Var(C)] });
+
+    /* (a ∨ ¬b) ∧ (b ∨ c) ≡ (a ∧ b) ∨ (a ∧ c) ∨ (¬b ∧ c), as ¬b ∧ b ≡ ⊥ */
+    let result = form1.conjunct(form2);
+    let expected = DNFForm::new()
+        .add_cube(DNFCube { terms: vec![Var(A), Var(B)] })
+        .add_cube(DNFCube { terms: vec![Var(A), Var(C)] })
+        .add_cube(DNFCube { terms: vec![NegVar(B), Var(C)] });
+    
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn test_conjunct_term_with_last() {
+    let form = DNFForm::new()
+        .add_cube(DNFCube { terms: vec![Var(A)] })
+        .add_cube(DNFCube { terms: vec![Var(B)] });
+
+    /* a ∨ b → a ∨ (b ∧ ¬c) */
+    let result = form.conjunct_term_with_last(NegVar(C));
+    assert_eq!(result.cubes[0].terms, vec![Var(A)]);
+    assert_eq!(result.cubes[1].terms, vec![Var(B), NegVar(C)]);
+}
+
+#[test]
+#[should_panic(expected = "no cubes")]
+fn test_conjunct_term_with_last_on_empty_form() {
+    let _ = DNFForm::<TestVar>::new().conjunct_term_with_last(Var(A));
+}
+
+#[test]
+fn test_optimize_matches_reduce_steps() {
+    /* Pseudo-random cubes over a few variables, so that plenty of reductions apply */
+    let mut seed = 12345u32;
+    let mut next = move || {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        seed >> 16
+    };
+
+    for _ in 0 .. 50 {
+        let mut form = DNFForm::<u32>::new();
+        for _ in 0 .. 1 + next() % 12 {
+            let mut cube = DNFCube::new();
+            for var in 0 .. 4 {
+                match next() % 3 {
+                    0 => cube.add_term(Var(var)),
+                    1 => cube.add_term(NegVar(var)),
+                    _ => (),
+                }
+            }
+            form = form.add_cube(cube);
+        }
+        let mut stepped = FormReducer::new(form.clone());
+        loop {
+            let (next, reduced) = stepped.reduce_step();
+            stepped = next;
+            if !reduced {
+                break;
+            }
+        }
+
+        assert_eq!(form.optimize().cubes, stepped.into_form().cubes);
+    }
+}
+
+#[test]
+fn test_optimization_is_order_independent() {
+    let cubes = [
+        DNFCube { terms: vec![NegVar(X), NegVar(Y), Var(Z)] },
+        DNFCube { terms: vec![Var(X), Var(Y), Var(Z)] },
+        DNFCube { terms: vec![NegVar(X), Var(Y), Var(Z)] },
+    ];
+    let permutations = [[0, 1, 2], [0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]];
+
+    let results: Vec<_> = permutations.iter()
+        .map(|permutation| {
+            permutation.iter()
+                .fold(DNFForm::new(), |form, idx| form.add_cube(cubes[*idx].clone()))
+                .optimize()
+        })
+        .collect();
+
+    let mut expected = DNFForm::new();
+    expected.cubes.push(DNFCube { terms: vec![NegVar(X), Var(Z)] });
+    expected.cubes.push(DNFCube { terms: vec![Var(Y), Var(Z)] });
+
+    for result in &results {
+        assert_eq!(result.num_cubes(), 2);
+        assert_eq!(*result, expected);
+        assert_eq!(result.canonical(), results[0].canonical());
+    }
+}
+
+#[test]
+fn test_complexity_metrics() {
+    let mut form = DNFForm::new();
+    assert_eq!((form.num_terms(), form.max_cube_len()), (0, 0));
+
+    form.cubes.push(DNFCube { terms: vec![Var(X), NegVar(Y)] });
+    form.cubes.push(DNFCube { terms: vec![Var(Z)] });
+    form.cubes.push(DNFCube { terms: vec![NegVar(X), Var(Y), Var(Z)] });
+
+    assert_eq!(form.num_cubes(), 3);
+    assert_eq!(form.num_terms(), 6);
+    assert_eq!(form.max_cube_len(), 3);
+}
+
+#[test]
+fn test_math_string_rendering() {
+    let mut form = DNFForm::new();
+    form.cubes.push(DNFCube { terms: vec![Var(X), NegVar(Y)] });
+    form.cubes.push(DNFCube { terms: vec![Var(Z)] });
+
+    let name = |var: &TestVar| format!("{:?}", var);
+    assert_eq!(form.to_math_string(name), "(x ∧ ¬y) ∨ z");
+    assert_eq!(form.to_latex(name), "(x \\land \\lnot y) \\lor z");
+
+    assert_eq!(DNFForm::<TestVar>::new().to_math_string(name), "⊥");
+}
+
+#[test]
+fn test_common_literals_of_overlapping_cubes() {
+    let a = DNFCube { terms: vec![Var(A), NegVar(B), Var(C), NegVar(E)] };
+    let b = DNFCube { terms: vec![NegVar(B), Var(D), Var(E), NegVar(E)] };
+
+    assert_eq!(a.common_literals(&b), DNFCube { terms: vec![NegVar(B), NegVar(E)] });
+    assert_eq!(b.common_literals(&a), a.common_literals(&b));
+    assert_eq!(a.difference(&b), DNFCube { terms: vec![Var(A), Var(C)] });
+    assert_eq!(b.difference(&a), DNFCube { terms: vec![Var(D), Var(E)] });
+
+    /* Literal of the opposite polarity is not common */
+    let c = DNFCube { terms: vec![Var(B)] };
+    assert_eq!(a.common_literals(&c), DNFCube::new());
+    assert_eq!(a.common_literals(&a), a);
+    assert_eq!(a.difference(&a), DNFCube::new());
+}
+
+#[test]
+fn test_common_literals_of_disjoint_cubes() {
+    let a = DNFCube { terms: vec![Var(A), NegVar(C)] };
+    let b = DNFCube { terms: vec![Var(B), Var(D)] };
+
+    assert_eq!(a.common_literals(&b), DNFCube::new());
+    assert_eq!(a.difference(&b), a);
+    assert_eq!(b.difference(&a), b);
+    assert_eq!(a.common_literals(&DNFCube::new()), DNFCube::new());
+    assert_eq!(DNFCube::new().difference(&a), DNFCube::new());
+}
+
+#[test]
+fn test_substitute() {
+    let form = DNFForm {
+        cubes: vec![
+            DNFCube { terms: vec![Var(A), NegVar(B)] },
+            DNFCube { terms: vec![Var(A), NegVar(C)] },
+            DNFCube { terms: vec![Var(B), NegVar(C)] },
+        ]
+    };
+
+    /* B := false, so ¬B is removed and cubes with B vanish */
+    let substituted = form.clone().substitute(|v| (*v == B).then(|| false));
+    assert_eq!(substituted.cubes, vec![
+        DNFCube { terms: vec![Var(A)] },
+        DNFCube { terms: vec![Var(A), NegVar(C)] },
+    ]);
+
+    /* A := true and C := false leave an empty cube */
+    let substituted = form.substitute(|v| match v {
+        A => Some(true),
+        C => Some(false),
+        _ => None,
+    });
+    assert_eq!(substituted, DNFForm::new().add_cube(DNFCube::new()));
+}
+
+#[cfg(feature = "serialize")]
+fn cube_json(terms: &[FormulaTerm<u32>]) -> String {
+    serde_json::to_string(&DNFCube { terms: terms.to_vec() }).unwrap()
+}
+
+#[cfg(feature = "serialize")]
+#[test]
+fn test_deserialize_sorts_cube_terms() {
+    let json = cube_json(&[Var(2), NegVar(0), Var(1)]);
+    let cube: DNFCube<u32> = serde_json::from_str(&json).unwrap();
+    assert_eq!(cube.terms, [NegVar(0), Var(1), Var(2)]);
+}
+
+#[cfg(feature = "serialize")]
+#[test]
+fn test_deserialize_assume_sorted() {
+    let json = cube_json(&[Var(0), NegVar(1), Var(2)]);
+    let AssumeSortedCube(cube) = serde_json::from_str::<AssumeSortedCube<u32>>(&json).unwrap();
+    assert_eq!(cube.terms, [Var(0), NegVar(1), Var(2)]);
+}
+
+#[cfg(all(feature = "serialize", debug_assertions))]
+#[test]
+#[should_panic(expected = "unsorted terms")]
+fn test_deserialize_assume_sorted_rejects_unsorted() {
+    let json = cube_json(&[Var(2), Var(0)]);
+    let _: serde_json::Result<AssumeSortedCube<u32>> = serde_json::from_str(&json);
+}
@@ -14,11 +14,11 @@
  */
 
 use clap::{arg, Parser};
-use lazy_static::__Deref;
-use crate::router::SitePinId;
+use crate::router::{SitePinId, PinDir, BELKind, VirtualConsts};
 use std::path::Path;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 #[macro_use]
 extern crate lazy_static;
@@ -29,18 +29,45 @@ extern crate serde;
 pub mod include_path;
 #[macro_use]
 pub mod log;
+#[macro_use]
+pub mod profiling;
 pub mod common;
 pub mod strings;
 pub mod ic_loader;
 pub mod logic_formula;
 pub mod router;
 pub mod exporter;
+pub mod graph_exporter;
 pub mod dot_exporter;
+pub mod graphml_exporter;
+pub mod bba_exporter;
+pub mod physnet_exporter;
+#[cfg(feature = "image")]
+pub mod heatmap_exporter;
+#[cfg(test)]
+mod test_utils;
+#[cfg(test)]
+mod tests;
 
 use crate::ic_loader::OpenOpts;
-use crate::router::site_brute_router::BruteRouter;
+use crate::router::site_brute_router::{BruteRouter, RoutingInfo};
+use crate::logic_formula::{DNFCube, DNFForm, CanonicalDNFForm};
+use crate::router::tile_routing::{
+    routed_site_pips,
+    site_pips_in_tile,
+    PinPairSitePips,
+    TilePinPairPips
+};
 use crate::exporter::Exporter;
+use crate::graph_exporter::GraphExporter;
 use crate::router::serialize::*;
+use crate::router::bundle::{
+    write_bundle,
+    read_bundle,
+    read_bundle_routing,
+    read_bundle_routing_assume_sorted
+};
+use crate::bba_exporter::{export_site_routing_bba, read_device_hash, CombinedBbaWriter};
 #[allow(unused)]
 use crate::log::*;
 use crate::common::*;
@@ -61,52 +88,550 @@ struct Args {
     bba: String,
     #[clap(long, help = "Use raw (uncompressed) device file")]
     raw: bool,
+    #[arg(
+        short,
+        long,
+        conflicts_with = "verbose",
+        help = "Print only critical messages, suppress the routing summary"
+    )]
+    quiet: bool,
+    #[arg(
+        short,
+        long,
+        action = clap::ArgAction::Count,
+        help = "Print more messages (-v for info, -vv for all)"
+    )]
+    verbose: u8,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "text",
+        help = "Format of errors printed to stderr"
+    )]
+    error_format: ErrorFormat,
+    #[cfg(feature = "profiling")]
+    #[arg(
+        long,
+        help = "Record processing phases as folded stacks (for inferno-flamegraph) to a file"
+    )]
+    profile: Option<String>,
     #[command(subcommand)]
     command: SubCommands,
 }
 
+impl Args {
+    /// Log level requested with command line flags. `None` if the level set by
+    /// `NISP_DBG_LOG_LEVEL` should be kept.
+    fn log_level(&self) -> Option<usize> {
+        if self.quiet {
+            return Some(DBG_CRITICAL);
+        }
+        match self.verbose {
+            0 => None,
+            1 => Some(DBG_INFO),
+            _ => Some(DBG_EXTRA2),
+        }
+    }
+
+    fn apply_log_level(&self) {
+        if let Some(lvl) = self.log_level() {
+            crate::log::set_log_level(lvl);
+        }
+    }
+}
+
+/// Options selecting site types and configuring their routers, shared by subcommands
+/// routing whole site types.
+#[derive(clap::Args, Debug)]
+struct RoutingOpts {
+    #[arg(long, help = "Site types to be routed (`*` and `?` wildcards are accepted)")]
+    site_types: Option<Vec<String>>,
+    #[arg(
+        long,
+        help = "File listing site types to be routed, one per line, in addition to \
+            --site-types. Lines starting with # are ignored"
+    )]
+    site_types_file: Option<String>,
+    #[arg(
+        short = 'c',
+        long,
+        help = "Add $VCC and $GND ports to sites with constant generators")
+    ]
+    virtual_consts: bool,
+    #[arg(
+        long,
+        help = "Add only the $VCC port to sites with VCC generators (implied by -c)"
+    )]
+    virtual_vcc: bool,
+    #[arg(
+        long,
+        help = "Add only the $GND port to sites with GND generators (implied by -c)"
+    )]
+    virtual_gnd: bool,
+    #[arg(
+        long,
+        help = "Split inout pins into separate input and output pins"
+    )]
+    flatten_inout: bool,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Routing BELs whose site PIPs should be treated as disabled"
+    )]
+    disable_pips: Vec<String>,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Kinds of BELs (logic, routing, siteport) to be excluded from routing"
+    )]
+    exclude_bel_kinds: Vec<BELKind>,
+    #[arg(
+        long,
+        help = "Abandon routing from a pin after given number of seconds"
+    )]
+    pin_timeout: Option<f64>,
+    #[arg(
+        long,
+        help = "Keep only pin pairs with a site port at either end, dropping pairs of BEL \
+            pins internal to sites"
+    )]
+    port_pairs_only: bool,
+    #[arg(
+        long,
+        help = "Count routes to sinks crossing each edge of the routing graph and save \
+            the counts along with routing information"
+    )]
+    edge_usage: bool,
+}
+
 #[derive(Parser, Debug)]
 struct PreprocessCmd {
-    #[arg(long, help = "Site types to be routed")]
-    site_types: Option<Vec<String>>,
+    #[command(flatten)]
+    routing: RoutingOpts,
+    #[arg(
+        long,
+        help = "Don't fail when no site types are selected or no routing information is found"
+    )]
+    allow_empty: bool,
     #[arg(
         long,
+        alias = "threads",
         default_value = "1",
-        help = "Number of threads to be used during preprocessing"
+        help = "Number of threads routing pins of a single site type"
     )]
-    threads: usize,
+    threads_per_site: usize,
+    #[arg(
+        long,
+        default_value = "1",
+        help = "Number of site types routed at the same time. Each of them holds its \
+                routing graph and results in memory."
+    )]
+    site_parallelism: usize,
+    #[arg(
+        long,
+        help = "Directory for saving all the exported files, unless overridden with the \
+            prefix options of their exporters"
+    )]
+    out_dir: Option<String>,
+    /* Positional BBA output of the top-level command. Not set for `preprocess` lines of
+     * batch scripts, which would overwrite each other's files. */
+    #[arg(skip)]
+    bba: Option<String>,
     #[arg(
         long,
         help = "Site types to have their routing graphs exported to graphviz .dot files"
     )]
     dot: Option<Vec<String>>,
-    #[arg(long, default_value = "", help = "Directory for saving .dot files")]
-    dot_prefix: String,
+    #[arg(long, help = "Directory for saving .dot files (defaults to --out-dir)")]
+    dot_prefix: Option<String>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = GraphFormat::Dot,
+        help = "Format of the exported routing graphs"
+    )]
+    dot_format: GraphFormat,
+    #[arg(
+        long,
+        help = "Sort BEL clusters and their pins by name in exported .dot files"
+    )]
+    dot_sorted: bool,
+    #[arg(
+        long,
+        help = "Color edges in exported .dot files by kind: site wires, site PIPs and \
+            virtual constant connections"
+    )]
+    dot_edge_kind_colors: bool,
     #[arg(
         long,
         help = "Site types to have their routing cache exported to JSON format"
     )]
     json: Option<Vec<String>>,
-    #[arg(long, default_value = "", help = " Directory for saving .json files")]
-    json_prefix: String,
+    #[arg(long, help = "Directory for saving .json files (defaults to --out-dir)")]
+    json_prefix: Option<String>,
+    #[arg(
+        long,
+        help = "Write JSON output as newline-delimited JSON (.jsonl), one site type per line"
+    )]
+    json_lines: bool,
     #[arg(long, help = "Do not optimize logic formulas for constraints")]
     no_formula_opt: bool,
     #[arg(
-        short = 'c',
         long,
-        help = "Add $VCC and $GND ports to sites with constant generators")
-    ]
-    virtual_consts: bool
+        conflicts_with = "no_formula_opt",
+        help = "Output both unoptimized and optimized logic formulas for comparison"
+    )]
+    emit_both_forms: bool,
+    #[arg(
+        long,
+        help = "Store distinct formulas in a table referenced by pin pairs in JSON output"
+    )]
+    dedup_formulas: bool,
+    #[arg(
+        long,
+        help = "Add tile wires connected to site ports of out-of-site sources/sinks to JSON output"
+    )]
+    site_port_tile_wires: bool,
+    #[arg(
+        long,
+        help = "Identify ports within formulas by their ids and add a table of port names \
+            to JSON output"
+    )]
+    numeric_port_ids: bool,
+    #[arg(
+        long,
+        help = "Add a table describing the BEL pin (BEL, pin, direction) of every site pin \
+            id to JSON output"
+    )]
+    pin_table: bool,
+    #[arg(
+        long,
+        help = "Add routing from BELs to sinks, combining routing from all the output pins \
+            of each BEL, to JSON output"
+    )]
+    bel_level: bool,
+    #[arg(long, help = "Add the number of distinct routes of each pin pair to JSON output")]
+    route_counts: bool,
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "1000",
+        requires = "route_counts",
+        help = "Stop counting routes of a pin pair once N of them have been found"
+    )]
+    max_routes_per_pair: usize,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = FormulaFormat::Implicit,
+        help = "Format of formulas in JSON output: nested arrays of DNF cubes (implicit), \
+            trees of or/and/var/not operators (explicit) or cubes with ports labeled as \
+            must_not_use_ports/uses_port (labeled)"
+    )]
+    formula_format: FormulaFormat,
+    #[arg(
+        long,
+        help = "Only print the routing summary, skip per-pair output (JSON export)"
+    )]
+    summary_only: bool,
+    #[arg(
+        long,
+        help = "Save the routing summary in JSON format to a given file, along with \
+                SHA-256 hash of the device file"
+    )]
+    summary_json: Option<String>,
+    #[arg(
+        long,
+        help = "Report pairs of inout pins which are routable only in one direction"
+    )]
+    check_symmetry: bool,
+    #[arg(
+        long,
+        help = "Check that activators of routes correspond to real drivers and that edges \
+            of routing graphs agree with directions of pins, and fail otherwise"
+    )]
+    validate: bool,
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Warn about site types with pin pairs requiring formulas of more than N cubes"
+    )]
+    warn_formula_budget: Option<usize>,
+    #[arg(
+        long,
+        help = "Report the ratio of unconstrained pin pairs to all routed pin pairs of each \
+                site type"
+    )]
+    report_unconstrained_ratio: bool,
+    #[arg(
+        long,
+        help = "Collapse pins connected only through site wires into a single representative"
+    )]
+    collapse_aliases: bool,
+    #[arg(
+        long,
+        help = "Omit activator (implies) cubes which are subsumed by the requirements"
+    )]
+    trim_implies: bool,
+    #[arg(
+        long,
+        value_name = "BEL.PIN",
+        help = "Pins which are never congested. Requirements don't exclude them as drivers"
+    )]
+    ignore_ports: Option<Vec<String>>,
+    #[arg(
+        long,
+        help = "Save site routing aggregated per tile type in JSON format to a given file"
+    )]
+    tile_json: Option<String>,
+    #[arg(
+        long,
+        help = "Save PIPs used by routes of pin pairs of routed site types, as PIP indices \
+                of tile types containing them (as used by nextpnr-fpga-interchange), in JSON \
+                format to a given file"
+    )]
+    nextpnr_pips: Option<String>,
+    #[arg(
+        long,
+        help = "Site types to have their routing graphs and results bundled into \
+            self-contained archives"
+    )]
+    bundle: Option<Vec<String>>,
+    #[arg(long, help = "Directory for saving bundles (defaults to --out-dir)")]
+    bundle_prefix: Option<String>,
+    #[arg(
+        long,
+        help = "Site types to have their routing information exported to separate .bba files"
+    )]
+    bba_split: Option<Vec<String>>,
+    #[arg(long, help = "Directory for saving per-site .bba files (defaults to --out-dir)")]
+    bba_prefix: Option<String>,
+    #[arg(
+        long,
+        help = "Skip site types whose per-site output files (.bba files, bundles) already exist"
+    )]
+    resume: bool,
+    #[arg(
+        long,
+        requires = "resume",
+        help = "When resuming, skip only site types whose files come from the same device file"
+    )]
+    resume_verify_hash: bool,
+    #[arg(
+        long,
+        value_name = "BUNDLE_DIR",
+        help = "Export only site types whose routing differs from their bundles in the given \
+            directory"
+    )]
+    changed_since: Option<String>,
+    #[arg(
+        long,
+        requires = "changed_since",
+        help = "Trust that formulas in the bundles of --changed-since have sorted terms, \
+            skipping their validation. Bundles written by NISP always do"
+    )]
+    assume_sorted: bool,
+    #[cfg(feature = "image")]
+    #[arg(
+        long,
+        help = "Site types to have their routability matrices rendered to .png files"
+    )]
+    heatmap: Option<Vec<String>>,
+    #[cfg(feature = "image")]
+    #[arg(long, help = "Directory for saving .png files (defaults to --out-dir)")]
+    heatmap_prefix: Option<String>,
+    #[arg(
+        long,
+        help = "Soft memory limit in MiB. When the estimated usage exceeds it, JSON output \
+            is written as site types get processed and fewer threads are used"
+    )]
+    max_memory: Option<usize>,
+    #[arg(
+        long,
+        help = "Route only from pins with ids starting at this index (for sharding a site \
+            type across processes)"
+    )]
+    pin_start: Option<usize>,
+    #[arg(
+        long,
+        help = "Route only from pins with ids below this index (for sharding a site type \
+            across processes)"
+    )]
+    pin_end: Option<usize>,
+}
+
+impl RoutingOpts {
+    /// Site type names or wildcard patterns given with `--site-types` and
+    /// `--site-types-file`. `None` if neither was used, which selects all site types.
+    fn site_type_patterns(&self) -> Result<Option<Vec<String>>, String> {
+        let mut patterns = self.site_types.clone();
+        if let Some(path) = &self.site_types_file {
+            let list = std::fs::read_to_string(path)
+                .map_err(|e| format!("Couldn't read site types file {}: {}", path, e))?;
+            patterns.get_or_insert_with(Vec::new).extend(list.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from));
+        }
+        Ok(patterns)
+    }
+
+    /// Site types of `device` selected by `--site-types` and `--site-types-file`, along
+    /// with their ids
+    fn select_site_types<'d>(&self, device: &ic_loader::archdef::Root<'d>)
+        -> Result<Vec<(usize, ic_loader::archdef::SiteTypeReader<'d>)>, String>
+    {
+        let site_type_patterns = self.site_type_patterns()?;
+        Ok(device.get_site_type_list().unwrap()
+            .into_iter()
+            .enumerate()
+            .filter(|(_, tt)| {
+                match &site_type_patterns {
+                    Some(accepted_site_types) => {
+                        accepted_site_types.iter()
+                            .find(|pattern| {
+                                device.try_ic_str(tt.get_name())
+                                    .map_or(false, |name| wildcard_match(pattern, name))
+                            })
+                            .is_some()
+                    },
+                    None => true,
+                }
+            })
+            .collect())
+    }
+}
+
+impl PreprocessCmd {
+    /// Directory for files of an exporter: its own prefix if given, `--out-dir` otherwise
+    fn out_prefix(&self, prefix: &Option<String>) -> String {
+        prefix.as_ref().or(self.out_dir.as_ref()).cloned().unwrap_or_default()
+    }
+
+    /// Path of a single output file, relative paths are placed under `--out-dir`
+    fn out_path(&self, path: &str) -> std::path::PathBuf {
+        Path::new(&self.out_prefix(&None)).join(path)
+    }
+
+    /// Whether any of the requested outputs records the hash of the device file
+    fn records_device_hash(&self) -> bool {
+        self.resume_verify_hash
+            || self.summary_json.is_some()
+            || self.bundle.is_some()
+            || self.bba_split.is_some()
+            || (self.json.is_some() && !self.summary_only)
+    }
+
+    /// Memory limit given with `--max-memory`, in bytes. Limits too large to be
+    /// represented saturate, which amounts to no limit.
+    fn max_memory_bytes(&self) -> Option<usize> {
+        self.max_memory.map(|max_memory| max_memory.saturating_mul(MIB))
+    }
+
+    /// Slice of source pins selected with `--pin-start` and `--pin-end`
+    fn pin_slice(&self) -> Option<PinSlice> {
+        if self.pin_start.is_none() && self.pin_end.is_none() {
+            return None;
+        }
+        Some(PinSlice { start: self.pin_start.unwrap_or(0), end: self.pin_end })
+    }
+}
+
+/// Bounds of a slice of source pins. Routing results of slices are partial and need to be
+/// merged to get complete routing information of a site type.
+#[derive(Serialize, Clone, Copy, Debug)]
+struct PinSlice {
+    start: usize,
+    /// `None` for slices reaching the last pin
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end: Option<usize>,
+}
+
+impl PinSlice {
+    fn range(&self) -> std::ops::Range<usize> {
+        self.start .. self.end.unwrap_or(usize::MAX)
+    }
+}
+
+/* Set on Ctrl-C. Preprocessing stops after finishing the site type being processed and
+ * writes out the results gathered so far. */
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Numbers of routing results found for a site type (or a whole device)
+#[derive(Serialize, Default, Clone, Copy, Debug)]
+struct RoutingSummary {
+    pairs: usize,
+    out_of_site_sources: usize,
+    out_of_site_sinks: usize,
+    timed_out: usize,
+    /// Pins with no path to or from any site port (see `BruteRouter::isolated_from_ports`)
+    isolated_pins: usize,
+    /// Pairs routable without any routing choices (see `RoutingInfo::unconstrained_ratio`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unconstrained_pairs: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unconstrained_ratio: Option<f64>,
+}
+
+impl RoutingSummary {
+    fn add(&mut self, other: &RoutingSummary) {
+        self.pairs += other.pairs;
+        self.out_of_site_sources += other.out_of_site_sources;
+        self.out_of_site_sinks += other.out_of_site_sinks;
+        self.timed_out += other.timed_out;
+        self.isolated_pins += other.isolated_pins;
+        if let Some(unconstrained) = other.unconstrained_pairs {
+            let unconstrained = self.unconstrained_pairs.unwrap_or(0) + unconstrained;
+            self.unconstrained_pairs = Some(unconstrained);
+            self.unconstrained_ratio =
+                (self.pairs != 0).then(|| unconstrained as f64 / self.pairs as f64);
+        }
+    }
+}
+
+#[derive(Serialize, Default, Debug)]
+struct DeviceRoutingSummary {
+    /// SHA-256 hash of the device file the summary was computed from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_hash: Option<String>,
+    /// Slice of source pins routed within each site type
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pin_slice: Option<PinSlice>,
+    site_types: HashMap<String, RoutingSummary>,
+    total: RoutingSummary,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum GraphFormat {
+    Dot,
+    Graphml,
+}
+
+impl GraphFormat {
+    fn file_suffix(&self) -> &'static str {
+        match self {
+            GraphFormat::Dot => ".dot",
+            GraphFormat::Graphml => ".graphml",
+        }
+    }
 }
 
 #[derive(Parser, Debug)]
 struct RoutePairCmd {
     #[arg(help = "Site Type")]
-    tile_type: String,
+    site_type: String,
     #[arg(help = "Path to source pin: bel_name.pin_name")]
     from: String,
     #[arg(help = "Path to destination pin: bel_name.pin_name")]
     to: String,
+    #[arg(long, help = "Write explored routes as a FPGA-Interchange physical netlist")]
+    physnet: Option<String>,
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Stop exploring once N routes to the destination pin have been found"
+    )]
+    max_routes_per_pair: Option<usize>,
 }
 
 impl RoutePairCmd {
@@ -124,151 +649,1979 @@ impl RoutePairCmd {
 }
 
 #[derive(Parser, Debug)]
-enum SubCommands {
-    Preprocess(PreprocessCmd),
-    RoutePair(RoutePairCmd),
+struct ListOutOfSitePortsCmd {
+    #[arg(help = "Site Type")]
+    site_type: String,
 }
 
-fn preprocess<'d>(args: PreprocessCmd, device: ic_loader::archdef::Root<'d>) {
-    let site_types: Vec<_> = device.get_site_type_list().unwrap()
-        .into_iter()
-        .enumerate()
-        .filter(|(_, tt)| {
-            match &args.site_types {
-                Some(accepted_site_types) => {
-                    accepted_site_types.iter()
-                        .find(|st_name| {
-                            *st_name == device.ic_str(tt.get_name())
-                        })
-                        .is_some()
-                },
-                None => true,
-            }
-        })
-        .collect();
-    
-    let mut dot_exporter =
-        MultiFileExporter::new(&args.dot, args.dot_prefix.clone(), ".dot".into());
-    
-    /* Unfortunately, since serde::Serialize is not object-safe, we need separate
-     * exporters for different types. */
-    let mut json_exporter = CompoundJsonExporter::new(
-        &args.json,
-        Path::new(&args.json_prefix).join(
-            format!("{}_site_routability.json", device.get_name().unwrap())
-        )
-    );
+#[derive(Parser, Debug)]
+struct RouteBelCmd {
+    #[arg(help = "Site Type")]
+    site_type: String,
+    #[arg(help = "Name of the BEL to route from")]
+    bel: String,
+}
 
-    for (st_id, st) in site_types {
-        let st_name = device.ic_str(st.get_name());
-        dbg_log!(DBG_INFO, "Processing site type {}", st_name);
-        let brouter = BruteRouter::<()>::new(&device, st_id as u32, args.virtual_consts);
+#[derive(Parser, Debug)]
+struct ShowPairCmd {
+    #[arg(help = "Site Type")]
+    site_type: String,
+    #[arg(help = "Path to source pin: bel_name.pin_name")]
+    from: String,
+    #[arg(help = "Path to destination pin: bel_name.pin_name")]
+    to: String,
+}
 
-        dot_exporter.ignore_or_export(&st_name, || {
-            brouter.create_dot_exporter().export_dot(&device, &st_name)
-        }).unwrap();
+#[derive(Parser, Debug)]
+struct DeviceInfoCmd {}
 
-        let brouter = Arc::new(brouter);
-        let routing_info = if args.threads == 1 {
-            brouter.as_ref().route_all(!args.no_formula_opt)
-        } else {
-            use crate::router::site_brute_router::MultiThreadedBruteRouter;
-            Arc::clone(&brouter)
-                .route_all_multithreaded(args.threads, !args.no_formula_opt)
-        };
+#[derive(Parser, Debug)]
+struct ServeCmd {}
 
-        println!(concat!(
-            "Site Type {}:\n",
-            "    No. of intra-site routing pairs:               {}\n",
-            "    No. of pins connected to out-of-site-sources:  {}\n",
-            "    No. of pins connected to out-of-site-sinks:    {}"
-            ),
-            st_name,
-            routing_info.pin_to_pin_routing.len(),
-            routing_info.out_of_site_sources.len(),
-            routing_info.out_of_site_sinks.len()
-        );
+#[derive(Parser, Debug)]
+struct FamilyCmd {
+    #[arg(help = "Other device files of the family, processed along with the main one")]
+    devices: Vec<String>,
+    #[command(flatten)]
+    routing: RoutingOpts,
+    #[arg(long, help = "File to save the combined routing information in JSON format to")]
+    output: String,
+}
 
-        json_exporter.ignore_or_export(&st_name, ||
-            routing_info.with_extras(brouter, &device)
-        ).unwrap();
-    }
-    
-    <MultiFileExporter as Exporter<String>>::flush(&mut dot_exporter).unwrap();
+#[derive(Parser, Debug)]
+struct DiffDevicesCmd {
+    #[arg(help = "Device file to compare the main device file with")]
+    other: String,
+}
 
-    json_exporter.flush().unwrap();
+#[derive(Parser, Debug)]
+struct MergeSlicesCmd {
+    #[arg(help = "File to save the bundle with merged routing information to")]
+    output: String,
+    #[arg(
+        required = true,
+        help = "Bundles of slices of source pins of a single site type, routed with \
+            --pin-start and --pin-end"
+    )]
+    slices: Vec<String>,
+    #[arg(
+        long,
+        help = "Also save the merged routing information as a BBA blob to a given file"
+    )]
+    bba: Option<String>,
 }
 
-fn route_pair<'d>(args: RoutePairCmd, device: ic_loader::archdef::Root<'d>) {
-    let (tt_id, _) = device.reborrow().get_tile_type_list().unwrap()
-        .into_iter()
-        .enumerate()
-        .find(|(_, tt)| device.ic_str(tt.get_name()) == args.tile_type)
-        .expect("Wrong tile type name");
-    
-    let (from_bel, from_pin) = args.get_from_tuple()
-        .expect("Incorrent from pin format!");
-    let (to_bel, to_pin) = args.get_to_tuple()
-        .expect("Incorrent to pin format!");
-    
-    let router_state = Arc::new(Mutex::new(HashMap::new()));
-    //let rs = Arc::clone(&router_state);
-    let routes = Arc::new(Mutex::new(Vec::new()));
-    let routes_l = Arc::clone(&routes);
+#[derive(Parser, Debug)]
+struct BatchCmd {
+    #[arg(
+        help = "File listing subcommands to run with their arguments, one per line. The \
+            device is opened once and shared by all of them. Empty lines and lines \
+            starting with # are ignored"
+    )]
+    script: String,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ErrorFormat {
+    Text,
+    Json,
+}
+
+/// Top-level errors of NISP's subcommands. Each kind of error has a stable name and
+/// exit code, so that tools wrapping NISP can tell them apart.
+#[derive(Debug)]
+enum NispError {
+    /// The device file couldn't be opened or is malformed
+    Device(String),
+    UnknownSiteType(String),
+    UnknownBel { site_type: String, bel: String },
+    /// Pin path not in the `bel_name.pin_name` format
+    BadPinPath(String),
+    UnknownPin { bel: String, pin: String },
+    /// Failure of the `preprocess` or `family` subcommands
+    Preprocess(String),
+    /// Output file couldn't be written
+    Io(String),
+    /// Routing results failed the checks enabled with `--validate`
+    Validation(String),
+    /// Unreadable or malformed script of the `batch` subcommand
+    Batch(String),
+    /// Malformed query of the `serve` subcommand
+    Query(String),
+    /// Slices of the `merge-slices` subcommand can't be merged
+    Merge(String),
+    #[cfg(feature = "profiling")]
+    Profile(String),
+}
+
+impl NispError {
+    fn kind(&self) -> &'static str {
+        match self {
+            NispError::Device(_) => "device",
+            NispError::UnknownSiteType(_) => "unknown_site_type",
+            NispError::UnknownBel { .. } => "unknown_bel",
+            NispError::BadPinPath(_) => "bad_pin_path",
+            NispError::UnknownPin { .. } => "unknown_pin",
+            NispError::Preprocess(_) => "preprocess",
+            NispError::Io(_) => "io",
+            NispError::Validation(_) => "validation",
+            NispError::Batch(_) => "batch",
+            NispError::Query(_) => "query",
+            NispError::Merge(_) => "merge",
+            #[cfg(feature = "profiling")]
+            NispError::Profile(_) => "profile",
+        }
+    }
+
+    /// Exit codes start at 3, because 1 is the generic failure code and 2 is used by clap
+    /// for usage errors. Code 4 belonged to an error which is no longer reported.
+    fn exit_code(&self) -> i32 {
+        match self {
+            NispError::Device(_) => 3,
+            NispError::UnknownSiteType(_) => 5,
+            NispError::UnknownBel { .. } => 6,
+            NispError::BadPinPath(_) => 7,
+            NispError::UnknownPin { .. } => 8,
+            #[cfg(feature = "profiling")]
+            NispError::Profile(_) => 9,
+            NispError::Batch(_) => 10,
+            NispError::Query(_) => 11,
+            NispError::Merge(_) => 12,
+            NispError::Preprocess(_) => 13,
+            NispError::Io(_) => 14,
+            NispError::Validation(_) => 15,
+        }
+    }
+
+    fn context(&self) -> serde_json::Value {
+        use serde_json::json;
+
+        match self {
+            NispError::Device(_)
+                | NispError::Preprocess(_)
+                | NispError::Io(_)
+                | NispError::Validation(_)
+                | NispError::Batch(_)
+                | NispError::Query(_)
+                | NispError::Merge(_) => json!({}),
+            #[cfg(feature = "profiling")]
+            NispError::Profile(_) => json!({}),
+            NispError::UnknownSiteType(name) => json!({ "site_type": name }),
+            NispError::UnknownBel { site_type, bel } =>
+                json!({ "site_type": site_type, "bel": bel }),
+            NispError::BadPinPath(path) => json!({ "path": path }),
+            NispError::UnknownPin { bel, pin } => json!({ "bel": bel, "pin": pin }),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::json!({
+            "error": self.kind(),
+            "message": self.to_string(),
+            "context": self.context(),
+        }).to_string()
+    }
+
+    fn report(&self, format: ErrorFormat) -> String {
+        match format {
+            ErrorFormat::Text => format!("Error: {}", self),
+            ErrorFormat::Json => self.to_json(),
+        }
+    }
+}
+
+impl std::fmt::Display for NispError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NispError::Device(msg) => write!(f, "{}", msg),
+            NispError::UnknownSiteType(name) => write!(f, "Wrong site type name: {}", name),
+            NispError::UnknownBel { site_type, bel } =>
+                write!(f, "Site type {} has no BEL named {}", site_type, bel),
+            NispError::BadPinPath(path) =>
+                write!(f, "Incorrect pin format: {} (expected bel_name.pin_name)", path),
+            NispError::UnknownPin { bel, pin } =>
+                write!(f, "Pin {}.{} does not exist", bel, pin),
+            NispError::Preprocess(msg) => write!(f, "{}", msg),
+            NispError::Io(msg) => write!(f, "{}", msg),
+            NispError::Validation(msg) => write!(f, "{}", msg),
+            NispError::Batch(msg) => write!(f, "{}", msg),
+            NispError::Query(msg) => write!(f, "{}", msg),
+            NispError::Merge(msg) => write!(f, "{}", msg),
+            #[cfg(feature = "profiling")]
+            NispError::Profile(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for NispError {}
+
+impl From<String> for NispError {
+    fn from(msg: String) -> Self {
+        NispError::Preprocess(msg)
+    }
+}
+
+/// Finds a site type by name
+fn find_site_type<'d>(device: &ic_loader::archdef::Root<'d>, name: &str)
+    -> Result<usize, NispError>
+{
+    device.get_site_type_list().unwrap()
+        .into_iter()
+        .position(|st| device.try_ic_str(st.get_name()) == Some(name))
+        .ok_or_else(|| NispError::UnknownSiteType(name.into()))
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum FormulaFormat {
+    Implicit,
+    Explicit,
+    Labeled,
+}
+
+#[derive(Parser, Debug)]
+enum SubCommands {
+    Preprocess(PreprocessCmd),
+    RoutePair(RoutePairCmd),
+    ListOutOfSitePorts(ListOutOfSitePortsCmd),
+    DeviceInfo(DeviceInfoCmd),
+    RouteBel(RouteBelCmd),
+    ShowPair(ShowPairCmd),
+    Family(FamilyCmd),
+    DiffDevices(DiffDevicesCmd),
+    Batch(BatchCmd),
+    Serve(ServeCmd),
+    MergeSlices(MergeSlicesCmd),
+}
+
+const MIB: usize = 1 << 20;
+
+/* Rounds up, so that a non-zero usage never shows up as 0 MiB */
+fn mib(bytes: usize) -> usize {
+    bytes / MIB + usize::from(bytes % MIB != 0)
+}
+
+/* Picks the JSON export strategy and concurrency of routing, so that the estimated memory
+ * usage fits within `--max-memory` */
+fn plan_memory_usage<'d, S>(
+    args: &PreprocessCmd,
+    device: &ic_loader::archdef::Root<'d>,
+    site_types: &[(usize, S)]
+)
+    -> (bool, SiteScheduler)
+{
+    use crate::router::memory::MemoryEstimate;
+
+    let estimate = MemoryEstimate::new(device, site_types.iter().map(|(id, _)| *id as u32));
+    let mut streaming = false;
+    let mut scheduler = SiteScheduler {
+        site_parallelism: args.site_parallelism,
+        threads_per_site: args.threads_per_site,
+    };
+    let peak = |streaming, scheduler: &SiteScheduler| {
+        estimate.peak_parallel(streaming, scheduler.threads_per_site, scheduler.site_parallelism)
+    };
+    dbg_log!(
+        DBG_INFO,
+        "Estimated peak memory usage: {} MiB",
+        mib(peak(streaming, &scheduler))
+    );
+
+    let max_memory = match args.max_memory_bytes() {
+        Some(max_memory) => max_memory,
+        None => return (streaming, scheduler),
+    };
+
+    if peak(streaming, &scheduler) > max_memory {
+        streaming = true;
+        dbg_log!(DBG_WARN, "Memory limit would be exceeded, switching to streaming JSON export");
+    }
+    if (peak(streaming, &scheduler) > max_memory) && (scheduler.site_parallelism > 1) {
+        scheduler.site_parallelism = 1;
+        dbg_log!(DBG_WARN, "Memory limit would be exceeded, routing one site type at a time");
+    }
+    if (peak(streaming, &scheduler) > max_memory) && (scheduler.threads_per_site > 1) {
+        scheduler.threads_per_site = 1;
+        dbg_log!(DBG_WARN, "Memory limit would be exceeded, routing with a single thread");
+    }
+    if peak(streaming, &scheduler) > max_memory {
+        dbg_log!(
+            DBG_WARN,
+            "Estimated memory usage ({} MiB) still exceeds the limit",
+            mib(peak(streaming, &scheduler))
+        );
+    }
+
+    (streaming, scheduler)
+}
+
+/* Concurrency of routing: up to `site_parallelism` site types are routed at the same time,
+ * with `threads_per_site` threads routing pins of each of them. */
+#[derive(Clone, Copy, Debug)]
+struct SiteScheduler {
+    site_parallelism: usize,
+    threads_per_site: usize,
+}
+
+impl SiteScheduler {
+    /* Upper bound of the number of threads working at the same time */
+    fn max_workers(&self) -> usize {
+        self.site_parallelism * self.threads_per_site
+    }
+
+    /* Creates a pool routing site types on threads of `scope` with `route`, which gets
+     * the number of threads it may use. */
+    fn pool<'scope, 'env, T, R, F>(
+        &self,
+        scope: &'scope std::thread::Scope<'scope, 'env>,
+        route: &'env F
+    )
+        -> SiteRoutingPool<'scope, 'env, T, R, F>
+    {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        SiteRoutingPool { scheduler: *self, scope, route, in_flight: 0, sender, receiver }
+    }
+}
+
+/* Site types being routed in the background. A new site type can be started as soon as
+ * any of the routed ones is done, so that a slow site type doesn't hold up the others. */
+struct SiteRoutingPool<'scope, 'env, T, R, F> {
+    scheduler: SiteScheduler,
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    route: &'env F,
+    in_flight: usize,
+    sender: std::sync::mpsc::Sender<(T, std::thread::Result<R>)>,
+    receiver: std::sync::mpsc::Receiver<(T, std::thread::Result<R>)>,
+}
+
+impl<'scope, 'env, T, R, F> SiteRoutingPool<'scope, 'env, T, R, F> where
+    T: Send + 'scope,
+    R: Send + 'scope,
+    F: Fn(&T, usize) -> R + Sync
+{
+    /* No site type can be started until one of the routed ones is done */
+    fn is_full(&self) -> bool {
+        self.in_flight >= self.scheduler.site_parallelism
+    }
+
+    fn start(&mut self, item: T) {
+        assert!(!self.is_full());
+        let route = self.route;
+        let threads = self.scheduler.threads_per_site;
+        let sender = self.sender.clone();
+        self.scope.spawn(move || {
+            let result = std::panic::catch_unwind(
+                std::panic::AssertUnwindSafe(|| route(&item, threads))
+            );
+            /* The pool is gone if processing of the results failed */
+            sender.send((item, result)).ok();
+        });
+        self.in_flight += 1;
+    }
+
+    /* Waits for any of the routed site types to be done, so results come in order of
+     * completion. Returns `None` if no site type is being routed. Panics of `route` are
+     * propagated to the caller. */
+    fn next_done(&mut self) -> Option<(T, R)> {
+        if self.in_flight == 0 {
+            return None;
+        }
+        let (item, result) = self.receiver.recv().unwrap();
+        self.in_flight -= 1;
+        match result {
+            Ok(result) => Some((item, result)),
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+/* Translates routes through site PIPs of site types into PIP indices of tile types
+ * containing them. Tile types without such sites are left out. */
+fn tile_site_pips<'d>(
+    device: &ic_loader::archdef::Root<'d>,
+    site_pips: &HashMap<u32, Vec<PinPairSitePips>>
+)
+    -> Result<HashMap<&'d str, Vec<TilePinPairPips>>, String>
+{
+    let mut tile_pips = HashMap::new();
+
+    for (tt_idx, tt) in device.get_tile_type_list().unwrap().iter().enumerate() {
+        let mut pips = Vec::new();
+        for (site, st) in tt.get_site_types().unwrap().iter().enumerate() {
+            let st_id = st.get_primary_type();
+            if let Some(pairs) = site_pips.get(&st_id) {
+                pips.extend(site_pips_in_tile(device, st_id, pairs, tt_idx as u32, site)?);
+            }
+        }
+        if !pips.is_empty() {
+            tile_pips.insert(device.ic_str(tt.get_name()), pips);
+        }
+    }
+
+    Ok(tile_pips)
+}
+
+/* Checks whether routing of a site type is the same as in its bundle within `baseline_dir`.
+ * Site types without a readable bundle count as changed. */
+fn matches_baseline<'d>(
+    baseline_dir: &str,
+    device: &ic_loader::archdef::Root<'d>,
+    brouter: &BruteRouter<()>,
+    routing_info: &RoutingInfo,
+    st_name: &str,
+    assume_sorted: bool
+)
+    -> bool
+{
+    let path = Path::new(baseline_dir).join(format!("{}.nisp.tar.gz", st_name));
+    std::fs::File::open(path).ok()
+        .and_then(|file| if assume_sorted {
+            read_bundle_routing_assume_sorted(file).ok()
+        } else {
+            read_bundle_routing(file).ok()
+        })
+        .map(|baseline| baseline.routing_matches(device, brouter, routing_info))
+        .unwrap_or(false)
+}
+
+/* Describes the pin with the most drivers, as it's the one responsible for the largest
+ * driver-exclusion constraints, along with the degree histogram of the routing graph */
+fn most_driven_pin_info<'d>(device: &ic_loader::archdef::Root<'d>, brouter: &BruteRouter<()>)
+    -> Option<String>
+{
+    let graph = brouter.get_graph();
+    let (pin, in_degree) = (0 .. graph.node_count())
+        .map(|pin| (pin, graph.in_degree(pin)))
+        .max_by_key(|(_, in_degree)| *in_degree)?;
+
+    let gsctx = GlobalStringsCtx::hold();
+    Some(format!(
+        "Pin with the most drivers: {} ({} drivers), degree histogram: {:?}",
+        brouter.get_pin_name(device, &gsctx, SitePinId::from(pin)).to_string(),
+        in_degree,
+        graph.degree_histogram()
+    ))
+}
+
+fn route_site(
+    brouter: &Arc<BruteRouter<()>>,
+    threads: usize,
+    optimize: bool,
+    pin_slice: Option<PinSlice>
+) -> RoutingInfo {
+    use crate::router::site_brute_router::{MultiThreadedBruteRouter, CancellationToken};
+
+    profile_span!("route_site", site_type = brouter.get_st_id());
+
+    /* Interrupts are handled between site types, so routing is never cancelled */
+    let cancel = CancellationToken::default();
+    match (pin_slice, threads) {
+        (None, 1) => brouter.route_all(optimize),
+        (None, _) => Arc::clone(brouter).route_all_multithreaded(threads, optimize, &cancel),
+        (Some(slice), 1) => brouter.route_pin_range(slice.range(), optimize),
+        (Some(slice), _) => Arc::clone(brouter)
+            .route_pin_range_multithreaded(slice.range(), threads, optimize, &cancel),
+    }
+}
+
+fn create_router<'d>(
+    args: &RoutingOpts,
+    device: &ic_loader::archdef::Root<'d>,
+    st_id: usize
+)
+    -> BruteRouter<()>
+{
+    let virtual_consts = VirtualConsts {
+        vcc: args.virtual_consts || args.virtual_vcc,
+        gnd: args.virtual_consts || args.virtual_gnd,
+    };
 
-    let brouter = BruteRouter::<Vec<SitePinId>>::new(&device, tt_id as u32, false);
+    BruteRouter::<()>::new(device, st_id as u32, virtual_consts)
+        .with_flattened_inout(args.flatten_inout)
+        .with_disabled_pips(device, &args.disable_pips)
+        .with_excluded_bel_kinds(&args.exclude_bel_kinds)
+        .with_pin_timeout(args.pin_timeout.map(std::time::Duration::from_secs_f64))
+        .with_port_pairs_only(args.port_pairs_only)
+        .with_edge_usage(args.edge_usage)
+}
+
+/* Checks whether a per-site output file (a .bba file or a bundle) can stand in for
+ * processing its site type again. With `expected_hash`, the file must have been created
+ * from the device file with the same hash. */
+fn is_valid_checkpoint(path: &Path, expected_hash: Option<&str>) -> bool {
+    if !path.exists() {
+        return false;
+    }
+    let expected_hash = match expected_hash {
+        Some(expected_hash) => expected_hash,
+        None => return true,
+    };
+
+    let recorded_hash = if path.to_string_lossy().ends_with(".bba") {
+        std::fs::read_to_string(path).ok()
+            .and_then(|bba| read_device_hash(&bba).map(String::from))
+    } else {
+        std::fs::File::open(path).ok()
+            .and_then(|file| read_bundle_routing(file).ok())
+            .and_then(|bundle| bundle.manifest.device_hash)
+    };
+    recorded_hash.as_deref() == Some(expected_hash)
+}
+
+/* Number of the largest formulas listed by `formula_budget_warning` */
+const FORMULA_BUDGET_OFFENDERS: usize = 5;
+
+/* Describes pin pairs of a site type whose `requires` formulas exceed `max_cubes` cubes,
+ * or returns `None` if there are none. */
+fn formula_budget_warning<'d>(
+    device: &ic_loader::archdef::Root<'d>,
+    brouter: &BruteRouter<()>,
+    routing_info: &RoutingInfo,
+    st_name: &str,
+    max_cubes: usize
+)
+    -> Option<String>
+{
+    let offenders = routing_info.over_formula_budget(max_cubes);
+    if offenders.is_empty() {
+        return None;
+    }
+
+    let gsctx = GlobalStringsCtx::hold();
+    let worst: Vec<_> = offenders.iter()
+        .take(FORMULA_BUDGET_OFFENDERS)
+        .map(|((from, to), cubes)| format!(
+            "{} -> {} ({} cubes)",
+            brouter.get_pin_name(device, &gsctx, *from).to_string(),
+            brouter.get_pin_name(device, &gsctx, *to).to_string(),
+            cubes
+        ))
+        .collect();
+    Some(format!(
+        "Site type {}: {} pin pairs exceed the formula budget of {} cubes, worst: {}",
+        st_name,
+        offenders.len(),
+        max_cubes,
+        worst.join(", ")
+    ))
+}
+
+/* Exports DOT files on worker threads while site types get routed. Device readers can't
+ * be shared between threads, so routers are built by the routing loop, with their names
+ * detached from the device, and handed over to the workers. A router gets dropped once
+ * it has been both routed and exported. */
+struct DotExportPool {
+    sender: Option<std::sync::mpsc::SyncSender<(String, Arc<BruteRouter<()>>)>>,
+    workers: Vec<std::thread::JoinHandle<std::io::Result<()>>>,
+}
+
+impl DotExportPool {
+    fn new(args: &PreprocessCmd, threads: usize) -> Self {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(threads);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let dot_exporter = Arc::new(MultiFileExporter::new(
+            &args.dot,
+            args.out_prefix(&args.dot_prefix),
+            args.dot_format.file_suffix().into()
+        ));
+        let (sorted, edge_kind_colors) = (args.dot_sorted, args.dot_edge_kind_colors);
+
+        let workers = (0 .. threads)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                let dot_exporter = Arc::clone(&dot_exporter);
+                std::thread::spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    let (st_name, brouter): (String, Arc<BruteRouter<()>>) = match job {
+                        Ok(job) => job,
+                        Err(_) => return Ok(()),
+                    };
+                    let dot = brouter.create_dot_exporter()
+                        .with_sorted_clusters(sorted)
+                        .with_edge_kind_colors(edge_kind_colors)
+                        .export_detached_dot(&st_name);
+                    dot_exporter.export(&st_name, dot)?;
+                })
+            })
+            .collect();
+
+        Self { sender: Some(sender), workers }
+    }
+
+    /* Queues export of a router with detached names. Blocks while all the workers are
+     * busy, so that routers don't pile up waiting for export. */
+    fn export(&self, st_name: &str, brouter: &Arc<BruteRouter<()>>) {
+        /* Workers only stop early on errors, which get reported by `finish` */
+        let _ = self.sender.as_ref().unwrap().send((st_name.to_string(), Arc::clone(brouter)));
+    }
+
+    /* Waits for the queued exports to finish */
+    fn finish(mut self) -> std::io::Result<()> {
+        self.sender = None;
+        self.workers.into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect()
+    }
+}
+
+/* Reports a failure to write one of the outputs of `preprocess` */
+fn write_error(what: &str) -> impl Fn(std::io::Error) -> NispError + '_ {
+    move |error| NispError::Io(format!("Couldn't write {}: {}", what, error))
+}
+
+fn preprocess<'d>(
+    args: PreprocessCmd,
+    device: ic_loader::archdef::Root<'d>,
+    device_hash: Option<String>,
+    interrupted: &AtomicBool,
+    out: &mut dyn std::io::Write
+)
+    -> Result<(), NispError>
+{
+    if args.threads_per_site == 0 {
+        return Err(NispError::Preprocess("--threads-per-site must be at least 1".into()));
+    }
+    if args.site_parallelism == 0 {
+        return Err(NispError::Preprocess("--site-parallelism must be at least 1".into()));
+    }
+
+    let site_types = args.routing.select_site_types(&device)?;
     
-    let from = brouter.get_pin_id(&device, from_bel, from_pin)
-        .expect("From pin does not exist!");
+    if site_types.is_empty() && !args.allow_empty {
+        return Err(NispError::Preprocess(format!(
+            "No site types match --site-types {} (use --allow-empty to proceed anyway)",
+            args.routing.site_type_patterns()?.as_deref().unwrap_or_default().join(" ")
+        )));
+    }
+
+    /* A misspelled BEL would leave its PIPs enabled without any sign of it */
+    let unknown_bels: Vec<_> = args.routing.disable_pips.iter()
+        .filter(|name| !site_types.iter().any(|(_, st)| {
+            st.get_bels().unwrap().iter()
+                .any(|bel| device.try_ic_str(bel.get_name()) == Some(name.as_str()))
+        }))
+        .map(String::as_str)
+        .collect();
+    if !unknown_bels.is_empty() {
+        return Err(NispError::Preprocess(format!(
+            "--disable-pips names BELs not found in any routed site type: {}",
+            unknown_bels.join(", ")
+        )));
+    }
+
+    let expected_hash = match (args.resume_verify_hash, &device_hash) {
+        (false, _) => None,
+        (true, Some(device_hash)) => Some(device_hash.clone()),
+        (true, None) => return Err(NispError::Preprocess(
+            "Device hash is unknown, can't verify output files".into()
+        )),
+    };
+
+    /* Pins are looked up in each site type, not all of them have to be present */
+    let ignored_ports = args.ignore_ports.iter().flatten()
+        .map(|path| path.split_once('.')
+            .ok_or_else(|| format!("Incorrect pin format: {} (expected bel_name.pin_name)", path)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut ignored_ports_found = vec![false; ignored_ports.len()];
+
+    let pin_slice = args.pin_slice();
+    if let Some(PinSlice { start, end: Some(end) }) = pin_slice {
+        if start > end {
+            return Err(NispError::Preprocess(
+                format!("--pin-start {} is past --pin-end {}", start, end)
+            ));
+        }
+    }
+
+    let (streaming, scheduler) = plan_memory_usage(&args, &device, &site_types);
+
+    let mut dot_exporter = MultiFileExporter::new(
+        &args.dot,
+        args.out_prefix(&args.dot_prefix),
+        args.dot_format.file_suffix().into()
+    );
     
-    let to = brouter.get_pin_id(&device, to_bel, to_pin)
-        .expect("To pin does not exist!");
+    /* Unfortunately, since serde::Serialize is not object-safe, we need separate
+     * exporters for different types. */
+    let json_path = Path::new(&args.out_prefix(&args.json_prefix)).join(format!(
+        "{}_site_routability.{}",
+        device.get_name().unwrap(),
+        if args.json_lines { "jsonl" } else { "json" }
+    ));
+    let mut json_exporter = if args.json_lines {
+        JsonExporter::Lines(JsonLinesExporter::new(&args.json, json_path))
+    } else if streaming {
+        JsonExporter::Streaming(StreamingJsonExporter::new(&args.json, json_path))
+    } else {
+        JsonExporter::Compound(CompoundJsonExporter::new(&args.json, json_path))
+    };
+
+    let mut bundle_exporter = MultiFileExporter::new(
+        &args.bundle,
+        args.out_prefix(&args.bundle_prefix),
+        ".nisp.tar.gz".into()
+    );
+
+    let mut bba_exporter = MultiFileExporter::new(
+        &args.bba_split,
+        args.out_prefix(&args.bba_prefix),
+        ".bba".into()
+    );
+
+    let mut combined_bba = args.bba.as_ref()
+        .map(|bba| {
+            let file = std::io::BufWriter::new(create_file(&args.out_path(bba))?);
+            CombinedBbaWriter::new(file, device_hash.as_deref())
+        })
+        .transpose()
+        .map_err(write_error("BBA output"))?;
+
+    #[cfg(feature = "image")]
+    let mut heatmap_exporter = MultiFileExporter::new(
+        &args.heatmap,
+        args.out_prefix(&args.heatmap_prefix),
+        ".png".into()
+    );
+
+    /* With multiple threads, DOT files get exported in parallel to routing */
+    let threads = scheduler.max_workers();
+    let parallel_dot = (threads > 1) && matches!(args.dot_format, GraphFormat::Dot);
+    let dot_pool = parallel_dot.then(|| DotExportPool::new(&args, threads));
+
+    let mut device_summary = DeviceRoutingSummary {
+        device_hash: device_hash.clone(),
+        pin_slice,
+        .. Default::default()
+    };
+    let mut site_routing = HashMap::new();
+    let mut site_pips = HashMap::new();
+    let mut skipped = 0;
+    let mut invalid_count = 0;
+
+    let mut site_types = site_types.into_iter();
+    let mut stop = false;
+
+    /* Site types are routed in the background, but routers are created and results
+     * exported on this thread, as both require access to the device */
+    let route = |(_, _, brouter): &(usize, &str, Arc<BruteRouter<()>>), threads| {
+        route_site(brouter, threads, !args.no_formula_opt, pin_slice)
+    };
+    std::thread::scope(|scope| -> Result<(), NispError> {
+        let mut pool = scheduler.pool(scope, &route);
+        loop {
+            while !stop && !pool.is_full() {
+                if interrupted.load(Ordering::SeqCst) {
+                    dbg_log!(DBG_WARN, "Preprocessing interrupted, writing out partial results");
+                    stop = true;
+                    break;
+                }
+                let (st_id, st) = match site_types.next() {
+                    Some(site_type) => site_type,
+                    None => {
+                        stop = true;
+                        break;
+                    },
+                };
+
+                let st_name = device.ic_str(st.get_name());
+
+                if args.resume {
+                    let checkpoints: Vec<_> = [&bba_exporter, &bundle_exporter].into_iter()
+                        .filter(|exporter| exporter.should_export(&st_name))
+                        .map(|exporter| exporter.path(&st_name))
+                        .collect();
+                    let done = !checkpoints.is_empty() && checkpoints.iter()
+                        .all(|path| is_valid_checkpoint(path, expected_hash.as_deref()));
+                    if done {
+                        writeln!(out, "Site Type {}: skipped, output files already exist", st_name)
+                            .unwrap();
+                        skipped += 1;
+                        continue;
+                    }
+                }
+
+                dbg_log!(DBG_INFO, "Processing site type {}", st_name);
+                let mut brouter = create_router(&args.routing, &device, st_id)
+                    .with_unoptimized_forms(args.emit_both_forms);
+                if crate::log::log_level() >= DBG_INFO {
+                    if let Some(info) = most_driven_pin_info(&device, &brouter) {
+                        dbg_log!(DBG_INFO, "{}", info);
+                    }
+                }
+
+                if parallel_dot && dot_exporter.should_export(&st_name) {
+                    brouter = brouter.with_detached_names(&device);
+                } else if !parallel_dot {
+                    dot_exporter.ignore_or_export(&st_name, || {
+                        let graph_exporter: Box<dyn GraphExporter> = match args.dot_format {
+                            GraphFormat::Dot => Box::new(
+                                brouter.create_dot_exporter()
+                                    .with_sorted_clusters(args.dot_sorted)
+                                    .with_edge_kind_colors(args.dot_edge_kind_colors)
+                            ),
+                            GraphFormat::Graphml => Box::new(brouter.create_graphml_exporter()),
+                        };
+                        graph_exporter.export_graph(&device, &st_name)
+                    }).map_err(write_error("graph"))?;
+                }
+
+                let brouter = Arc::new(brouter);
+                if let Some(dot_pool) = &dot_pool {
+                    if dot_exporter.should_export(&st_name) {
+                        dot_pool.export(&st_name, &brouter);
+                    }
+                }
+                pool.start((st_id, st_name, brouter));
+            }
+
+            let ((st_id, st_name, brouter), mut routing_info) = match pool.next_done() {
+                Some(done) => done,
+                None => break,
+            };
+            if let Some(max_cubes) = args.warn_formula_budget {
+                let warning =
+                    formula_budget_warning(&device, &brouter, &routing_info, &st_name, max_cubes);
+                if let Some(warning) = warning {
+                    writeln!(out, "Warning: {}", warning).unwrap();
+                }
+            }
+            let (invalid_activators, inconsistent_directions) = match args.validate {
+                true => (
+                    brouter.find_invalid_activators(&routing_info),
+                    brouter.find_inconsistent_directions()
+                ),
+                false => (Vec::new(), Vec::new()),
+            };
+            /* Routes are taken before they get altered by the options below */
+            let pip_routes = args.nextpnr_pips.is_some()
+                .then(|| routed_site_pips(&device, brouter.as_ref(), &routing_info))
+                .transpose()?;
+            if args.collapse_aliases {
+                routing_info.collapse_aliases(brouter.find_pin_aliases());
+            }
+            if !ignored_ports.is_empty() {
+                let ports = ignored_ports.iter().zip(ignored_ports_found.iter_mut())
+                    .filter_map(|((bel, pin), found)| {
+                        let pin_id = brouter.get_pin_id(&device, bel, pin).ok()?;
+                        *found = true;
+                        Some(pin_id)
+                    })
+                    .collect();
+                routing_info.ignore_ports(&ports);
+            }
+            if args.trim_implies {
+                routing_info.trim_implies();
+            }
+
+            if let Some(baseline_dir) = &args.changed_since {
+                let unchanged = matches_baseline(
+                    baseline_dir,
+                    &device,
+                    &brouter,
+                    &routing_info,
+                    &st_name,
+                    args.assume_sorted
+                );
+                if unchanged {
+                    writeln!(out, "Site Type {}: skipped, routing unchanged", st_name).unwrap();
+                    skipped += 1;
+                    continue;
+                }
+            }
+
+            bundle_exporter.ignore_or_export(&st_name, || {
+                let mut data = Vec::new();
+                write_bundle(
+                    &mut data,
+                    &device,
+                    brouter.as_ref(),
+                    &routing_info,
+                    device_hash.as_deref()
+                ).unwrap();
+                data
+            }).map_err(write_error("bundle"))?;
+
+            if bba_exporter.should_export(&st_name) {
+                let bba = export_site_routing_bba(
+                    &device,
+                    brouter.as_ref(),
+                    &routing_info,
+                    device_hash.as_deref()
+                )?;
+                bba_exporter.export(&st_name, bba).map_err(write_error("BBA file"))?;
+            }
+            if let Some(combined_bba) = &mut combined_bba {
+                let blob = combined_bba.blob(&device, brouter.as_ref(), &routing_info)?;
+                combined_bba.write_blob(blob).map_err(write_error("BBA output"))?;
+            }
+
+            #[cfg(feature = "image")]
+            heatmap_exporter.ignore_or_export(&st_name, || {
+                use crate::heatmap_exporter::{routability_heatmap, encode_png};
+                let heatmap = routability_heatmap(brouter.get_graph().node_count(), &routing_info);
+                encode_png(&heatmap).expect("Couldn't encode the heatmap")
+            }).map_err(write_error("heatmap"))?;
+
+            let summary = RoutingSummary {
+                pairs: routing_info.pin_to_pin_routing.len(),
+                out_of_site_sources: routing_info.out_of_site_sources.len(),
+                out_of_site_sinks: routing_info.out_of_site_sinks.len(),
+                timed_out: routing_info.timed_out.len(),
+                isolated_pins: brouter.isolated_from_ports().len(),
+                unconstrained_pairs: args.report_unconstrained_ratio
+                    .then(|| routing_info.unconstrained_pairs()),
+                unconstrained_ratio: args.report_unconstrained_ratio
+                    .then(|| routing_info.unconstrained_ratio())
+                    .flatten(),
+            };
+            writeln!(out, concat!(
+                "Site Type {}:\n",
+                "    No. of intra-site routing pairs:               {}\n",
+                "    No. of pins connected to out-of-site-sources:  {}\n",
+                "    No. of pins connected to out-of-site-sinks:    {}"
+                ),
+                st_name,
+                summary.pairs,
+                summary.out_of_site_sources,
+                summary.out_of_site_sinks
+            ).unwrap();
+            if summary.timed_out != 0 {
+                writeln!(
+                    out,
+                    "    No. of pins which timed out:                   {}",
+                    summary.timed_out
+                ).unwrap();
+            }
+            if summary.isolated_pins != 0 {
+                writeln!(
+                    out,
+                    "    No. of pins isolated from site ports:          {}",
+                    summary.isolated_pins
+                ).unwrap();
+            }
+            if let Some(unconstrained) = summary.unconstrained_pairs {
+                writeln!(
+                    out,
+                    "    Ratio of unconstrained pairs:                  {:.3} ({}/{})",
+                    summary.unconstrained_ratio.unwrap_or(0.0),
+                    unconstrained,
+                    summary.pairs
+                ).unwrap();
+            }
+            if args.check_symmetry {
+                let gsctx = GlobalStringsCtx::hold();
+                for (from, to) in brouter.find_asymmetric_pairs(&routing_info) {
+                    writeln!(
+                        out,
+                        "    Asymmetric routing: {} -> {} is routable, {} -> {} is not",
+                        brouter.get_pin_name(&device, &gsctx, from).to_string(),
+                        brouter.get_pin_name(&device, &gsctx, to).to_string(),
+                        brouter.get_pin_name(&device, &gsctx, to).to_string(),
+                        brouter.get_pin_name(&device, &gsctx, from).to_string()
+                    ).unwrap();
+                }
+            }
+
+            if !invalid_activators.is_empty() {
+                let gsctx = GlobalStringsCtx::hold();
+                for ((from, to), port) in &invalid_activators {
+                    writeln!(
+                        out,
+                        "    Invalid activator: {} in routing {} -> {}",
+                        brouter.get_pin_name(&device, &gsctx, *port).to_string(),
+                        brouter.get_pin_name(&device, &gsctx, *from).to_string(),
+                        brouter.get_pin_name(&device, &gsctx, *to).to_string()
+                    ).unwrap();
+                }
+                invalid_count += invalid_activators.len();
+            }
+            if !inconsistent_directions.is_empty() {
+                let gsctx = GlobalStringsCtx::hold();
+                let graph = brouter.get_graph();
+                for (from, to) in &inconsistent_directions {
+                    writeln!(
+                        out,
+                        "    Inconsistent direction: {:?} edge from {} ({:?}) to {} ({:?})",
+                        graph.get_edge(from.index(), to.index()),
+                        brouter.get_pin_name(&device, &gsctx, *from).to_string(),
+                        graph.get_node(from.index()).dir,
+                        brouter.get_pin_name(&device, &gsctx, *to).to_string(),
+                        graph.get_node(to.index()).dir
+                    ).unwrap();
+                }
+                invalid_count += inconsistent_directions.len();
+            }
+
+            device_summary.total.add(&summary);
+            device_summary.site_types.insert(st_name.to_string(), summary);
+
+            if args.tile_json.is_some() {
+                site_routing.insert(st_id as u32, routing_info.clone());
+            }
+            if let Some(pip_routes) = pip_routes {
+                site_pips.insert(st_id as u32, pip_routes);
+            }
+
+            if args.summary_only {
+                continue;
+            }
+
+            profile_span!("serialize", site_type = st_id);
+            json_exporter.ignore_or_export(&st_name, ||
+                routing_info.with_extras(brouter, &device)
+                    .with_deduplicated_formulas(args.dedup_formulas)
+                    .with_site_port_tile_wires(args.site_port_tile_wires)
+                    .with_numeric_port_ids(args.numeric_port_ids)
+                    .with_explicit_formulas(
+                        matches!(args.formula_format, FormulaFormat::Explicit)
+                    )
+                    .with_labeled_constraints(
+                        matches!(args.formula_format, FormulaFormat::Labeled)
+                    )
+                    .with_device_hash(device_hash.clone())
+                    .with_pin_table(args.pin_table)
+                    .with_bel_level(args.bel_level)
+                    .with_route_counts(args.route_counts, args.max_routes_per_pair)
+            ).map_err(write_error("JSON output"))?;
+        }
+        Ok(())
+    })?;
     
-    let brouter = brouter.with_callback(move |frame| {
-        let mut rs = router_state.deref().lock().unwrap();
+    <MultiFileExporter as Exporter<String>>::flush(&mut dot_exporter)
+        .map_err(write_error("graph"))?;
+    if let Some(dot_pool) = dot_pool {
+        dot_pool.finish().map_err(write_error("graph"))?;
+    }
 
-        rs.insert(frame.node, frame.prev_node);
+    if let Some(tile_path) = &args.tile_json {
+        use crate::router::tile_routing::aggregate_tile_types;
+
+        let tile_routing: HashMap<_, _> =
+            aggregate_tile_types(&device, &site_routing).into_iter()
+                .map(|tt_routing| {
+                    let tt = device.get_tile_type_list().unwrap().get(tt_routing.tile_type);
+                    (device.ic_str(tt.get_name()), tt_routing)
+                })
+                .collect();
+        let data = serde_json::to_string_pretty(&tile_routing).unwrap();
+        std::fs::write(args.out_path(tile_path), data).map_err(write_error("tile routing file"))?;
+    }
+
+    if let Some(pips_path) = &args.nextpnr_pips {
+        let tile_pips = tile_site_pips(&device, &site_pips)?;
+        let data = serde_json::to_string_pretty(&tile_pips).unwrap();
+        std::fs::write(args.out_path(pips_path), data).map_err(write_error("nextpnr PIPs file"))?;
+    }
+
+    if let Some(summary_path) = &args.summary_json {
+        let data = serde_json::to_string_pretty(&device_summary).unwrap();
+        std::fs::write(args.out_path(summary_path), data).map_err(write_error("summary file"))?;
+    }
 
+    if !args.summary_only {
+        profile_span!("serialize");
+        json_exporter.flush().map_err(write_error("JSON output"))?;
+    }
+    if let Some(combined_bba) = combined_bba {
+        combined_bba.finish().map_err(write_error("BBA output"))?;
+    }
+
+    /* Site types skipped before routing might have been the ones with the pins */
+    if !interrupted.load(Ordering::SeqCst) && (skipped == 0) {
+        let unknown: Vec<_> = ignored_ports.iter().zip(&ignored_ports_found)
+            .filter(|(_, found)| !**found)
+            .map(|((bel, pin), _)| format!("{}.{}", bel, pin))
+            .collect();
+        if !unknown.is_empty() {
+            return Err(NispError::Preprocess(format!(
+                "--ignore-ports pins not found in any site type: {}",
+                unknown.join(", ")
+            )));
+        }
+    }
+
+    if invalid_count != 0 {
+        return Err(NispError::Validation(format!(
+            "Validation failed, found {} invalid activators or inconsistent pin directions",
+            invalid_count
+        )));
+    }
+
+    /* Nothing might have been routed yet when interrupted */
+    let finished = !interrupted.load(Ordering::SeqCst);
+    if finished && (device_summary.total.pairs == 0) && (skipped == 0) && !args.allow_empty {
+        return Err(NispError::Preprocess(
+            "No routing information was found (use --allow-empty to proceed anyway)".into()
+        ));
+    }
+
+    Ok(())
+}
+
+/* Explores routes from `from` and collects the ones reaching `to`. Each route is
+ * accumulated as the list of pins along it. With `max_routes`, routing is abandoned once
+ * that many routes have been collected, as highly connected pairs can have enormous
+ * numbers of them. */
+fn explore_routes(
+    brouter: BruteRouter<Vec<SitePinId>>,
+    from: SitePinId,
+    to: SitePinId,
+    max_routes: Option<usize>
+)
+    -> (BruteRouter<Vec<SitePinId>>, Vec<Vec<SitePinId>>)
+{
+    let routes = Arc::new(Mutex::new(Vec::new()));
+    let routes_cb = Arc::clone(&routes);
+    let cap_reached = Arc::new(AtomicBool::new(false));
+    let cap_reached_cb = Arc::clone(&cap_reached);
+    let is_capped = move |count: usize| max_routes.map_or(false, |max| count >= max);
+
+    let brouter = brouter.with_callback(move |frame| {
         let mut acc = frame.accumulator.clone();
         acc.push(frame.node);
 
         /* Save newly found route */
         if frame.node == to {
-            routes.deref().lock().unwrap().push(acc.clone());
+            let mut routes = routes_cb.lock().unwrap();
+            if !is_capped(routes.len()) {
+                routes.push(acc.clone());
+            }
+            if is_capped(routes.len()) {
+                cap_reached_cb.store(true, Ordering::Relaxed);
+            }
         }
 
         (None, None, acc)
     });
 
-    let _ = brouter.route_pins(from, false);
+    /* Hitting the cap abandons routing, which is reported as a timeout */
+    let _ = brouter.route_pins_until(from, false, Some(&cap_reached));
+
+    let routes = std::mem::take(&mut *routes.lock().unwrap());
+    (brouter, routes)
+}
+
+fn route_pair<'d>(args: RoutePairCmd, device: ic_loader::archdef::Root<'d>)
+    -> Result<(), NispError>
+{
+    let st_id = find_site_type(&device, &args.site_type)?;
+
+    let (from_bel, from_pin) = args.get_from_tuple()
+        .map_err(|_| NispError::BadPinPath(args.from.clone()))?;
+    let (to_bel, to_pin) = args.get_to_tuple()
+        .map_err(|_| NispError::BadPinPath(args.to.clone()))?;
+    let unknown_pin = |bel: &str, pin: &str| NispError::UnknownPin {
+        bel: bel.into(),
+        pin: pin.into(),
+    };
+
+    let brouter = BruteRouter::<Vec<SitePinId>>::new(&device, st_id as u32, false);
+    
+    let from = brouter.get_pin_id(&device, from_bel, from_pin)
+        .map_err(|_| unknown_pin(from_bel, from_pin))?;
+    
+    let to = brouter.get_pin_id(&device, to_bel, to_pin)
+        .map_err(|_| unknown_pin(to_bel, to_pin))?;
+    
+    let (brouter, routes) = explore_routes(brouter, from, to, args.max_routes_per_pair);
 
     let gsctx = GlobalStringsCtx::hold();
     println!("Explored the following routes:");
-    for (route_id, route) in routes_l.deref().lock().unwrap().deref().iter().enumerate() {
+    for (route_id, route) in routes.iter().enumerate() {
         println!("  Route #{}:", route_id);
         for pin in route {
             println!("    {}", brouter.get_pin_name(&device, &gsctx, *pin).to_string());
         }
     }
+
+    if let Some(path) = &args.physnet {
+        let routes: Vec<_> = routes.iter()
+            .map(|route| physnet_exporter::route_segments(&device, &brouter, route))
+            .collect();
+        let data = physnet_exporter::export_routes_physnet(&args.site_type, &routes)
+            .map_err(|e| NispError::Device(format!("Can't encode routes: {}", e)))?;
+        std::fs::write(path, data)
+            .map_err(write_error(path))?;
+    }
+
+    Ok(())
+}
+
+fn list_out_of_site_ports<'d>(
+    args: ListOutOfSitePortsCmd,
+    device: ic_loader::archdef::Root<'d>,
+    out: &mut dyn std::io::Write
+) -> Result<(), NispError> {
+    let st_id = find_site_type(&device, &args.site_type)?;
+    let brouter = BruteRouter::<()>::new(&device, st_id as u32, false);
+
+    let gsctx = GlobalStringsCtx::hold();
+    let pin_names = |pins: &[SitePinId]| {
+        pins.iter()
+            .map(|pin| brouter.get_pin_name(&device, &gsctx, *pin).to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    writeln!(out, "Out-of-site ports of site type {}:", args.site_type).unwrap();
+    for port in brouter.list_out_of_site_ports() {
+        let dir = match port.dir {
+            PinDir::Output => "in",
+            PinDir::Input => "out",
+            PinDir::Inout => "inout",
+        };
+        writeln!(
+            out,
+            "  {} ({})",
+            brouter.get_pin_name(&device, &gsctx, port.pin).to_string(),
+            dir
+        ).unwrap();
+        if !port.drives.is_empty() {
+            writeln!(out, "    drives: {}", pin_names(&port.drives)).unwrap();
+        }
+        if !port.driven_by.is_empty() {
+            writeln!(out, "    driven by: {}", pin_names(&port.driven_by)).unwrap();
+        }
+    }
+
+    Ok(())
+}
+
+fn route_bel<'d>(
+    args: RouteBelCmd,
+    device: ic_loader::archdef::Root<'d>,
+    out: &mut dyn std::io::Write
+) -> Result<(), NispError> {
+    use crate::router::site_brute_router::ConstrainingElement;
+
+    let st_id = find_site_type(&device, &args.site_type)?;
+    let brouter = BruteRouter::<()>::new(&device, st_id as u32, false);
+    let routing_info = brouter.route_bel(&device, &args.bel, true)
+        .map_err(|_| NispError::UnknownBel {
+            site_type: args.site_type.clone(),
+            bel: args.bel.clone(),
+        })?;
+
+    let mut pairs: Vec<_> = routing_info.pin_to_pin_routing.iter().collect();
+    pairs.sort_by_key(|(pair, _)| **pair);
+
+    let gsctx = GlobalStringsCtx::hold();
+    let pin_name = |pin: SitePinId| brouter.get_pin_name(&device, &gsctx, pin).to_string();
+
+    writeln!(out, "Routes from BEL {} of site type {}:", args.bel, args.site_type).unwrap();
+    for ((from, to), ppri) in pairs {
+        let requires = crate::logic_formula::DNFForm { cubes: ppri.requires.clone() }
+            .to_math_string(|element| match element {
+                ConstrainingElement::Port(pin) => pin_name(SitePinId::from(*pin as usize)),
+            });
+        writeln!(out, "  {} -> {}: {}", pin_name(*from), pin_name(*to), requires).unwrap();
+    }
+    for pin in &routing_info.timed_out {
+        writeln!(out, "  {}: timed out", pin_name(*pin)).unwrap();
+    }
+
+    Ok(())
+}
+
+/// Routing information of site types of a device family
+#[derive(Serialize, Default, Debug)]
+struct FamilyRouting {
+    /// Distinct routing results. Site types routed identically in multiple devices are
+    /// stored once.
+    site_types: Vec<FamilySiteType>,
+    /// Indices of `site_types`, keyed by device name and then by site type name
+    index: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+#[derive(Serialize, Debug)]
+struct FamilySiteType {
+    name: String,
+    /// Devices sharing the routing results
+    devices: Vec<String>,
+    routing: serde_json::Value,
+}
+
+/* Identifies routing results of a site type independently of the device: names of its
+ * pins, and pin pairs along with their canonical formulas, with ports referred to by name */
+type RoutingSignature = (
+    Vec<String>,
+    Vec<(String, String, CanonicalDNFForm<String>, CanonicalDNFForm<String>)>,
+);
+
+fn routing_signature<'d>(
+    device: &ic_loader::archdef::Root<'d>,
+    brouter: &BruteRouter<()>,
+    routing_info: &RoutingInfo
+) -> RoutingSignature {
+    use crate::router::site_brute_router::ConstrainingElement;
+
+    let gsctx = GlobalStringsCtx::hold();
+    let pin_name = |pin: SitePinId| brouter.get_pin_name(device, &gsctx, pin).to_string();
+    let canonical = |cubes: &[DNFCube<ConstrainingElement>]| {
+        let cubes = cubes.iter()
+            .map(|cube| cube.clone().map(|ConstrainingElement::Port(pin)| {
+                pin_name(SitePinId::from(pin as usize))
+            }))
+            .collect();
+        CanonicalDNFForm::from(DNFForm { cubes })
+    };
+
+    let pins = (0 .. brouter.get_graph().node_count())
+        .map(|pin| pin_name(SitePinId::from(pin)))
+        .collect();
+    let mut pairs: Vec<_> = routing_info.pin_to_pin_routing.iter()
+        .map(|((from, to), ppri)| (
+            pin_name(*from),
+            pin_name(*to),
+            canonical(&ppri.requires),
+            canonical(&ppri.implies)
+        ))
+        .collect();
+    pairs.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+    (pins, pairs)
+}
+
+/// Routes site types of multiple devices. Results of a site type are compared across
+/// devices by hashing their canonical formulas, so that each distinct result is stored
+/// only once.
+/// 
+/// # Arguments
+/// * `devices` - devices of the family
+/// * `opts` - selection of site types and options of their routers, as in `preprocess`
+fn route_family<'d>(
+    devices: &[ic_loader::archdef::Root<'d>],
+    opts: &RoutingOpts
+) -> Result<FamilyRouting, String> {
+    use crate::router::serialize::IntoRoutingInfoWithExtras;
+
+    let mut family = FamilyRouting::default();
+    let mut distinct: HashMap<(String, RoutingSignature), usize> = HashMap::new();
+
+    for device in devices {
+        let device_name = device.get_name().unwrap().to_string();
+        for (st_id, st) in opts.select_site_types(device)? {
+            let st_name = device.ic_str(st.get_name()).to_string();
+
+            dbg_log!(DBG_INFO, "Routing site type {} of device {}", st_name, device_name);
+            let brouter = Arc::new(create_router(opts, device, st_id));
+            let routing_info = brouter.route_all(true);
+            let key = (st_name.clone(), routing_signature(device, &brouter, &routing_info));
+
+            let idx = match distinct.get(&key) {
+                Some(idx) => *idx,
+                None => {
+                    let routing = serde_json::to_value(
+                        routing_info.with_extras(Arc::clone(&brouter), device)
+                    ).unwrap();
+                    family.site_types.push(FamilySiteType {
+                        name: st_name.clone(),
+                        devices: Vec::new(),
+                        routing,
+                    });
+                    distinct.insert(key, family.site_types.len() - 1);
+                    family.site_types.len() - 1
+                },
+            };
+            family.site_types[idx].devices.push(device_name.clone());
+            family.index.entry(device_name.clone()).or_default().insert(st_name, idx);
+        }
+    }
+
+    Ok(family)
+}
+
+fn family<'d>(
+    args: FamilyCmd,
+    device: ic_loader::archdef::Root<'d>,
+    raw: bool
+) -> Result<(), NispError> {
+    let messages = args.devices.iter()
+        .map(|path| open_device(path, raw, false).map(|(msg, _)| msg))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut devices = vec![device];
+    for msg in &messages {
+        devices.push(device_root(msg.as_ref())?);
+    }
+
+    let family = route_family(&devices, &args.routing).map_err(NispError::Preprocess)?;
+    let data = serde_json::to_string_pretty(&family).unwrap();
+    std::fs::write(&args.output, data).map_err(write_error(&args.output))
+}
+
+/// Structural differences between site types of two devices
+#[derive(Default, Debug)]
+struct DeviceDiff {
+    removed_site_types: Vec<String>,
+    added_site_types: Vec<String>,
+    /// Site types present in both devices, whose routing graphs differ
+    changed_site_types: Vec<SiteTypeDiff>,
+    unchanged_site_types: usize,
+}
+
+/// Differences between routing graphs of a site type. Pins are identified by their
+/// names, as ids may differ between the devices. BELs which were added or removed show up
+/// through their pins.
+#[derive(Default, Debug)]
+struct SiteTypeDiff {
+    name: String,
+    old_pin_count: usize,
+    new_pin_count: usize,
+    removed_pins: Vec<String>,
+    added_pins: Vec<String>,
+    removed_edges: Vec<String>,
+    added_edges: Vec<String>,
+}
+
+/* Names of pins and edges of the routing graph of a site type, sorted */
+fn site_type_structure<'d>(device: &ic_loader::archdef::Root<'d>, st_id: usize)
+    -> (std::collections::BTreeSet<String>, std::collections::BTreeSet<String>)
+{
+    let brouter = BruteRouter::<()>::new(device, st_id as u32, false);
+    let graph = brouter.get_graph();
+    let gsctx = GlobalStringsCtx::hold();
+    let name = |pin: usize| brouter.get_pin_name(device, &gsctx, SitePinId::from(pin)).to_string();
+
+    let pins = (0 .. graph.node_count()).map(name).collect();
+    let edges = (0 .. graph.node_count())
+        .flat_map(|from| graph.edges_from(from).map(move |to| (from, to)))
+        .map(|(from, to)| {
+            format!("{} -> {} ({:?})", name(from), name(to), graph.get_edge(from, to))
+        })
+        .collect();
+    (pins, edges)
+}
+
+/// Compares site types of two devices, by names of their pins and edges of their routing
+/// graphs. Site types are matched by name.
+fn diff_site_types<'a, 'b>(
+    old: &ic_loader::archdef::Root<'a>,
+    new: &ic_loader::archdef::Root<'b>
+) -> DeviceDiff {
+    let site_types = |device: &ic_loader::archdef::Root| -> BTreeMap<String, usize> {
+        device.get_site_type_list().unwrap().iter()
+            .enumerate()
+            .map(|(st_id, st)| (device.ic_str(st.get_name()).to_string(), st_id))
+            .collect()
+    };
+    let (old_site_types, new_site_types) = (site_types(old), site_types(new));
+
+    let mut diff = DeviceDiff {
+        removed_site_types: old_site_types.keys()
+            .filter(|name| !new_site_types.contains_key(*name))
+            .cloned()
+            .collect(),
+        added_site_types: new_site_types.keys()
+            .filter(|name| !old_site_types.contains_key(*name))
+            .cloned()
+            .collect(),
+        .. DeviceDiff::default()
+    };
+
+    for (name, old_id) in &old_site_types {
+        let new_id = match new_site_types.get(name) {
+            Some(new_id) => *new_id,
+            None => continue,
+        };
+        dbg_log!(DBG_INFO, "Comparing site type {}", name);
+        let (old_pins, old_edges) = site_type_structure(old, *old_id);
+        let (new_pins, new_edges) = site_type_structure(new, new_id);
+        if (old_pins == new_pins) && (old_edges == new_edges) {
+            diff.unchanged_site_types += 1;
+            continue;
+        }
+        diff.changed_site_types.push(SiteTypeDiff {
+            name: name.clone(),
+            old_pin_count: old_pins.len(),
+            new_pin_count: new_pins.len(),
+            removed_pins: old_pins.difference(&new_pins).cloned().collect(),
+            added_pins: new_pins.difference(&old_pins).cloned().collect(),
+            removed_edges: old_edges.difference(&new_edges).cloned().collect(),
+            added_edges: new_edges.difference(&old_edges).cloned().collect(),
+        });
+    }
+
+    diff
+}
+
+fn diff_devices<'d>(
+    args: DiffDevicesCmd,
+    device: ic_loader::archdef::Root<'d>,
+    raw: bool,
+    out: &mut dyn std::io::Write
+) -> Result<(), NispError> {
+    let (other_msg, _) = open_device(&args.other, raw, false)?;
+    let other = device_root(other_msg.as_ref())?;
+    let diff = diff_site_types(&device, &other);
+
+    for name in &diff.removed_site_types {
+        writeln!(out, "Site Type {}: removed", name).unwrap();
+    }
+    for name in &diff.added_site_types {
+        writeln!(out, "Site Type {}: added", name).unwrap();
+    }
+    for st in &diff.changed_site_types {
+        writeln!(
+            out,
+            "Site Type {}: changed, {} -> {} pins",
+            st.name,
+            st.old_pin_count,
+            st.new_pin_count
+        ).unwrap();
+        for pin in &st.removed_pins {
+            writeln!(out, "    - pin {}", pin).unwrap();
+        }
+        for pin in &st.added_pins {
+            writeln!(out, "    + pin {}", pin).unwrap();
+        }
+        for edge in &st.removed_edges {
+            writeln!(out, "    - edge {}", edge).unwrap();
+        }
+        for edge in &st.added_edges {
+            writeln!(out, "    + edge {}", edge).unwrap();
+        }
+    }
+    writeln!(out, "Unchanged site types: {}", diff.unchanged_site_types).unwrap();
+
+    Ok(())
+}
+
+/// Merges routing information of slices of source pins of a site type, saved as bundles
+/// by separate runs of `preprocess` with `--pin-start` and `--pin-end`.
+fn merge_slices<'d>(args: MergeSlicesCmd, device: ic_loader::archdef::Root<'d>)
+    -> Result<(), NispError>
+{
+    let bundles = args.slices.iter()
+        .map(|path| {
+            std::fs::File::open(path)
+                .and_then(read_bundle)
+                .map_err(|e| NispError::Merge(format!("Couldn't read bundle {}: {}", path, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let first = &bundles[0];
+    for (path, bundle) in args.slices.iter().zip(&bundles).skip(1) {
+        let same_site_type = (bundle.manifest.site_type == first.manifest.site_type)
+            && (bundle.manifest.pin_count == first.manifest.pin_count)
+            && (bundle.manifest.device_hash == first.manifest.device_hash);
+        if !same_site_type {
+            return Err(NispError::Merge(format!(
+                "{} doesn't hold the same site type of the same device as {}",
+                path,
+                args.slices[0]
+            )));
+        }
+    }
+    let st_id = find_site_type(&device, &first.manifest.site_type)?;
+    if (st_id as u32) != first.manifest.site_type_id {
+        return Err(NispError::Merge(format!(
+            "Site type {} of the slices doesn't come from this device",
+            first.manifest.site_type
+        )));
+    }
+
+    let mut ranges: Vec<_> = args.slices.iter().zip(&bundles)
+        .map(|(path, bundle)| {
+            let range = bundle.routing_info.pin_range.clone()
+                .unwrap_or(0 .. bundle.manifest.pin_count);
+            (range, path)
+        })
+        .collect();
+    ranges.sort_by_key(|(range, _)| range.start);
+    for pair in ranges.windows(2) {
+        if pair[0].0.end > pair[1].0.start {
+            return Err(NispError::Merge(format!(
+                "Slices {} and {} overlap",
+                pair[0].1,
+                pair[1].1
+            )));
+        }
+    }
+
+    let router = &first.router;
+    let device_hash = first.manifest.device_hash.clone();
+    let merged = router.merge_routing(
+        bundles.iter().map(|bundle| bundle.routing_info.clone())
+    );
+
+    let write_error = |path: &str, e: String| {
+        NispError::Merge(format!("Couldn't write {}: {}", path, e))
+    };
+    let mut data = Vec::new();
+    write_bundle(&mut data, &device, router, &merged, device_hash.as_deref())
+        .and_then(|_| std::fs::write(&args.output, data))
+        .map_err(|e| write_error(&args.output, e.to_string()))?;
+    if let Some(bba_path) = &args.bba {
+        let bba = export_site_routing_bba(&device, router, &merged, device_hash.as_deref())
+            .map_err(|e| write_error(bba_path, e))?;
+        std::fs::write(bba_path, bba).map_err(|e| write_error(bba_path, e.to_string()))?;
+    }
+
+    Ok(())
+}
+
+fn show_pair<'d>(
+    args: ShowPairCmd,
+    device: ic_loader::archdef::Root<'d>,
+    out: &mut dyn std::io::Write
+) -> Result<(), NispError> {
+    let st_id = find_site_type(&device, &args.site_type)?;
+    let brouter = BruteRouter::<()>::new(&device, st_id as u32, false);
+
+    let find_pin = |path: &String| {
+        let (bel, pin) = path.split_once('.')
+            .ok_or_else(|| NispError::BadPinPath(path.clone()))?;
+        brouter.get_pin_id(&device, bel, pin)
+            .map_err(|_| NispError::UnknownPin { bel: bel.into(), pin: pin.into() })
+    };
+    let from = find_pin(&args.from)?;
+    let to = find_pin(&args.to)?;
+
+    writeln!(out, "{} -> {} in site type {}:", args.from, args.to, args.site_type).unwrap();
+    match brouter.query_routed_pair(from, to, true) {
+        Ok(Some(ppri)) => {
+            let gsctx = GlobalStringsCtx::hold();
+            for line in ppri.render(&brouter, &device, &gsctx).lines() {
+                writeln!(out, "  {}", line).unwrap();
+            }
+        },
+        Ok(None) => writeln!(out, "  not routable").unwrap(),
+        Err(_) => writeln!(out, "  timed out").unwrap(),
+    }
+
+    Ok(())
+}
+
+/* Answers a single query of the `serve` subcommand. Routers are built on the first query
+ * for their site type and kept for the following ones. */
+fn serve_query<'d>(
+    device: &ic_loader::archdef::Root<'d>,
+    routers: &mut HashMap<usize, BruteRouter<()>>,
+    query: &[&str]
+)
+    -> Result<serde_json::Value, NispError>
+{
+    use crate::router::site_brute_router::ConstrainingElement;
+
+    let (site_type, from_path, to_path) = match query {
+        ["query", site_type, from, to] => (*site_type, *from, *to),
+        _ => return Err(NispError::Query(format!(
+            "Malformed query: {} (expected query <site_type> <from> <to>)",
+            query.join(" ")
+        ))),
+    };
+
+    let st_id = find_site_type(device, site_type)?;
+    let brouter = routers.entry(st_id)
+        .or_insert_with(|| BruteRouter::<()>::new(device, st_id as u32, false));
+
+    let find_pin = |path: &str| {
+        let (bel, pin) = path.split_once('.')
+            .ok_or_else(|| NispError::BadPinPath(path.into()))?;
+        brouter.get_pin_id(device, bel, pin)
+            .map_err(|_| NispError::UnknownPin { bel: bel.into(), pin: pin.into() })
+    };
+    let from = find_pin(from_path)?;
+    let to = find_pin(to_path)?;
+
+    let mut response = serde_json::json!({
+        "site_type": site_type,
+        "from": from_path,
+        "to": to_path,
+    });
+    match brouter.query_routed_pair(from, to, true) {
+        Ok(Some(ppri)) => {
+            let gsctx = GlobalStringsCtx::hold();
+            let named = |form: &[DNFCube<ConstrainingElement>]| -> Vec<Vec<_>> {
+                form.iter().map(|cube| cube.terms.iter().map(|term| {
+                    term.clone().map(|ConstrainingElement::Port(v)| {
+                        let pin = SitePinId::from(v as usize);
+                        let name = brouter.get_pin_name(device, &gsctx, pin);
+                        StringConstrainingElement::Port(name.to_string())
+                    })
+                }).collect()).collect()
+            };
+            response["routable"] = true.into();
+            response["requires"] = serde_json::to_value(named(&ppri.requires)).unwrap();
+            response["implies"] = serde_json::to_value(named(&ppri.implies)).unwrap();
+        },
+        Ok(None) => response["routable"] = false.into(),
+        Err(_) => response["timed_out"] = true.into(),
+    }
+
+    Ok(response)
+}
+
+/* Answers queries read from `input` line by line, with a line of JSON each, until the
+ * input ends, a `quit` line is read or `interrupted` gets set. Failed queries are
+ * answered with errors in the format of `--error-format json` and don't stop serving. */
+fn serve<'d>(
+    device: ic_loader::archdef::Root<'d>,
+    input: &mut dyn std::io::BufRead,
+    interrupted: &AtomicBool,
+    out: &mut dyn std::io::Write
+) -> Result<(), NispError> {
+    let mut routers = HashMap::new();
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = input.read_line(&mut line)
+            .map_err(|e| NispError::Query(format!("Couldn't read a query: {}", e)))?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        let query: Vec<_> = line.split_whitespace().collect();
+        match query[..] {
+            [] => continue,
+            ["quit"] => return Ok(()),
+            _ => (),
+        }
+
+        let response = match serve_query(&device, &mut routers, &query) {
+            Ok(response) => response.to_string(),
+            Err(e) => e.to_json(),
+        };
+        writeln!(out, "{}", response).unwrap();
+        out.flush().unwrap();
+
+        if interrupted.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+    }
+}
+
+fn device_info<'d>(
+    device: ic_loader::archdef::Root<'d>,
+    format: Option<ic_loader::FileFormat>,
+    out: &mut dyn std::io::Write
+) {
+    use crate::ic_loader::archdef::DeviceInfo;
+
+    let info = DeviceInfo::gather(&device);
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+    writeln!(out, "Device: {}", info.name).unwrap();
+    match format {
+        Some(format) => writeln!(out, "File format: {}", format).unwrap(),
+        None => writeln!(out, "File format: unknown").unwrap(),
+    }
+    writeln!(out, "Site types: {}", info.site_type_count).unwrap();
+    writeln!(out, "Tile types: {}", info.tile_type_count).unwrap();
+    writeln!(out, "Strings: {}", info.string_count).unwrap();
+    writeln!(
+        out,
+        "Constant site sources: {}",
+        yes_no(info.has_constant_site_sources)
+    ).unwrap();
 }
 
 fn main() {
     let args = Args::parse();
+    args.apply_log_level();
 
-    if let SubCommands::Preprocess(prepreocess) = &args.command {
-        assert!(prepreocess.threads != 0);
+    let interruptible = matches!(
+        args.command,
+        SubCommands::Preprocess(_) | SubCommands::Batch(_) | SubCommands::Serve(_)
+    );
+    if interruptible {
+        /* The first interrupt lets the current site type (or query) finish, the second
+         * one terminates immediately */
+        ctrlc::set_handler(|| {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+            dbg_log!(DBG_WARN, "Interrupted, finishing the current work...");
+        }).expect("Couldn't set the interrupt handler");
     }
 
-    let archdef_msg = ic_loader::open(
-        Path::new(&args.device), 
-        OpenOpts { raw: args.raw }
-    ).expect("Couldn't open device file");
-    
-    let device = archdef_msg.get_archdef_root()
-        .expect("Device file does not contain a valid root structure");
-    
-    match args.command {
-        SubCommands::Preprocess(sargs) => preprocess(sargs, device),
+    let error_format = args.error_format;
+    if let Err(e) = run(args) {
+        eprintln!("{}", e.report(error_format));
+        std::process::exit(e.exit_code());
+    }
+}
+
+/* Runs a subcommand on an opened device. With `quiet`, the output of `preprocess` is
+ * discarded. Other device files get opened as raw files if `raw` is set. Long-running
+ * subcommands stop early once `interrupted` is set. */
+fn run_subcommand<'d>(
+    command: SubCommands,
+    device: ic_loader::archdef::Root<'d>,
+    device_hash: Option<&str>,
+    format: Option<ic_loader::FileFormat>,
+    raw: bool,
+    quiet: bool,
+    interrupted: &AtomicBool,
+    out: &mut dyn std::io::Write
+) -> Result<(), NispError> {
+    let device_hash = device_hash.map(String::from);
+    match command {
+        SubCommands::Preprocess(sargs) => {
+            let result = if quiet {
+                preprocess(sargs, device, device_hash, interrupted, &mut std::io::sink())
+            } else {
+                preprocess(sargs, device, device_hash, interrupted, out)
+            };
+            result
+        },
         SubCommands::RoutePair(sargs) => route_pair(sargs, device),
+        SubCommands::ListOutOfSitePorts(sargs) => list_out_of_site_ports(sargs, device, out),
+        SubCommands::RouteBel(sargs) => route_bel(sargs, device, out),
+        SubCommands::ShowPair(sargs) => show_pair(sargs, device, out),
+        SubCommands::DeviceInfo(_) => {
+            device_info(device, format, out);
+            Ok(())
+        },
+        SubCommands::Family(sargs) => family(sargs, device, raw),
+        SubCommands::DiffDevices(sargs) => diff_devices(sargs, device, raw, out),
+        SubCommands::Batch(sargs) => batch(
+            sargs,
+            device,
+            device_hash.as_deref(),
+            format,
+            raw,
+            quiet,
+            interrupted,
+            out
+        ),
+        SubCommands::Serve(_) =>
+            serve(device, &mut std::io::stdin().lock(), interrupted, out),
+        SubCommands::MergeSlices(sargs) => merge_slices(sargs, device),
     }
 }
+
+/// Runs subcommands listed in a script on a single opened device, so that the device
+/// gets parsed only once. Each line holds a subcommand with its arguments, separated
+/// with whitespace. Stops at the first failing subcommand. Once `interrupted` is set,
+/// the running subcommand finishes (writing partial results) and the rest is skipped.
+fn batch<'d>(
+    args: BatchCmd,
+    device: ic_loader::archdef::Root<'d>,
+    device_hash: Option<&str>,
+    format: Option<ic_loader::FileFormat>,
+    raw: bool,
+    quiet: bool,
+    interrupted: &AtomicBool,
+    out: &mut dyn std::io::Write
+) -> Result<(), NispError> {
+    for (line, command) in batch_script(&args)? {
+        dbg_log!(DBG_INFO, "Batch: {}", line);
+        run_subcommand(
+            command,
+            device.reborrow(),
+            device_hash,
+            format,
+            raw,
+            quiet,
+            interrupted,
+            out
+        )?;
+        if interrupted.load(Ordering::SeqCst) {
+            dbg_log!(DBG_WARN, "Batch interrupted, skipping the remaining lines");
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/* Reads a batch script, parsing all of its lines before any of them gets run */
+fn batch_script(args: &BatchCmd) -> Result<Vec<(String, SubCommands)>, NispError> {
+    let script = std::fs::read_to_string(&args.script).map_err(|e| NispError::Batch(
+        format!("Couldn't read batch script {}: {}", args.script, e)
+    ))?;
+
+    let mut commands = Vec::new();
+    for (line_idx, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let command = SubCommands::try_parse_from(
+            std::iter::once("batch").chain(line.split_whitespace())
+        ).map_err(|e| NispError::Batch(format!("Line {}: {}", line_idx + 1, e)))?;
+        if let SubCommands::Batch(_) = command {
+            return Err(NispError::Batch(
+                format!("Line {}: batch scripts can't be nested", line_idx + 1)
+            ));
+        }
+        commands.push((line.to_string(), command));
+    }
+
+    Ok(commands)
+}
+
+impl SubCommands {
+    /* Hashing the whole device file takes a while, so it's done only for subcommands
+     * which record the hash in their outputs. Unreadable batch scripts fail later on. */
+    fn records_device_hash(&self) -> bool {
+        match self {
+            SubCommands::Preprocess(args) => args.records_device_hash(),
+            SubCommands::Batch(args) => batch_script(args)
+                .map_or(false, |commands| {
+                    commands.iter().any(|(_, command)| command.records_device_hash())
+                }),
+            _ => false,
+        }
+    }
+}
+
+/* Opens a device file, returning the message along with SHA-256 hash of the file if
+ * `hash` is set */
+fn open_device(path: &str, raw: bool, hash: bool)
+    -> Result<(Box<dyn ic_loader::MsgReader>, Option<String>), NispError>
+{
+    let opened = match hash {
+        true => ic_loader::open_with_hash(Path::new(path), OpenOpts { raw })
+            .map(|(msg, hash)| (msg, Some(hash))),
+        false => ic_loader::open(Path::new(path), OpenOpts { raw }).map(|msg| (msg, None)),
+    };
+    opened.map_err(|e| NispError::Device(format!("Couldn't open device file: {:?}", e)))
+}
+
+/* Gets the root of a device message, checking that the device is well-formed */
+fn device_root<'m>(msg: &'m dyn ic_loader::MsgReader)
+    -> Result<ic_loader::archdef::Root<'m>, NispError>
+{
+    let device = msg.get_archdef_root()
+        .map_err(|e| NispError::Device(
+            format!("Device file does not contain a valid root structure: {}", e)
+        ))?;
+    ic_loader::archdef::validate_root(&device)
+        .map_err(|e| NispError::Device(format!("Device file is malformed: {:?}", e)))?;
+    Ok(device)
+}
+
+fn run(mut args: Args) -> Result<(), NispError> {
+    if let SubCommands::Preprocess(sargs) = &mut args.command {
+        sargs.bba = Some(args.bba.clone());
+    }
+    let hash = args.command.records_device_hash();
+    let (archdef_msg, device_hash) = open_device(&args.device, args.raw, hash)?;
+    let device = device_root(archdef_msg.as_ref())?;
+
+    #[cfg(feature = "profiling")]
+    let profiler = args.profile.as_deref()
+        .map(|path| crate::profiling::Profiler::start(Path::new(path)))
+        .transpose()
+        .map_err(NispError::Profile)?;
+    
+    let format = ic_loader::detect_format(&args.device).ok();
+    let result = run_subcommand(
+        args.command,
+        device,
+        device_hash.as_deref(),
+        format,
+        args.raw,
+        args.quiet,
+        &INTERRUPTED,
+        &mut std::io::stdout()
+    );
+
+    #[cfg(feature = "profiling")]
+    if let Some(profiler) = profiler {
+        profiler.finish().map_err(NispError::Profile)?;
+    }
+
+    result
+}
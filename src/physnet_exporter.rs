@@ -0,0 +1,168 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Writing routes found within a site as FPGA-Interchange physical netlists.
+//!
+//! Every route becomes a separate physical net with a single chain of route branches,
+//! so the result can be inspected with the tools that already understand the
+//! interchange format. Only site-local resources are emitted: site pins, BEL pins and
+//! site PIPs.
+
+use std::collections::HashMap;
+
+use crate::ic_loader::archdef::Root as Device;
+use crate::ic_loader::PhysicalNetlist_capnp::phys_netlist;
+use crate::router::SitePinId;
+use crate::router::BELCategory;
+use crate::router::site_brute_router::{BruteRouter, RoutingGraphEdge};
+use crate::strings::GlobalStringsCtx;
+
+#[cfg(test)]
+mod tests;
+
+/// A single step of a route within a site.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RouteSegment {
+    /// Pin of the site, represented in the routing graph by a site port BEL
+    SitePin { pin: String },
+    BelPin { bel: String, pin: String },
+    /// Pseudo-pip of a routing BEL, identified by its input pin
+    SitePip { bel: String, pin: String },
+}
+
+/// Translates a path through the routing graph into route segments. A site PIP segment
+/// is inserted before the output pin of a routing BEL whenever the path crosses its
+/// pseudo-pip.
+pub fn route_segments<A>(
+    device: &Device,
+    router: &BruteRouter<A>,
+    route: &[SitePinId]
+)
+    -> Vec<RouteSegment>
+where
+    A: Default + Clone + std::fmt::Debug + 'static
+{
+    let gsctx = GlobalStringsCtx::hold();
+    let graph = router.get_graph();
+    let bels = router.get_bels();
+
+    let mut segments = Vec::with_capacity(route.len());
+    /* Names are copied out right away, so that no name guards are held while looking up
+     * the next ones */
+    let names = |pin_id| {
+        let name = router.get_pin_name(device, &gsctx, pin_id);
+        (name.bel().to_string(), name.pin().to_string())
+    };
+    let mut prev: Option<(SitePinId, (String, String))> = None;
+    for &pin_id in route {
+        let (bel, pin) = names(pin_id);
+
+        if let Some((prev_id, (prev_bel, prev_pin))) = prev.take() {
+            if *graph.get_edge(prev_id.index(), pin_id.index()) == RoutingGraphEdge::PseudoPip {
+                segments.push(RouteSegment::SitePip { bel: prev_bel, pin: prev_pin });
+            }
+        }
+
+        let (bel_idx, _) = router.pin_to_bel_pin(pin_id);
+        segments.push(match bels[bel_idx].category {
+            BELCategory::SitePort => RouteSegment::SitePin { pin: pin.clone() },
+            BELCategory::LogicOrRouting =>
+                RouteSegment::BelPin { bel: bel.clone(), pin: pin.clone() },
+        });
+        prev = Some((pin_id, (bel, pin)));
+    }
+
+    segments
+}
+
+#[derive(Default)]
+struct StrList {
+    strings: Vec<String>,
+    ids: HashMap<String, u32>,
+}
+
+impl StrList {
+    fn id(&mut self, s: &str) -> u32 {
+        if let Some(id) = self.ids.get(s) {
+            return *id;
+        }
+        let id = self.strings.len() as u32;
+        self.strings.push(s.to_string());
+        self.ids.insert(s.to_string(), id);
+        id
+    }
+}
+
+/// Serializes routes as a physical netlist. Route `i` is emitted as a net named
+/// `route_<i>`.
+///
+/// # Arguments
+/// * `site` - name of the site instance to place the routes in
+/// * `routes` - paths through the routing graph, as returned by [`route_segments`]
+pub fn export_routes_physnet(site: &str, routes: &[Vec<RouteSegment>])
+    -> capnp::Result<Vec<u8>>
+{
+    let mut message = capnp::message::Builder::new_default();
+    let mut netlist = message.init_root::<phys_netlist::Builder>();
+    let mut str_list = StrList::default();
+    let site_id = str_list.id(site);
+
+    let mut nets = netlist.reborrow().init_phys_nets(routes.len() as u32);
+    for (route_idx, route) in routes.iter().enumerate() {
+        let mut net = nets.reborrow().get(route_idx as u32);
+        net.set_name(str_list.id(&format!("route_{}", route_idx)));
+
+        let sources = net.init_sources(if route.is_empty() { 0 } else { 1 });
+        if route.is_empty() {
+            continue;
+        }
+
+        let mut branch = sources.get(0);
+        for (seg_idx, segment) in route.iter().enumerate() {
+            let rs = branch.reborrow().init_route_segment();
+            match segment {
+                RouteSegment::SitePin { pin } => {
+                    let mut site_pin = rs.init_site_pin();
+                    site_pin.set_site(site_id);
+                    site_pin.set_pin(str_list.id(pin));
+                },
+                RouteSegment::BelPin { bel, pin } => {
+                    let mut bel_pin = rs.init_bel_pin();
+                    bel_pin.set_site(site_id);
+                    bel_pin.set_bel(str_list.id(bel));
+                    bel_pin.set_pin(str_list.id(pin));
+                },
+                RouteSegment::SitePip { bel, pin } => {
+                    let mut site_pip = rs.init_site_p_i_p();
+                    site_pip.set_site(site_id);
+                    site_pip.set_bel(str_list.id(bel));
+                    site_pip.set_pin(str_list.id(pin));
+                },
+            }
+            if seg_idx + 1 < route.len() {
+                branch = branch.init_branches(1).get(0);
+            }
+        }
+    }
+
+    let mut strings = netlist.init_str_list(str_list.strings.len() as u32);
+    for (idx, s) in str_list.strings.iter().enumerate() {
+        strings.set(idx as u32, s.as_str());
+    }
+
+    let mut data = Vec::new();
+    capnp::serialize::write_message(&mut data, &message)?;
+    Ok(data)
+}
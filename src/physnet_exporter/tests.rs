@@ -0,0 +1,92 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+use super::*;
+use crate::ic_loader::PhysicalNetlist_capnp::phys_netlist::route_branch;
+use crate::test_utils::*;
+
+/* Follows the chain of branches of the first source of a net */
+fn read_route(strings: &[&str], mut branch: route_branch::Reader) -> Vec<RouteSegment> {
+    use route_branch::route_segment::Which;
+
+    let mut segments = Vec::new();
+    loop {
+        segments.push(match branch.get_route_segment().which().unwrap() {
+            Which::SitePin(sp) => RouteSegment::SitePin {
+                pin: strings[sp.unwrap().get_pin() as usize].to_string(),
+            },
+            Which::BelPin(bp) => {
+                let bp = bp.unwrap();
+                RouteSegment::BelPin {
+                    bel: strings[bp.get_bel() as usize].to_string(),
+                    pin: strings[bp.get_pin() as usize].to_string(),
+                }
+            },
+            Which::SitePIP(sp) => {
+                let sp = sp.unwrap();
+                RouteSegment::SitePip {
+                    bel: strings[sp.get_bel() as usize].to_string(),
+                    pin: strings[sp.get_pin() as usize].to_string(),
+                }
+            },
+            Which::Pip(_) => panic!("Unexpected inter-site PIP"),
+        });
+        let branches = branch.get_branches().unwrap();
+        if branches.len() == 0 {
+            return segments;
+        }
+        assert_eq!(branches.len(), 1);
+        branch = branches.get(0);
+    }
+}
+
+#[test]
+fn test_physnet_route_roundtrip() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let pin = |bel, pin| router.get_pin_id(&device, bel, pin).unwrap();
+
+    let route = [pin("A", "A"), pin("MUX", "I0"), pin("MUX", "O"), pin("FF", "D")];
+    let segments = route_segments(&device, &router, &route);
+    assert_eq!(segments, vec![
+        RouteSegment::SitePin { pin: "A".into() },
+        RouteSegment::BelPin { bel: "MUX".into(), pin: "I0".into() },
+        RouteSegment::SitePip { bel: "MUX".into(), pin: "I0".into() },
+        RouteSegment::BelPin { bel: "MUX".into(), pin: "O".into() },
+        RouteSegment::BelPin { bel: "FF".into(), pin: "D".into() },
+    ]);
+
+    let data = export_routes_physnet("MUX_SITE_X0Y0", &[segments.clone()]).unwrap();
+    let reader = capnp::serialize::read_message(
+        data.as_slice(),
+        capnp::message::ReaderOptions::new()
+    ).unwrap();
+    let netlist = reader.get_root::<phys_netlist::Reader>().unwrap();
+    let strings: Vec<&str> = netlist.get_str_list().unwrap()
+        .iter()
+        .map(|s| s.unwrap())
+        .collect();
+
+    let nets = netlist.get_phys_nets().unwrap();
+    assert_eq!(nets.len(), 1);
+    let net = nets.get(0);
+    assert_eq!(strings[net.get_name() as usize], "route_0");
+
+    let sources = net.get_sources().unwrap();
+    assert_eq!(sources.len(), 1);
+    assert_eq!(read_route(&strings, sources.get(0)), segments);
+}
@@ -0,0 +1,68 @@
+/* Copyright (C) 2022 Antmicro
+ * 
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * 
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ * 
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Profiling of major processing phases.
+//!
+//! With the `profiling` feature enabled, phases marked with `profile_span!` are recorded
+//! as `tracing` spans and written by `Profiler` as folded stacks, which can be turned into
+//! a flamegraph with `inferno-flamegraph`. Without the feature, `profile_span!` expands to
+//! nothing.
+
+#[cfg(feature = "profiling")]
+use std::fs::File;
+#[cfg(feature = "profiling")]
+use std::io::BufWriter;
+#[cfg(feature = "profiling")]
+use std::path::Path;
+
+/// Marks the rest of the enclosing block as a profiled phase.
+#[cfg(feature = "profiling")]
+macro_rules! profile_span {
+    ($name:literal $(, $field:ident = $v:expr )*) => {
+        let _profile_span = tracing::info_span!($name $(, $field = $v )*).entered();
+    };
+}
+
+#[cfg(not(feature = "profiling"))]
+macro_rules! profile_span {
+    ($name:literal $(, $field:ident = $v:expr )*) => {};
+}
+
+/// Records profiled phases of all threads into a folded stack file, until finished.
+#[cfg(feature = "profiling")]
+pub struct Profiler {
+    guard: tracing_flame::FlameGuard<BufWriter<File>>,
+}
+
+#[cfg(feature = "profiling")]
+impl Profiler {
+    /// Starts recording into a file at `path`. Profiling can be started only once per
+    /// process.
+    pub fn start(path: &Path) -> Result<Self, String> {
+        use tracing_subscriber::prelude::*;
+
+        let (layer, guard) = tracing_flame::FlameLayer::with_file(path)
+            .map_err(|e| format!("Couldn't create profile {}: {}", path.display(), e))?;
+        tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+            .map_err(|e| format!("Couldn't start profiling: {}", e))?;
+
+        Ok(Self { guard })
+    }
+
+    /// Writes out the recorded spans. Spans closed after this point are not recorded.
+    pub fn finish(self) -> Result<(), String> {
+        self.guard.flush().map_err(|e| format!("Couldn't write profile: {}", e))
+    }
+}
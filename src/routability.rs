@@ -0,0 +1,137 @@
+/* Copyright (C) 2022 Antmicro
+ * 
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * 
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ * 
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! High-level entry point for querying site routability.
+//!
+//! `SiteRoutability` owns the device file and creates routers of its site types, so that
+//! library users don't need to deal with loading the device and setting up routers:
+//!
+//! ```ignore
+//! let routability = SiteRoutability::open("xc7a35t.device", OpenOpts::default())?;
+//! let route = routability.query("SLICEL", "A6LUT.O6", "AFF.D")?;
+//! ```
+//!
+//! Pins are identified by names in `BEL.PIN` format.
+
+use std::path::Path;
+
+use crate::common::IcStr;
+use crate::ic_loader::{self, MsgReader, OpenOpts, OpenWriteError};
+use crate::ic_loader::archdef::Root as Device;
+use crate::router::SitePinId;
+use crate::router::site_brute_router::{BruteRouter, PinPairRoutingInfo, RoutingInfo};
+
+#[cfg(test)]
+mod tests;
+
+/// Device loaded for routability queries.
+pub struct SiteRoutability {
+    msg: Box<dyn MsgReader>,
+}
+
+impl SiteRoutability {
+    /// Opens and validates a device file (see `ic_loader::open`).
+    pub fn open<P>(path: P, opts: OpenOpts) -> Result<Self, OpenWriteError> where
+        P: AsRef<Path>
+    {
+        Self::from_reader(ic_loader::open(path, opts)?)
+    }
+
+    /// Wraps an already loaded device message, validating it first.
+    pub fn from_reader(msg: Box<dyn MsgReader>) -> Result<Self, OpenWriteError> {
+        let device = msg.get_archdef_root()
+            .map_err(|e| OpenWriteError::CapnProtoError(format!("{:?}", e)))?;
+        ic_loader::archdef::validate_root(&device)?;
+        Ok(Self { msg })
+    }
+
+    /// Gets the root of the device.
+    pub fn device<'s>(&'s self) -> Device<'s> {
+        /* Validated when loaded */
+        self.msg.get_archdef_root().unwrap()
+    }
+
+    /// Lists names of all site types of the device, in order of their ids.
+    pub fn list_site_types(&self) -> Vec<String> {
+        let device = self.device();
+        device.get_site_type_list().unwrap().iter()
+            .map(|st| device.ic_str(st.get_name()).to_string())
+            .collect()
+    }
+
+    /// Creates a router of a site type, or returns `None` if there's no site type with
+    /// the given name.
+    pub fn site_type<'s>(&'s self, name: &str) -> Option<SiteRouter<'s>> {
+        let device = self.device();
+        let st_id = device.get_site_type_list().unwrap().iter()
+            .position(|st| device.try_ic_str(st.get_name()) == Some(name))?;
+        let router = BruteRouter::new(&device, st_id as u32, false);
+        Some(SiteRouter { device, router })
+    }
+
+    /// Gets routing information of a single pin pair. Creates a new router on each call,
+    /// so `site_type` should be preferred for multiple queries on the same site type.
+    /// 
+    /// # Arguments
+    /// * `site` - name of the site type
+    /// * `from` - source pin, in `BEL.PIN` format
+    /// * `to` - sink pin, in `BEL.PIN` format
+    /// 
+    /// # Return
+    /// Routing information, or `None` if `to` can't be reached from `from`
+    pub fn query(&self, site: &str, from: &str, to: &str)
+        -> Result<Option<PinPairRoutingInfo>, String>
+    {
+        self.site_type(site)
+            .ok_or_else(|| format!("No site type named {}", site))?
+            .query(from, to)
+    }
+}
+
+/// Router of a single site type of a `SiteRoutability` device.
+pub struct SiteRouter<'s> {
+    device: Device<'s>,
+    router: BruteRouter<()>,
+}
+
+impl<'s> SiteRouter<'s> {
+    /// Gets the underlying router, for features not covered by `SiteRouter`.
+    pub fn router(&self) -> &BruteRouter<()> {
+        &self.router
+    }
+
+    /// Finds a pin by its name in `BEL.PIN` format.
+    pub fn pin(&self, name: &str) -> Result<SitePinId, String> {
+        let (bel_name, pin_name) = name.split_once('.')
+            .ok_or_else(|| format!("Pin name {} is not in BEL.PIN format", name))?;
+        self.router.get_pin_id(&self.device, bel_name, pin_name)
+    }
+
+    /// Gets routing information of a pin pair. Results are cached per source pin (see
+    /// `BruteRouter::query_routed_pair`).
+    /// 
+    /// # Return
+    /// Routing information, or `None` if `to` can't be reached from `from`
+    pub fn query(&self, from: &str, to: &str) -> Result<Option<PinPairRoutingInfo>, String> {
+        let (from_id, to_id) = (self.pin(from)?, self.pin(to)?);
+        self.router.query_routed_pair(from_id, to_id, true)
+            .map_err(|_| format!("Routing from {} timed out", from))
+    }
+
+    /// Routes all pins of the site type (see `BruteRouter::route_all`).
+    pub fn route_all(&self) -> RoutingInfo {
+        self.router.route_all(true)
+    }
+}
@@ -0,0 +1,60 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+use super::*;
+use crate::test_utils::*;
+
+fn load(fixture: DeviceFixture) -> SiteRoutability {
+    let mut data = Vec::new();
+    capnp::serialize::write_message(&mut data, &fixture.build()).unwrap();
+    let reader = capnp::serialize::read_message(
+        data.as_slice(),
+        capnp::message::ReaderOptions::new()
+    ).unwrap();
+    SiteRoutability::from_reader(Box::new(reader)).unwrap()
+}
+
+#[test]
+fn test_facade_queries() {
+    let routability = load(mux_site().site_type(SiteTypeFixture::new("EMPTY")));
+
+    assert_eq!(routability.list_site_types(), vec!["MUX_SITE", "EMPTY"]);
+    assert!(routability.site_type("NO_SUCH_SITE").is_none());
+    assert!(routability.query("NO_SUCH_SITE", "A.A", "FF.D").is_err());
+
+    let a_to_d = routability.query("MUX_SITE", "A.A", "FF.D").unwrap().unwrap();
+    assert!(!a_to_d.requires.is_empty());
+    assert!(routability.query("MUX_SITE", "FF.D", "A.A").unwrap().is_none());
+    assert!(routability.query("MUX_SITE", "A.A", "A.A").unwrap().is_none());
+    assert!(routability.query("MUX_SITE", "A", "FF.D").is_err());
+    assert!(routability.query("MUX_SITE", "A.A", "FF.X").is_err());
+
+    /* Queries agree with routing the whole site */
+    let site = routability.site_type("MUX_SITE").unwrap();
+    let info = site.route_all();
+    let (a, d) = (site.pin("A.A").unwrap(), site.pin("FF.D").unwrap());
+    assert_eq!(info.pin_to_pin_routing[&(a, d)].requires, a_to_d.requires);
+    for from in ["A.A", "B.B", "MUX.O", "FF.Q"] {
+        for to in ["MUX.I0", "MUX.I1", "FF.D", "Q.Q"] {
+            let pair = (site.pin(from).unwrap(), site.pin(to).unwrap());
+            assert_eq!(
+                site.query(from, to).unwrap().is_some(),
+                info.pin_to_pin_routing.contains_key(&pair),
+                "{} -> {}", from, to
+            );
+        }
+    }
+}
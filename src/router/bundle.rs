@@ -0,0 +1,487 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Self-contained archives of site routing data.
+//!
+//! A bundle holds everything needed to inspect routing of a single site type without
+//! the original device file: the routing graph, BELs of the site type and the computed
+//! routing information. Bundles are gzipped tar archives with the following entries:
+//! * `manifest.json` - see `BundleManifest`
+//! * `bels.json` - BELs of the site type, with names resolved to strings
+//! * `graph.json` - the routing graph, along with BEL pins of its nodes
+//! * `routing.json` - routing information
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Serialize, Deserialize};
+
+use crate::ic_loader::archdef::Root as Device;
+use crate::common::IcStr;
+use crate::logic_formula::{DNFCube, AssumeSortedCube};
+use crate::strings::*;
+use super::*;
+use super::site_brute_router::{
+    BruteRouter,
+    RoutingGraph,
+    RoutingInfo,
+    PinPairRoutingInfo,
+    UnoptimizedForms,
+    ConstrainingElement,
+};
+
+/// Version of the bundle format. Bumped on incompatible changes.
+pub const BUNDLE_FORMAT_VERSION: u32 = 1;
+
+const MANIFEST_ENTRY: &str = "manifest.json";
+const BELS_ENTRY: &str = "bels.json";
+const GRAPH_ENTRY: &str = "graph.json";
+const ROUTING_ENTRY: &str = "routing.json";
+
+/// Describes the contents of a bundle.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BundleManifest {
+    pub format_version: u32,
+    /// Name of the device the site type comes from
+    pub device: String,
+    /// Name of the site type
+    pub site_type: String,
+    /// Index of the site type within the device
+    pub site_type_id: u32,
+    /// Number of nodes in the routing graph
+    pub pin_count: usize,
+    /// SHA-256 hash of the device file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_hash: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BundledBELPin {
+    name: String,
+    dir: PinDir,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BundledBEL {
+    name: String,
+    category: BELCategory,
+    pins: Vec<BundledBELPin>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BundledGraph {
+    site_belpin_idx_to_bel_pin: Vec<(usize, usize)>,
+    graph: RoutingGraph,
+}
+
+/* JSON supports only string keys, so maps are stored as lists of entries */
+#[derive(Serialize, Deserialize)]
+struct BundledRoutingInfo<P = PinPairRoutingInfo> {
+    pin_to_pin_routing: Vec<(SitePinId, SitePinId, P)>,
+    out_of_site_sources: Vec<(SitePinId, Vec<SitePinId>)>,
+    out_of_site_sinks: Vec<(SitePinId, Vec<SitePinId>)>,
+    #[serde(default)]
+    timed_out: Vec<SitePinId>,
+    #[serde(default)]
+    aliases: Vec<(SitePinId, SitePinId)>,
+    #[serde(default)]
+    edge_usage: Vec<(SitePinId, SitePinId, usize)>,
+    #[serde(default)]
+    pin_range: Option<std::ops::Range<usize>>,
+}
+
+impl BundledRoutingInfo<AssumeSortedPinPairRoutingInfo> {
+    fn into_routing_info(self) -> BundledRoutingInfo {
+        BundledRoutingInfo {
+            pin_to_pin_routing: self.pin_to_pin_routing.into_iter()
+                .map(|(from, to, ppri)| (from, to, ppri.into()))
+                .collect(),
+            out_of_site_sources: self.out_of_site_sources,
+            out_of_site_sinks: self.out_of_site_sinks,
+            timed_out: self.timed_out,
+            aliases: self.aliases,
+            edge_usage: self.edge_usage,
+            pin_range: self.pin_range,
+        }
+    }
+}
+
+/* `PinPairRoutingInfo` read with `AssumeSortedCube`s, for bundles known to be written
+ * by NISP */
+#[derive(Deserialize)]
+struct AssumeSortedPinPairRoutingInfo {
+    requires: Vec<AssumeSortedCube<ConstrainingElement>>,
+    implies: Vec<AssumeSortedCube<ConstrainingElement>>,
+    #[serde(default)]
+    unoptimized: Option<AssumeSortedUnoptimizedForms>,
+}
+
+#[derive(Deserialize)]
+struct AssumeSortedUnoptimizedForms {
+    requires: Vec<AssumeSortedCube<ConstrainingElement>>,
+    implies: Vec<AssumeSortedCube<ConstrainingElement>>,
+}
+
+fn unwrap_cubes(cubes: Vec<AssumeSortedCube<ConstrainingElement>>)
+    -> Vec<DNFCube<ConstrainingElement>>
+{
+    cubes.into_iter().map(|AssumeSortedCube(cube)| cube).collect()
+}
+
+impl From<AssumeSortedPinPairRoutingInfo> for PinPairRoutingInfo {
+    fn from(ppri: AssumeSortedPinPairRoutingInfo) -> Self {
+        Self {
+            requires: unwrap_cubes(ppri.requires),
+            implies: unwrap_cubes(ppri.implies),
+            unoptimized: ppri.unoptimized.map(|forms| UnoptimizedForms {
+                requires: unwrap_cubes(forms.requires),
+                implies: unwrap_cubes(forms.implies),
+            }),
+        }
+    }
+}
+
+/// Site routing data loaded from a bundle.
+pub struct SiteRoutingBundle {
+    pub manifest: BundleManifest,
+    /// Router reconstructed out of the bundled parts. BEL and pin names are stored as
+    /// virtual names, so they can be accessed without the device.
+    pub router: BruteRouter<()>,
+    pub routing_info: RoutingInfo,
+    pin_names: Vec<(String, String)>,
+}
+
+impl SiteRoutingBundle {
+    /// Finds a site pin by names of its BEL and pin.
+    pub fn find_pin(&self, bel_name: &str, pin_name: &str) -> Option<SitePinId> {
+        self.pin_names.iter()
+            .position(|(bel, pin)| bel == bel_name && pin == pin_name)
+            .map(SitePinId)
+    }
+
+    /// Gets `(BEL name, pin name)` of a site pin.
+    pub fn pin_name<'s>(&'s self, pin: SitePinId) -> (&'s str, &'s str) {
+        let (bel, pin) = &self.pin_names[pin.0];
+        (bel, pin)
+    }
+}
+
+/// Routing information loaded from a bundle without reconstructing its router, so that
+/// no names get created in the global string table (see `read_bundle_routing`). Enough
+/// to compare routing with the bundled one.
+pub struct BundledRouting {
+    pub manifest: BundleManifest,
+    pub routing_info: RoutingInfo,
+    pin_names: Vec<(String, String)>,
+}
+
+impl BundledRouting {
+    /// Checks whether routing of a site type is the same as the bundled one. Pin pairs
+    /// are compared only if both routers have the same pins, in the same order.
+    /// 
+    /// # Arguments
+    /// * `device` - device `router` was created for
+    /// * `router` - router of the site type
+    /// * `routing_info` - results of routing the site type with `router`
+    pub fn routing_matches<'d, A>(
+        &self,
+        device: &Device<'d>,
+        router: &BruteRouter<A>,
+        routing_info: &RoutingInfo
+    )
+        -> bool
+    where
+        A: Default + Clone + std::fmt::Debug + 'static
+    {
+        let gsctx = GlobalStringsCtx::hold();
+        let bels = router.get_bels();
+        let site_belpin_map = router.get_site_belpin_map();
+
+        let same_pins = (site_belpin_map.len() == self.pin_names.len())
+            && site_belpin_map.iter().zip(&self.pin_names)
+                .all(|((bel_idx, pin_idx), (bel_name, pin_name))| {
+                    /* Names are compared one by one, so that only one of them holds
+                     * the global strings lock at a time */
+                    let bel = &bels[*bel_idx];
+                    let same_bel = *bel.name.get(device, &gsctx) == **bel_name;
+                    same_bel && (*bel.pins[*pin_idx].name.get(device, &gsctx) == **pin_name)
+                });
+
+        same_pins
+            && (routing_info.pin_to_pin_routing == self.routing_info.pin_to_pin_routing)
+            && (routing_info.timed_out == self.routing_info.timed_out)
+    }
+}
+
+fn invalid_data<E>(error: E) -> std::io::Error where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>
+{
+    std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+}
+
+fn append_json<W, T>(archive: &mut tar::Builder<W>, name: &str, data: &T)
+    -> std::io::Result<()>
+where
+    W: Write,
+    T: Serialize
+{
+    let data = serde_json::to_vec(data).map_err(invalid_data)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    archive.append_data(&mut header, name, data.as_slice())
+}
+
+/// Writes a bundle of a site type routed by `router`.
+///
+/// # Arguments
+/// * `writer` - destination of the archive
+/// * `device` - device the router was created for
+/// * `router` - router of the site type
+/// * `routing_info` - results of routing the site type with `router`
+/// * `device_hash` - hash of the device file, stored in the manifest
+pub fn write_bundle<'d, W, A>(
+    writer: W,
+    device: &Device<'d>,
+    router: &BruteRouter<A>,
+    routing_info: &RoutingInfo,
+    device_hash: Option<&str>
+)
+    -> std::io::Result<()>
+where
+    W: Write,
+    A: Default + Clone + std::fmt::Debug + 'static
+{
+    let st = device.get_site_type_list().unwrap().get(router.get_st_id());
+
+    let manifest = BundleManifest {
+        format_version: BUNDLE_FORMAT_VERSION,
+        device: device.get_name().unwrap().to_string(),
+        site_type: device.ic_str(st.get_name()).to_string(),
+        site_type_id: router.get_st_id(),
+        pin_count: router.get_graph().node_count(),
+        device_hash: device_hash.map(String::from),
+    };
+
+    let gsctx = GlobalStringsCtx::hold();
+    let bels: Vec<_> = router.get_bels().iter()
+        .map(|bel| BundledBEL {
+            name: bel.name.get(device, &gsctx).to_string(),
+            category: bel.category,
+            pins: bel.pins.iter()
+                .map(|pin| BundledBELPin {
+                    name: pin.name.get(device, &gsctx).to_string(),
+                    dir: pin.dir,
+                })
+                .collect(),
+        })
+        .collect();
+
+    let graph = BundledGraph {
+        site_belpin_idx_to_bel_pin: router.get_site_belpin_map().to_vec(),
+        graph: router.get_graph().clone(),
+    };
+
+    let mut pin_to_pin_routing: Vec<_> = routing_info.pin_to_pin_routing.iter()
+        .map(|((from, to), ppri)| (*from, *to, ppri.clone()))
+        .collect();
+    pin_to_pin_routing.sort_by_key(|(from, to, _)| (*from, *to));
+
+    let sorted_entries = |map: &HashMap<SitePinId, Vec<SitePinId>>| {
+        let mut entries: Vec<_> = map.iter()
+            .map(|(pin, pins)| (*pin, pins.clone()))
+            .collect();
+        entries.sort_by_key(|(pin, _)| *pin);
+        entries
+    };
+
+    let routing = BundledRoutingInfo {
+        pin_to_pin_routing,
+        out_of_site_sources: sorted_entries(&routing_info.out_of_site_sources),
+        out_of_site_sinks: sorted_entries(&routing_info.out_of_site_sinks),
+        timed_out: routing_info.timed_out.clone(),
+        aliases: {
+            let mut aliases: Vec<_> = routing_info.aliases.iter()
+                .map(|(alias, representative)| (*alias, *representative))
+                .collect();
+            aliases.sort();
+            aliases
+        },
+        edge_usage: {
+            let mut edge_usage: Vec<_> = routing_info.edge_usage.iter()
+                .map(|((from, to), count)| (*from, *to, *count))
+                .collect();
+            edge_usage.sort();
+            edge_usage
+        },
+        pin_range: routing_info.pin_range.clone(),
+    };
+
+    let mut archive = tar::Builder::new(GzEncoder::new(writer, Compression::default()));
+    append_json(&mut archive, MANIFEST_ENTRY, &manifest)?;
+    append_json(&mut archive, BELS_ENTRY, &bels)?;
+    append_json(&mut archive, GRAPH_ENTRY, &graph)?;
+    append_json(&mut archive, ROUTING_ENTRY, &routing)?;
+    archive.into_inner()?.finish()?;
+
+    Ok(())
+}
+
+/// Reads a bundle created with `write_bundle`, like `read_bundle`, but trusts the order of
+/// terms in its formulas (see `AssumeSortedCube`).
+pub fn read_bundle_assume_sorted<R>(reader: R) -> std::io::Result<SiteRoutingBundle> where
+    R: Read
+{
+    let parts = read_bundle_parts(reader, true)?;
+    Ok(assemble_bundle(parts))
+}
+
+/// Reads routing information of a bundle created with `write_bundle`, leaving the router
+/// out (see `BundledRouting`).
+pub fn read_bundle_routing<R>(reader: R) -> std::io::Result<BundledRouting> where R: Read {
+    let (routing, _, _) = read_bundle_parts(reader, false)?;
+    Ok(routing)
+}
+
+/// Reads routing information of a bundle like `read_bundle_routing`, but trusts the order
+/// of terms in its formulas (see `AssumeSortedCube`).
+pub fn read_bundle_routing_assume_sorted<R>(reader: R) -> std::io::Result<BundledRouting> where
+    R: Read
+{
+    let (routing, _, _) = read_bundle_parts(reader, true)?;
+    Ok(routing)
+}
+
+/// Reads a bundle created with `write_bundle`.
+pub fn read_bundle<R>(reader: R) -> std::io::Result<SiteRoutingBundle> where R: Read {
+    let parts = read_bundle_parts(reader, false)?;
+    Ok(assemble_bundle(parts))
+}
+
+/* Reconstructs the router out of parts read with `read_bundle_parts` */
+fn assemble_bundle(
+    (routing, bundled_bels, graph): (BundledRouting, Vec<BundledBEL>, BundledGraph)
+) -> SiteRoutingBundle {
+    let BundledRouting { manifest, routing_info, pin_names } = routing;
+
+    /* Names are created in a single batch, in order of the BELs and their pins */
+    let mut gsctx = GlobalStringsCtx::hold();
+    let names = bundled_bels.iter()
+        .flat_map(|bel| std::iter::once(&bel.name).chain(bel.pins.iter().map(|pin| &pin.name)))
+        .cloned();
+    let mut name_ids = gsctx.create_global_strings(names).into_iter();
+    let bels = bundled_bels.into_iter()
+        .map(|bel| BELInfo {
+            name: ResourceName::Virtual(name_ids.next().unwrap()),
+            category: bel.category,
+            pins: bel.pins.into_iter()
+                .map(|pin| BELPin {
+                    name: ResourceName::Virtual(name_ids.next().unwrap()),
+                    dir: pin.dir,
+                })
+                .collect(),
+        })
+        .collect();
+
+    let router = BruteRouter::from_parts(
+        manifest.site_type_id,
+        bels,
+        graph.site_belpin_idx_to_bel_pin,
+        graph.graph
+    );
+
+    SiteRoutingBundle { manifest, router, routing_info, pin_names }
+}
+
+/* Reads entries of a bundle, resolving pin names to plain strings. With `assume_sorted`
+ * set, formulas are read as `AssumeSortedCube`s. */
+fn read_bundle_parts<R>(reader: R, assume_sorted: bool)
+    -> std::io::Result<(BundledRouting, Vec<BundledBEL>, BundledGraph)>
+where
+    R: Read
+{
+    let mut entries = HashMap::new();
+
+    let mut archive = tar::Archive::new(GzDecoder::new(reader));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        entries.insert(name, data);
+    }
+
+    let mut take_entry = |name: &str| {
+        let data = entries.remove(name)
+            .ok_or_else(|| invalid_data(format!("Bundle has no {} entry", name)))?;
+        Ok::<_, std::io::Error>(data)
+    };
+
+    let manifest: BundleManifest =
+        serde_json::from_slice(&take_entry(MANIFEST_ENTRY)?).map_err(invalid_data)?;
+    if manifest.format_version != BUNDLE_FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "Unsupported bundle format version {}",
+            manifest.format_version
+        )));
+    }
+
+    let bundled_bels: Vec<BundledBEL> =
+        serde_json::from_slice(&take_entry(BELS_ENTRY)?).map_err(invalid_data)?;
+    let graph: BundledGraph =
+        serde_json::from_slice(&take_entry(GRAPH_ENTRY)?).map_err(invalid_data)?;
+    let routing_data = take_entry(ROUTING_ENTRY)?;
+    let routing: BundledRoutingInfo = if assume_sorted {
+        serde_json::from_slice::<BundledRoutingInfo<AssumeSortedPinPairRoutingInfo>>(
+            &routing_data
+        ).map_err(invalid_data)?.into_routing_info()
+    } else {
+        serde_json::from_slice(&routing_data).map_err(invalid_data)?
+    };
+
+    if graph.graph.node_count() != manifest.pin_count {
+        return Err(invalid_data("Routing graph doesn't match the manifest"));
+    }
+
+    let pin_names = graph.site_belpin_idx_to_bel_pin.iter()
+        .map(|(bel_idx, pin_idx)| {
+            let bel = bundled_bels.get(*bel_idx)
+                .ok_or_else(|| invalid_data("Graph node refers to a non-existent BEL"))?;
+            let pin = bel.pins.get(*pin_idx)
+                .ok_or_else(|| invalid_data("Graph node refers to a non-existent pin"))?;
+            Ok((bel.name.clone(), pin.name.clone()))
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    let routing_info = RoutingInfo {
+        pin_to_pin_routing: routing.pin_to_pin_routing.into_iter()
+            .map(|(from, to, ppri)| ((from, to), ppri))
+            .collect(),
+        out_of_site_sources: routing.out_of_site_sources.into_iter().collect(),
+        out_of_site_sinks: routing.out_of_site_sinks.into_iter().collect(),
+        timed_out: routing.timed_out,
+        aliases: routing.aliases.into_iter().collect(),
+        edge_usage: routing.edge_usage.into_iter()
+            .map(|(from, to, count)| ((from, to), count))
+            .collect(),
+        cancelled: false,
+        pin_range: routing.pin_range,
+    };
+
+    Ok((BundledRouting { manifest, routing_info, pin_names }, bundled_bels, graph))
+}
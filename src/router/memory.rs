@@ -0,0 +1,112 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Upfront estimation of memory needed for preprocessing.
+//!
+//! Estimates are based on BEL pin counts of site types only, so nodes added by the
+//! router (virtual constant ports, split inout pins) are not accounted for. Routing
+//! results are estimated pessimistically, assuming that every pair of pins is routable.
+
+use std::mem::size_of;
+
+use crate::ic_loader::archdef::Root as Device;
+use super::SitePinId;
+use super::site_brute_router::{RoutingGraphNode, RoutingGraphEdge, PinPairRoutingInfo};
+
+/* Heap memory held by formulas of a single pin pair. Formulas are usually made of
+ * a couple of short cubes. */
+const FORMULA_BYTES_PER_PAIR: usize = 128;
+
+/// Estimated memory usage of a single site type, in bytes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SiteTypeMemoryEstimate {
+    /// Routing graph: nodes and the dense edge matrix
+    pub graph: usize,
+    /// Routing results of all pairs of pins
+    pub routing: usize,
+}
+
+/// Estimates memory usage of a single site type.
+pub fn estimate_site_type<'d>(device: &Device<'d>, st_id: u32) -> SiteTypeMemoryEstimate {
+    let st = device.get_site_type_list().unwrap().get(st_id);
+    let pins = st.get_bel_pins().unwrap().len() as usize;
+    let pairs = pins * pins;
+
+    /* Hash maps keep a control byte per bucket and at most 7/8 of buckets occupied */
+    let pair_bytes = size_of::<((SitePinId, SitePinId), PinPairRoutingInfo)>() + 1;
+    let pair_bytes = pair_bytes * 8 / 7 + FORMULA_BYTES_PER_PAIR;
+
+    SiteTypeMemoryEstimate {
+        graph: pins * size_of::<RoutingGraphNode>() + pairs * size_of::<RoutingGraphEdge>(),
+        routing: pairs * pair_bytes,
+    }
+}
+
+/// Estimated memory usage of preprocessing a set of site types.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryEstimate {
+    pub site_types: Vec<(u32, SiteTypeMemoryEstimate)>,
+}
+
+impl MemoryEstimate {
+    pub fn new<'d, I>(device: &Device<'d>, st_ids: I) -> Self where
+        I: IntoIterator<Item = u32>
+    {
+        Self {
+            site_types: st_ids.into_iter()
+                .map(|st_id| (st_id, estimate_site_type(device, st_id)))
+                .collect(),
+        }
+    }
+
+    /// Estimates peak memory usage, in bytes.
+    /// 
+    /// # Arguments
+    /// * `streaming` - results of a site type are dropped once it's processed. Otherwise
+    ///   results of all site types are kept until the end.
+    /// * `threads` - number of routing threads. Results gathered by threads are merged,
+    ///   so multithreaded routing holds results of a site type twice for a moment.
+    pub fn peak(&self, streaming: bool, threads: usize) -> usize {
+        self.peak_parallel(streaming, threads, 1)
+    }
+
+    /// Same as `peak`, but with `site_parallelism` site types being routed at the same
+    /// time. The largest site types are assumed to end up being routed together.
+    pub fn peak_parallel(&self, streaming: bool, threads: usize, site_parallelism: usize)
+        -> usize
+    {
+        let merge_copy = |estimate: &SiteTypeMemoryEstimate| {
+            if threads > 1 { estimate.routing } else { 0 }
+        };
+        let largest = |mut in_flight: Vec<usize>| -> usize {
+            in_flight.sort_unstable_by(|a, b| b.cmp(a));
+            in_flight.into_iter().take(site_parallelism).sum()
+        };
+
+        if streaming {
+            largest(self.site_types.iter()
+                .map(|(_, estimate)| estimate.graph + estimate.routing + merge_copy(estimate))
+                .collect())
+        } else {
+            let all_routing: usize = self.site_types.iter()
+                .map(|(_, estimate)| estimate.routing)
+                .sum();
+            let current = largest(self.site_types.iter()
+                .map(|(_, estimate)| estimate.graph + merge_copy(estimate))
+                .collect());
+            all_routing + current
+        }
+    }
+}
@@ -25,11 +25,19 @@ use crate::strings::*;
 use crate::ic_loader::{DeviceResources_capnp, LogicalNetlist_capnp};
 
 pub mod site_brute_router;
+#[cfg(feature = "serialize")]
 pub mod serialize;
+pub mod tile_routing;
+pub mod memory;
+#[cfg(feature = "serialize")]
+pub mod bundle;
+#[cfg(test)]
+mod tests;
 
 /* XXX: crate::ic_loader::LogicalNetlist_capnp::netlist::Direction doe not implement Hash */
 /// Represents a direction of a pin.
-#[derive(Copy, Clone, Hash, PartialEq, Eq)]
+#[derive(Copy, Clone, Hash, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum PinDir {
     Inout,
     Input,
@@ -47,13 +55,57 @@ impl From<LogicalNetlist_capnp::netlist::Direction> for PinDir {
     }
 }
 
+/// Name which doesn't match any variant of an enum parsed from a string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownVariant(pub String);
+
+impl std::fmt::Display for UnknownVariant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown variant \"{}\"", self.0)
+    }
+}
+
+impl std::error::Error for UnknownVariant {}
+
+impl std::str::FromStr for PinDir {
+    type Err = UnknownVariant;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "inout" => Ok(Self::Inout),
+            "input" => Ok(Self::Input),
+            "output" => Ok(Self::Output),
+            _ => Err(UnknownVariant(s.into())),
+        }
+    }
+}
+
+impl TryFrom<&str> for PinDir {
+    type Error = UnknownVariant;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::fmt::Display for PinDir {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Inout => "inout",
+            Self::Input => "input",
+            Self::Output => "output",
+        })
+    }
+}
+
 /// Represents the role of the BEL within a site.
 /// 
 /// **NOTE**:
 /// We do not distinguish between Logic and Routing categories, because some 
 /// logic bels can also be route-throughs, and more precise routing information 
 /// can be deduced by examining site-pips (pseudo-pips).
-#[derive(Copy, Clone, Hash, PartialEq, Eq)]
+#[derive(Copy, Clone, Hash, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum BELCategory {
     LogicOrRouting,
     SitePort,
@@ -70,6 +122,77 @@ impl From<DeviceResources_capnp::device::BELCategory> for BELCategory {
     }
 }
 
+/* Both "logic" and "routing" are accepted, as they map to the same category */
+impl std::str::FromStr for BELCategory {
+    type Err = UnknownVariant;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "logic" | "routing" => Ok(Self::LogicOrRouting),
+            "siteport" => Ok(Self::SitePort),
+            _ => Err(UnknownVariant(s.into())),
+        }
+    }
+}
+
+impl TryFrom<&str> for BELCategory {
+    type Error = UnknownVariant;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::fmt::Display for BELCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::LogicOrRouting => "logic",
+            Self::SitePort => "siteport",
+        })
+    }
+}
+
+/// Finer classification of BELs than `BELCategory`, which tells logic and routing BELs
+/// apart. A BEL is considered a routing BEL if it has any site PIPs, so logic BELs with
+/// route-throughs count as routing BELs.
+#[derive(Copy, Clone, Hash, PartialEq, Eq, Debug)]
+pub enum BELKind {
+    Logic,
+    Routing,
+    SitePort,
+}
+
+impl std::str::FromStr for BELKind {
+    type Err = UnknownVariant;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "logic" => Ok(Self::Logic),
+            "routing" => Ok(Self::Routing),
+            "siteport" => Ok(Self::SitePort),
+            _ => Err(UnknownVariant(s.into())),
+        }
+    }
+}
+
+impl TryFrom<&str> for BELKind {
+    type Error = UnknownVariant;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::fmt::Display for BELKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Logic => "logic",
+            Self::Routing => "routing",
+            Self::SitePort => "siteport",
+        })
+    }
+}
+
 /// Represents a single pin of a BEL.
 #[derive(Clone, Hash, PartialEq, Eq)]
 pub struct BELPin {
@@ -253,28 +376,104 @@ where
     ))
 }
 
+/// Suffix appended to names of virtual constant site ports which collide with names of
+/// BELs present in the device
+const VCONST_COLLISION_SUFFIX: &str = "_NISP";
+
+/// Picks a name for the virtual site port of a constant network. Normally it's the name
+/// of the network. If the site type already contains a BEL with that name, the virtual
+/// port gets renamed to keep BEL names unambiguous.
+/// 
+/// # Arguments
+/// * `device` - device resources
+/// * `st` - site type the port will be added to
+/// * `net_name` - name of the constant network ("$VCC"/"$GND")
+fn virtual_const_port_name<'a>(
+    device: &Device<'a>,
+    st: &crate::ic_loader::archdef::SiteTypeReader<'a>,
+    net_name: &str
+)
+    -> String
+{
+    let collides = |name: &str| {
+        st.get_bels().unwrap().iter()
+            .any(|bel| device.try_ic_str(bel.get_name()) == Some(name))
+    };
+
+    let mut name = net_name.to_string();
+    while collides(&name) {
+        name.push_str(VCONST_COLLISION_SUFFIX);
+    }
+
+    if name != net_name {
+        dbg_log!(
+            DBG_WARN,
+            "  Site {} contains a BEL named {}, virtual port renamed to {}",
+            device.ic_str(st.get_name()),
+            net_name,
+            name
+        );
+    }
+
+    name
+}
+
+/// Creates a lookup from `(bel name, pin name)` to the site wires connected to the BEL pin.
+/// A single pin might be connected to multiple wires.
 fn create_belname_pinname_to_wire_lookup<'d>(
     st: &DeviceResources_capnp::device::site_type::Reader<'d>
 )
-    -> HashMap<(u32, u32), u32>
+    -> HashMap<(u32, u32), Vec<u32>>
 {
     st.get_site_wires().unwrap().iter()
         .enumerate()    
         .fold(HashMap::new(), |mut map, (wire_id, wire)| {
-            map.extend(wire.get_pins().unwrap().iter()
-                .map(|pin| {
-                    let bpin = st.get_bel_pins().unwrap().get(pin);
-                    
-                    ((bpin.get_bel(), bpin.get_name()), wire_id as u32)
-                }));
+            for pin in wire.get_pins().unwrap().iter() {
+                let bpin = st.get_bel_pins().unwrap().get(pin);
+
+                map.entry((bpin.get_bel(), bpin.get_name()))
+                    .or_insert_with(Vec::new)
+                    .push(wire_id as u32);
+            }
             map
         })
 }
 
+/// Constant nets which get virtual site ports (`$VCC`, `$GND`) along with connections to
+/// their site sources (see `BruteRouter::new`). `true` and `false` stand for all or none
+/// of them.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct VirtualConsts {
+    pub vcc: bool,
+    pub gnd: bool,
+}
+
+impl VirtualConsts {
+    pub fn any(&self) -> bool {
+        self.vcc || self.gnd
+    }
+
+    fn includes(&self, constant: DeviceResources_capnp::device::ConstantType) -> bool {
+        use DeviceResources_capnp::device::ConstantType;
+
+        match constant {
+            ConstantType::Vcc => self.vcc,
+            ConstantType::Gnd => self.gnd,
+            _ => false,
+        }
+    }
+}
+
+impl From<bool> for VirtualConsts {
+    fn from(all: bool) -> Self {
+        Self { vcc: all, gnd: all }
+    }
+}
+
 fn gather_bels_in_site_type<'a>(
     device: &'a Device<'a>,
     st: &crate::ic_loader::archdef::SiteTypeReader<'a>,
-    add_virtual_consts: bool
+    virtual_consts: VirtualConsts
 ) -> Vec<BELInfo> {
     let mut bels: Vec<_> = st.get_bels().unwrap().into_iter()
         .map(|reader| BELInfo {
@@ -291,34 +490,36 @@ fn gather_bels_in_site_type<'a>(
                 .collect(),
         }).collect();
 
-    if add_virtual_consts {
+    if virtual_consts.any() {
         use crate::ic_loader::DeviceResources_capnp::device::ConstantType;
 
         let site_sources = device.get_constants().unwrap().get_site_sources().unwrap();
         
+        let vcc_name = virtual_const_port_name(device, st, "$VCC");
+        let gnd_name = virtual_const_port_name(device, st, "$GND");
+
         let mut gsctx = GlobalStringsCtx::hold();
 
         let (vcc_added, gnd_added) = site_sources.iter()
             .filter(|site_source| {
                 site_source.get_site_type() == st.get_name()
+                    && virtual_consts.includes(site_source.get_constant().unwrap())
             })
             .fold((false, false), |(vcc_added, gnd_added), site_source| {
                 let bel_name = device.ic_str(site_source.get_bel());
 
                 let (acc, net_name) = match site_source.get_constant().unwrap() {
                     ConstantType::Vcc => if !vcc_added {
-                        bels.push(create_input_port_bel("$VCC".into()));
-                        ((true, gnd_added), "$VCC")
+                        bels.push(create_input_port_bel(vcc_name.clone()));
+                        ((true, gnd_added), vcc_name.as_str())
                     } else {
-                        ((vcc_added, gnd_added), "$VCC")
+                        ((vcc_added, gnd_added), vcc_name.as_str())
                     },
                     ConstantType::Gnd => if !gnd_added {
-                        bels.push(create_input_port_bel(
-                            "$GND".into()
-                        ));
-                        ((vcc_added, true), "$GND")
+                        bels.push(create_input_port_bel(gnd_name.clone()));
+                        ((vcc_added, true), gnd_name.as_str())
                     } else {
-                        ((vcc_added, gnd_added), "$GND")
+                        ((vcc_added, gnd_added), gnd_name.as_str())
                     },
                     u @ _ => panic!("Unexpected constant type `{:?}`", u),
                 };
@@ -335,14 +536,16 @@ fn gather_bels_in_site_type<'a>(
         if vcc_added {
             dbg_log!(
                 DBG_INFO,
-                "  Added $VCC input to site {}",
+                "  Added {} input to site {}",
+                vcc_name,
                 device.ic_str(st.get_name())
             );
         }
         if gnd_added {
             dbg_log!(
                 DBG_INFO,
-                "  Added $GND input to site {}",
+                "  Added {} input to site {}",
+                gnd_name,
                 device.ic_str(st.get_name())
             );
         }
@@ -351,19 +554,88 @@ fn gather_bels_in_site_type<'a>(
     bels
 }
 
+/// A tile wire which a site pin connects to within a tile type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct SitePinTileWire {
+    /// Name of the tile type
+    pub tile_type: String,
+    /// Index of the site within the tile type
+    pub site: usize,
+    /// Name of the tile wire
+    pub wire: String,
+}
+
+/// Resolves pins of a site type to tile wires they connect to in every tile type
+/// instantiating the site type. Site pins share names with their site port BELs.
+/// 
+/// # Return
+/// Tile wires connected to each site pin, keyed by the name of the site pin.
+/// Pins without tile wire information in the device (or connected to an unnamed wire)
+/// are missing from the map.
+pub fn site_pin_tile_wires<'d>(device: &Device<'d>, st_id: u32)
+    -> HashMap<String, Vec<SitePinTileWire>>
+{
+    let st = device.get_site_type_list().unwrap().get(st_id);
+    let site_pins = st.get_pins().unwrap();
+
+    let mut tile_wires: HashMap<String, Vec<SitePinTileWire>> = HashMap::new();
+    for tt in device.get_tile_type_list().unwrap().iter() {
+        let tt_name = device.ic_str(tt.get_name());
+        let stitts = tt.get_site_types().unwrap().iter()
+            .enumerate()
+            .filter(|(_, stitt)| stitt.get_primary_type() == st_id);
+
+        for (site, stitt) in stitts {
+            /* Devices are not required to provide this mapping */
+            let wires = stitt.get_primary_pins_to_tile_wires().unwrap();
+            for (pin, wire) in site_pins.iter().zip(wires.iter()) {
+                let wire = device.ic_str(wire);
+                if wire.is_empty() {
+                    continue;
+                }
+                tile_wires.entry(device.ic_str(pin.get_name()).to_string())
+                    .or_default()
+                    .push(SitePinTileWire {
+                        tile_type: tt_name.to_string(),
+                        site,
+                        wire: wire.to_string(),
+                    });
+            }
+        }
+    }
+
+    tile_wires
+}
+
 /// Contains information about all aspecs of routing
 /// 
 /// Fields:
 /// * `intra` - intra-site routing info
+#[cfg(feature = "serialize")]
 #[derive(Serialize)]
 pub struct FullRoutingInfo<I> where I: serde::Serialize {
     pub intra: I,
 }
 
 /// Uniquely identifies a site pin within a given site type.
-#[derive(Copy, Clone, Serialize, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct SitePinId(usize);
 
+impl SitePinId {
+    /// Index of the pin in the routing graph of its site type.
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+impl From<usize> for SitePinId {
+    fn from(index: usize) -> Self {
+        Self(index)
+    }
+}
+
 /// Holds various name components of a site pin within a site type.
 pub struct SitePinName<'b, 'p, B, P> where
     B: Borrow<str> + 'b,
@@ -387,6 +659,14 @@ impl<'b, 'p, B, P>  SitePinName<'b, 'p, B, P> where
             _pin_lifetime: Default::default(),
         }
     }
+
+    pub fn bel(&self) -> &str {
+        self.bel.borrow()
+    }
+
+    pub fn pin(&self) -> &str {
+        self.pin.borrow()
+    }
 }
 
 impl<'b, 'p, B, P> ToString for SitePinName<'b, 'p, B, P> where
@@ -14,13 +14,17 @@
  */
 
 
-use serde::{Serialize, Serializer, ser::{SerializeStruct, SerializeMap, SerializeSeq}};
+use serde::{Serialize, Deserialize, Serializer};
+use serde::ser::{SerializeStruct, SerializeMap, SerializeSeq};
 use std::collections::HashMap;
-use crate::logic_formula::{DNFCube, FormulaTerm};
+use crate::logic_formula::{DNFCube, FormulaTerm, CanonicalDNFForm};
 use std::sync::Arc;
 
 use super::*;
 
+/// Number of pins entered while counting routes of a single pin pair, after which the
+/// count is reported as capped.
+const MAX_ROUTE_COUNT_FRAMES: usize = 1 << 20;
 
 fn serialize_standard_routing_info_fields<'r, 'd, A, S>(
     ri: &RoutingInfoWithExtras<'d, A>,
@@ -34,30 +38,162 @@ where
         ri.map_routing_map_to_serializable(&ri.pin_to_pin_routing);
         
     ser.serialize_field("pin_to_pin_routing", &serializable_map)?;
+    serialize_common_routing_info_fields(ri, ser)
+}
+
+/* Fields following `pin_to_pin_routing`, which don't depend on how formulas are stored */
+fn serialize_common_routing_info_fields<'r, 'd, A, S>(
+    ri: &RoutingInfoWithExtras<'d, A>,
+    ser: &mut S,
+) -> Result<(), S::Error>
+where
+    A: Default + Clone + std::fmt::Debug + 'static,
+    S: serde::ser::SerializeStruct,
+{
     ser.serialize_field("out_of_site_sources", &ri.out_of_site_sources)?;
     ser.serialize_field("out_of_site_sinks", &ri.out_of_site_sinks)?;
+    if !ri.timed_out.vec.is_empty() {
+        ser.serialize_field("timed_out", &ri.timed_out)?;
+    }
+    if !ri.aliases.is_empty() {
+        ser.serialize_field("pin_aliases", &ri.alias_names())?;
+    }
+    if !ri.edge_usage.is_empty() {
+        ser.serialize_field("edge_usage", &ri.map_routing_map_to_serializable(&ri.edge_usage))?;
+    }
+    if let Some(tile_wires) = &ri.site_port_tile_wires {
+        ser.serialize_field("site_port_tile_wires", &ri.out_of_site_tile_wires(tile_wires))?;
+    }
+    if ri.numeric_port_ids {
+        ser.serialize_field("port_names", &ri.port_names())?;
+    }
+    if ri.pin_table {
+        ser.serialize_field("pin_table", &ri.pin_table())?;
+    }
+    if ri.bel_level {
+        ser.serialize_field("bel_to_pin_routing", &ri.bel_level_routing())?;
+    }
+    if let Some(pin_range) = &ri.pin_range {
+        ser.serialize_field("pin_range", pin_range)?;
+    }
+    if let Some(device_hash) = &ri.device_hash {
+        ser.serialize_field("device_hash", device_hash)?;
+    }
 
     Ok(())
 }
 
+/// Entry of the pin table, describing the BEL pin behind a `SitePinId`
+#[derive(Serialize, Debug)]
+pub struct PinTableEntry {
+    pub bel: String,
+    pub pin: String,
+    pub dir: PinDir,
+}
+
+/// Pin pair routing info referencing formulas stored in a deduplicated table
+#[derive(Serialize)]
+struct DeduplicatedPinPairRoutingInfo {
+    requires: usize,
+    implies: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    route_count: Option<usize>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    route_count_capped: bool,
+}
+
+/// Table of distinct formulas. Formulas are identified by their index within the table.
+struct FormulaTable {
+    formulas: Vec<CanonicalDNFForm<site_brute_router::ConstrainingElement>>,
+    lookup: HashMap<CanonicalDNFForm<site_brute_router::ConstrainingElement>, usize>,
+}
+
+impl FormulaTable {
+    fn new() -> Self {
+        Self { formulas: Vec::new(), lookup: HashMap::new() }
+    }
+
+    fn get_or_insert(&mut self, form: &[DNFCube<site_brute_router::ConstrainingElement>])
+        -> usize
+    {
+        let canonical = CanonicalDNFForm::new(form);
+        if let Some(idx) = self.lookup.get(&canonical) {
+            return *idx;
+        }
+        let idx = self.formulas.len();
+        self.formulas.push(canonical.clone());
+        self.lookup.insert(canonical, idx);
+        idx
+    }
+}
+
+fn serialize_deduplicated_routing_info_fields<'r, 'd, A, S>(
+    ri: &RoutingInfoWithExtras<'d, A>,
+    ser: &mut S,
+) -> Result<(), S::Error>
+where
+    A: Default + Clone + std::fmt::Debug + 'static,
+    S: serde::ser::SerializeStruct,
+{
+    let mut table = FormulaTable::new();
+
+    let pairs: HashMap<_, _> = ri.pin_to_pin_routing.iter()
+        .map(|(key, ppri)| {
+            let dppri = DeduplicatedPinPairRoutingInfo {
+                requires: table.get_or_insert(&ppri.ppri.requires),
+                implies: table.get_or_insert(&ppri.ppri.implies),
+                route_count: ppri.route_count,
+                route_count_capped: ppri.route_count_capped,
+            };
+            (*key, dppri)
+        })
+        .collect();
+
+    let formulas: Vec<_> = table.formulas.iter()
+        .map(|form| {
+            dnf_to_serializable(
+                &ri.pin_names,
+                form.cubes(),
+                ri.numeric_port_ids,
+                ri.explicit_formulas,
+                ri.labeled_constraints
+            )
+        })
+        .collect();
+    
+    ser.serialize_field("formulas", &formulas)?;
+    ser.serialize_field("pin_to_pin_routing", &ri.map_routing_map_to_serializable(&pairs))?;
+    serialize_common_routing_info_fields(ri, ser)
+}
+
 impl<'r, 'd, A> Serialize for RoutingInfoWithExtras<'d, A> where
     A: Default + Clone + std::fmt::Debug + 'static
 {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where
         S: Serializer
     {
+        if self.deduplicate_formulas {
+            let mut s = serializer.serialize_struct("RoutingInfo", 4)?;
+            serialize_deduplicated_routing_info_fields(self, &mut s)?;
+            return s.end();
+        }
+
         let mut s = serializer.serialize_struct("RoutingInfo", 3)?;
         serialize_standard_routing_info_fields(self, &mut s)?;
         s.end()
     }
 }
 
-pub struct PinPairRoutingInfoWithExtras<'d, A> where 
-    A: Default + Clone + std::fmt::Debug
-{
-    device: &'d Device<'d>,
-    router: Arc<site_brute_router::BruteRouter<A>>,
-    ppri: site_brute_router::PinPairRoutingInfo
+pub struct PinPairRoutingInfoWithExtras {
+    pin_names: Arc<Vec<String>>,
+    ppri: site_brute_router::PinPairRoutingInfo,
+    numeric_port_ids: bool,
+    explicit_formulas: bool,
+    labeled_constraints: bool,
+    /// Number of distinct routes of the pair (see `BruteRouter::count_routes`)
+    route_count: Option<usize>,
+    /// Whether the pair has more routes than `route_count`, which is the counting limit
+    route_count_capped: bool,
 }
 
 #[derive(PartialOrd, Ord, PartialEq, Eq, Serialize)]
@@ -65,94 +201,230 @@ pub enum StringConstrainingElement {
     Port(String)
 }
 
-impl<'d, A> PinPairRoutingInfoWithExtras<'d, A> where
-    A: Default + Clone + std::fmt::Debug + 'static
+/// Formula serialized as an explicit tree of operators, eg.
+/// `{"or": [{"and": [{"var": "A.A"}, {"not": "B.B"}]}]}`, instead of nested arrays which
+/// rely on the DNF convention. `Or` always holds `And` nodes, which hold the rest.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExplicitFormula<T> {
+    Or(Vec<ExplicitFormula<T>>),
+    And(Vec<ExplicitFormula<T>>),
+    Var(T),
+    Not(T),
+    True,
+    False,
+}
+
+impl<T> ExplicitFormula<T> {
+    /// Builds the operator tree of a DNF formula, mapping variables with `var`.
+    pub fn from_dnf<Id, F>(form: &[DNFCube<Id>], mut var: F) -> Self where
+        Id: Ord + Eq,
+        F: FnMut(&Id) -> T
+    {
+        ExplicitFormula::Or(form.iter()
+            .map(|cube| ExplicitFormula::And(cube.terms.iter()
+                .map(|term| match term {
+                    FormulaTerm::Var(id) => ExplicitFormula::Var(var(id)),
+                    FormulaTerm::NegVar(id) => ExplicitFormula::Not(var(id)),
+                    FormulaTerm::True => ExplicitFormula::True,
+                    FormulaTerm::False => ExplicitFormula::False,
+                })
+                .collect()))
+            .collect())
+    }
+}
+
+/// Cube of a formula with its terms labeled by their meaning, instead of relying on the
+/// polarity of terms. Negated terms (`requires`) name ports which mustn't drive the route,
+/// positive terms (activators in `implies`) name ports which the route uses, eg.
+/// `{"must_not_use_ports": ["B.B"], "uses_port": ["A.A"]}`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LabeledCube<T> {
+    pub must_not_use_ports: Vec<T>,
+    pub uses_port: Vec<T>,
+    /// The cube contains a false constant, so it can never be met
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unsatisfiable: bool,
+}
+
+impl<T> LabeledCube<T> {
+    /// Sorts terms of a cube by their polarity, mapping variables with `var`.
+    pub fn from_cube<Id, F>(cube: &DNFCube<Id>, mut var: F) -> Self where
+        Id: Ord + Eq,
+        F: FnMut(&Id) -> T
+    {
+        let mut labeled = Self {
+            must_not_use_ports: Vec::new(),
+            uses_port: Vec::new(),
+            unsatisfiable: false,
+        };
+        for term in &cube.terms {
+            match term {
+                FormulaTerm::Var(id) => labeled.uses_port.push(var(id)),
+                FormulaTerm::NegVar(id) => labeled.must_not_use_ports.push(var(id)),
+                FormulaTerm::True => (),
+                FormulaTerm::False => labeled.unsatisfiable = true,
+            }
+        }
+        labeled
+    }
+}
+
+/// Formula with ports identified either by their names, or by their ids, which can be
+/// resolved with the `port_names` table. Either form can be serialized as nested arrays,
+/// as an explicit tree of operators or as a list of labeled cubes.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum SerializableDNF {
+    Named(Vec<Vec<FormulaTerm<StringConstrainingElement>>>),
+    Numeric(Vec<Vec<FormulaTerm<site_brute_router::ConstrainingElement>>>),
+    NamedExplicit(ExplicitFormula<String>),
+    NumericExplicit(ExplicitFormula<u32>),
+    NamedLabeled(Vec<LabeledCube<String>>),
+    NumericLabeled(Vec<LabeledCube<u32>>),
+}
+
+fn dnf_to_serializable(
+    pin_names: &[String],
+    form: &[DNFCube<site_brute_router::ConstrainingElement>],
+    numeric_port_ids: bool,
+    explicit: bool,
+    labeled: bool
+)
+    -> SerializableDNF
 {
+    use site_brute_router::ConstrainingElement::*;
+
+    if numeric_port_ids {
+        if labeled {
+            return SerializableDNF::NumericLabeled(form.iter()
+                .map(|cube| LabeledCube::from_cube(cube, |Port(v)| *v))
+                .collect());
+        }
+        if explicit {
+            return SerializableDNF::NumericExplicit(
+                ExplicitFormula::from_dnf(form, |Port(v)| *v)
+            );
+        }
+        return SerializableDNF::Numeric(form.iter()
+            .map(|cube| cube.terms.clone())
+            .collect());
+    }
+
+    let port_name = |Port(v): &site_brute_router::ConstrainingElement| {
+        pin_names[*v as usize].clone()
+    };
+
+    if labeled {
+        return SerializableDNF::NamedLabeled(form.iter()
+            .map(|cube| LabeledCube::from_cube(cube, port_name))
+            .collect());
+    }
+    if explicit {
+        return SerializableDNF::NamedExplicit(ExplicitFormula::from_dnf(form, port_name));
+    }
+
+    SerializableDNF::Named(form.iter().map(|cube| {
+        cube.terms.iter().map(|term| {
+            term.clone().map(|c| StringConstrainingElement::Port(port_name(&c)))
+        }).collect()
+    }).collect())
+}
+
+impl PinPairRoutingInfoWithExtras {
     fn dnf_to_serializable(
         &self,
         form: &[DNFCube<site_brute_router::ConstrainingElement>]
     )
-        -> Vec<Vec<FormulaTerm<StringConstrainingElement>>>
+        -> SerializableDNF
     {
-        use site_brute_router::ConstrainingElement::*;
-
-        let gsctx = GlobalStringsCtx::hold();
-
-        form.iter().map(|cube| {
-            cube.terms.iter().map(|term| {
-                term.clone().map(|c| match c {
-                    Port(v) => StringConstrainingElement::Port(
-                        self.router.get_pin_name(self.device, &gsctx, SitePinId(v as usize))
-                            .to_string()
-                    )
-                })
-            }).collect()
-        }).collect()
+        dnf_to_serializable(
+            &self.pin_names,
+            form,
+            self.numeric_port_ids,
+            self.explicit_formulas,
+            self.labeled_constraints
+        )
     }
 }
 
-impl<'d, A> Serialize for PinPairRoutingInfoWithExtras<'d, A> where
-    A: Default + Clone + std::fmt::Debug + 'static
-{
+impl Serialize for PinPairRoutingInfoWithExtras {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer
     {
-        let mut s = serializer.serialize_struct("PinPairRoutingInfo", 2)?;
+        if let Some(unoptimized) = &self.ppri.unoptimized {
+            let mut s = serializer.serialize_struct("PinPairRoutingInfo", 4)?;
+            s.serialize_field(
+                "requires_raw",
+                &self.dnf_to_serializable(&unoptimized.requires)
+            )?;
+            s.serialize_field(
+                "requires_optimized",
+                &self.dnf_to_serializable(&self.ppri.requires)
+            )?;
+            s.serialize_field(
+                "implies_raw",
+                &self.dnf_to_serializable(&unoptimized.implies)
+            )?;
+            s.serialize_field(
+                "implies_optimized",
+                &self.dnf_to_serializable(&self.ppri.implies)
+            )?;
+            if let Some(route_count) = self.route_count {
+                s.serialize_field("route_count", &route_count)?;
+            }
+            if self.route_count_capped {
+                s.serialize_field("route_count_capped", &true)?;
+            }
+            return s.end();
+        }
+
+        let mut s = serializer.serialize_struct("PinPairRoutingInfo", 3)?;
         s.serialize_field("requires", &self.dnf_to_serializable(&self.ppri.requires))?;
         s.serialize_field("implies", &self.dnf_to_serializable(&self.ppri.implies))?;
+        if let Some(route_count) = self.route_count {
+            s.serialize_field("route_count", &route_count)?;
+        }
+        if self.route_count_capped {
+            s.serialize_field("route_count_capped", &true)?;
+        }
         s.end()
     }
 }
 
-pub struct SitePinHashMap<'d, A, T> where
-    A: Default + Clone + std::fmt::Debug + 'static
-{
-    router: Arc<site_brute_router::BruteRouter<A>>,
-    device: &'d Device<'d>,
+pub struct SitePinHashMap<T> {
+    pin_names: Arc<Vec<String>>,
     hashmap: HashMap<SitePinId, T>,
 }
 
-impl<'d, A, T> Serialize for SitePinHashMap<'d, A, T> where
-    T: Serialize,
-    A: Default + Clone + std::fmt::Debug + 'static
+impl<T> Serialize for SitePinHashMap<T> where
+    T: Serialize
 {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer
     {
-        let gsctx = GlobalStringsCtx::hold();
         let mut s = serializer.serialize_map(Some(self.hashmap.len()))?;
         for (key, value) in &self.hashmap {
-            s.serialize_entry(
-                &self.router.get_pin_name(self.device, &gsctx, *key).to_string(),
-                value
-            )?;
+            s.serialize_entry(&self.pin_names[key.0], value)?;
         }
         s.end()
     }
 }
 
-pub struct SitePinVec<'d, A> where
-    A: Default + Clone + std::fmt::Debug + 'static
-{
-    router: Arc<site_brute_router::BruteRouter<A>>,
-    device: &'d Device<'d>,
+pub struct SitePinVec {
+    pin_names: Arc<Vec<String>>,
     vec: Vec<SitePinId>,
 }
 
-impl<'d, A> Serialize for SitePinVec<'d, A> where
-    A: Default + Clone + std::fmt::Debug + 'static
-{
+impl Serialize for SitePinVec {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where
         S: Serializer
     {
-        let gsctx = GlobalStringsCtx::hold();
         let mut s = serializer.serialize_seq(Some(self.vec.len()))?;
         for pin in &self.vec {
-            s.serialize_element(
-                &self.router.get_pin_name(self.device, &gsctx, *pin).to_string()
-            )?;
+            s.serialize_element(&self.pin_names[pin.0])?;
         }
         s.end()
     }
@@ -163,49 +435,236 @@ pub struct RoutingInfoWithExtras<'d, A> where
 {
     router: Arc<site_brute_router::BruteRouter<A>>,
     device: &'d Device<'d>,
+    /* Names of all pins of the site type indexed by `SitePinId`, resolved once instead
+     * of holding the global strings context for every name being serialized. */
+    pin_names: Arc<Vec<String>>,
     pub pin_to_pin_routing:
-        HashMap<(SitePinId, SitePinId), PinPairRoutingInfoWithExtras<'d, A>>,
-    pub out_of_site_sources: SitePinHashMap<'d, A, SitePinVec<'d, A>>,
-    pub out_of_site_sinks: SitePinHashMap<'d, A, SitePinVec<'d, A>>,
+        HashMap<(SitePinId, SitePinId), PinPairRoutingInfoWithExtras>,
+    pub out_of_site_sources: SitePinHashMap<SitePinVec>,
+    pub out_of_site_sinks: SitePinHashMap<SitePinVec>,
+    pub timed_out: SitePinVec,
+    aliases: HashMap<SitePinId, SitePinId>,
+    edge_usage: HashMap<(SitePinId, SitePinId), usize>,
+    deduplicate_formulas: bool,
+    site_port_tile_wires: Option<HashMap<String, Vec<SitePinTileWire>>>,
+    numeric_port_ids: bool,
+    explicit_formulas: bool,
+    labeled_constraints: bool,
+    pin_range: Option<std::ops::Range<usize>>,
+    pin_table: bool,
+    bel_level: bool,
+    device_hash: Option<String>,
 }
 
 impl<'d, A> RoutingInfoWithExtras<'d, A> where
     A: Default + Clone + std::fmt::Debug + 'static
 {
+    /// Serialize formulas into a table of distinct formulas, which pin pairs reference
+    /// by indices, instead of serializing formulas of each pair separately.
+    /// Only the optimized forms are serialized in this mode.
+    pub fn with_deduplicated_formulas(self, deduplicate: bool) -> Self {
+        Self {
+            deduplicate_formulas: deduplicate,
+            .. self
+        }
+    }
+
+    /// Include tile wires connected to the site ports used by out-of-site sources and
+    /// sinks, so that they can be connected to the fabric by name. Requires the device to
+    /// provide the mapping of site pins to tile wires.
+    pub fn with_site_port_tile_wires(self, resolve: bool) -> Self {
+        let site_port_tile_wires = resolve
+            .then(|| site_pin_tile_wires(self.device, self.router.get_st_id()));
+        Self {
+            site_port_tile_wires,
+            .. self
+        }
+    }
+
+    /// Identify ports within formulas by their ids instead of names, and add a table
+    /// of port names indexed by the ids. This makes output considerably smaller for
+    /// sites with many constraints.
+    pub fn with_numeric_port_ids(mut self, numeric: bool) -> Self {
+        for ppri in self.pin_to_pin_routing.values_mut() {
+            ppri.numeric_port_ids = numeric;
+        }
+        Self {
+            numeric_port_ids: numeric,
+            .. self
+        }
+    }
+
+    /// Serialize formulas as explicit trees of `or`, `and`, `var` and `not` operators
+    /// (see `ExplicitFormula`), instead of nested arrays.
+    pub fn with_explicit_formulas(mut self, explicit: bool) -> Self {
+        for ppri in self.pin_to_pin_routing.values_mut() {
+            ppri.explicit_formulas = explicit;
+        }
+        Self {
+            explicit_formulas: explicit,
+            .. self
+        }
+    }
+
+    /// Serialize cubes of formulas as objects labeling the ports by their meaning (see
+    /// `LabeledCube`): `must_not_use_ports` for negated drivers and `uses_port` for
+    /// activators. Takes precedence over explicit formulas.
+    pub fn with_labeled_constraints(mut self, labeled: bool) -> Self {
+        for ppri in self.pin_to_pin_routing.values_mut() {
+            ppri.labeled_constraints = labeled;
+        }
+        Self {
+            labeled_constraints: labeled,
+            .. self
+        }
+    }
+
+    /// Add the number of distinct routes to each pin pair, counting up to `max_routes`
+    /// routes (see `BruteRouter::count_routes`). Pairs for which the limit was reached,
+    /// or which took more than `MAX_ROUTE_COUNT_FRAMES` steps to count, are marked with
+    /// `route_count_capped`.
+    pub fn with_route_counts(mut self, count: bool, max_routes: usize) -> Self {
+        if !count {
+            return self;
+        }
+        /* One route past the limit tells capped counts from exact ones */
+        let limit = max_routes.saturating_add(1);
+        for ((from, to), ppri) in self.pin_to_pin_routing.iter_mut() {
+            let (route_count, capped) = self.router
+                .count_routes(*from, *to, Some(limit), Some(MAX_ROUTE_COUNT_FRAMES));
+            ppri.route_count = Some(route_count.min(max_routes));
+            ppri.route_count_capped = capped;
+        }
+        self
+    }
+
+    /// Record SHA-256 hash of the device file the routing information was gathered
+    /// from, so that outputs of different devices can't be mixed up.
+    pub fn with_device_hash(self, device_hash: Option<String>) -> Self {
+        Self {
+            device_hash,
+            .. self
+        }
+    }
+
+    /// Add a table describing the BEL pin (BEL, pin name and direction) of every
+    /// `SitePinId`, indexed by the ids. Together with numeric port ids, this lets
+    /// consumers work on indices without the router.
+    pub fn with_pin_table(self, pin_table: bool) -> Self {
+        Self {
+            pin_table,
+            .. self
+        }
+    }
+
+    /// Add routing from BELs to sinks, aggregated over the source pins of each BEL (see
+    /// `RoutingInfo::bel_level`). Keys name the BEL and the sink, eg. `"LUT->FF.D"`.
+    pub fn with_bel_level(self, bel_level: bool) -> Self {
+        Self {
+            bel_level,
+            .. self
+        }
+    }
+
+    fn bel_level_routing(&self) -> HashMap<String, PinPairRoutingInfoWithExtras> {
+        let gsctx = GlobalStringsCtx::hold();
+        let pairs = self.pin_to_pin_routing.iter()
+            .map(|(pair, ppri)| (*pair, &ppri.ppri));
+
+        site_brute_router::bel_level_routing(&*self.router, pairs).into_iter()
+            .map(|((bel, to), ppri)| {
+                let bel_name = self.router.get_bels()[bel].name.get(self.device, &gsctx);
+                let key = format!("{}->{}", bel_name, self.pin_names[to.0]);
+                (key, PinPairRoutingInfoWithExtras {
+                    pin_names: Arc::clone(&self.pin_names),
+                    ppri,
+                    numeric_port_ids: self.numeric_port_ids,
+                    explicit_formulas: self.explicit_formulas,
+                    labeled_constraints: self.labeled_constraints,
+                    route_count: None,
+                    route_count_capped: false,
+                })
+            })
+            .collect()
+    }
+
+    fn pin_table(&self) -> Vec<PinTableEntry> {
+        let gsctx = GlobalStringsCtx::hold();
+        let graph = self.router.get_graph();
+
+        (0 .. graph.node_count())
+            .map(|pin| {
+                let name = self.router.get_pin_name(self.device, &gsctx, SitePinId(pin));
+                PinTableEntry {
+                    bel: name.bel().to_string(),
+                    pin: name.pin().to_string(),
+                    dir: graph.get_node(pin).dir,
+                }
+            })
+            .collect()
+    }
+
+    fn port_names(&self) -> &[String] {
+        &self.pin_names
+    }
+
+    fn alias_names(&self) -> HashMap<String, String> {
+        self.aliases.iter()
+            .map(|(alias, representative)| (
+                self.pin_names[alias.0].clone(),
+                self.pin_names[representative.0].clone()
+            ))
+            .collect()
+    }
+
+    /* Site port BELs have a single pin named after the corresponding site pin */
+    fn out_of_site_tile_wires<'w>(
+        &self,
+        tile_wires: &'w HashMap<String, Vec<SitePinTileWire>>
+    )
+        -> HashMap<String, &'w Vec<SitePinTileWire>>
+    {
+        let gsctx = GlobalStringsCtx::hold();
+
+        self.out_of_site_sources.hashmap.keys()
+            .chain(self.out_of_site_sinks.hashmap.keys())
+            .filter_map(|pin| {
+                let (bel_id, bel_pin_id) = self.router.pin_to_bel_pin(*pin);
+                let bel = &self.router.get_bels()[bel_id];
+                if bel.category != BELCategory::SitePort {
+                    return None;
+                }
+                let pin_name = bel.pins[bel_pin_id].name.get(self.device, &gsctx);
+                let wires = tile_wires.get(&*pin_name)?;
+                Some((self.pin_names[pin.0].clone(), wires))
+            })
+            .collect()
+    }
+
     fn map_routing_map_to_serializable<'h, S>(
         &self,
         routing_map: &'h HashMap<(SitePinId, SitePinId), S>
     )
         -> HashMap<String, &'h S>
     {
-        let gsctx = GlobalStringsCtx::hold();
-
         routing_map.iter()
             .map(|((from, to), v)| {
-                let from_name =
-                    self.router.get_pin_name(self.device, &gsctx, *from);
-                let to_name =
-                    self.router.get_pin_name(self.device, &gsctx, *to);
-                (format!("{}->{}", from_name.to_string(), to_name.to_string()), v)
+                (format!("{}->{}", self.pin_names[from.0], self.pin_names[to.0]), v)
             })
             .collect()    
     }
 
     fn convert_hashmap(
-        router: Arc<site_brute_router::BruteRouter<A>>,
-        device: &'d Device<'d>,
+        pin_names: &Arc<Vec<String>>,
         hm: HashMap<SitePinId, Vec<SitePinId>>
     )
-        -> SitePinHashMap<'d, A, SitePinVec<'d, A>>
+        -> SitePinHashMap<SitePinVec>
     {
-        let router_c = Arc::clone(&router);
         SitePinHashMap {
-            router,
-            device,
+            pin_names: Arc::clone(pin_names),
             hashmap: hm.into_iter().map(|(k, v)| {
                 let vec = SitePinVec {
-                    router: Arc::clone(&router_c),
-                    device,
+                    pin_names: Arc::clone(pin_names),
                     vec: v
                 };
 
@@ -236,13 +695,18 @@ impl<'d, A> IntoRoutingInfoWithExtras<'d, A> for site_brute_router::RoutingInfo
     )
         -> RoutingInfoWithExtras<'d, A>
     {
-        let router_ref = &router;
+        let pin_names = Arc::new(router.pin_names(device));
+        let pin_names_ref = &pin_names;
         let ptpr: HashMap::<_, _> = self.pin_to_pin_routing.into_iter()
             .map(move |(key, ppri)|
                 (key, PinPairRoutingInfoWithExtras {
-                    router: Arc::clone(router_ref),
-                    device,
-                    ppri
+                    pin_names: Arc::clone(pin_names_ref),
+                    ppri,
+                    numeric_port_ids: false,
+                    explicit_formulas: false,
+                    labeled_constraints: false,
+                    route_count: None,
+                    route_count_capped: false,
                 })
             ).collect();
         
@@ -250,16 +714,30 @@ impl<'d, A> IntoRoutingInfoWithExtras<'d, A> for site_brute_router::RoutingInfo
             router: Arc::clone(&router),
             device,
             pin_to_pin_routing: ptpr,
-            out_of_site_sources: RoutingInfoWithExtras::convert_hashmap(
-                Arc::clone(&router),
-                device,
+            pin_names,
+            out_of_site_sources: RoutingInfoWithExtras::<A>::convert_hashmap(
+                &pin_names,
                 self.out_of_site_sources
             ),
-            out_of_site_sinks: RoutingInfoWithExtras::convert_hashmap(
-                Arc::clone(&router),
-                device,
+            out_of_site_sinks: RoutingInfoWithExtras::<A>::convert_hashmap(
+                &pin_names,
                 self.out_of_site_sinks
             ),
+            timed_out: SitePinVec {
+                pin_names: Arc::clone(&pin_names),
+                vec: self.timed_out,
+            },
+            aliases: self.aliases,
+            edge_usage: self.edge_usage,
+            deduplicate_formulas: false,
+            site_port_tile_wires: None,
+            numeric_port_ids: false,
+            explicit_formulas: false,
+            labeled_constraints: false,
+            pin_range: self.pin_range,
+            pin_table: false,
+            bel_level: false,
+            device_hash: None,
         }
     }
 }
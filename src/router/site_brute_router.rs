@@ -16,7 +16,7 @@
 
 use std::borrow::Borrow;
 use core::panic;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use crate::common::{
     IcStr,
     split_range_nicely
@@ -26,20 +26,140 @@ use lazy_static::__Deref;
 use replace_with::replace_with_or_abort;
 use std::thread;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 #[allow(unused)]
 use crate::log::*;
 use crate::ic_loader::archdef::Root as Device;
+use crate::ic_loader::OpenWriteError;
+#[cfg(feature = "serialize")]
 use serde::{Serialize, Deserialize};
 use crate::dot_exporter::SiteRoutingGraphDotExporter;
+use crate::graphml_exporter::SiteRoutingGraphGraphMLExporter;
 use super::*;
 
-#[derive(Serialize)]
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct PinPairRoutingInfo {
     pub requires: Vec<DNFCube<ConstrainingElement>>,
     pub implies: Vec<DNFCube<ConstrainingElement>>,
+    /// Formulas before optimization. Present only if the router was asked to keep them
+    /// (see `BruteRouter::with_unoptimized_forms`).
+    #[cfg_attr(feature = "serialize", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub unoptimized: Option<UnoptimizedForms>,
+}
+
+/// Constraint formulas as gathered by the router, before optimization.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct UnoptimizedForms {
+    pub requires: Vec<DNFCube<ConstrainingElement>>,
+    pub implies: Vec<DNFCube<ConstrainingElement>>,
 }
 
 impl PinPairRoutingInfo {
+    /// Combines routing information of two consecutive route segments (`A → M` with
+    /// `M → B`) into information for a route passing through both of them. Requirements
+    /// of both segments need to be met, so `requires` get conjoined. `implies` of both
+    /// segments are gathered together.
+    /// 
+    /// Unoptimized forms are not carried over.
+    /// 
+    /// # Arguments
+    /// * `next` - routing information of the segment following this one
+    pub fn merge(&self, next: &Self) -> Self {
+        let requires = DNFForm { cubes: self.requires.clone() }
+            .conjunct(DNFForm { cubes: next.requires.clone() });
+
+        let mut implies = self.implies.clone();
+        for cube in &next.implies {
+            if !implies.contains(cube) {
+                implies.push(cube.clone());
+            }
+        }
+
+        let mut me = Self {
+            requires: requires.cubes,
+            implies,
+            unoptimized: None,
+        };
+        me.default_sort();
+        me
+    }
+
+    /// Combines routing information of two alternative routes to the same sink (`A → B`
+    /// with `A' → B`) into information for using either of them. Meeting requirements of
+    /// any of the routes is enough, so `requires` get disjoined. `implies` of both routes
+    /// are gathered together.
+    /// 
+    /// Unoptimized forms are not carried over.
+    /// 
+    /// # Arguments
+    /// * `other` - routing information of the alternative route
+    pub fn disjunct(&self, other: &Self) -> Self {
+        let requires = DNFForm { cubes: self.requires.clone() }
+            .disjunct(DNFForm { cubes: other.requires.clone() });
+
+        let mut implies = self.implies.clone();
+        for cube in &other.implies {
+            if !implies.contains(cube) {
+                implies.push(cube.clone());
+            }
+        }
+
+        let mut me = Self {
+            requires: requires.cubes,
+            implies,
+            unoptimized: None,
+        };
+        me.default_sort();
+        me
+    }
+
+    /// Checks whether the route needs no routing choices, ie. whether `requires` is
+    /// satisfied by any configuration of the site.
+    pub fn is_unconstrained(&self) -> bool {
+        self.requires.iter().any(DNFCube::is_true_const)
+    }
+
+    /// Removes `implies` cubes subsumed by `requires`, ie. cubes forming a subformula of
+    /// `requires` (see `DNFForm::is_subformula_of`). Only activator information which
+    /// can't be derived from `requires` is kept.
+    pub fn trim_implies(&mut self) {
+        let requires = DNFForm { cubes: self.requires.clone() };
+        self.implies.retain(|cube| {
+            !DNFForm { cubes: vec![cube.clone()] }.is_subformula_of(&requires)
+        });
+    }
+
+    /// Renders `requires` and `implies` for terminal inspection, one formula per line,
+    /// using `∧`, `∨` and `¬` with ports resolved into pin names
+    /// (see `DNFForm::to_math_string`).
+    /// 
+    /// # Arguments
+    /// * `router` - router which produced the routing information
+    /// * `device` - device the router was created for
+    /// * `gsctx` - context for resolving virtual names
+    pub fn render<A>(
+        &self,
+        router: &BruteRouter<A>,
+        device: &Device,
+        gsctx: &GlobalStringsCtx
+    )
+        -> String
+    where
+        A: Default + Clone + std::fmt::Debug + 'static
+    {
+        let render = |cubes: &[DNFCube<ConstrainingElement>]| {
+            DNFForm { cubes: cubes.to_vec() }.to_math_string(|element| match element {
+                ConstrainingElement::Port(pin) =>
+                    router.get_pin_name(device, gsctx, SitePinId(*pin as usize)).to_string(),
+            })
+        };
+
+        format!("requires: {}\nimplies:  {}", render(&self.requires), render(&self.implies))
+    }
+
     /// A primitive heuristic for sorting constraints by number of terms.
     /// The idea is that a greedy algorithm would set value of the least
     /// constraints when placing a cell. Perhaps a better heuristic could
@@ -59,27 +179,284 @@ impl From<PTPRMarker> for PinPairRoutingInfo {
         let mut me = Self {
             requires: marker.constraints.cubes,
             implies: marker.activated.cubes,
+            unoptimized: None,
         };
         me.default_sort();
         me
     }
 }
 
+#[derive(Clone)]
 pub struct RoutingInfo {
     pub pin_to_pin_routing: HashMap<(SitePinId, SitePinId), PinPairRoutingInfo>,
+    /// Site ports which can drive a pin, keyed by the driven pin. `Inout` site ports
+    /// are included.
     pub out_of_site_sources: HashMap<SitePinId, Vec<SitePinId>>,
+    /// Site ports which can be driven by a pin, keyed by the driving pin. `Inout` site
+    /// ports are included, so a pair of `Inout` ports is present here and in
+    /// `out_of_site_sources`.
     pub out_of_site_sinks: HashMap<SitePinId, Vec<SitePinId>>,
+    /// Source pins which exceeded the routing timeout (see `BruteRouter::with_pin_timeout`).
+    /// Sorted. No results are present for these pins.
+    pub timed_out: Vec<SitePinId>,
+    /// Pins collapsed into representatives of their alias groups
+    /// (see `RoutingInfo::collapse_aliases`). No results are present for these pins.
+    pub aliases: HashMap<SitePinId, SitePinId>,
+    /// Number of discovered routes to sinks crossing each edge of the routing graph,
+    /// keyed by `(from, to)` nodes of the edge. Empty unless enabled with
+    /// `BruteRouter::with_edge_usage`.
+    pub edge_usage: HashMap<(SitePinId, SitePinId), usize>,
+    /// Routing was cancelled with a `CancellationToken` before all the source pins were
+    /// routed. Results are present only for the pins routed before that.
+    pub cancelled: bool,
+    /// Ids of source pins routing was limited to (see `BruteRouter::route_pin_range`).
+    /// `None` if all the source pins were routed.
+    pub pin_range: Option<std::ops::Range<usize>>,
+}
+
+impl RoutingInfo {
+    /// Removes results for aliased pins, leaving only the representatives of alias groups.
+    /// Routing between a representative and its aliases is trivial, so it's removed as
+    /// well. The mapping is stored in `aliases`, so that the results can be expanded back.
+    /// 
+    /// # Arguments
+    /// * `aliases` - map of aliased pins to their representatives
+    ///   (see `BruteRouter::find_pin_aliases`)
+    pub fn collapse_aliases(&mut self, aliases: HashMap<SitePinId, SitePinId>) {
+        self.pin_to_pin_routing.retain(|(from, to), _| {
+            !aliases.contains_key(from) && !aliases.contains_key(to)
+        });
+
+        let collapse_map = |map: &mut HashMap<SitePinId, Vec<SitePinId>>| {
+            map.retain(|pin, _| !aliases.contains_key(pin));
+            for pins in map.values_mut() {
+                for pin in pins.iter_mut() {
+                    if let Some(representative) = aliases.get(pin) {
+                        *pin = *representative;
+                    }
+                }
+                pins.sort();
+                pins.dedup();
+            }
+        };
+        collapse_map(&mut self.out_of_site_sources);
+        collapse_map(&mut self.out_of_site_sinks);
+
+        self.aliases.extend(aliases);
+    }
+
+    /// Removes `implies` cubes which carry no information beyond `requires` from all
+    /// pairs (see `PinPairRoutingInfo::trim_implies`).
+    pub fn trim_implies(&mut self) {
+        for ppri in self.pin_to_pin_routing.values_mut() {
+            ppri.trim_implies();
+        }
+    }
+
+    /// Removes driver-exclusion terms of `ports` from `requires` of all pairs, as if the
+    /// ports were never congested. Only their `¬port` terms are substituted with true,
+    /// cubes requiring a port to be driven are kept as they are.
+    pub fn ignore_ports(&mut self, ports: &HashSet<SitePinId>) {
+        if ports.is_empty() {
+            return;
+        }
+        let is_ignored = |term: &FormulaTerm<ConstrainingElement>| match term {
+            FormulaTerm::NegVar(ConstrainingElement::Port(pin)) =>
+                ports.contains(&SitePinId(*pin as usize)),
+            _ => false,
+        };
+        for ppri in self.pin_to_pin_routing.values_mut() {
+            let mut cubes: Vec<DNFCube<_>> = Vec::with_capacity(ppri.requires.len());
+            for mut cube in std::mem::take(&mut ppri.requires) {
+                cube.terms.retain(|term| !is_ignored(term));
+                /* p ∨ ⊤ ≡ ⊤ */
+                if cube.terms.is_empty() {
+                    cubes = vec![DNFCube::new()];
+                    break;
+                }
+                /* Removing terms keeps them sorted */
+                if !cubes.contains(&cube) {
+                    cubes.push(cube);
+                }
+            }
+            ppri.requires = cubes;
+        }
+    }
+
+    /// Finds pin pairs whose `requires` formulas consist of more than `max_cubes` cubes.
+    /// 
+    /// # Return
+    /// Offending pairs along with the number of cubes, largest formulas first
+    pub fn over_formula_budget(&self, max_cubes: usize) -> Vec<((SitePinId, SitePinId), usize)> {
+        let mut offenders: Vec<_> = self.iter()
+            .map(|(pair, ppri)| (pair, ppri.requires.len()))
+            .filter(|(_, cubes)| *cubes > max_cubes)
+            .collect();
+        offenders.sort_by(|(a, a_cubes), (b, b_cubes)| b_cubes.cmp(a_cubes).then(a.cmp(b)));
+        offenders
+    }
+
+    /// Number of routed pin pairs which are unconstrained
+    /// (see `PinPairRoutingInfo::is_unconstrained`).
+    pub fn unconstrained_pairs(&self) -> usize {
+        self.iter().filter(|(_, ppri)| ppri.is_unconstrained()).count()
+    }
+
+    /// Ratio of unconstrained pin pairs to all routed (ie. reachable) pin pairs. A high
+    /// ratio means that most of the routing within the site is choice-free, a low one
+    /// points to a site heavy with congestion.
+    /// 
+    /// # Return
+    /// The ratio, or `None` if no pairs were routed
+    pub fn unconstrained_ratio(&self) -> Option<f64> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(self.unconstrained_pairs() as f64 / self.len() as f64)
+    }
+
+    /// Aggregates routing of pin pairs into routing from BELs to sinks. Pairs sharing
+    /// the BEL of their source pin and the sink get disjoined (see
+    /// `PinPairRoutingInfo::disjunct`), so a sink is routable from a BEL if it's routable
+    /// from any of its pins.
+    /// 
+    /// # Return
+    /// Routing information keyed by `(BEL index, sink)`
+    pub fn bel_level<A>(&self, router: &BruteRouter<A>)
+        -> HashMap<(usize, SitePinId), PinPairRoutingInfo>
+    where
+        A: Default + Clone + std::fmt::Debug + 'static
+    {
+        bel_level_routing(router, self.iter())
+    }
+
+    /// Iterates over routed pin pairs, in no particular order.
+    pub fn iter<'r>(&'r self) -> RoutingInfoIter<'r> {
+        RoutingInfoIter(self.pin_to_pin_routing.iter())
+    }
+
+    /// Number of routed pin pairs.
+    pub fn len(&self) -> usize {
+        self.pin_to_pin_routing.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pin_to_pin_routing.is_empty()
+    }
+}
+
+/* Pairs get disjoined in order of their ids, so that the cube order doesn't depend on
+ * the order of the hash map */
+pub(crate) fn bel_level_routing<'r, A, I>(router: &BruteRouter<A>, pairs: I)
+    -> HashMap<(usize, SitePinId), PinPairRoutingInfo>
+where
+    A: Default + Clone + std::fmt::Debug + 'static,
+    I: IntoIterator<Item = ((SitePinId, SitePinId), &'r PinPairRoutingInfo)>
+{
+    let mut pairs: Vec<_> = pairs.into_iter().collect();
+    pairs.sort_by_key(|(pair, _)| *pair);
+
+    let mut bel_level: HashMap<(usize, SitePinId), PinPairRoutingInfo> = HashMap::new();
+    for ((from, to), ppri) in pairs {
+        let (bel, _) = router.pin_to_bel_pin(from);
+        let merged = match bel_level.get(&(bel, to)) {
+            Some(prev) => prev.disjunct(ppri),
+            None => ppri.clone(),
+        };
+        bel_level.insert((bel, to), merged);
+    }
+    bel_level
+}
+
+/// Iterator over routed pin pairs of `RoutingInfo` (see `RoutingInfo::iter`).
+pub struct RoutingInfoIter<'r>(
+    std::collections::hash_map::Iter<'r, (SitePinId, SitePinId), PinPairRoutingInfo>
+);
+
+impl<'r> Iterator for RoutingInfoIter<'r> {
+    type Item = ((SitePinId, SitePinId), &'r PinPairRoutingInfo);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(pair, ppri)| (*pair, ppri))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'r> ExactSizeIterator for RoutingInfoIter<'r> {}
+
+impl<'r> IntoIterator for &'r RoutingInfo {
+    type Item = ((SitePinId, SitePinId), &'r PinPairRoutingInfo);
+    type IntoIter = RoutingInfoIter<'r>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Site port along with pins within the site it connects to
+/// (see `BruteRouter::list_out_of_site_ports`).
+pub struct OutOfSitePort {
+    pub pin: SitePinId,
+    /// Direction of the site port BEL pin. Signals enter the site through `Output` pins.
+    pub dir: PinDir,
+    /// Pins within the site which can be driven by the port
+    pub drives: Vec<SitePinId>,
+    /// Pins within the site which can drive the port
+    pub driven_by: Vec<SitePinId>,
+}
+
+/// Routing from a pin took longer than the allowed time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RoutingTimedOut;
+
+/// Describes the kind of a connection between two nodes of the routing graph.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum RoutingGraphEdge {
+    /// No connection
+    None,
+    /// Connection following a site wire
+    SiteWire,
+    /// Connection through a pseudo-pip (routing BEL)
+    PseudoPip,
+    /// Connection synthesized for virtual constant networks
+    VirtualConst,
 }
 
-pub type RoutingGraphEdge = bool;
+impl RoutingGraphEdge {
+    pub fn exists(&self) -> bool {
+        *self != Self::None
+    }
+
+    /// Returns `true` if traversing this edge uses a routing resource that can be
+    /// congested. Virtual constant connections are modelled as PIPs joining the
+    /// constant network with wires of constant generators.
+    pub fn is_pip(&self) -> bool {
+        match self {
+            Self::PseudoPip | Self::VirtualConst => true,
+            Self::None | Self::SiteWire => false,
+        }
+    }
+}
+
+impl Default for RoutingGraphEdge {
+    fn default() -> Self {
+        Self::None
+    }
+}
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct RoutingGraphNode {
     pub kind: RoutingGraphNodeKind,
     pub dir: PinDir,
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum RoutingGraphNodeKind {
     BelPort(usize),
     RoutingBelPort(usize),
@@ -96,6 +473,8 @@ impl Default for RoutingGraphNode {
     }
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct RoutingGraph {
     nodes: Vec<RoutingGraphNode>,
     edges: Vec<RoutingGraphEdge>,  /* Edges between BEL pins */
@@ -109,27 +488,111 @@ impl RoutingGraph {
         }
     }
 
+    /* A flat index wouldn't catch `to` overflowing into the next row of the matrix */
+    fn edge_idx(&self, from: usize, to: usize) -> Option<usize> {
+        let node_count = self.nodes.len();
+        (from < node_count && to < node_count).then(|| from * node_count + to)
+    }
+
+    fn expect_edge_idx(&self, from: usize, to: usize) -> usize {
+        self.edge_idx(from, to).unwrap_or_else(|| panic!(
+            "Edge {} -> {} is out of bounds of a routing graph with {} nodes",
+            from, to, self.nodes.len()
+        ))
+    }
+
+    /// Gets the edge between two nodes.
+    /// 
+    /// Panics if any of the nodes doesn't exist (see `try_get_edge`).
     #[allow(unused)]
     pub fn get_edge<'a>(&'a self, from: usize, to: usize) -> &'a RoutingGraphEdge {
-        &self.edges[from * self.nodes.len() + to]
+        &self.edges[self.expect_edge_idx(from, to)]
+    }
+
+    /// Gets the edge between two nodes, or `None` if any of the nodes doesn't exist.
+    pub fn try_get_edge<'a>(&'a self, from: usize, to: usize) -> Option<&'a RoutingGraphEdge> {
+        self.edge_idx(from, to).map(|idx| &self.edges[idx])
+    }
+
+    /// Checks whether there's an edge going from `from` to `to`. Returns `false` if any
+    /// of the nodes doesn't exist.
+    pub fn has_edge(&self, from: usize, to: usize) -> bool {
+        self.try_get_edge(from, to).map(RoutingGraphEdge::exists).unwrap_or(false)
+    }
+
+    /// Yields existing edges joining two nodes, in any direction, as
+    /// `(from, to, edge)` triples.
+    pub fn edges_between<'a>(&'a self, a: usize, b: usize)
+        -> impl Iterator<Item = (usize, usize, RoutingGraphEdge)> + 'a
+    {
+        let backward = (a != b).then(|| (b, a));
+        std::iter::once((a, b))
+            .chain(backward)
+            .filter_map(move |(from, to)| {
+                self.try_get_edge(from, to)
+                    .filter(|edge| edge.exists())
+                    .map(|edge| (from, to, *edge))
+            })
     }
 
     fn get_edge_mut<'a>(&'a mut self, from: usize, to: usize) -> &'a mut RoutingGraphEdge {
-        &mut self.edges[from * self.nodes.len() + to]
+        let idx = self.expect_edge_idx(from, to);
+        &mut self.edges[idx]
     }
 
-    pub fn connect<'a>(&'a mut self, from: usize, to: usize)
+    /// Creates an edge between two nodes, unless it already exists. Self-loops are
+    /// refused, as they would only produce degenerate routes.
+    pub fn connect<'a>(&'a mut self, from: usize, to: usize, kind: RoutingGraphEdge)
         -> Option<&'a mut RoutingGraphEdge>
     {
+        if from == to {
+            return None;
+        }
+
         let edge = self.get_edge_mut(from, to);
 
-        match edge {
-            true => None,
-            false => {
-                *edge = true;
-                Some(edge)
+        if edge.exists() {
+            None
+        } else {
+            *edge = kind;
+            Some(edge)
+        }
+    }
+
+    /// Finds edges contradicting directions of the nodes they join. A site wire edge goes
+    /// from a driver to a sink, so it can't start at an `Input` node or end at an
+    /// `Output` node. A pseudo-pip edge goes the other way around, from a routing BEL's
+    /// input to its output. Virtual constant edges play both roles, so they need to
+    /// satisfy either of the rules.
+    /// 
+    /// # Return
+    /// Sorted `(from, to)` nodes of the inconsistent edges
+    pub fn inconsistent_directions(&self) -> Vec<(usize, usize)> {
+        let wire_ok = |from: PinDir, to: PinDir| from != PinDir::Input && to != PinDir::Output;
+        let pip_ok = |from: PinDir, to: PinDir| from != PinDir::Output && to != PinDir::Input;
+
+        let mut inconsistent = Vec::new();
+        for from in 0 .. self.nodes.len() {
+            for to in self.edges_from(from) {
+                let (from_dir, to_dir) = (self.nodes[from].dir, self.nodes[to].dir);
+                let consistent = match self.get_edge(from, to) {
+                    RoutingGraphEdge::SiteWire => wire_ok(from_dir, to_dir),
+                    RoutingGraphEdge::PseudoPip => pip_ok(from_dir, to_dir),
+                    RoutingGraphEdge::VirtualConst =>
+                        wire_ok(from_dir, to_dir) || pip_ok(from_dir, to_dir),
+                    RoutingGraphEdge::None => true,
+                };
+                if !consistent {
+                    inconsistent.push((from, to));
+                }
             }
         }
+        inconsistent
+    }
+
+    /// Yields nodes which have an edge to themselves. A well-formed graph has none.
+    pub fn self_loops<'a>(&'a self) -> impl Iterator<Item = usize> + 'a {
+        (0 .. self.nodes.len()).filter(move |node| self.has_edge(*node, *node))
     }
 
     #[allow(unused)]
@@ -141,25 +604,193 @@ impl RoutingGraph {
         &mut self.nodes[node]
     }
 
+    /// Removes an edge between two nodes.
+    /// 
+    /// # Return
+    /// The kind of the removed edge (`RoutingGraphEdge::None` if there was no edge)
+    pub fn disconnect(&mut self, from: usize, to: usize) -> RoutingGraphEdge {
+        std::mem::take(self.get_edge_mut(from, to))
+    }
+
     pub fn edges_from<'a>(&'a self, from: usize) -> impl Iterator<Item = usize> + 'a {
         self.edges.iter()
             .skip(from * self.nodes.len())
             .take(self.nodes.len())
             .enumerate()
-            .filter(|(_, e)| **e)
+            .filter(|(_, e)| e.exists())
             .map(|(idx, _)| idx)
     }
 
+    /// Successors of a node in canonical order, sorted by pin id. Routing expands
+    /// successors in this order, so the discovery order, and with it the results of
+    /// order-sensitive optimizations, doesn't depend on how the edges are stored.
+    /// Rows of the adjacency matrix are indexed by target pins, so its edges already come
+    /// out sorted. Storage which doesn't keep them sorted has to sort them here.
+    pub fn successors<'a>(&'a self, from: usize) -> impl Iterator<Item = usize> + 'a {
+        self.edges_from(from)
+    }
+
     pub fn edges_to<'a>(&'a self, to: usize) -> impl Iterator<Item = usize> + 'a {
         self.edges.iter()
             .skip(to)
             .step_by(self.nodes.len())
             .take(self.nodes.len())
             .enumerate()
-            .filter(|(_, e)| **e)
+            .filter(|(_, e)| e.exists())
             .map(|(idx, _)| idx)
     }
 
+    /// Marks nodes reachable from any of `roots` (including the roots themselves),
+    /// following edges forward, or backward if `forward` is false.
+    /// 
+    /// # Return
+    /// A flag for each node of the graph
+    pub fn reachable(&self, roots: impl IntoIterator<Item = usize>, forward: bool)
+        -> Vec<bool>
+    {
+        let mut visited = vec![false; self.nodes.len()];
+        let mut queue = VecDeque::new();
+        for root in roots {
+            if !visited[root] {
+                visited[root] = true;
+                queue.push_back(root);
+            }
+        }
+        while let Some(node) = queue.pop_front() {
+            let mut visit = |next: usize| if !visited[next] {
+                visited[next] = true;
+                queue.push_back(next);
+            };
+            if forward {
+                self.edges_from(node).for_each(&mut visit);
+            } else {
+                self.edges_to(node).for_each(&mut visit);
+            }
+        }
+        visited
+    }
+
+    /// Finds groups of nodes which can reach each other using only site wires
+    /// (strongly connected components of the site wire subgraph).
+    /// 
+    /// # Return
+    /// Sorted groups of sorted node indices. Groups of a single node are omitted.
+    pub fn site_wire_components(&self) -> Vec<Vec<usize>> {
+        let node_count = self.nodes.len();
+        let is_wire = |from: usize, to: usize| {
+            *self.get_edge(from, to) == RoutingGraphEdge::SiteWire
+        };
+        let successors = |node: usize| -> Vec<usize> {
+            self.edges_from(node).filter(|to| is_wire(node, *to)).collect()
+        };
+
+        /* Kosaraju's algorithm. First pass orders nodes by DFS finish time. */
+        let mut visited = vec![false; node_count];
+        let mut finished = Vec::with_capacity(node_count);
+        for root in 0 .. node_count {
+            if visited[root] {
+                continue;
+            }
+            visited[root] = true;
+            let mut stack = vec![(root, successors(root))];
+            while let Some((node, next)) = stack.last_mut() {
+                let node = *node;
+                match next.pop() {
+                    Some(to) if !visited[to] => {
+                        visited[to] = true;
+                        stack.push((to, successors(to)));
+                    },
+                    Some(_) => (),
+                    None => {
+                        finished.push(node);
+                        stack.pop();
+                    },
+                }
+            }
+        }
+
+        /* Second pass gathers components on the transposed graph */
+        let mut assigned = vec![false; node_count];
+        let mut components = Vec::new();
+        for root in finished.into_iter().rev() {
+            if assigned[root] {
+                continue;
+            }
+            assigned[root] = true;
+            let mut component = Vec::new();
+            let mut stack = vec![root];
+            while let Some(node) = stack.pop() {
+                component.push(node);
+                for from in self.edges_to(node).filter(|from| is_wire(*from, node)) {
+                    if !assigned[from] {
+                        assigned[from] = true;
+                        stack.push(from);
+                    }
+                }
+            }
+            if component.len() > 1 {
+                component.sort();
+                components.push(component);
+            }
+        }
+
+        components.sort();
+        components
+    }
+
+    /// Splits every `Inout` node into an input node and an output node. The original
+    /// node becomes the input and keeps the incoming edges. The output node is appended
+    /// to the graph and takes over the outgoing edges. An extra edge between the two
+    /// preserves reachability of routes passing through the original node.
+    /// 
+    /// # Return
+    /// Pairs of indices of the created output nodes and the nodes they were split from.
+    pub fn split_inout_nodes(&mut self) -> Vec<(usize, usize)> {
+        let old_count = self.nodes.len();
+        let inouts: Vec<usize> = (0 .. old_count)
+            .filter(|node| self.nodes[*node].dir == PinDir::Inout)
+            .collect();
+
+        if inouts.is_empty() {
+            return Vec::new();
+        }
+
+        let new_count = old_count + inouts.len();
+        let mut edges = vec![RoutingGraphEdge::None; new_count * new_count];
+        for from in 0 .. old_count {
+            for to in 0 .. old_count {
+                edges[from * new_count + to] = self.edges[from * old_count + to];
+            }
+        }
+        self.edges = edges;
+
+        let mut split = Vec::new();
+        for (split_idx, node) in inouts.into_iter().enumerate() {
+            let output = old_count + split_idx;
+            self.nodes.push(RoutingGraphNode {
+                kind: self.nodes[node].kind.clone(),
+                dir: PinDir::Output,
+            });
+            self.nodes[node].dir = PinDir::Input;
+
+            for to in 0 .. new_count {
+                let edge = std::mem::take(&mut self.edges[node * new_count + to]);
+                self.edges[output * new_count + to] = edge;
+            }
+            self.edges[node * new_count + output] = RoutingGraphEdge::SiteWire;
+
+            split.push((output, node));
+        }
+
+        split
+    }
+
+    /// Heap memory allocated for the graph, in bytes.
+    pub fn allocated_bytes(&self) -> usize {
+        self.nodes.capacity() * std::mem::size_of::<RoutingGraphNode>()
+            + self.edges.capacity() * std::mem::size_of::<RoutingGraphEdge>()
+    }
+
     pub fn node_count(&self) -> usize {
         self.nodes.len()
     }
@@ -167,13 +798,40 @@ impl RoutingGraph {
     pub fn edge_count(&self) -> usize {
         self.edges.len()
     }
+
+    /// Number of nodes driving `node`. Nodes driven by many others produce large
+    /// driver-exclusion constraints.
+    pub fn in_degree(&self, node: usize) -> usize {
+        self.edges_to(node).count()
+    }
+
+    /// Number of nodes driven by `node`.
+    pub fn out_degree(&self, node: usize) -> usize {
+        self.edges_from(node).count()
+    }
+
+    /// Numbers of nodes by their degree (sum of in-degree and out-degree). The value at
+    /// index `d` is the number of nodes of degree `d`. The histogram ends at the highest
+    /// degree present in the graph.
+    pub fn degree_histogram(&self) -> Vec<usize> {
+        let mut histogram = Vec::new();
+        for node in 0 .. self.nodes.len() {
+            let degree = self.in_degree(node) + self.out_degree(node);
+            if histogram.len() <= degree {
+                histogram.resize(degree + 1, 0);
+            }
+            histogram[degree] += 1;
+        }
+        histogram
+    }
 }
 
 /* This enum is currently being reused for both constraint requirements
  * and constraint activators, but later it it might prove to be useful to 
  * have two different enums for activators and requirements. */
 /// Represents a resource congesting nets.
-#[derive(PartialOrd, PartialEq, Ord, Eq, Clone, Debug, Serialize, Deserialize)]
+#[derive(PartialOrd, PartialEq, Ord, Eq, Hash, Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum ConstrainingElement {
     /// Usage of a port
     Port(u32),
@@ -188,6 +846,8 @@ pub struct PortToPortRouterFrame<A> {
     #[cfg(debug_assertions)]
     _cube_count: usize,
     pub accumulator: A,
+    /* Step of the route trace which queued this frame */
+    parent_step: Option<usize>,
 }
 
 /// PortToPort Router represents a routing context for net expansion coming from a selected
@@ -219,33 +879,119 @@ struct PortToPortRouter<'g, A> where A: Default + Clone + std::fmt::Debug + 'sta
     queue: VecDeque<PortToPortRouterFrame<A>>,
     callback: &'g Option<BruteRouterCallback<A>>,
     optimize_implies: bool,
+    deadline: Option<Instant>,
+    cancel: Option<&'g AtomicBool>,
+    /* Edges crossed by processed frames, if routes are traced */
+    trace: Option<Vec<RouteStep>>,
+}
+
+/* A single edge of a traced route. Following `parent` yields the rest of the route,
+ * back to the source pin. */
+struct RouteStep {
+    parent: Option<usize>,
+    from: SitePinId,
+    to: SitePinId,
 }
 
-#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 struct PTPRMarker {
     constraints: DNFForm<ConstrainingElement>,
     activated: DNFForm<ConstrainingElement>,
 }
 
+impl PTPRMarker {
+    fn new() -> Self {
+        Self {
+            constraints: DNFForm::new(),
+            activated: DNFForm::new(),
+        }
+    }
+
+    /* Empties the formulas, keeping their allocations */
+    fn reset(&mut self) {
+        self.constraints.cubes.clear();
+        self.activated.cubes.clear();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.constraints.cubes.is_empty() && self.activated.cubes.is_empty()
+    }
+}
+
 impl<'g, A> PortToPortRouter<'g, A> where A: Default + Clone + std::fmt::Debug + 'static {
     fn new(
         graph: &'g RoutingGraph,
         from: SitePinId,
         callback: &'g Option<BruteRouterCallback<A>>,
-        optimize_implies: bool
+        optimize_implies: bool,
+        deadline: Option<Instant>
+    ) -> Self {
+        Self::with_markers(graph, from, Vec::new(), callback, optimize_implies, deadline)
+    }
+
+    /// Creates a router which reuses `markers` left by a previous run, instead of
+    /// allocating new ones. The markers get reset.
+    fn with_markers(
+        graph: &'g RoutingGraph,
+        from: SitePinId,
+        mut markers: Vec<PTPRMarker>,
+        callback: &'g Option<BruteRouterCallback<A>>,
+        optimize_implies: bool,
+        deadline: Option<Instant>
     ) -> Self {
+        markers.truncate(graph.nodes.len());
+        markers.iter_mut().for_each(PTPRMarker::reset);
+        markers.resize_with(graph.nodes.len(), PTPRMarker::new);
+
         Self {
             graph,
             from,
-            markers: (0 .. graph.nodes.len()).map(|_| {
-                PTPRMarker {
-                    constraints: DNFForm::new(),
-                    activated: DNFForm::new(),
-                }
-            }).collect(),
+            markers,
             queue: VecDeque::new(),
             callback,
             optimize_implies,
+            deadline,
+            cancel: None,
+            trace: None,
+        }
+    }
+
+    /// Makes the router give up with `RoutingTimedOut` once `cancel` gets set.
+    fn with_cancel(mut self, cancel: Option<&'g AtomicBool>) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Makes the router record every edge crossed by the processed frames
+    /// (see `count_edge_usage`).
+    fn with_trace(mut self, trace: bool) -> Self {
+        self.trace = trace.then(Vec::new);
+        self
+    }
+
+    /// Adds up routes recorded in the trace, which reach pins accepted by `is_sink`.
+    /// Every such route increments the counts of all the edges along it.
+    fn count_edge_usage<F>(
+        &self,
+        usage: &mut HashMap<(SitePinId, SitePinId), usize>,
+        is_sink: F
+    ) where
+        F: Fn(SitePinId) -> bool
+    {
+        let trace = match &self.trace {
+            Some(trace) => trace,
+            None => return,
+        };
+        for (idx, step) in trace.iter().enumerate() {
+            if !is_sink(step.to) {
+                continue;
+            }
+            let mut step_idx = Some(idx);
+            while let Some(idx) = step_idx {
+                let step = &trace[idx];
+                *usage.entry((step.from, step.to)).or_insert(0) += 1;
+                step_idx = step.parent;
+            }
         }
     }
 
@@ -281,6 +1027,18 @@ impl<'g, A> PortToPortRouter<'g, A> where A: Default + Clone + std::fmt::Debug +
 
         dbg_log!(DBG_EXTRA2, "(RS FRAME) {:?}", frame);
 
+        let step = match (&mut self.trace, frame.prev_node) {
+            (Some(trace), Some(prev)) => {
+                trace.push(RouteStep {
+                    parent: frame.parent_step,
+                    from: prev,
+                    to: frame.node,
+                });
+                Some(trace.len() - 1)
+            },
+            _ => None,
+        };
+
         /* Callbacks for debugging */
         let (mut add_creq_cb, mut add_cact_cb, new_acc) =
             self.callback.as_ref().map(|callback| {
@@ -338,7 +1096,7 @@ impl<'g, A> PortToPortRouter<'g, A> where A: Default + Clone + std::fmt::Debug +
             self.markers[frame.node.0].constraints
         );
         
-        for next in self.graph.edges_from(frame.node.0) {
+        for next in self.graph.successors(frame.node.0) {
             let is_subformular =
                 self.is_constr_subformular(Some(frame.node), SitePinId(next));
             if !is_subformular {
@@ -350,6 +1108,7 @@ impl<'g, A> PortToPortRouter<'g, A> where A: Default + Clone + std::fmt::Debug +
                     #[cfg(debug_assertions)]
                     _cube_count: self.markers[frame.node.0].constraints.num_cubes(),
                     accumulator: new_acc.clone(),
+                    parent_step: step,
                 });
             }
         }
@@ -360,11 +1119,12 @@ impl<'g, A> PortToPortRouter<'g, A> where A: Default + Clone + std::fmt::Debug +
     fn scan_constraint_requirements(&self, node: SitePinId, prev_node: Option<SitePinId>)
         -> impl Iterator<Item = FormulaTerm<ConstrainingElement>> + 'g
     {
-        /* Add constraints for no multiple drivers (yield all except prev_node) */
+        /* Add constraints for no multiple drivers (yield all except prev_node).
+         * Only drivers connected through PIPs can be congested, site wires are fixed. */
         let graph = self.graph;
         prev_node.into_iter().map(move |prev| {
             graph.edges_to(node.0).filter_map(move |driver| {
-                (driver != prev.0)
+                (driver != prev.0 && graph.get_edge(driver, node.0).is_pip())
                     .then(|| FormulaTerm::NegVar(ConstrainingElement::Port(driver as u32)))
             })
         }).flatten()
@@ -374,12 +1134,9 @@ impl<'g, A> PortToPortRouter<'g, A> where A: Default + Clone + std::fmt::Debug +
         -> impl Iterator<Item = FormulaTerm<ConstrainingElement>> + 'g
     {
         let graph = self.graph;
-        prev_node.into_iter().map(move |prev| {
-            graph.edges_to(node.0).filter_map(move |pnode| {
-                (pnode == prev.0)
-                    .then(|| FormulaTerm::Var(ConstrainingElement::Port(prev.0 as u32)))  
-            })
-        }).flatten()
+        prev_node.into_iter()
+            .filter(move |prev| graph.has_edge(prev.0, node.0))
+            .map(|prev| FormulaTerm::Var(ConstrainingElement::Port(prev.0 as u32)))
     }
 
     fn init_constraints_and_activators(&mut self, node: usize) {
@@ -389,8 +1146,38 @@ impl<'g, A> PortToPortRouter<'g, A> where A: Default + Clone + std::fmt::Debug +
             .add_cube(DNFCube::new());
     }
 
-    fn route_all(mut self) -> Vec<PTPRMarker> {
+    /// Routes within a graph without routing choices (see `RoutingChoices`). Each pin
+    /// has at most one driver, so every reachable pin gets reached by a single route,
+    /// which requires nothing and implies usage of all the drivers along it. Produces
+    /// the same markers as `run`, without queueing frames.
+    fn run_unconstrained(&mut self) {
+        self.init_constraints_and_activators(self.from.0);
+
+        let mut pending = vec![self.from.0];
+        while let Some(node) = pending.pop() {
+            for next in self.graph.successors(node) {
+                if !self.markers[next].is_empty() {
+                    continue;
+                }
+                let activated = self.markers[node].activated.clone()
+                    .conjunct_term(&FormulaTerm::Var(ConstrainingElement::Port(node as u32)));
+                self.markers[next].constraints = DNFForm::new().add_cube(DNFCube::new());
+                self.markers[next].activated = activated;
+                pending.push(next);
+            }
+        }
+    }
+
+    fn route_all(mut self) -> Result<Vec<PTPRMarker>, RoutingTimedOut> {
+        self.run()?;
+        Ok(self.markers)
+    }
+
+    fn run(&mut self) -> Result<(), RoutingTimedOut> {
         self.init_constraints_and_activators(self.from.0);
+        if let Some(trace) = &mut self.trace {
+            trace.clear();
+        }
 
         self.queue.clear();
         self.queue.push_back(PortToPortRouterFrame {
@@ -401,11 +1188,20 @@ impl<'g, A> PortToPortRouter<'g, A> where A: Default + Clone + std::fmt::Debug +
             #[cfg(debug_assertions)]
             _cube_count: 1,
             accumulator: Default::default(),
+            parent_step: None,
         });
         loop {
-            if let None = self.routing_step() { return self.markers; }
-        }
-    }
+            if let Some(deadline) = self.deadline {
+                if Instant::now() >= deadline {
+                    return Err(RoutingTimedOut);
+                }
+            }
+            if is_cancelled(self.cancel) {
+                return Err(RoutingTimedOut);
+            }
+            if let None = self.routing_step() { return Ok(()); }
+        }
+    }
 }
 
 pub type BruteRouterCallback<A> = 
@@ -417,12 +1213,77 @@ pub type BruteRouterCallback<A> =
         ) + Send
     >>>;
 
+/* Results of routing from multiple sources: pin-to-pin routing, timed out pins, edge
+ * usage and whether routing got cancelled */
+type SourcesRouting = (
+    HashMap<(SitePinId, SitePinId), PinPairRoutingInfo>,
+    Vec<SitePinId>,
+    HashMap<(SitePinId, SitePinId), usize>,
+    bool,
+);
+
+/// Flag shared with routing threads, which makes them stop early once set
+/// (see `MultiThreadedBruteRouter`).
+pub type CancellationToken = Arc<AtomicBool>;
+
+fn is_cancelled(cancel: Option<&AtomicBool>) -> bool {
+    cancel.map_or(false, |cancel| cancel.load(Ordering::Relaxed))
+}
+
 pub struct BruteRouter<A> {
     st_id: u32,
     bels: Vec<BELInfo>,
     site_belpin_idx_to_bel_pin: Vec<(usize, usize)>,
     graph: RoutingGraph,
     callback: Option<BruteRouterCallback<A>>,
+    keep_unoptimized: bool,
+    /* Output nodes created by splitting `Inout` nodes */
+    split_inout_outputs: HashSet<usize>,
+    pin_timeout: Option<Duration>,
+    count_edge_usage: bool,
+    port_pairs_only: bool,
+    /* Site PIPs left out of the graph, as their pins are already joined by a site wire */
+    redundant_pips: Vec<(SitePinId, SitePinId)>,
+    /* Routing choices present in the graph (see `RoutingChoices`). Graph modifications
+     * only remove edges, so these stay valid as an upper bound. */
+    routing_choices: RoutingChoices,
+    /* Number of source pins routed through the fast path */
+    fast_path_routes: AtomicUsize,
+    query_cache: Mutex<QueryCache>,
+}
+
+/* Elements of a routing graph which make routes depend on each other. Without them, each
+ * pin is driven by at most one other pin, so routes are unique and unconstrained. */
+#[derive(Copy, Clone, Debug)]
+struct RoutingChoices {
+    /* Pins of routing BELs, or other PIP-like edges */
+    routing_bel_ports: bool,
+    /* Pins driven by more than one pin */
+    multi_driver_wires: bool,
+}
+
+impl RoutingChoices {
+    fn detect(graph: &RoutingGraph) -> Self {
+        let routing_bel_ports = (0 .. graph.node_count()).any(|node| {
+            matches!(graph.get_node(node).kind, RoutingGraphNodeKind::RoutingBelPort(_))
+                || graph.edges_to(node).any(|driver| graph.get_edge(driver, node).is_pip())
+        });
+        let multi_driver_wires = (0 .. graph.node_count())
+            .any(|node| graph.edges_to(node).nth(1).is_some());
+
+        Self { routing_bel_ports, multi_driver_wires }
+    }
+
+    fn any(&self) -> bool {
+        self.routing_bel_ports || self.multi_driver_wires
+    }
+}
+
+/* Routing results of source pins queried with `BruteRouter::query_pair` */
+#[derive(Default)]
+struct QueryCache {
+    routes: HashMap<(SitePinId, bool), Arc<Vec<PinPairRoutingInfo>>>,
+    misses: usize,
 }
 
 impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
@@ -466,12 +1327,37 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
     /// already present wires coming from the generators via extra BELs which act as PIPs.
     /// These are marked with `◦` symbol.
     /// 
+    /// Flows which route only one of the constants as a net can request just that one
+    /// (see `VirtualConsts`). Generators of the other constant are left as they are.
+    /// 
     /// # Arguments
     /// * `device` - `DeviceResources::Device` root
     /// * `st_id` - SiteType's ID
     /// * `add_virtual_consts` - add extra BELs and connections used for constant networks,
-    ///   see the explanations above
-    pub fn new<'a>(device: &'a Device<'a>, st_id: u32, add_virtual_consts: bool) -> Self {
+    ///   see the explanations above. `true` adds them for both constants.
+    /// 
+    /// # Panics
+    /// Panics if the site type is malformed. Use `try_new` to handle that gracefully.
+    pub fn new<'a, V>(device: &'a Device<'a>, st_id: u32, add_virtual_consts: V) -> Self where
+        V: Into<VirtualConsts>
+    {
+        match Self::try_new(device, st_id, add_virtual_consts) {
+            Ok(router) => router,
+            Err(OpenWriteError::MalformedDevice(msg)) => panic!("{}", msg),
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+
+    /// Same as `new`, but fails with `OpenWriteError::MalformedDevice` instead of
+    /// panicking if the site type refers to BELs or pins which don't exist.
+    pub fn try_new<'a, V>(device: &'a Device<'a>, st_id: u32, add_virtual_consts: V)
+        -> Result<Self, OpenWriteError>
+    where
+        V: Into<VirtualConsts>
+    {
+        let add_virtual_consts = add_virtual_consts.into();
+        profile_span!("build_graph", site_type = st_id);
+
         let st = device.get_site_type_list().unwrap().get(st_id);
 
         /* Create mappings between elements and indices */
@@ -485,7 +1371,11 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
         for (bel_idx, bel) in bels.iter().enumerate() {
             if bel_name_to_bel_idx.insert(bel.name, bel_idx).is_some() {
                 let gsctx = GlobalStringsCtx::hold();
-                panic!("BEL {} already exists", bel.name.get(device, &gsctx));
+                return Err(OpenWriteError::MalformedDevice(format!(
+                    "BEL {} already exists in site type {}",
+                    bel.name.get(device, &gsctx),
+                    device.ic_str(st.get_name())
+                )));
             }
             for pin_idx in 0 .. bel.pins.len() {
                 let r = tile_belpin_idx.insert((bel_idx, pin_idx), belpin_idx);
@@ -495,26 +1385,96 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
             }
         }
 
-        let graph = Self::create_routing_graph(
+        let (graph, redundant_pips) = Self::create_routing_graph(
             device,
             &st,
             &bels,
             &bel_name_to_bel_idx,
             &tile_belpin_idx,
             add_virtual_consts
-        );
+        )?;
 
         assert_eq!(tile_belpin_idx_to_bel_pin.len(), graph.nodes.len());
 
-        Self {
+        let router = Self {
             st_id,
             bels,
             site_belpin_idx_to_bel_pin: tile_belpin_idx_to_bel_pin,
+            routing_choices: RoutingChoices::detect(&graph),
             graph,
             callback: None,
+            keep_unoptimized: false,
+            split_inout_outputs: HashSet::new(),
+            pin_timeout: None,
+            count_edge_usage: false,
+            port_pairs_only: false,
+            redundant_pips,
+            fast_path_routes: AtomicUsize::new(0),
+            query_cache: Mutex::default(),
+        };
+
+        let gsctx = GlobalStringsCtx::hold();
+        for (from, to) in &router.redundant_pips {
+            dbg_log!(
+                DBG_INFO,
+                "Site PIP {} -> {} of site type {} duplicates a site wire and was left out",
+                router.get_pin_name(device, &gsctx, *from).to_string(),
+                router.get_pin_name(device, &gsctx, *to).to_string(),
+                device.ic_str(st.get_name())
+            );
         }
+
+        Ok(router)
     }
     
+    /// Create a BruteRouter out of previously extracted parts (see `get_bels`,
+    /// `get_site_belpin_map` and `get_graph`), without accessing the device.
+    /// 
+    /// # Arguments
+    /// * `st_id` - index of the site type the parts were extracted for
+    /// * `bels` - BELs of the site type
+    /// * `site_belpin_idx_to_bel_pin` - `(BEL index, pin index)` of each graph node
+    /// * `graph` - routing graph
+    pub fn from_parts(
+        st_id: u32,
+        bels: Vec<BELInfo>,
+        site_belpin_idx_to_bel_pin: Vec<(usize, usize)>,
+        graph: RoutingGraph
+    )
+        -> Self
+    {
+        assert_eq!(site_belpin_idx_to_bel_pin.len(), graph.nodes.len());
+
+        Self {
+            st_id,
+            bels,
+            site_belpin_idx_to_bel_pin,
+            routing_choices: RoutingChoices::detect(&graph),
+            graph,
+            callback: None,
+            keep_unoptimized: false,
+            split_inout_outputs: HashSet::new(),
+            pin_timeout: None,
+            count_edge_usage: false,
+            port_pairs_only: false,
+            redundant_pips: Vec::new(),
+            fast_path_routes: AtomicUsize::new(0),
+            query_cache: Mutex::default(),
+        }
+    }
+
+    pub fn get_st_id(&self) -> u32 {
+        self.st_id
+    }
+
+    pub fn get_bels<'s>(&'s self) -> &'s [BELInfo] {
+        &self.bels
+    }
+
+    pub fn get_site_belpin_map<'s>(&'s self) -> &'s [(usize, usize)] {
+        &self.site_belpin_idx_to_bel_pin
+    }
+
     /// Add a callback to the siterouter. The callback will be executed at each step
     /// and will gain access to the accumulator used by the router.
     /// The callback should return a new value for the accumulator, that will be
@@ -534,10 +1494,210 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
     {
         Self {
             callback: Some(Arc::new(Mutex::new(Box::new(callback)))),
+            query_cache: Mutex::default(),
             .. self
         }
     }
 
+    /// Make the router keep formulas in their unoptimized form alongside the optimized
+    /// ones. When enabled, both `requires` and `implies` get optimized regardless of the
+    /// `optimize` argument passed to routing functions and the original formulas are
+    /// stored in `PinPairRoutingInfo::unoptimized`.
+    /// 
+    /// # Arguments
+    /// * `keep` - whether to keep the unoptimized formulas
+    pub fn with_unoptimized_forms(self, keep: bool) -> Self {
+        Self {
+            keep_unoptimized: keep,
+            query_cache: Mutex::default(),
+            .. self
+        }
+    }
+
+    /// Normalize the routing graph to contain only pure input and output pins, by
+    /// splitting each `Inout` pin into an input node and an output node
+    /// (see `RoutingGraph::split_inout_nodes`). Both nodes refer to the same BEL pin.
+    /// The output nodes are never reported as sinks, so that each pin pair appears in
+    /// results only once.
+    /// 
+    /// # Arguments
+    /// * `flatten` - whether to split the `Inout` pins
+    pub fn with_flattened_inout(mut self, flatten: bool) -> Self {
+        if flatten {
+            for (output, node) in self.graph.split_inout_nodes() {
+                debug_assert_eq!(output, self.site_belpin_idx_to_bel_pin.len());
+                let bel_pin = self.site_belpin_idx_to_bel_pin[node];
+                self.site_belpin_idx_to_bel_pin.push(bel_pin);
+                self.split_inout_outputs.insert(output);
+            }
+            self.clear_query_cache();
+        }
+        self
+    }
+
+    /// Limit the time spent on routing from a single pin. Routing of pins exceeding the
+    /// limit gets abandoned and such pins are listed in `RoutingInfo::timed_out`.
+    /// 
+    /// # Arguments
+    /// * `timeout` - time limit for a single pin, `None` for no limit
+    pub fn with_pin_timeout(self, timeout: Option<Duration>) -> Self {
+        Self {
+            pin_timeout: timeout,
+            .. self
+        }
+    }
+
+    /// Make `route_all` count the discovered routes crossing each edge of the routing
+    /// graph (see `RoutingInfo::edge_usage`). Each route reaching a sink (a site port or
+    /// a pin of a logic BEL) increments the counts of all the edges along it, so heavily
+    /// shared edges stand out.
+    /// 
+    /// # Arguments
+    /// * `count` - whether to count edge usage
+    pub fn with_edge_usage(self, count: bool) -> Self {
+        Self {
+            count_edge_usage: count,
+            .. self
+        }
+    }
+
+    /// Make routing keep only pin pairs with at least one endpoint being a site port.
+    /// Pairs of BEL pins internal to the site are dropped, which shrinks the results of
+    /// dense sites considerably, as placers care mostly about routing between site ports
+    /// and BEL pins.
+    /// 
+    /// # Arguments
+    /// * `only` - whether to drop the internal pairs
+    pub fn with_port_pairs_only(self, only: bool) -> Self {
+        Self {
+            port_pairs_only: only,
+            query_cache: Mutex::default(),
+            .. self
+        }
+    }
+
+    /// Remove site PIPs of the given routing BELs from the routing graph, so that
+    /// the routing reflects a device with these PIPs disabled.
+    /// 
+    /// # Arguments
+    /// * `device` - device the router was created for
+    /// * `routing_bels` - names of routing BELs whose PIPs should be disabled
+    pub fn with_disabled_pips<'d, S>(mut self, device: &Device<'d>, routing_bels: &[S])
+        -> Self
+    where
+        S: AsRef<str>
+    {
+        if routing_bels.is_empty() {
+            return self;
+        }
+
+        let gsctx = GlobalStringsCtx::hold();
+        let disabled_bels: HashSet<_> = self.bels.iter()
+            .enumerate()
+            .filter(|(_, bel)| {
+                let name = bel.name.get(device, &gsctx);
+                routing_bels.iter().any(|rb| rb.as_ref() == &*name)
+            })
+            .map(|(bel_idx, _)| bel_idx)
+            .collect();
+
+        let node_count = self.graph.nodes.len();
+        for from in 0 .. node_count {
+            let (from_bel, _) = self.site_belpin_idx_to_bel_pin[from];
+            if !disabled_bels.contains(&from_bel) {
+                continue;
+            }
+            for to in 0 .. node_count {
+                let (to_bel, _) = self.site_belpin_idx_to_bel_pin[to];
+                if (to_bel == from_bel) &&
+                    (*self.graph.get_edge(from, to) == RoutingGraphEdge::PseudoPip)
+                {
+                    self.graph.disconnect(from, to);
+                }
+            }
+        }
+
+        self.clear_query_cache();
+        self
+    }
+
+    /// Site PIPs whose pins are already joined by a site wire, as `(input, output)`
+    /// pins. Such PIPs offer no routing choice, so they're left out of the routing graph
+    /// and never show up in constraints. Empty for routers created with `from_parts`.
+    pub fn redundant_pips(&self) -> &[(SitePinId, SitePinId)] {
+        &self.redundant_pips
+    }
+
+    /// Classifies a BEL based on kinds of its pins' nodes in the routing graph.
+    pub fn bel_kind(&self, bel_idx: usize) -> BELKind {
+        let mut kind = BELKind::Logic;
+        for (node, (node_bel, _)) in self.site_belpin_idx_to_bel_pin.iter().enumerate() {
+            if *node_bel != bel_idx {
+                continue;
+            }
+            match self.graph.get_node(node).kind {
+                RoutingGraphNodeKind::SitePort(_) => return BELKind::SitePort,
+                RoutingGraphNodeKind::RoutingBelPort(_) => kind = BELKind::Routing,
+                RoutingGraphNodeKind::BelPort(_) | RoutingGraphNodeKind::FreePort => (),
+            }
+        }
+        kind
+    }
+
+    /// Remove all edges leading to and from pins of BELs of the given kinds, so that
+    /// routes can't pass through or end at these BELs. Eg. excluding logic BELs leaves
+    /// only connectivity between site ports through routing BELs.
+    /// 
+    /// # Arguments
+    /// * `kinds` - kinds of BELs to be excluded
+    pub fn with_excluded_bel_kinds(mut self, kinds: &[BELKind]) -> Self {
+        if kinds.is_empty() {
+            return self;
+        }
+
+        let excluded_bels: HashSet<_> = (0 .. self.bels.len())
+            .filter(|bel_idx| kinds.contains(&self.bel_kind(*bel_idx)))
+            .collect();
+
+        let node_count = self.graph.nodes.len();
+        for node in 0 .. node_count {
+            let (bel, _) = self.site_belpin_idx_to_bel_pin[node];
+            if !excluded_bels.contains(&bel) {
+                continue;
+            }
+            for other in 0 .. node_count {
+                self.graph.disconnect(node, other);
+                self.graph.disconnect(other, node);
+            }
+        }
+
+        self.clear_query_cache();
+        self
+    }
+
+    /// Replace names of BELs and pins loaded from the device with virtual names holding
+    /// the same strings. Such router can be used without the device, eg. on other threads,
+    /// as device readers can't be shared between them.
+    /// 
+    /// # Arguments
+    /// * `device` - device the router was created for
+    pub fn with_detached_names<'d>(mut self, device: &Device<'d>) -> Self {
+        let mut gsctx = GlobalStringsCtx::hold();
+        let mut detach = |name: &mut ResourceName| {
+            if let ResourceName::DeviceResources(id) = *name {
+                *name = ResourceName::Virtual(gsctx.create_global_string(device.ic_str(id)));
+            }
+        };
+
+        for bel in &mut self.bels {
+            detach(&mut bel.name);
+            for pin in &mut bel.pins {
+                detach(&mut pin.name);
+            }
+        }
+        self
+    }
+
     /// Initialize BEL information associated with graph nodes
     fn init_bels_in_graph(
         graph: &mut RoutingGraph,
@@ -563,51 +1723,116 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
         }
     }
 
+    /// Finds graph nodes of pins connected to a site wire.
+    /// 
+    /// # Return
+    /// A pair of lists of nodes: drivers and sinks. `Inout` pins are present in both.
+    /// Fails with `OpenWriteError::MalformedDevice` if the wire refers to a BEL pin
+    /// which doesn't exist.
+    fn resolve_site_wire_pins<'d>(
+        device: &Device<'d>,
+        st: &crate::ic_loader::archdef::SiteTypeReader<'d>,
+        wire: &DeviceResources_capnp::device::site_wire::Reader<'d>,
+        bels: &[BELInfo],
+        bel_name_to_bel_idx: &HashMap<ResourceName, usize>,
+        tile_belpin_idx: &HashMap<(usize, usize), usize>
+    )
+        -> Result<(Vec<usize>, Vec<usize>), OpenWriteError>
+    {
+        let mut drivers = Vec::new();
+        let mut sinks = Vec::new();
+
+        let malformed = |what: String| OpenWriteError::MalformedDevice(format!(
+            "Site wire {} of site type {} {}",
+            device.ic_str(wire.get_name()),
+            device.ic_str(st.get_name()),
+            what
+        ));
+
+        let ic_bel_pins = st.reborrow().get_bel_pins().unwrap();
+        for pin_idx in wire.get_pins().unwrap() {
+            if pin_idx >= ic_bel_pins.len() {
+                return Err(malformed(format!(
+                    "references BEL pin #{}, but the site type has only {} BEL pins",
+                    pin_idx,
+                    ic_bel_pins.len()
+                )));
+            }
+            let ic_pin = ic_bel_pins.get(pin_idx);
+            let ic_bel_name = device.ic_str(ic_pin.get_bel());
+            let ic_pin_name = device.ic_str(ic_pin.get_name());
+
+            let bel_idx = *bel_name_to_bel_idx
+                .get(&ResourceName::DeviceResources(ic_pin.get_bel()))
+                .ok_or_else(|| malformed(format!(
+                    "references pin {}.{}, but BEL {} doesn't exist",
+                    ic_bel_name, ic_pin_name, ic_bel_name
+                )))?;
+            let bel = &bels[bel_idx];
+            let (pin_idx, pin) = bel.pins.iter()
+                .enumerate()
+                .find(|(_, pin)|
+                    pin.name == ResourceName::DeviceResources(ic_pin.get_name())
+                )
+                .ok_or_else(|| malformed(format!(
+                    "references pin {}.{}, but BEL {} has no pin {}",
+                    ic_bel_name, ic_pin_name, ic_bel_name, ic_pin_name
+                )))?;
+            let tbpidx = tile_belpin_idx[&(bel_idx, pin_idx)];
+            if let PinDir::Output | PinDir::Inout = pin.dir {
+                drivers.push(tbpidx);
+            }
+            if let PinDir::Input | PinDir::Inout = pin.dir {
+                sinks.push(tbpidx);
+            }
+        }
+
+        Ok((drivers, sinks))
+    }
+
     /// Create connections between BELs in site's routing graph based on the site wires
     /// present in DeviceResources.
     fn init_site_wires_in_graph<'d>(
         graph: &mut RoutingGraph,
+        device: &Device<'d>,
         st: &crate::ic_loader::archdef::SiteTypeReader<'d>,
         bels: &[BELInfo],
         bel_name_to_bel_idx: &HashMap<ResourceName, usize>,
         tile_belpin_idx: &HashMap<(usize, usize), usize>
-    ) {
+    )
+        -> Result<(), OpenWriteError>
+    {
         let sw_list = st.get_site_wires().unwrap();
         
         for wire in sw_list {
-            let mut drivers = Vec::new();
-            let mut sinks = Vec::new();
-
-            for pin_idx in wire.get_pins().unwrap() {
-                let ic_pin = st.reborrow().get_bel_pins().unwrap().get(pin_idx);
-                let bel_idx = bel_name_to_bel_idx[
-                    &ResourceName::DeviceResources(ic_pin.get_bel())
-                ];
-                let bel = &bels[bel_idx];
-                let ic_pin_name = ic_pin.get_name();
-                let (pin_idx, pin) = bel.pins.iter()
-                    .enumerate()
-                    .find(|(_, pin)|
-                        pin.name == ResourceName::DeviceResources(ic_pin_name)
-                    ).unwrap();    
-                let tbpidx = tile_belpin_idx[&(bel_idx, pin_idx)];
-                if let PinDir::Output | PinDir::Inout = pin.dir {
-                    drivers.push(tbpidx);
-                }
-                if let PinDir::Input | PinDir::Inout = pin.dir {
-                    sinks.push(tbpidx);
-                }
+            let (drivers, sinks) = Self::resolve_site_wire_pins(
+                device,
+                st,
+                &wire,
+                bels,
+                bel_name_to_bel_idx,
+                tile_belpin_idx
+            )?;
+
+            for pin in wire_self_loops(&drivers, &sinks) {
+                dbg_log!(
+                    DBG_WARN,
+                    "Site wire {} of site type {} connects pin {} only to itself",
+                    device.ic_str(wire.get_name()),
+                    device.ic_str(st.get_name()),
+                    pin
+                );
             }
 
             for driver in drivers {
                 for sink in &sinks {
-                    /* XXX: driver can equal to sink in case of Inout */
-                    if driver != *sink {
-                        let _ = graph.connect(driver, *sink);
-                    }
+                    /* driver equals sink in case of Inout, `connect` skips such pairs */
+                    let _ = graph.connect(driver, *sink, RoutingGraphEdge::SiteWire);
                 }
             }
         }
+
+        Ok(())
     }
 
     /// Create connections that represent pseudo-PIPs (routing BELs) in site's routing graph.
@@ -617,7 +1842,10 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
         bels: &[BELInfo],
         bel_name_to_bel_idx: &HashMap<ResourceName, usize>,
         tile_belpin_idx: &HashMap<(usize, usize), usize>
-    ) {
+    )
+        -> Vec<(SitePinId, SitePinId)>
+    {
+        let mut redundant = Vec::new();
         let ic_bel_pins = st.reborrow().get_bel_pins().unwrap();
         for spip in st.get_site_p_i_ps().unwrap() {
             let in_pin_idx = spip.get_inpin();
@@ -669,8 +1897,20 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
                     panic!("Pin {} uninitialized", tile_in_pin_idx)
             }
 
-            let _ = graph.connect(tile_in_pin_idx, tile_out_pin_idx);
+            /* A site wire already joining the pins always connects them, so the PIP
+             * offers no choice. Keeping the wire edge keeps the PIP out of constraints. */
+            if *graph.get_edge(tile_in_pin_idx, tile_out_pin_idx) == RoutingGraphEdge::SiteWire {
+                redundant.push((SitePinId(tile_in_pin_idx), SitePinId(tile_out_pin_idx)));
+                continue;
+            }
+
+            let _ = graph.connect(
+                tile_in_pin_idx,
+                tile_out_pin_idx,
+                RoutingGraphEdge::PseudoPip
+            );
         }
+        redundant
     }
 
     /// Creates connections between `$VCC`, `$GND` site-ports and routing BELs associated
@@ -681,7 +1921,8 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
         st: &crate::ic_loader::archdef::SiteTypeReader<'d>,
         bels: &[BELInfo],
         bel_name_to_bel_idx: &HashMap<ResourceName, usize>,
-        tile_belpin_idx: &HashMap<(usize, usize), usize>
+        tile_belpin_idx: &HashMap<(usize, usize), usize>,
+        virtual_consts: VirtualConsts
     ) {
         use crate::ic_loader::DeviceResources_capnp::device::ConstantType;
         
@@ -689,12 +1930,16 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
     
         let mut gsctx = GlobalStringsCtx::hold();
 
-        let vcc_bel_name = gsctx.create_global_string("$VCC");
+        let const_bel_names = gsctx.create_global_strings([
+            virtual_const_port_name(device, st, "$VCC"),
+            virtual_const_port_name(device, st, "$GND"),
+        ]);
+        let (vcc_bel_name, gnd_bel_name) = (const_bel_names[0], const_bel_names[1]);
+
         let vcc_bel_idx_opt = bel_name_to_bel_idx.get(
             &ResourceName::Virtual(vcc_bel_name)
         );
         
-        let gnd_bel_name = gsctx.create_global_string("$GND");
         let gnd_bel_idx_opt = bel_name_to_bel_idx.get(
             &ResourceName::Virtual(gnd_bel_name)
         );
@@ -703,7 +1948,10 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
 
         let sources =
             device.get_constants().unwrap().get_site_sources().unwrap().iter()
-                .filter(|src| src.get_site_type() == st.get_name());
+                .filter(|src| {
+                    src.get_site_type() == st.get_name()
+                        && virtual_consts.includes(src.get_constant().unwrap())
+                });
         
         let mut gsctx = GlobalStringsCtx::hold();
 
@@ -735,14 +1983,22 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
             ).unwrap();
 
             let src_belpin = tile_belpin_idx[&(src_bel_idx, src_pin_idx)];
-            let src_wire_id = bel_pin_to_wire[&(src.get_bel(), src.get_bel_pin())];
-            let src_wire = sw_list.get(src_wire_id);
+            let src_wire_ids = &bel_pin_to_wire[&(src.get_bel(), src.get_bel_pin())];
 
-            /* Borrowing rules won't let me iterate over connections and add
-                * new ones at the same time.
-                * TODO: implement an associated function for `RoutingGraph` that would
-                * allow creating new edges while iterating over existing ones. */
-            let sinks: Vec<_> = graph.edges_from(src_belpin).collect();
+            /* A constant source pin can drive multiple site wires. Collect sinks of
+             * all of them. */
+            let mut sinks = Vec::new();
+            for src_wire_id in src_wire_ids {
+                let (_, wire_sinks) = Self::resolve_site_wire_pins(
+                    device,
+                    st,
+                    &sw_list.get(*src_wire_id),
+                    bels,
+                    bel_name_to_bel_idx,
+                    tile_belpin_idx
+                ).expect("Site wires are resolved before adding virtual wires");
+                sinks.extend(wire_sinks.into_iter().filter(|sink| *sink != src_belpin));
+            }
 
             let net_name = gsctx.get_global_string(from_bel_name).to_string();
             let pip_bel_name =
@@ -759,8 +2015,8 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
             let pip_bel_output_pin_idx = bels[pip_bel_idx]
                 .find_pin(create_vconst_net_wire_name(net_name, &mut gsctx))
                 .unwrap_or_else(|| panic!(
-                    "Can't find PIP BEL pin for const source wire `{}`",
-                    device.ic_str(src_wire.get_name())
+                    "Can't find PIP BEL pin for const source wire of `{}`",
+                    device.ic_str(src.get_bel())
                 ));
             
             let pip_bel_input_belpin =
@@ -769,14 +2025,22 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
                 tile_belpin_idx[&(pip_bel_idx, pip_bel_output_pin_idx)];
                 
             /* Connect the BEL pip to virtual site-port BEL */
-            graph.connect(from_belpin_idx, pip_bel_input_belpin);
+            graph.connect(
+                from_belpin_idx,
+                pip_bel_input_belpin,
+                RoutingGraphEdge::VirtualConst
+            );
 
             /* Create pseoudo-pip connection */
-            graph.connect(pip_bel_input_belpin, pip_bel_output_belpin);
+            graph.connect(
+                pip_bel_input_belpin,
+                pip_bel_output_belpin,
+                RoutingGraphEdge::VirtualConst
+            );
 
             /* Create connections to original sinks */
             for sink in sinks {
-                graph.connect(pip_bel_output_belpin, sink);
+                graph.connect(pip_bel_output_belpin, sink, RoutingGraphEdge::VirtualConst);
             }
         }
     }
@@ -787,22 +2051,23 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
         bels: &[BELInfo],
         bel_name_to_bel_idx: &HashMap<ResourceName, usize>,
         site_belpin_idx: &HashMap<(usize, usize), usize>,
-        add_virtual_consts: bool
+        virtual_consts: VirtualConsts
     )
-        -> RoutingGraph
+        -> Result<(RoutingGraph, Vec<(SitePinId, SitePinId)>), OpenWriteError>
     {
         let mut graph = RoutingGraph::new(site_belpin_idx.len());
 
         Self::init_bels_in_graph(&mut graph, bels, site_belpin_idx);
         Self::init_site_wires_in_graph(
             &mut graph,
+            device,
             st,
             bels,
             bel_name_to_bel_idx,
             site_belpin_idx
-        );
+        )?;
        
-        Self::init_pseudopips_in_graph(
+        let redundant_pips = Self::init_pseudopips_in_graph(
             &mut graph,
             st,
             bels,
@@ -810,17 +2075,20 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
             site_belpin_idx
         );
 
-        if add_virtual_consts {
+        if virtual_consts.any() {
             Self::init_virtual_wires_in_graph(
                 &mut graph,
                 device,
                 st,
                 bels,
                 bel_name_to_bel_idx,
-                site_belpin_idx
+                site_belpin_idx,
+                virtual_consts
             )
         }
 
+        debug_assert!(graph.self_loops().next().is_none());
+
         /* Check that all nodes have been initialized. */
         #[cfg(debug_assertions)]
         assert!(
@@ -832,7 +2100,7 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
             }) { true } else { false }
         );
 
-        graph
+        Ok((graph, redundant_pips))
     }
 
     pub fn get_pin_id<'d>(
@@ -846,36 +2114,31 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
         let st_list = device.get_site_type_list().unwrap();
         let st = st_list.get(self.st_id);
 
-        let mut bel_found = None;
-
         let gsctx = GlobalStringsCtx::hold();
 
-        (0 .. self.graph.nodes.len())
-            .find(|belpin_id| {
-                let (bel_id, bel_pin_id) = self.site_belpin_idx_to_bel_pin[*belpin_id];
-                if &*self.bels[bel_id].name.get(device, &gsctx) == bel_name {
-                    bel_found = Some(bel_id);
-                } else {
-                    return false;
-                } 
-                let bel_pin_name = self.bels[bel_id].pins[bel_pin_id].name
-                    .get(device, &gsctx);
-                &*bel_pin_name == pin_name
-            }).ok_or_else(|| match bel_found {
-                    Some(bel_id) => format!(
-                        "Pin {}/{}.{} not found",
-                        device.ic_str(st.get_name()),
-                        self.bels[bel_id].name.get(device, &gsctx),
-                        pin_name
-                    ),
-                    None => format!(
-                        "BEL {}/{} not found",
-                        device.ic_str(st.get_name()),
-                        bel_name
-                    ),
-                }
-            )
-            .map(SitePinId)
+        /* The BEL is looked up first, so that a missing pin isn't reported as a missing
+         * BEL, or the other way around */
+        let bel_id = self.bels.iter()
+            .position(|bel| &*bel.name.get(device, &gsctx) == bel_name)
+            .ok_or_else(|| format!(
+                "BEL {}/{} not found",
+                device.ic_str(st.get_name()),
+                bel_name
+            ))?;
+
+        let bel_pin_id = self.bels[bel_id].pins.iter()
+            .position(|pin| &*pin.name.get(device, &gsctx) == pin_name)
+            .ok_or_else(|| format!(
+                "Pin {}/{}.{} not found",
+                device.ic_str(st.get_name()),
+                bel_name,
+                pin_name
+            ))?;
+
+        let belpin_id = self.site_belpin_idx_to_bel_pin.iter()
+            .position(|bel_pin| *bel_pin == (bel_id, bel_pin_id))
+            .expect("Every pin of a BEL has a site pin");
+        Ok(SitePinId(belpin_id))
     }
 
     pub fn get_pin_name<'d>(
@@ -886,38 +2149,300 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
     )
         -> SitePinName<'d, 'd, impl Borrow<str> + 'd, impl Borrow<str> + 'd>
     {
-        let (bel_id, bel_pin_id) = self.site_belpin_idx_to_bel_pin[pin_id.0];
+        let (bel_id, bel_pin_id) = self.pin_to_bel_pin(pin_id);
         let bel = self.bels[bel_id].name.get(device, gsctx);
         let pin = self.bels[bel_id].pins[bel_pin_id].name.get(device, gsctx);
 
         return SitePinName::new(bel, pin)
     }
 
+    /// Resolves names of all site pins at once, indexed by `SitePinId`. This holds the
+    /// global strings context only once, which is considerably cheaper than calling
+    /// `get_pin_name` for every pin being serialized.
+    pub fn pin_names(&self, device: &Device) -> Vec<String> {
+        let gsctx = GlobalStringsCtx::hold();
+
+        (0 .. self.graph.node_count())
+            .map(|pin| self.get_pin_name(device, &gsctx, SitePinId(pin)).to_string())
+            .collect()
+    }
+
+    /// Resolves a site pin into a pair of indices: an index of a BEL within the site type
+    /// and an index of a pin within that BEL.
+    pub fn pin_to_bel_pin(&self, pin_id: SitePinId) -> (usize, usize) {
+        self.site_belpin_idx_to_bel_pin[pin_id.0]
+    }
+
+    /// Returns the BEL which a given site pin belongs to.
+    pub fn bel_of_pin<'s>(&'s self, pin_id: SitePinId) -> &'s BELInfo {
+        let (bel_id, _) = self.pin_to_bel_pin(pin_id);
+        &self.bels[bel_id]
+    }
+
+    /// Routes from a given pin. Yields routing information for every pin of the site,
+    /// in order of pin indices.
+    /// 
+    /// Fails if routing takes longer than allowed (see `with_pin_timeout`).
     pub fn route_pins(
         &self,
         from: SitePinId,
         optimize: bool
     )
-        -> impl Iterator<Item = PinPairRoutingInfo>
+        -> Result<impl Iterator<Item = PinPairRoutingInfo>, RoutingTimedOut>
     {
-        let router = PortToPortRouter::<A>::new(&self.graph, from, &self.callback, optimize);
-        router.route_all()
+        self.route_pins_until(from, optimize, None)
+    }
+
+    /// Same as `route_pins`, but gives up with `RoutingTimedOut` once `stop` gets set. This
+    /// lets a callback (see `with_callback`) end routing once it has seen enough.
+    pub fn route_pins_until(
+        &self,
+        from: SitePinId,
+        optimize: bool,
+        stop: Option<&AtomicBool>
+    )
+        -> Result<impl Iterator<Item = PinPairRoutingInfo>, RoutingTimedOut>
+    {
+        let deadline = self.pin_timeout.map(|timeout| Instant::now() + timeout);
+        let router = PortToPortRouter::<A>::new(
+            &self.graph,
+            from,
+            &self.callback,
+            optimize,
+            deadline
+        ).with_cancel(stop);
+        let keep_unoptimized = self.keep_unoptimized;
+        Ok(router.route_all()?
             .into_iter()
-            .map(move |mut marker| {
-                if optimize {
-                    marker.constraints = marker.constraints.optimize()
-                }
-                marker
-            })
-            .map(Into::into)
+            .map(move |marker| Self::marker_into_ppri(marker, keep_unoptimized, optimize)))
     }
 
-    fn route_range(&self, range: std::ops::Range<SitePinId>, optimize: bool)
-        -> HashMap<(SitePinId, SitePinId), PinPairRoutingInfo>
+    fn marker_into_ppri(mut marker: PTPRMarker, keep_unoptimized: bool, optimize: bool)
+        -> PinPairRoutingInfo
+    {
+        profile_span!("optimize");
+
+        if keep_unoptimized {
+            /* Route once, optimize a clone */
+            let unoptimized = UnoptimizedForms {
+                requires: marker.constraints.cubes.clone(),
+                implies: marker.activated.cubes.clone(),
+            };
+            marker.constraints = marker.constraints.optimize();
+            marker.activated = marker.activated.optimize();
+            let mut ppri: PinPairRoutingInfo = marker.into();
+            ppri.unoptimized = Some(unoptimized);
+            return ppri;
+        }
+        if optimize {
+            marker.constraints = marker.constraints.optimize()
+        }
+        marker.into()
+    }
+
+    /// Same as `route_pins`, but reuses the buffer of `markers` from previous runs to
+    /// avoid allocating it for every source pin. Only pins reached from `from` are yielded, along with
+    /// their indices. Routes reaching sinks are added to `edge_usage`, if given.
+    /// Setting `cancel` abandons routing, as if it timed out.
+    fn route_pins_reusing<'s>(
+        &'s self,
+        from: SitePinId,
+        optimize: bool,
+        markers: &'s mut Vec<PTPRMarker>,
+        edge_usage: Option<&mut HashMap<(SitePinId, SitePinId), usize>>,
+        cancel: Option<&AtomicBool>
+    )
+        -> Result<impl Iterator<Item = (usize, PinPairRoutingInfo)> + 's, RoutingTimedOut>
+    {
+        profile_span!("route_pin", pin = from.0);
+
+        let deadline = self.pin_timeout.map(|timeout| Instant::now() + timeout);
+        let mut router = PortToPortRouter::<A>::with_markers(
+            &self.graph,
+            from,
+            std::mem::take(markers),
+            &self.callback,
+            optimize,
+            deadline
+        ).with_trace(edge_usage.is_some()).with_cancel(cancel);
+
+        /* Callbacks expect to see every routing step, so they rule the fast path out */
+        let fast_path = !self.routing_choices.any()
+            && self.callback.is_none()
+            && edge_usage.is_none();
+        let result = if fast_path {
+            self.fast_path_routes.fetch_add(1, Ordering::Relaxed);
+            router.run_unconstrained();
+            Ok(())
+        } else {
+            router.run()
+        };
+        if let (Ok(()), Some(edge_usage)) = (&result, edge_usage) {
+            /* Pins of routing BELs are passed through on the way to the actual sinks,
+             * counting routes ending at them would count the edges before them again */
+            router.count_edge_usage(edge_usage, |to| {
+                (to != from)
+                    && !self.split_inout_outputs.contains(&to.0)
+                    && !matches!(
+                        self.graph.get_node(to.0).kind,
+                        RoutingGraphNodeKind::RoutingBelPort(_)
+                    )
+            });
+        }
+        *markers = router.markers;
+        result?;
+
+        let keep_unoptimized = self.keep_unoptimized;
+        Ok(markers.iter_mut()
+            .enumerate()
+            .filter(|(_, marker)| !marker.is_empty())
+            .map(move |(to, marker)| {
+                /* Formulas are moved out, only the buffer of markers gets reused */
+                let marker = std::mem::replace(marker, PTPRMarker::new());
+                (to, Self::marker_into_ppri(marker, keep_unoptimized, optimize))
+            }))
+    }
+
+    /// Counts distinct routes from `from` to `to`, ie. paths in the routing graph which
+    /// don't pass through any pin twice. The number of routes can grow exponentially
+    /// with the size of the site, so counting stops once `max_routes` are found or
+    /// `max_frames` pins have been entered. Pins which can't reach `to` are never entered.
+    /// 
+    /// # Return
+    /// The number of routes found and whether counting stopped before all the routes were
+    /// explored
+    pub fn count_routes(
+        &self,
+        from: SitePinId,
+        to: SitePinId,
+        max_routes: Option<usize>,
+        max_frames: Option<usize>
+    )
+        -> (usize, bool)
+    {
+        let max_routes = max_routes.unwrap_or(usize::MAX);
+        let mut frames_left = max_frames.unwrap_or(usize::MAX);
+        let reaches_sink = self.graph.reachable([to.0], false);
+        if !reaches_sink[from.0] || (frames_left == 0) {
+            return (0, reaches_sink[from.0]);
+        }
+
+        let mut on_route = vec![false; self.graph.node_count()];
+        let mut count = 0;
+
+        /* Pins of the route explored so far, along with their successors left to visit */
+        on_route[from.0] = true;
+        frames_left -= 1;
+        let mut stack = vec![(from.0, self.graph.successors(from.0))];
+        while let Some((_, successors)) = stack.last_mut() {
+            if count >= max_routes {
+                return (count, true);
+            }
+            match successors.next() {
+                Some(node) if node == to.0 => count += 1,
+                Some(node) if !on_route[node] && reaches_sink[node] => {
+                    if frames_left == 0 {
+                        return (count, true);
+                    }
+                    frames_left -= 1;
+                    on_route[node] = true;
+                    stack.push((node, self.graph.successors(node)));
+                },
+                Some(_) => (),
+                None => {
+                    let (node, _) = stack.pop().unwrap();
+                    on_route[node] = false;
+                },
+            }
+        }
+        (count, false)
+    }
+
+    /// Gets routing information for a single pin pair. Routing results are cached per
+    /// source pin, so subsequent queries for other sinks of the same source don't route
+    /// again. Timeouts are not cached.
+    /// 
+    /// # Arguments
+    /// * `from` - source pin
+    /// * `to` - sink pin
+    /// * `optimize` - optimize `requires` formulas (cached separately)
+    /// 
+    /// # Return
+    /// `None` if either of the pins is out of range of the site type's pins
+    pub fn query_pair(&self, from: SitePinId, to: SitePinId, optimize: bool)
+        -> Result<Option<PinPairRoutingInfo>, RoutingTimedOut>
+    {
+        let node_count = self.graph.node_count();
+        if (from.0 >= node_count) || (to.0 >= node_count) {
+            return Ok(None);
+        }
+        Ok(Some(self.cached_route_pins(from, optimize)?[to.0].clone()))
+    }
+
+    /// Like `query_pair`, but also returns `None` for pairs that would be left out of
+    /// results of `route_all`, ie. pairs that are unroutable or start at an input pin.
+    pub fn query_routed_pair(&self, from: SitePinId, to: SitePinId, optimize: bool)
+        -> Result<Option<PinPairRoutingInfo>, RoutingTimedOut>
+    {
+        let routing_info = match self.query_pair(from, to, optimize)? {
+            Some(routing_info) => routing_info,
+            None => return Ok(None),
+        };
+        if let PinDir::Input = self.graph.get_node(from.0).dir {
+            return Ok(None);
+        }
+        Ok(self.is_routed_pair(from, to, &routing_info).then(|| routing_info))
+    }
+
+    fn cached_route_pins(&self, from: SitePinId, optimize: bool)
+        -> Result<Arc<Vec<PinPairRoutingInfo>>, RoutingTimedOut>
+    {
+        if let Some(routes) = self.query_cache.lock().unwrap().routes.get(&(from, optimize)) {
+            return Ok(Arc::clone(routes));
+        }
+
+        /* Don't hold the lock while routing, so that other sources can be queried
+         * concurrently */
+        let routes = Arc::new(self.route_pins(from, optimize)?.collect::<Vec<_>>());
+
+        let mut cache = self.query_cache.lock().unwrap();
+        cache.misses += 1;
+        Ok(Arc::clone(cache.routes.entry((from, optimize)).or_insert(routes)))
+    }
+
+    /// Number of source pins routed through the fast path, which skips gathering
+    /// constraints in sites without routing BELs and wires with multiple drivers.
+    /// In such sites all routes are unique and unconstrained.
+    pub fn fast_path_count(&self) -> usize {
+        self.fast_path_routes.load(Ordering::Relaxed)
+    }
+
+    /// Number of times `query_pair` had to route from a source pin, because its results
+    /// were not cached.
+    pub fn query_cache_misses(&self) -> usize {
+        self.query_cache.lock().unwrap().misses
+    }
+
+    /// Drops routing results cached by `query_pair`.
+    pub fn clear_query_cache(&mut self) {
+        *self.query_cache.get_mut().unwrap() = QueryCache::default();
+    }
+
+    /// Routes from all pins within `range`, until `cancel` gets set.
+    /// 
+    /// # Return
+    /// Routing information of pin pairs, a list of pins which timed out, edge usage
+    /// (empty unless enabled with `with_edge_usage`) and whether routing was cancelled
+    fn route_range(
+        &self,
+        range: std::ops::Range<SitePinId>,
+        optimize: bool,
+        cancel: Option<&AtomicBool>
+    )
+        -> SourcesRouting
     {
-        let mut pin_to_pin_map = HashMap::new();
         if range.is_empty() {
-            return pin_to_pin_map;
+            return (HashMap::new(), Vec::new(), HashMap::new(), false);
         }
 
         let pin_cnt = self.graph.nodes.len();
@@ -926,22 +2451,186 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
         debug_assert!(range.end <= range.end);
 
         /* XXX: std::iter::Step is experimental, but required to iterate elegantly */
-        for from in range.start.0 .. range.end.0 {
-            if let PinDir::Input = self.graph.get_node(from).dir {
-                continue; /* We don't need routing information for input pins */
+        self.route_sources((range.start.0 .. range.end.0).map(SitePinId), optimize, cancel)
+    }
+
+    fn is_site_port_pin(&self, pin_id: SitePinId) -> bool {
+        self.bel_of_pin(pin_id).category == BELCategory::SitePort
+    }
+
+    /// Routes from all pins yielded by `sources`. Once `cancel` gets set, the pin being
+    /// routed is abandoned and the remaining pins are skipped.
+    /// 
+    /// # Return
+    /// Routing information of pin pairs, a list of pins which timed out, edge usage
+    /// (empty unless enabled with `with_edge_usage`) and whether routing was cancelled
+    fn route_sources<I>(&self, sources: I, optimize: bool, cancel: Option<&AtomicBool>)
+        -> SourcesRouting
+    where
+        I: IntoIterator<Item = SitePinId>
+    {
+        let mut pin_to_pin_map = HashMap::new();
+        let mut timed_out = Vec::new();
+        let mut edge_usage = HashMap::new();
+
+        /* Markers are reused across source pins to avoid reallocating them */
+        let mut markers = Vec::new();
+
+        for from in sources {
+            if is_cancelled(cancel) {
+                return (pin_to_pin_map, timed_out, edge_usage, true);
             }
-            dbg_log!(DBG_EXTRA1, "Routing from pin {}/{}", from, pin_cnt);
-            let routing_results = self.route_pins(SitePinId(from), optimize);
-            for (to, routing_info) in routing_results.enumerate() {
-                if to == from { continue; }
-                if (routing_info.requires.len() != 0) || (routing_info.implies.len() != 0) {
-                    pin_to_pin_map.insert((SitePinId(from), SitePinId(to)), routing_info);
+            let usage = if self.count_edge_usage { Some(&mut edge_usage) } else { None };
+            match self.route_source::<Vec<_>>(from, optimize, &mut markers, usage, cancel) {
+                Ok(Some(routing_results)) =>
+                    pin_to_pin_map.extend(routing_results.into_iter()
+                        .map(|(to, routing_info)| ((from, to), routing_info))),
+                Ok(None) => (),
+                /* Edge usage of the abandoned pin is never counted, as it didn't finish */
+                Err(RoutingTimedOut) if is_cancelled(cancel) =>
+                    return (pin_to_pin_map, timed_out, edge_usage, true),
+                Err(RoutingTimedOut) => {
+                    dbg_log!(DBG_WARN, "Routing from pin {} timed out", from.0);
+                    timed_out.push(from);
+                },
+            }
+        }
+        (pin_to_pin_map, timed_out, edge_usage, false)
+    }
+
+    /// Routes from a single source pin and gathers routing information for all the
+    /// reachable pins, in order of their ids. Returns `None` for pins that can't act as
+    /// sources. With `with_port_pairs_only`, only pairs involving a site port are kept.
+    fn route_source<C>(
+        &self,
+        from: SitePinId,
+        optimize: bool,
+        markers: &mut Vec<PTPRMarker>,
+        edge_usage: Option<&mut HashMap<(SitePinId, SitePinId), usize>>,
+        cancel: Option<&AtomicBool>
+    )
+        -> Result<Option<C>, RoutingTimedOut>
+    where
+        C: FromIterator<(SitePinId, PinPairRoutingInfo)>
+    {
+        if let PinDir::Input = self.graph.get_node(from.0).dir {
+            return Ok(None); /* We don't need routing information for input pins */
+        }
+        dbg_log!(DBG_EXTRA1, "Routing from pin {}/{}", from.0, self.graph.node_count());
+
+        let routing_results = self.route_pins_reusing(from, optimize, markers, edge_usage, cancel)?
+            .filter(|(to, routing_info)| self.is_routed_pair(from, SitePinId(*to), routing_info))
+            .filter(|(to, _)| !self.port_pairs_only
+                || self.is_site_port_pin(from)
+                || self.is_site_port_pin(SitePinId(*to)))
+            .map(|(to, routing_info)| (SitePinId(to), routing_info))
+            .collect();
+
+        Ok(Some(routing_results))
+    }
+
+    /// Checks whether a routed pin pair belongs to routing results.
+    fn is_routed_pair(&self, from: SitePinId, to: SitePinId, routing_info: &PinPairRoutingInfo)
+        -> bool
+    {
+        (to != from)
+            && !self.split_inout_outputs.contains(&to.0)
+            && ((routing_info.requires.len() != 0) || (routing_info.implies.len() != 0))
+    }
+
+    fn is_out_of_site_source(&self, pin: SitePinId) -> bool {
+        let node = self.graph.get_node(pin.0);
+        matches!(node.dir, PinDir::Output | PinDir::Inout)
+            && matches!(node.kind, RoutingGraphNodeKind::SitePort(_))
+    }
+
+    fn is_out_of_site_sink(&self, pin: SitePinId) -> bool {
+        let node = self.graph.get_node(pin.0);
+        matches!(node.dir, PinDir::Input | PinDir::Inout)
+            && matches!(node.kind, RoutingGraphNodeKind::SitePort(_))
+    }
+
+    /* The idea is that we want to pick a connecion that is not very constraining, but
+     * we also want later sinks to allow more routability options, as reaching them
+     * would mean that the site is more constrained */
+    fn out_of_site_heuristic(routing_info: &PinPairRoutingInfo) -> usize {
+        assert!(routing_info.requires.len() >= 1);
+        routing_info.requires[0].len().pow(2) * routing_info.requires.len()
+    }
+
+    /// Finds site ports which can drive `sink`. Gives the same pins as
+    /// `RoutingInfo::out_of_site_sources` does for `sink`, but routes only from site
+    /// ports. Routing results are cached as with `query_pair`.
+    /// 
+    /// # Arguments
+    /// * `sink` - pin driven by the site ports
+    /// * `optimize` - optimize `requires` formulas, which affects the order of the ports
+    /// 
+    /// # Return
+    /// `RoutingTimedOut` if routing from any of the site ports timed out
+    pub fn sources_for(&self, sink: SitePinId, optimize: bool)
+        -> Result<Vec<SitePinId>, RoutingTimedOut>
+    {
+        let mut sources = Vec::new();
+        for from in (0 .. self.graph.node_count()).map(SitePinId) {
+            if !self.is_out_of_site_source(from) {
+                continue;
+            }
+            let routes = self.cached_route_pins(from, optimize)?;
+            if let Some(routing_info) = routes.get(sink.0) {
+                if self.is_routed_pair(from, sink, routing_info) {
+                    sources.push((from, Self::out_of_site_heuristic(routing_info)));
                 }
             }
         }
-        pin_to_pin_map
+
+        sources.sort_by_key(|(_, heuristic)| *heuristic);
+        Ok(sources.into_iter().map(|(from, _)| from).collect())
+    }
+
+    /// Finds site ports which can be driven by `source`. Gives the same pins as
+    /// `RoutingInfo::out_of_site_sinks` does for `source`. Routing results are cached as
+    /// with `query_pair`.
+    /// 
+    /// # Arguments
+    /// * `source` - pin driving the site ports
+    /// * `optimize` - optimize `requires` formulas, which affects the order of the ports
+    /// 
+    /// # Return
+    /// `RoutingTimedOut` if routing from `source` timed out
+    pub fn sinks_for(&self, source: SitePinId, optimize: bool)
+        -> Result<Vec<SitePinId>, RoutingTimedOut>
+    {
+        if source.0 >= self.graph.node_count() {
+            return Ok(Vec::new());
+        }
+        if let PinDir::Input = self.graph.get_node(source.0).dir {
+            return Ok(Vec::new());
+        }
+        let routes = self.cached_route_pins(source, optimize)?;
+
+        let mut sinks: Vec<_> = routes.iter()
+            .enumerate()
+            .map(|(to, routing_info)| (SitePinId(to), routing_info))
+            .filter(|(to, routing_info)| {
+                self.is_out_of_site_sink(*to) && self.is_routed_pair(source, *to, routing_info)
+            })
+            .map(|(to, routing_info)| (to, Self::out_of_site_heuristic(routing_info)))
+            .collect();
+
+        sinks.sort_by_key(|(_, heuristic)| *heuristic);
+        Ok(sinks.into_iter().map(|(to, _)| to).collect())
     }
 
+    /// Classifies routed pairs which start or end at site ports.
+    /// 
+    /// A pair starting at an `Output` or `Inout` site port makes the port an out-of-site
+    /// source of the pair's sink. A pair ending at an `Input` or `Inout` site port makes
+    /// the port an out-of-site sink of the pair's source. An `Inout` site port is thus
+    /// listed both as a source of the pins it drives and as a sink of the pins driving it.
+    /// If both ends of a pair are `Inout` site ports, the pair is recorded in both maps.
+    /// This is intentional: the signal may enter the site through one port and leave it
+    /// through the other. Each pair contributes at most one entry to each map.
     fn gather_out_of_site_info(
         &self,
         map: &HashMap<(SitePinId, SitePinId), PinPairRoutingInfo>
@@ -952,30 +2641,18 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
         let mut out_of_site_sinks = HashMap::new();
 
         for ((from, to), _) in map {
-            let from_node = self.graph.get_node(from.0);
-            if let PinDir::Output | PinDir::Inout = from_node.dir {
-                if let RoutingGraphNodeKind::SitePort(_) = from_node.kind {
-                    out_of_site_sources.entry(*to).or_insert_with(Vec::new).push(*from);
-                }
+            if self.is_out_of_site_source(*from) {
+                out_of_site_sources.entry(*to).or_insert_with(Vec::new).push(*from);
             }
-
-            let to_node = self.graph.get_node(to.0);
-            if let PinDir::Input | PinDir::Inout = to_node.dir {
-                if let RoutingGraphNodeKind::SitePort(_) = to_node.kind {
-                    out_of_site_sinks.entry(*from).or_insert_with(Vec::new).push(*to);
-                }
+            if self.is_out_of_site_sink(*to) {
+                out_of_site_sinks.entry(*from).or_insert_with(Vec::new).push(*to);
             }
         }
 
         /* Sort routings. */
 
         let heuristic = |from: SitePinId, to: SitePinId| {
-            let routing_info = &map[&(from, to)];
-            assert!(routing_info.requires.len() >= 1);
-            /* The idea is that we want to pick a connecion that is not very constraining, but
-             * we also want later sinks to allow more routability options, as reaching them
-             * would mean that the site is more constrained */
-            routing_info.requires[0].len().pow(2) * routing_info.requires.len()
+            Self::out_of_site_heuristic(&map[&(from, to)])
         };
 
         for (dst, sources) in out_of_site_sources.iter_mut() {
@@ -990,9 +2667,24 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
     }
 
     pub fn route_all(&self, optimize: bool) -> RoutingInfo {
-        let map = self.route_range(
-            SitePinId(0) .. SitePinId(self.graph.node_count()),
-            optimize
+        RoutingInfo {
+            pin_range: None,
+            .. self.route_pin_range(0 .. self.graph.node_count(), optimize)
+        }
+    }
+
+    /// Routes only from source pins with ids within `range`, which gets clamped to the
+    /// number of pins. Allows splitting routing of a site type into slices, which can be
+    /// routed separately and combined with `merge_routing`. Out-of-site information
+    /// covers only the routed slice.
+    pub fn route_pin_range(&self, range: std::ops::Range<usize>, optimize: bool)
+        -> RoutingInfo
+    {
+        let range = self.clamp_pin_range(range);
+        let (map, timed_out, edge_usage, cancelled) = self.route_range(
+            SitePinId(range.start) .. SitePinId(range.end),
+            optimize,
+            None
         );
 
         let (out_of_site_sources, out_of_site_sinks) =
@@ -1002,7 +2694,322 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
             pin_to_pin_routing: map,
             out_of_site_sources,
             out_of_site_sinks,
+            timed_out,
+            aliases: HashMap::new(),
+            edge_usage,
+            cancelled,
+            pin_range: Some(range),
+        }
+    }
+
+    /// Limits a range of source pin ids to the pins of the site. Reversed ranges become
+    /// empty.
+    pub fn clamp_pin_range(&self, range: std::ops::Range<usize>) -> std::ops::Range<usize> {
+        let end = range.end.min(self.graph.node_count());
+        range.start.min(end) .. end
+    }
+
+    /// Combines routing information of slices routed with `route_pin_range`. Slices
+    /// are expected not to overlap. Out-of-site information is gathered again for all
+    /// the pin pairs. The merged slice spans pins of all the slices, so slices covering
+    /// all the pins give the same results as `route_all`.
+    pub fn merge_routing<I>(&self, slices: I) -> RoutingInfo where
+        I: IntoIterator<Item = RoutingInfo>
+    {
+        let mut pin_to_pin_routing = HashMap::new();
+        let mut timed_out = Vec::new();
+        let mut edge_usage = HashMap::new();
+        let mut cancelled = false;
+        let mut pin_ranges = Vec::new();
+
+        for slice in slices {
+            pin_to_pin_routing.extend(slice.pin_to_pin_routing);
+            timed_out.extend(slice.timed_out);
+            cancelled |= slice.cancelled;
+            for (edge, count) in slice.edge_usage {
+                *edge_usage.entry(edge).or_insert(0) += count;
+            }
+            pin_ranges.push(slice.pin_range.unwrap_or(0 .. self.graph.node_count()));
+        }
+        timed_out.sort();
+
+        let start = pin_ranges.iter().map(|range| range.start).min().unwrap_or(0);
+        let end = pin_ranges.iter().map(|range| range.end).max().unwrap_or(0);
+        let pin_range = Some(start .. end)
+            .filter(|range| *range != (0 .. self.graph.node_count()));
+
+        let (out_of_site_sources, out_of_site_sinks) =
+            self.gather_out_of_site_info(&pin_to_pin_routing);
+
+        RoutingInfo {
+            pin_to_pin_routing,
+            out_of_site_sources,
+            out_of_site_sinks,
+            timed_out,
+            aliases: HashMap::new(),
+            edge_usage,
+            cancelled,
+            pin_range,
+        }
+    }
+
+    /// Routes all pins within the site, like `route_all`, but returns only pin-to-pin
+    /// routing information, sorted by `(from, to)`. Results are produced in order, so no
+    /// sorting takes place.
+    /// 
+    /// # Return
+    /// Sorted routing information of pin pairs and a sorted list of pins which timed out
+    pub fn route_all_sorted(&self, optimize: bool)
+        -> (Vec<((SitePinId, SitePinId), PinPairRoutingInfo)>, Vec<SitePinId>)
+    {
+        let mut sorted = Vec::new();
+        let mut timed_out = Vec::new();
+        let mut markers = Vec::new();
+        for from in (0 .. self.graph.node_count()).map(SitePinId) {
+            match self.route_source::<Vec<_>>(from, optimize, &mut markers, None, None) {
+                Ok(Some(routing_results)) => sorted.extend(routing_results.into_iter()
+                    .map(|(to, routing_info)| ((from, to), routing_info))),
+                Ok(None) => (),
+                Err(RoutingTimedOut) => {
+                    dbg_log!(DBG_WARN, "Routing from pin {} timed out", from.0);
+                    timed_out.push(from);
+                },
+            }
+        }
+        (sorted, timed_out)
+    }
+
+    /// Routes only from pins of a single BEL. Pins of other BELs appear only as sinks.
+    /// Useful for inspecting connectivity of a single BEL without routing the whole site.
+    /// 
+    /// # Arguments
+    /// * `device` - device the router was created for
+    /// * `bel_name` - name of the BEL to route from
+    /// * `optimize` - optimize constraint formulas
+    pub fn route_bel<'d>(&self, device: &Device<'d>, bel_name: &str, optimize: bool)
+        -> Result<RoutingInfo, String>
+    {
+        let gsctx = GlobalStringsCtx::hold();
+        let bel_idx = self.bels.iter()
+            .position(|bel| &*bel.name.get(device, &gsctx) == bel_name)
+            .ok_or_else(|| format!("BEL {} does not exist", bel_name))?;
+        drop(gsctx);
+
+        let sources: Vec<_> = self.site_belpin_idx_to_bel_pin.iter()
+            .enumerate()
+            .filter(|(_, (pin_bel_idx, _))| *pin_bel_idx == bel_idx)
+            .map(|(node, _)| SitePinId(node))
+            .collect();
+
+        let (map, mut timed_out, edge_usage, _) = self.route_sources(sources, optimize, None);
+        timed_out.sort();
+
+        let (out_of_site_sources, out_of_site_sinks) =
+            self.gather_out_of_site_info(&map);
+
+        Ok(RoutingInfo {
+            pin_to_pin_routing: map,
+            out_of_site_sources,
+            out_of_site_sinks,
+            timed_out,
+            aliases: HashMap::new(),
+            edge_usage,
+            cancelled: false,
+            pin_range: None,
+        })
+    }
+
+    pub fn get_graph<'s>(&'s self) -> &'s RoutingGraph {
+        &self.graph
+    }
+
+    /// Lists site ports with pins they can reach within the site, using plain
+    /// reachability in the routing graph. No constraints are computed, so this is much
+    /// cheaper than `route_all`.
+    /// 
+    /// # Return
+    /// Site port pins, sorted
+    pub fn list_out_of_site_ports(&self) -> Vec<OutOfSitePort> {
+        let node_count = self.graph.node_count();
+        let is_site_port = |node: usize| {
+            matches!(self.graph.get_node(node).kind, RoutingGraphNodeKind::SitePort(_))
+        };
+        let reachable = |from: usize, forward: bool| -> Vec<SitePinId> {
+            let visited = self.graph.reachable([from], forward);
+            (0 .. node_count)
+                .filter(|node| visited[*node] && !is_site_port(*node))
+                .map(SitePinId)
+                .collect()
+        };
+
+        (0 .. node_count)
+            .filter(|node| is_site_port(*node))
+            .map(|node| {
+                let dir = self.graph.get_node(node).dir;
+                let drives = match dir {
+                    PinDir::Output | PinDir::Inout => reachable(node, true),
+                    PinDir::Input => Vec::new(),
+                };
+                let driven_by = match dir {
+                    PinDir::Input | PinDir::Inout => reachable(node, false),
+                    PinDir::Output => Vec::new(),
+                };
+                OutOfSitePort { pin: SitePinId(node), dir, drives, driven_by }
+            })
+            .collect()
+    }
+
+    /// Finds pins with no path to or from any site port. No external signal can reach
+    /// such pins and they can't drive any external signal, so they're dead within the
+    /// routing model. This usually indicates a gap in the model of the site type.
+    /// 
+    /// # Return
+    /// Isolated pins, sorted
+    pub fn isolated_from_ports(&self) -> Vec<SitePinId> {
+        let node_count = self.graph.node_count();
+        let ports: Vec<usize> = (0 .. node_count)
+            .filter(|node| {
+                matches!(self.graph.get_node(*node).kind, RoutingGraphNodeKind::SitePort(_))
+            })
+            .collect();
+        let reached = |forward: bool| self.graph.reachable(ports.iter().copied(), forward);
+
+        let (from_ports, to_ports) = (reached(true), reached(false));
+        (0 .. node_count)
+            .filter(|node| !from_ports[*node] && !to_ports[*node])
+            .map(SitePinId)
+            .collect()
+    }
+
+    /// Finds pins which are aliases of each other: they're connected with site wires
+    /// only, in both directions, so they are always on the same net. Reporting routing
+    /// between them is just noise.
+    /// 
+    /// # Return
+    /// Map of aliased pins to representatives of their alias groups. The pin with the
+    /// lowest index in a group is its representative.
+    pub fn find_pin_aliases(&self) -> HashMap<SitePinId, SitePinId> {
+        self.graph.site_wire_components().into_iter()
+            .flat_map(|component| {
+                let representative = SitePinId(component[0]);
+                component.into_iter()
+                    .skip(1)
+                    .map(move |pin| (SitePinId(pin), representative))
+            })
+            .collect()
+    }
+
+    /// Finds pairs of `Inout` pins, such that the first pin reaches the second one,
+    /// but not the other way around. Such asymmetries on bidirectional pins usually
+    /// indicate a modeling issue.
+    /// 
+    /// # Arguments
+    /// * `info` - results of routing all pins (see `route_all`)
+    /// 
+    /// # Return
+    /// Sorted list of asymmetric pairs
+    pub fn find_asymmetric_pairs(&self, info: &RoutingInfo) -> Vec<(SitePinId, SitePinId)> {
+        let is_inout = |pin: SitePinId| {
+            matches!(self.graph.get_node(pin.0).dir, PinDir::Inout)
+        };
+
+        let mut asymmetric: Vec<_> = info.pin_to_pin_routing.keys()
+            .filter(|(from, to)| is_inout(*from) && is_inout(*to))
+            .filter(|(from, to)| !info.pin_to_pin_routing.contains_key(&(*to, *from)))
+            .copied()
+            .collect();
+        
+        asymmetric.sort();
+        asymmetric
+    }
+
+    /// Finds edges of the routing graph contradicting directions of the pins they join
+    /// (see `RoutingGraph::inconsistent_directions`). Such edges come from pins whose
+    /// direction got lost while synthesizing parts of the graph, eg. for `Inout` pins.
+    /// 
+    /// # Return
+    /// Sorted list of `(from, to)` pins of the inconsistent edges
+    pub fn find_inconsistent_directions(&self) -> Vec<(SitePinId, SitePinId)> {
+        self.graph.inconsistent_directions().into_iter()
+            .map(|(from, to)| (SitePinId(from), SitePinId(to)))
+            .collect()
+    }
+
+    /// Finds activators (`implies` terms) which don't correspond to real drivers. Each
+    /// cube of `implies` lists the pins driving the nodes of a single route, so every
+    /// activator must have an edge to another pin of the route: either to a pin driven
+    /// by another activator of the same cube, or to the sink of the pair. Other
+    /// activators indicate an inconsistency of the graph.
+    /// 
+    /// # Arguments
+    /// * `info` - results of routing all pins (see `route_all`), before collapsing aliases
+    /// 
+    /// # Return
+    /// Sorted list of pin pairs along with the invalid activator ports found in them
+    pub fn find_invalid_activators(&self, info: &RoutingInfo)
+        -> Vec<((SitePinId, SitePinId), SitePinId)>
+    {
+        let node_count = self.graph.node_count();
+        let mut invalid = Vec::new();
+
+        for ((from, to), ppri) in info.iter() {
+            for cube in &ppri.implies {
+                let ports: Vec<_> = cube.terms.iter()
+                    .filter_map(|term| match term {
+                        FormulaTerm::Var(ConstrainingElement::Port(port))
+                        | FormulaTerm::NegVar(ConstrainingElement::Port(port)) =>
+                            Some(*port as usize),
+                        FormulaTerm::True | FormulaTerm::False => None,
+                    })
+                    .collect();
+
+                let drives_route = |port: usize| {
+                    ports.iter().chain(std::iter::once(&to.0))
+                        .any(|&next| (next != port) && self.graph.has_edge(port, next))
+                };
+                invalid.extend(ports.iter()
+                    .filter(|&&port| (port >= node_count) || !drives_route(port))
+                    .map(|&port| ((from, to), SitePinId(port))));
+            }
+        }
+
+        invalid.sort();
+        invalid.dedup();
+        invalid
+    }
+
+    /// Routes all pins within the site, like `route_all`, but instead of gathering all the
+    /// results in one map, passes complete results for each source pin to `callback` and
+    /// drops them afterwards. This way only results for one source pin are held in memory
+    /// at a time.
+    /// 
+    /// Out-of-site information is not gathered, as it requires all results at once.
+    /// Pins which exceed the routing timeout are not passed to `callback`.
+    /// 
+    /// # Arguments
+    /// * `optimize` - optimize constraint formulas
+    /// * `callback` - called with a source pin and a map of routing information for pins
+    ///   reachable from it
+    /// 
+    /// # Return
+    /// A sorted list of pins which timed out
+    pub fn route_all_streaming<F>(&self, optimize: bool, mut callback: F) -> Vec<SitePinId>
+    where
+        F: FnMut(SitePinId, HashMap<SitePinId, PinPairRoutingInfo>)
+    {
+        let mut timed_out = Vec::new();
+        let mut markers = Vec::new();
+        for from in (0 .. self.graph.node_count()).map(SitePinId) {
+            match self.route_source(from, optimize, &mut markers, None, None) {
+                Ok(Some(routing_results)) => callback(from, routing_results),
+                Ok(None) => (),
+                Err(RoutingTimedOut) => {
+                    dbg_log!(DBG_WARN, "Routing from pin {} timed out", from.0);
+                    timed_out.push(from);
+                },
+            }
         }
+        timed_out
     }
 
     pub fn create_dot_exporter<'s>(&'s self)
@@ -1018,10 +3025,58 @@ impl<A> BruteRouter<A> where A: Default + Clone + std::fmt::Debug + 'static {
             &self.site_belpin_idx_to_bel_pin
         )
     }
+
+    pub fn create_graphml_exporter<'s>(&'s self)
+        -> SiteRoutingGraphGraphMLExporter<
+            &'s RoutingGraph,
+            &'s Vec<BELInfo>,
+            &'s Vec<(usize, usize)>
+           >
+        {
+        SiteRoutingGraphGraphMLExporter::new(
+            &self.graph,
+            &self.bels,
+            &self.site_belpin_idx_to_bel_pin
+        )
+    }
+}
+
+/// Finds drivers of a site wire which would be connected only to themselves. Pairing
+/// an `Inout` pin with itself is expected, but a wire with no other pins to connect it
+/// to implies a self-loop, which most likely means the device data is malformed.
+/// 
+/// # Arguments
+/// * `drivers` - nodes driving the wire
+/// * `sinks` - nodes driven by the wire
+pub(super) fn wire_self_loops<'a>(drivers: &'a [usize], sinks: &'a [usize])
+    -> impl Iterator<Item = usize> + 'a
+{
+    drivers.iter()
+        .copied()
+        .filter(move |driver| {
+            sinks.contains(driver) && sinks.iter().all(|sink| sink == driver)
+        })
 }
 
+/// Routing split across threads. Workers check `cancel` between pins and between routing
+/// steps, so setting it stops routing early. Results routed until then are returned,
+/// with `RoutingInfo::cancelled` set.
 pub trait MultiThreadedBruteRouter<A> {
-    fn route_all_multithreaded(self, thread_count: usize, optimize: bool) -> RoutingInfo;
+    fn route_all_multithreaded(
+        self,
+        thread_count: usize,
+        optimize: bool,
+        cancel: &CancellationToken
+    ) -> RoutingInfo;
+
+    /// Multithreaded version of `BruteRouter::route_pin_range`
+    fn route_pin_range_multithreaded(
+        self,
+        range: std::ops::Range<usize>,
+        thread_count: usize,
+        optimize: bool,
+        cancel: &CancellationToken
+    ) -> RoutingInfo;
 }
 
 impl<R, A> MultiThreadedBruteRouter<A> for R
@@ -1029,28 +3084,59 @@ where
     R: Borrow<BruteRouter<A>> + Clone + Send + 'static,
     A: Default + Clone + std::fmt::Debug + 'static
 {
-    /* Not the best multithreading, but should improve the runtime nevertheless. */
-    fn route_all_multithreaded(self, thread_count: usize, optimize: bool) -> RoutingInfo
+    fn route_all_multithreaded(
+        self,
+        thread_count: usize,
+        optimize: bool,
+        cancel: &CancellationToken
+    ) -> RoutingInfo
     {
+        let pin_cnt = self.borrow().site_belpin_idx_to_bel_pin.len();
+        RoutingInfo {
+            pin_range: None,
+            .. self.route_pin_range_multithreaded(0 .. pin_cnt, thread_count, optimize, cancel)
+        }
+    }
+
+    /* Not the best multithreading, but should improve the runtime nevertheless. */
+    fn route_pin_range_multithreaded(
+        self,
+        range: std::ops::Range<usize>,
+        thread_count: usize,
+        optimize: bool,
+        cancel: &CancellationToken
+    ) -> RoutingInfo {
         let mut total_map = HashMap::new();
+        let mut timed_out = Vec::new();
+        let mut edge_usage = HashMap::new();
+        let mut cancelled = false;
         let mut handles = Vec::new();
-        
-        let pin_cnt = self.borrow().site_belpin_idx_to_bel_pin.len();
 
-        for range in split_range_nicely(0 .. pin_cnt, thread_count) {
+        let range = self.borrow().clamp_pin_range(range);
+
+        for range in split_range_nicely(range.clone(), thread_count) {
             let me = self.clone();
+            let cancel = Arc::clone(cancel);
             let handle = thread::spawn(move || {
                 me.borrow().route_range(
                     SitePinId(range.start) .. SitePinId(range.end),
-                    optimize
+                    optimize,
+                    Some(&cancel)
                 )
             });
             handles.push(handle);
         }
         for handle in handles {
-            let map = handle.join().unwrap();
+            let (map, range_timed_out, range_edge_usage, range_cancelled) =
+                handle.join().unwrap();
             total_map.extend(map.into_iter());
+            timed_out.extend(range_timed_out);
+            cancelled |= range_cancelled;
+            for (edge, count) in range_edge_usage {
+                *edge_usage.entry(edge).or_insert(0) += count;
+            }
         }
+        timed_out.sort();
 
         let (out_of_site_sources, out_of_site_sinks) =
             self.borrow().gather_out_of_site_info(&total_map);
@@ -1059,6 +3145,11 @@ where
             pin_to_pin_routing: total_map,
             out_of_site_sources,
             out_of_site_sinks,
+            timed_out,
+            aliases: HashMap::new(),
+            edge_usage,
+            cancelled,
+            pin_range: Some(range),
         }
     }
 }
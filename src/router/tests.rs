@@ -0,0 +1,2097 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+use super::*;
+use std::collections::HashSet;
+use super::site_brute_router::*;
+use crate::logic_formula::*;
+use crate::test_utils::*;
+/* Fixtures are described with fpga-interchange BEL categories */
+use crate::test_utils::BELCategory;
+
+fn pin(router: &BruteRouter<()>, device: &Device, bel: &str, pin: &str) -> SitePinId {
+    router.get_pin_id(device, bel, pin).unwrap()
+}
+
+#[test]
+fn test_edge_kinds() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let graph = router.get_graph();
+
+    let a = pin(&router, &device, "A", "A");
+    let i0 = pin(&router, &device, "MUX", "I0");
+    let i1 = pin(&router, &device, "MUX", "I1");
+    let o = pin(&router, &device, "MUX", "O");
+    let d = pin(&router, &device, "FF", "D");
+
+    assert_eq!(*graph.get_edge(a.0, i0.0), RoutingGraphEdge::SiteWire);
+    assert_eq!(*graph.get_edge(o.0, d.0), RoutingGraphEdge::SiteWire);
+    assert_eq!(*graph.get_edge(i0.0, o.0), RoutingGraphEdge::PseudoPip);
+    assert_eq!(*graph.get_edge(i1.0, o.0), RoutingGraphEdge::PseudoPip);
+    assert_eq!(*graph.get_edge(i0.0, a.0), RoutingGraphEdge::None);
+    assert_eq!(*graph.get_edge(d.0, o.0), RoutingGraphEdge::None);
+}
+
+#[test]
+fn test_constraints_only_on_pip_edges() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let a = pin(&router, &device, "A", "A");
+    let i1 = pin(&router, &device, "MUX", "I1");
+    let d = pin(&router, &device, "FF", "D");
+
+    let info = router.route_all(true);
+    let a_to_d = &info.pin_to_pin_routing[&(a, d)];
+
+    /* The only alternative driver crossed on the way is the other mux input */
+    assert_eq!(a_to_d.requires.len(), 1);
+    assert_eq!(
+        a_to_d.requires[0].terms,
+        vec![FormulaTerm::NegVar(ConstrainingElement::Port(i1.0 as u32))]
+    );
+}
+
+#[test]
+fn test_route_all_streaming_matches_route_all() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let info = router.route_all(true);
+
+    let mut streamed = HashMap::new();
+    let timed_out = router.route_all_streaming(true, |from, results| {
+        streamed.extend(results.into_iter().map(|(to, ppri)| ((from, to), ppri)));
+    });
+    assert!(timed_out.is_empty());
+
+    assert_eq!(streamed.len(), info.pin_to_pin_routing.len());
+    for (pair, ppri) in &info.pin_to_pin_routing {
+        let streamed_ppri = &streamed[pair];
+        assert_eq!(streamed_ppri.requires, ppri.requires);
+        assert_eq!(streamed_ppri.implies, ppri.implies);
+    }
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn test_emit_both_forms() {
+    use super::serialize::IntoRoutingInfoWithExtras;
+    use std::sync::Arc;
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false)
+        .with_unoptimized_forms(true);
+
+    let info = router.route_all(false);
+    assert!(!info.pin_to_pin_routing.is_empty());
+
+    for ppri in info.pin_to_pin_routing.values() {
+        let unoptimized = ppri.unoptimized.as_ref().unwrap();
+        let raw = DNFForm { cubes: unoptimized.requires.clone() };
+        let optimized = DNFForm { cubes: ppri.requires.clone() };
+        assert_eq!(optimized, raw);
+    }
+
+    let json = serde_json::to_value(info.with_extras(Arc::new(router), &device)).unwrap();
+    let pairs = json["pin_to_pin_routing"].as_object().unwrap();
+    let a_to_d = &pairs["A.A->FF.D"];
+    for field in ["requires_raw", "requires_optimized", "implies_raw", "implies_optimized"] {
+        assert!(a_to_d.get(field).is_some(), "Missing field {}", field);
+    }
+    assert!(a_to_d.get("requires").is_none());
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn test_explicit_formula_format() {
+    use super::serialize::{ExplicitFormula, IntoRoutingInfoWithExtras};
+    use std::sync::Arc;
+
+    /* Rebuilds a DNF formula out of its operator tree */
+    fn to_dnf(formula: ExplicitFormula<String>) -> DNFForm<String> {
+        let cubes = match formula {
+            ExplicitFormula::Or(cubes) => cubes,
+            _ => panic!("Expected an or node"),
+        };
+        DNFForm {
+            cubes: cubes.into_iter().map(|cube| {
+                let terms = match cube {
+                    ExplicitFormula::And(terms) => terms,
+                    _ => panic!("Expected an and node"),
+                };
+                DNFCube {
+                    terms: terms.into_iter().map(|term| match term {
+                        ExplicitFormula::Var(name) => FormulaTerm::Var(name),
+                        ExplicitFormula::Not(name) => FormulaTerm::NegVar(name),
+                        ExplicitFormula::True => FormulaTerm::True,
+                        ExplicitFormula::False => FormulaTerm::False,
+                        _ => panic!("Unexpected nested operator"),
+                    }).collect()
+                }
+            }).collect()
+        }
+    }
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = Arc::new(BruteRouter::<()>::new(&device, 0, false));
+    let info = router.route_all(true);
+    let named = |form: &[DNFCube<ConstrainingElement>]| {
+        let gsctx = GlobalStringsCtx::hold();
+        let name = |ConstrainingElement::Port(v)| {
+            router.get_pin_name(&device, &gsctx, SitePinId(v as usize)).to_string()
+        };
+        DNFForm {
+            cubes: form.iter().map(|cube| DNFCube {
+                terms: cube.terms.iter().map(|term| term.clone().map(name)).collect()
+            }).collect()
+        }
+    };
+
+    let json = serde_json::to_value(
+        info.clone().with_extras(Arc::clone(&router), &device).with_explicit_formulas(true)
+    ).unwrap();
+    let pairs = json["pin_to_pin_routing"].as_object().unwrap();
+    assert_eq!(pairs.len(), info.pin_to_pin_routing.len());
+
+    let a_to_d = &pairs["A.A->FF.D"]["requires"];
+    assert!(a_to_d["or"][0]["and"].is_array());
+
+    let gsctx = GlobalStringsCtx::hold();
+    for ((from, to), ppri) in &info.pin_to_pin_routing {
+        let key = format!(
+            "{}->{}",
+            router.get_pin_name(&device, &gsctx, *from).to_string(),
+            router.get_pin_name(&device, &gsctx, *to).to_string()
+        );
+        for (field, form) in [("requires", &ppri.requires), ("implies", &ppri.implies)] {
+            let explicit: ExplicitFormula<String> =
+                serde_json::from_value(pairs[&key][field].clone()).unwrap();
+            assert_eq!(to_dnf(explicit), named(form), "{} of {}", field, key);
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn test_labeled_constraints() {
+    use super::serialize::{LabeledCube, IntoRoutingInfoWithExtras};
+    use std::sync::Arc;
+    use FormulaTerm::*;
+    use ConstrainingElement::Port;
+
+    let cube = DNFCube { terms: vec![Var(Port(1)), NegVar(Port(2)), True] };
+    let labeled = LabeledCube::from_cube(&cube, |Port(v)| *v);
+    assert_eq!(labeled.uses_port, vec![1]);
+    assert_eq!(labeled.must_not_use_ports, vec![2]);
+    assert!(!labeled.unsatisfiable);
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = Arc::new(BruteRouter::<()>::new(&device, 0, false));
+    let info = router.route_all(true);
+
+    let json = serde_json::to_value(
+        info.with_extras(Arc::clone(&router), &device).with_labeled_constraints(true)
+    ).unwrap();
+
+    /* A reaches D only if I1 doesn't drive the MUX output */
+    let a_to_d: Vec<LabeledCube<String>> = serde_json::from_value(
+        json["pin_to_pin_routing"]["A.A->FF.D"]["requires"].clone()
+    ).unwrap();
+    assert_eq!(a_to_d.len(), 1);
+    assert_eq!(a_to_d[0].must_not_use_ports, vec!["MUX.I1".to_string()]);
+    assert!(a_to_d[0].uses_port.is_empty());
+}
+
+#[test]
+fn test_get_pin_id_errors() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    assert_eq!(
+        router.get_pin_id(&device, "NOPE", "I0"),
+        Err("BEL MUX_SITE/NOPE not found".to_string())
+    );
+    assert_eq!(
+        router.get_pin_id(&device, "MUX", "X"),
+        Err("Pin MUX_SITE/MUX.X not found".to_string())
+    );
+    /* A pin of another BEL, placed after the matching BEL, isn't found either */
+    assert_eq!(
+        router.get_pin_id(&device, "MUX", "D"),
+        Err("Pin MUX_SITE/MUX.D not found".to_string())
+    );
+
+    let d = router.get_pin_id(&device, "FF", "D").unwrap();
+    assert_eq!(router.get_pin_name(&device, &GlobalStringsCtx::hold(), d).to_string(), "FF.D");
+}
+
+#[test]
+fn test_route_count_skips_dead_ends() {
+    const FAN_OUT: usize = 32;
+
+    /* A drives many buffers leading nowhere, and reaches D only through the mux */
+    let buf_names: Vec<_> = (0 .. FAN_OUT).map(|idx| format!("BUF{}", idx)).collect();
+    let mut site_type = SiteTypeFixture::new("FAN_OUT_SITE")
+        .port("A", Dir::Output)
+        .bel("MUX", BELCategory::Routing, &[
+            ("I0", Dir::Input),
+            ("I1", Dir::Input),
+            ("O", Dir::Output),
+        ])
+        .bel("FF", BELCategory::Logic, &[("D", Dir::Input)])
+        .wire("MUX_O", &[("MUX", "O"), ("FF", "D")])
+        .pip("MUX", "I0", "O")
+        .pip("MUX", "I1", "O");
+    let mut a_wire = vec![("A", "A"), ("MUX", "I0"), ("MUX", "I1")];
+    for name in &buf_names {
+        site_type = site_type
+            .bel(name, BELCategory::Routing, &[("I", Dir::Input), ("O", Dir::Output)])
+            .pip(name, "I", "O");
+        a_wire.push((name.as_str(), "I"));
+    }
+    let msg = DeviceFixture::new("test_device")
+        .site_type(site_type.wire("A_W", &a_wire))
+        .build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let a = pin(&router, &device, "A", "A");
+    let d = pin(&router, &device, "FF", "D");
+    let buf_o = pin(&router, &device, "BUF0", "O");
+
+    /* A, I0, O and I1, O again: buffers are never entered */
+    assert_eq!(router.count_routes(a, d, None, Some(5)), (2, false));
+    assert_eq!(router.count_routes(a, buf_o, None, Some(2)), (1, false));
+    assert_eq!(router.count_routes(buf_o, d, None, None), (0, false));
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn test_route_count() {
+    use super::serialize::IntoRoutingInfoWithExtras;
+    use std::sync::Arc;
+
+    /* A reaches D through either of the mux inputs */
+    let msg = DeviceFixture::new("test_device").site_type(dual_mux_site_type()).build();
+    let device = fixture_root(&msg);
+    let router = Arc::new(BruteRouter::<()>::new(&device, 0, false));
+
+    let a = pin(&router, &device, "A", "A");
+    let i0 = pin(&router, &device, "MUX", "I0");
+    let d = pin(&router, &device, "FF", "D");
+    assert_eq!(router.count_routes(a, d, None, None), (2, false));
+    assert_eq!(router.count_routes(a, d, Some(1), None), (1, true));
+    assert_eq!(router.count_routes(a, d, None, Some(3)), (1, true));
+    assert_eq!(router.count_routes(a, i0, None, None), (1, false));
+
+    let json = serde_json::to_value(
+        router.route_all(true).with_extras(Arc::clone(&router), &device)
+            .with_route_counts(true, 1000)
+    ).unwrap();
+    let pairs = &json["pin_to_pin_routing"];
+    assert_eq!(pairs["A.A->FF.D"]["route_count"], 2);
+    assert_eq!(pairs["A.A->MUX.I0"]["route_count"], 1);
+    assert!(pairs["A.A->FF.D"].get("route_count_capped").is_none());
+
+    /* Reaching the limit is recorded */
+    let json = serde_json::to_value(
+        router.route_all(true).with_extras(Arc::clone(&router), &device)
+            .with_route_counts(true, 1)
+    ).unwrap();
+    let pairs = &json["pin_to_pin_routing"];
+    assert_eq!(pairs["A.A->FF.D"]["route_count"], 1);
+    assert_eq!(pairs["A.A->FF.D"]["route_count_capped"], true);
+    assert_eq!(pairs["A.A->MUX.I0"]["route_count"], 1);
+    assert!(pairs["A.A->MUX.I0"].get("route_count_capped").is_none());
+}
+
+#[test]
+fn test_pin_to_bel_pin() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let gsctx = GlobalStringsCtx::hold();
+
+    for pin_idx in 0 .. router.get_graph().node_count() {
+        let pin_id = SitePinId(pin_idx);
+        let (_, bel_pin_idx) = router.pin_to_bel_pin(pin_id);
+        let bel = router.bel_of_pin(pin_id);
+
+        let expected = router.get_pin_name(&device, &gsctx, pin_id).to_string();
+        let resolved = format!(
+            "{}.{}",
+            bel.name.get(&device, &gsctx),
+            bel.pins[bel_pin_idx].name.get(&device, &gsctx)
+        );
+        assert_eq!(resolved, expected);
+    }
+}
+
+#[test]
+fn test_const_source_driving_multiple_wires() {
+    let msg = DeviceFixture::new("test_device").site_type(
+        SiteTypeFixture::new("CONST_SITE")
+            .bel("VCC", BELCategory::Logic, &[("P", Dir::Output)])
+            .bel("A", BELCategory::Logic, &[("I", Dir::Input)])
+            .bel("B", BELCategory::Logic, &[("I", Dir::Input)])
+            .wire("W0", &[("VCC", "P"), ("A", "I")])
+            .wire("W1", &[("VCC", "P"), ("B", "I")])
+            .const_source("VCC", "P", ConstantType::Vcc)
+    ).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, true);
+    let graph = router.get_graph();
+
+    let pip_out = pin(&router, &device, "VCC_$VCC_SITE_WIRE", "$VCC_SITE_WIRE");
+    let a = pin(&router, &device, "A", "I");
+    let b = pin(&router, &device, "B", "I");
+
+    assert_eq!(*graph.get_edge(pip_out.0, a.0), RoutingGraphEdge::VirtualConst);
+    assert_eq!(*graph.get_edge(pip_out.0, b.0), RoutingGraphEdge::VirtualConst);
+}
+
+#[test]
+fn test_virtual_gnd_only() {
+    use super::VirtualConsts;
+
+    let msg = DeviceFixture::new("test_device").site_type(
+        SiteTypeFixture::new("CONST_SITE")
+            .bel("VCC", BELCategory::Logic, &[("P", Dir::Output)])
+            .bel("GND", BELCategory::Logic, &[("G", Dir::Output)])
+            .bel("A", BELCategory::Logic, &[("I", Dir::Input)])
+            .bel("B", BELCategory::Logic, &[("I", Dir::Input)])
+            .wire("W0", &[("VCC", "P"), ("A", "I")])
+            .wire("W1", &[("GND", "G"), ("B", "I")])
+            .const_source("VCC", "P", ConstantType::Vcc)
+            .const_source("GND", "G", ConstantType::Gnd)
+    ).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, VirtualConsts { vcc: false, gnd: true });
+    let graph = router.get_graph();
+
+    assert!(router.get_pin_id(&device, "$GND", "$GND").is_ok());
+    let pip_out = pin(&router, &device, "GND_$GND_SITE_WIRE", "$GND_SITE_WIRE");
+    let b = pin(&router, &device, "B", "I");
+    assert_eq!(*graph.get_edge(pip_out.0, b.0), RoutingGraphEdge::VirtualConst);
+
+    assert!(router.get_pin_id(&device, "$VCC", "$VCC").is_err());
+    assert!(router.get_pin_id(&device, "VCC_$VCC_SITE_WIRE", "$VCC_SITE_WIRE").is_err());
+    let a = pin(&router, &device, "A", "I");
+    assert!(graph.edges_to(a.0)
+        .all(|from| *graph.get_edge(from, a.0) != RoutingGraphEdge::VirtualConst));
+
+    let both = BruteRouter::<()>::new(&device, 0, true);
+    assert!(both.get_pin_id(&device, "$VCC", "$VCC").is_ok());
+    assert!(both.get_pin_id(&device, "$GND", "$GND").is_ok());
+}
+
+/* Cross-check of the router against plain reachability in its routing graph
+ * (`RoutingGraph::reachable`). Routing through the BFS frames shouldn't lose or invent
+ * pairs in sites without conflicting constraints. There's no legacy router in this tree
+ * (`src/site_brute_router.rs` doesn't exist) to compare results with, so the graph
+ * serves as the reference instead. */
+#[test]
+fn test_routed_pairs_match_graph_reachability() {
+    for msg in [mux_site().build(), mux_chain_site(4).build()] {
+        let device = fixture_root(&msg);
+        let router = BruteRouter::<()>::new(&device, 0, false);
+        let graph = router.get_graph();
+
+        let routed: HashSet<_> = router.route_all(false).pin_to_pin_routing.into_keys()
+            .map(|(from, to)| (from.0, to.0))
+            .collect();
+
+        let reachable_pairs: HashSet<_> = (0 .. graph.node_count())
+            .filter(|from| graph.get_node(*from).dir != PinDir::Input)
+            .flat_map(|from| {
+                graph.reachable([from], true).into_iter()
+                    .enumerate()
+                    .filter(move |(to, reached)| *reached && (*to != from))
+                    .map(move |(to, _)| (from, to))
+            })
+            .collect();
+
+        assert!(!routed.is_empty());
+        assert_eq!(routed, reachable_pairs);
+    }
+}
+
+#[test]
+fn test_flatten_inout_preserves_reachability() {
+    let msg = DeviceFixture::new("test_device").site_type(
+        SiteTypeFixture::new("INOUT_SITE")
+            .port("P", Dir::Output)
+            .bel("D", BELCategory::Logic, &[("I", Dir::Input), ("O", Dir::Output)])
+            .bel("X", BELCategory::Logic, &[("IO", Dir::Inout)])
+            .bel("S", BELCategory::Logic, &[("I", Dir::Input)])
+            .wire("PW", &[("P", "P"), ("D", "I")])
+            .wire("W", &[("D", "O"), ("X", "IO"), ("S", "I")])
+    ).build();
+    let device = fixture_root(&msg);
+
+    let original = BruteRouter::<()>::new(&device, 0, false);
+    let flattened = BruteRouter::<()>::new(&device, 0, false)
+        .with_flattened_inout(true);
+
+    let original_graph = original.get_graph();
+    let flattened_graph = flattened.get_graph();
+    let node_count = original_graph.node_count();
+
+    assert_eq!(flattened_graph.node_count(), node_count + 1);
+    for node in 0 .. flattened_graph.node_count() {
+        assert!(flattened_graph.get_node(node).dir != PinDir::Inout);
+    }
+
+    let x = pin(&original, &device, "X", "IO").0;
+    let x_out = node_count;
+    let as_source = |node: usize| if node == x { x_out } else { node };
+
+    for from in 0 .. node_count {
+        let original_reach = reachable(original_graph, from);
+        let flattened_reach = reachable(flattened_graph, as_source(from));
+        for to in 0 .. node_count {
+            if to == from { continue; }
+            assert_eq!(
+                original_reach.contains(&to),
+                flattened_reach.contains(&to),
+                "Reachability {} -> {} differs", from, to
+            );
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn test_deduplicated_formulas() {
+    use super::serialize::IntoRoutingInfoWithExtras;
+    use std::sync::Arc;
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let info = router.route_all(true);
+    let json = serde_json::to_value(
+        info.with_extras(Arc::new(router), &device).with_deduplicated_formulas(true)
+    ).unwrap();
+
+    let formulas = json["formulas"].as_array().unwrap();
+    for (idx, formula) in formulas.iter().enumerate() {
+        assert!(!formulas[.. idx].contains(formula), "Formula {} is duplicated", idx);
+    }
+
+    /* Pairs which do not cross any PIP share the same, always-true formula */
+    let pairs = json["pin_to_pin_routing"].as_object().unwrap();
+    let a_to_i0 = pairs["A.A->MUX.I0"]["requires"].as_u64().unwrap();
+    let b_to_i1 = pairs["B.B->MUX.I1"]["requires"].as_u64().unwrap();
+    assert_eq!(a_to_i0, b_to_i1);
+    assert_eq!(formulas[a_to_i0 as usize], serde_json::json!([[]]));
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn test_numeric_port_ids() {
+    use super::serialize::IntoRoutingInfoWithExtras;
+    use std::sync::Arc;
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = Arc::new(BruteRouter::<()>::new(&device, 0, false));
+    let info = router.route_all(true);
+
+    let named = serde_json::to_value(
+        info.clone().with_extras(Arc::clone(&router), &device)
+    ).unwrap();
+    let numeric = serde_json::to_value(
+        info.with_extras(router, &device).with_numeric_port_ids(true)
+    ).unwrap();
+    assert!(named.get("port_names").is_none());
+
+    /* Resolve port ids back to names, which should give the named form */
+    let port_names = numeric["port_names"].as_array().unwrap().clone();
+    let mut resolved = numeric;
+    resolved.as_object_mut().unwrap().remove("port_names");
+    fn resolve(value: &mut serde_json::Value, port_names: &[serde_json::Value]) {
+        match value {
+            serde_json::Value::Object(map) => for (key, value) in map.iter_mut() {
+                match (key.as_str(), value.as_u64()) {
+                    ("Port", Some(id)) => *value = port_names[id as usize].clone(),
+                    _ => resolve(value, port_names),
+                }
+            },
+            serde_json::Value::Array(values) =>
+                values.iter_mut().for_each(|value| resolve(value, port_names)),
+            _ => (),
+        }
+    }
+    resolve(&mut resolved, &port_names);
+
+    assert!(named.to_string().contains("\"Port\""));
+    assert_eq!(resolved, named);
+}
+
+#[test]
+fn test_disabled_pips() {
+    let msg = DeviceFixture::new("test_device").site_type(buf_site_type()).build();
+    let device = fixture_root(&msg);
+
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let a = pin(&router, &device, "A", "A");
+    let q = pin(&router, &device, "Q", "Q");
+    assert!(router.route_all(true).pin_to_pin_routing.contains_key(&(a, q)));
+
+    let router = router.with_disabled_pips(&device, &["BUF"]);
+    let i = pin(&router, &device, "BUF", "I");
+    let o = pin(&router, &device, "BUF", "O");
+    assert_eq!(*router.get_graph().get_edge(i.0, o.0), RoutingGraphEdge::None);
+
+    let info = router.route_all(true);
+    assert!(!info.pin_to_pin_routing.contains_key(&(a, q)));
+    assert!(info.pin_to_pin_routing.contains_key(&(a, i)));
+}
+
+#[test]
+fn test_virtual_const_port_name_collision() {
+    let msg = DeviceFixture::new("test_device").site_type(
+        SiteTypeFixture::new("CONST_SITE")
+            .bel("$VCC", BELCategory::Logic, &[("P", Dir::Output)])
+            .bel("A", BELCategory::Logic, &[("I", Dir::Input)])
+            .wire("W", &[("$VCC", "P"), ("A", "I")])
+            .const_source("$VCC", "P", ConstantType::Vcc)
+    ).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, true);
+    let graph = router.get_graph();
+
+    let real = pin(&router, &device, "$VCC", "P");
+    let port = pin(&router, &device, "$VCC_NISP", "$VCC_NISP");
+    let pip_out = pin(&router, &device, "$VCC_$VCC_NISP_SITE_WIRE", "$VCC_NISP_SITE_WIRE");
+    let a = pin(&router, &device, "A", "I");
+
+    assert_ne!(real, port);
+    assert_eq!(*graph.get_edge(real.0, a.0), RoutingGraphEdge::SiteWire);
+    assert_eq!(*graph.get_edge(pip_out.0, a.0), RoutingGraphEdge::VirtualConst);
+}
+
+#[test]
+fn test_symmetric_bidirectional_wire() {
+    let msg = DeviceFixture::new("test_device").site_type(
+        SiteTypeFixture::new("BIDIR_SITE")
+            .bel("X", BELCategory::Logic, &[("IO", Dir::Inout)])
+            .bel("Y", BELCategory::Logic, &[("IO", Dir::Inout)])
+            .wire("W", &[("X", "IO"), ("Y", "IO")])
+    ).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let info = router.route_all(true);
+    assert!(!info.pin_to_pin_routing.is_empty());
+    assert!(router.find_asymmetric_pairs(&info).is_empty());
+}
+
+#[test]
+fn test_asymmetric_pip() {
+    let msg = DeviceFixture::new("test_device").site_type(
+        SiteTypeFixture::new("ASYM_SITE")
+            .bel("X", BELCategory::Logic, &[("IO", Dir::Inout)])
+            .bel("Y", BELCategory::Logic, &[("IO", Dir::Inout)])
+            .bel("R", BELCategory::Routing, &[("IO0", Dir::Inout), ("IO1", Dir::Inout)])
+            .wire("W0", &[("X", "IO"), ("R", "IO0")])
+            .wire("W1", &[("R", "IO1"), ("Y", "IO")])
+            .pip("R", "IO0", "IO1")
+    ).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let x = pin(&router, &device, "X", "IO");
+    let y = pin(&router, &device, "Y", "IO");
+
+    let info = router.route_all(true);
+    let asymmetric = router.find_asymmetric_pairs(&info);
+    assert!(asymmetric.contains(&(x, y)));
+    assert!(!asymmetric.contains(&(y, x)));
+}
+
+#[test]
+fn test_tile_type_aggregation() {
+    use super::tile_routing::*;
+
+    let msg = mux_site()
+        .tile_type("MUX_TILE", &["MUX_SITE"])
+        .build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let mut site_routing = HashMap::new();
+    site_routing.insert(0, router.route_all(true));
+
+    let tiles = aggregate_tile_types(&device, &site_routing);
+    assert_eq!(tiles.len(), 1);
+
+    let tile = &tiles[0];
+    assert_eq!(tile.tile_type, 0);
+    assert_eq!(tile.site_types, vec![0]);
+
+    let site_info = &site_routing[&0];
+    assert_eq!(tile.pin_to_pin_routing.len(), site_info.pin_to_pin_routing.len());
+    for pair in &tile.pin_to_pin_routing {
+        assert_eq!(pair.from.site, 0);
+        assert_eq!(pair.to.site, 0);
+        let site_pair = &site_info.pin_to_pin_routing[&(pair.from.pin, pair.to.pin)];
+        assert!(std::ptr::eq(pair.routing, site_pair));
+    }
+
+    let a = pin(&router, &device, "A", "A");
+    let d = pin(&router, &device, "FF", "D");
+    assert!(tile.pin_to_pin_routing.iter().any(|pair| {
+        pair.from == TileSitePinId { site: 0, pin: a }
+            && pair.to == TileSitePinId { site: 0, pin: d }
+    }));
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn test_bundle_round_trip() {
+    use super::bundle::*;
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let info = router.route_all(true);
+
+    let mut archive = Vec::new();
+    write_bundle(&mut archive, &device, &router, &info, Some("0123abcd")).unwrap();
+    drop(msg);
+
+    let bundle = read_bundle(archive.as_slice()).unwrap();
+    assert_eq!(bundle.manifest.format_version, BUNDLE_FORMAT_VERSION);
+    assert_eq!(bundle.manifest.device, "test_device");
+    assert_eq!(bundle.manifest.site_type, "MUX_SITE");
+    assert_eq!(bundle.manifest.device_hash.as_deref(), Some("0123abcd"));
+    assert_eq!(bundle.routing_info.pin_to_pin_routing.len(), info.pin_to_pin_routing.len());
+
+    let a = bundle.find_pin("A", "A").unwrap();
+    let i1 = bundle.find_pin("MUX", "I1").unwrap();
+    let d = bundle.find_pin("FF", "D").unwrap();
+    assert_eq!(bundle.pin_name(d), ("FF", "D"));
+
+    /* Query the stored results */
+    let a_to_d = &bundle.routing_info.pin_to_pin_routing[&(a, d)];
+    assert_eq!(
+        a_to_d.requires[0].terms,
+        vec![FormulaTerm::NegVar(ConstrainingElement::Port(i1.0 as u32))]
+    );
+
+    /* Route again using the reconstructed router */
+    let rerouted = bundle.router.route_all(true);
+    assert_eq!(rerouted.pin_to_pin_routing[&(a, d)].requires, a_to_d.requires);
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn test_bundle_routing_matches() {
+    use super::bundle::*;
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let info = router.route_all(true);
+
+    let mut archive = Vec::new();
+    write_bundle(&mut archive, &device, &router, &info, None).unwrap();
+
+    /* Routing is read without the router, so it can be compared without creating names */
+    let baseline = read_bundle_routing(archive.as_slice()).unwrap();
+    assert_eq!(baseline.manifest.site_type, "MUX_SITE");
+    assert_eq!(baseline.routing_info.pin_to_pin_routing, info.pin_to_pin_routing);
+    assert!(baseline.routing_matches(&device, &router, &info));
+
+    let assumed_sorted = read_bundle_routing_assume_sorted(archive.as_slice()).unwrap();
+    assert_eq!(assumed_sorted.routing_info.pin_to_pin_routing, info.pin_to_pin_routing);
+
+    let disabled = BruteRouter::<()>::new(&device, 0, false).with_disabled_pips(&device, &["MUX"]);
+    assert!(!baseline.routing_matches(&device, &disabled, &disabled.route_all(true)));
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn test_serialized_edge_usage() {
+    use std::sync::Arc;
+    use super::bundle::*;
+    use super::serialize::IntoRoutingInfoWithExtras;
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false).with_edge_usage(true);
+    let info = router.route_all(true);
+    assert!(!info.edge_usage.is_empty());
+
+    let mut archive = Vec::new();
+    write_bundle(&mut archive, &device, &router, &info, None).unwrap();
+    let bundle = read_bundle(archive.as_slice()).unwrap();
+    assert_eq!(bundle.routing_info.edge_usage, info.edge_usage);
+
+    let json = serde_json::to_value(info.with_extras(Arc::new(router), &device)).unwrap();
+    let edge_usage = json["edge_usage"].as_object().unwrap();
+    assert_eq!(edge_usage.len(), bundle.routing_info.edge_usage.len());
+    /* FF.D is the only sink reachable from A */
+    assert_eq!(edge_usage["A.A->MUX.I0"], 1);
+}
+
+#[test]
+fn test_trim_subsumed_implies() {
+    use ConstrainingElement::Port;
+    use FormulaTerm::{Var, NegVar};
+
+    let mut ppri = PinPairRoutingInfo {
+        requires: vec![DNFCube { terms: vec![Var(Port(1))] }],
+        implies: vec![DNFCube { terms: vec![Var(Port(1)), NegVar(Port(2))] }],
+        unoptimized: None,
+    };
+    ppri.trim_implies();
+    assert!(ppri.implies.is_empty());
+    assert_eq!(ppri.requires, vec![DNFCube { terms: vec![Var(Port(1))] }]);
+}
+
+#[test]
+fn test_trim_keeps_distinct_implies() {
+    use ConstrainingElement::Port;
+    use FormulaTerm::{Var, NegVar};
+
+    let distinct = DNFCube { terms: vec![Var(Port(3))] };
+    let mut ppri = PinPairRoutingInfo {
+        requires: vec![DNFCube { terms: vec![NegVar(Port(1)), NegVar(Port(2))] }],
+        implies: vec![
+            distinct.clone(),
+            DNFCube { terms: vec![NegVar(Port(1)), NegVar(Port(2)), Var(Port(4))] },
+        ],
+        unoptimized: None,
+    };
+    ppri.trim_implies();
+    assert_eq!(ppri.implies, vec![distinct]);
+}
+
+#[test]
+fn test_merge_segments() {
+    use ConstrainingElement::Port;
+    use FormulaTerm::{Var, NegVar};
+
+    let first = PinPairRoutingInfo {
+        requires: vec![
+            DNFCube { terms: vec![NegVar(Port(1))] },
+            DNFCube { terms: vec![NegVar(Port(2))] },
+        ],
+        implies: vec![DNFCube { terms: vec![Var(Port(3))] }],
+        unoptimized: None,
+    };
+    let second = PinPairRoutingInfo {
+        requires: vec![DNFCube { terms: vec![NegVar(Port(4))] }],
+        implies: vec![
+            DNFCube { terms: vec![Var(Port(3))] },
+            DNFCube { terms: vec![Var(Port(5))] },
+        ],
+        unoptimized: None,
+    };
+
+    let merged = first.merge(&second);
+
+    let expected_requires = DNFForm { cubes: first.requires.clone() }
+        .conjunct(DNFForm { cubes: second.requires.clone() });
+    assert_eq!(DNFForm { cubes: merged.requires.clone() }, expected_requires);
+    assert_eq!(
+        merged.requires,
+        vec![
+            DNFCube { terms: vec![NegVar(Port(1)), NegVar(Port(4))] },
+            DNFCube { terms: vec![NegVar(Port(2)), NegVar(Port(4))] },
+        ]
+    );
+    assert_eq!(merged.implies.len(), 2);
+}
+
+#[test]
+fn test_pin_timeout() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false)
+        .with_pin_timeout(Some(std::time::Duration::ZERO));
+
+    let a = pin(&router, &device, "A", "A");
+    let q = pin(&router, &device, "Q", "Q");
+
+    assert_eq!(router.route_pins(a, true).err(), Some(RoutingTimedOut));
+
+    let info = router.route_all(true);
+    assert!(info.pin_to_pin_routing.is_empty());
+    assert!(info.timed_out.contains(&a));
+    /* Input pins are not routed from, so they can't time out */
+    assert!(!info.timed_out.contains(&q));
+
+    let (sorted, timed_out) = router.route_all_sorted(true);
+    assert!(sorted.is_empty());
+    assert_eq!(timed_out, info.timed_out);
+
+    let timed_out = router.route_all_streaming(true, |from, _| panic!("{:?} was routed", from));
+    assert_eq!(timed_out, info.timed_out);
+
+    let router = router.with_pin_timeout(None);
+    let info = router.route_all(true);
+    assert!(info.timed_out.is_empty());
+    assert!(!info.pin_to_pin_routing.is_empty());
+}
+
+/* Compile check for builds with `--no-default-features`: querying routability must not
+ * depend on serialization support. */
+#[test]
+#[cfg(not(feature = "serialize"))]
+fn test_query_without_serialize() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let a = pin(&router, &device, "A", "A");
+    let d = pin(&router, &device, "FF", "D");
+
+    let info = router.route_all(true);
+    assert!(info.pin_to_pin_routing.contains_key(&(a, d)));
+}
+
+#[test]
+fn test_has_edge_bounds() {
+    let mut graph = RoutingGraph::new(2);
+    graph.connect(1, 0, RoutingGraphEdge::SiteWire);
+
+    assert!(graph.has_edge(1, 0));
+    assert!(!graph.has_edge(0, 1));
+
+    /* A flat index of (0, 2) would point at the existing (1, 0) edge */
+    assert!(!graph.has_edge(0, 2));
+    assert!(!graph.has_edge(2, 0));
+    assert!(!graph.has_edge(usize::MAX, 0));
+    assert!(graph.try_get_edge(0, 2).is_none());
+
+    let between: Vec<_> = graph.edges_between(0, 1).collect();
+    assert_eq!(between, vec![(1, 0, RoutingGraphEdge::SiteWire)]);
+    assert_eq!(graph.edges_between(0, 5).count(), 0);
+}
+
+#[test]
+fn test_inout_site_ports_out_of_site_info() {
+    let msg = DeviceFixture::new("test_device").site_type(
+        SiteTypeFixture::new("INOUT_PORT_SITE")
+            .port("P", Dir::Inout)
+            .port("R", Dir::Inout)
+            .bel("X", BELCategory::Logic, &[("IO", Dir::Inout)])
+            .wire("W", &[("P", "P"), ("R", "R"), ("X", "IO")])
+    ).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let p = pin(&router, &device, "P", "P");
+    let r = pin(&router, &device, "R", "R");
+    let x = pin(&router, &device, "X", "IO");
+
+    let info = router.route_all(true);
+    let sorted = |map: &HashMap<SitePinId, Vec<SitePinId>>, key: SitePinId| {
+        let mut pins = map.get(&key).cloned().unwrap_or_default();
+        pins.sort();
+        pins
+    };
+
+    /* Both ports drive X.IO and each other, but never themselves */
+    let mut p_and_r = vec![p, r];
+    p_and_r.sort();
+    assert_eq!(sorted(&info.out_of_site_sources, x), p_and_r);
+    assert_eq!(sorted(&info.out_of_site_sources, p), vec![r]);
+    assert_eq!(sorted(&info.out_of_site_sources, r), vec![p]);
+
+    /* Symmetrically, both ports are sinks of X.IO and of each other */
+    assert_eq!(sorted(&info.out_of_site_sinks, x), p_and_r);
+    assert_eq!(sorted(&info.out_of_site_sinks, p), vec![r]);
+    assert_eq!(sorted(&info.out_of_site_sinks, r), vec![p]);
+
+    assert_eq!(info.out_of_site_sources.len(), 3);
+    assert_eq!(info.out_of_site_sinks.len(), 3);
+}
+
+/* A chain of `stages` muxes. Each mux selects between the previous stage and a site port
+ * of its own. */
+fn mux_chain_site(stages: usize) -> DeviceFixture {
+    let mut st = SiteTypeFixture::new("MUX_CHAIN_SITE")
+        .port("Q", Dir::Input);
+    for stage in 0 .. stages {
+        let mux = format!("M{}", stage);
+        let port = format!("P{}", stage);
+        let prev = if stage == 0 { "P".to_string() } else { format!("M{}", stage - 1) };
+        let prev_pin = if stage == 0 { "P" } else { "O" };
+        st = st
+            .port(&port, Dir::Output)
+            .bel(&mux, BELCategory::Routing, &[
+                ("I0", Dir::Input),
+                ("I1", Dir::Input),
+                ("O", Dir::Output),
+            ])
+            .pip(&mux, "I0", "O")
+            .pip(&mux, "I1", "O")
+            .wire(&format!("{}_W", port), &[(&port, &port), (&mux, "I1")])
+            .wire(&format!("{}_I0_W", mux), &[(&prev, prev_pin), (&mux, "I0")]);
+    }
+    st = st
+        .port("P", Dir::Output)
+        .wire("Q_W", &[(&format!("M{}", stages - 1), "O"), ("Q", "Q")]);
+    DeviceFixture::new("test_device").site_type(st)
+}
+
+/* Routes from each pin with a fresh `route_pins` call, the way `route_all` did before
+ * markers were reused across pins */
+fn route_all_fresh(router: &BruteRouter<()>, optimize: bool)
+    -> HashMap<(SitePinId, SitePinId), PinPairRoutingInfo>
+{
+    let mut results = HashMap::new();
+    for from in 0 .. router.get_graph().node_count() {
+        if router.get_graph().get_node(from).dir == PinDir::Input {
+            continue;
+        }
+        for (to, ppri) in router.route_pins(SitePinId(from), optimize).unwrap().enumerate() {
+            if (to != from) && (!ppri.requires.is_empty() || !ppri.implies.is_empty()) {
+                results.insert((SitePinId(from), SitePinId(to)), ppri);
+            }
+        }
+    }
+    results
+}
+
+#[test]
+fn test_reused_markers_match_fresh_routing() {
+    let msg = mux_chain_site(4).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false)
+        .with_unoptimized_forms(true);
+
+    let reused = router.route_all(true).pin_to_pin_routing;
+    let fresh = route_all_fresh(&router, true);
+
+    assert!(!fresh.is_empty());
+    assert_eq!(reused.len(), fresh.len());
+    for (pair, ppri) in &fresh {
+        let reused_ppri = &reused[pair];
+        assert_eq!(reused_ppri.requires, ppri.requires);
+        assert_eq!(reused_ppri.implies, ppri.implies);
+        let (reused_unopt, fresh_unopt) =
+            (reused_ppri.unoptimized.as_ref().unwrap(), ppri.unoptimized.as_ref().unwrap());
+        assert_eq!(reused_unopt.requires, fresh_unopt.requires);
+        assert_eq!(reused_unopt.implies, fresh_unopt.implies);
+    }
+}
+
+/* Benchmark of reusing markers across source pins. Run with
+ * `cargo test --release -- --ignored --nocapture bench_reused_markers` */
+#[test]
+#[ignore]
+fn bench_reused_markers() {
+    use std::time::Instant;
+
+    let msg = mux_chain_site(48).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let start = Instant::now();
+    let fresh = route_all_fresh(&router, false);
+    let fresh_time = start.elapsed();
+
+    let start = Instant::now();
+    let reused = router.route_all(false).pin_to_pin_routing;
+    let reused_time = start.elapsed();
+
+    assert_eq!(fresh.len(), reused.len());
+    println!(
+        "{} pins, {} pairs: fresh markers {:?}, reused markers {:?}",
+        router.get_graph().node_count(),
+        reused.len(),
+        fresh_time,
+        reused_time
+    );
+}
+
+#[test]
+fn test_pin_names_match_per_pin_resolution() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, true);
+
+    let names = router.pin_names(&device);
+    let gsctx = GlobalStringsCtx::hold();
+    assert_eq!(names.len(), router.get_graph().node_count());
+    for (pin, name) in names.iter().enumerate() {
+        assert_eq!(*name, router.get_pin_name(&device, &gsctx, SitePinId(pin)).to_string());
+    }
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn test_serialized_pin_names_match_per_pin_resolution() {
+    use super::serialize::IntoRoutingInfoWithExtras;
+    use std::sync::Arc;
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = Arc::new(BruteRouter::<()>::new(&device, 0, false));
+
+    let info = router.route_all(false);
+    let expected: HashSet<_> = {
+        let gsctx = GlobalStringsCtx::hold();
+        info.pin_to_pin_routing.keys()
+            .map(|(from, to)| format!(
+                "{}->{}",
+                router.get_pin_name(&device, &gsctx, *from).to_string(),
+                router.get_pin_name(&device, &gsctx, *to).to_string()
+            ))
+            .collect()
+    };
+
+    let json = serde_json::to_value(info.with_extras(Arc::clone(&router), &device)).unwrap();
+    let keys: HashSet<_> = json["pin_to_pin_routing"].as_object().unwrap()
+        .keys()
+        .cloned()
+        .collect();
+    assert_eq!(keys, expected);
+}
+
+/* Benchmark of resolving all pin names at once against resolving each name separately.
+ * Run with `cargo test --release -- --ignored --nocapture bench_pin_names` */
+#[test]
+#[ignore]
+fn bench_pin_names() {
+    use std::time::Instant;
+
+    let msg = mux_chain_site(48).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let pairs: Vec<_> = router.route_all(false).pin_to_pin_routing.into_keys().collect();
+
+    let start = Instant::now();
+    let per_pin: Vec<_> = pairs.iter()
+        .map(|(from, to)| {
+            let gsctx = GlobalStringsCtx::hold();
+            let from_name = router.get_pin_name(&device, &gsctx, *from).to_string();
+            let to_name = router.get_pin_name(&device, &gsctx, *to).to_string();
+            format!("{}->{}", from_name, to_name)
+        })
+        .collect();
+    let per_pin_time = start.elapsed();
+
+    let start = Instant::now();
+    let names = router.pin_names(&device);
+    let bulk: Vec<_> = pairs.iter()
+        .map(|(from, to)| format!("{}->{}", names[from.0], names[to.0]))
+        .collect();
+    let bulk_time = start.elapsed();
+
+    assert_eq!(per_pin, bulk);
+    println!(
+        "{} pins, {} pairs: per-pin resolution {:?}, bulk resolution {:?}",
+        router.get_graph().node_count(),
+        pairs.len(),
+        per_pin_time,
+        bulk_time
+    );
+}
+
+#[test]
+fn test_unconstrained_ratio() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let a = pin(&router, &device, "A", "A");
+    let i0 = pin(&router, &device, "MUX", "I0");
+    let d = pin(&router, &device, "FF", "D");
+
+    let info = router.route_all(false);
+    /* A reaches I0 over a site wire alone, while reaching D requires I1 not to drive
+     * the MUX output */
+    assert!(info.pin_to_pin_routing[&(a, i0)].is_unconstrained());
+    assert!(!info.pin_to_pin_routing[&(a, d)].is_unconstrained());
+
+    let unconstrained = info.unconstrained_pairs();
+    assert!(unconstrained > 0 && unconstrained < info.len());
+    assert_eq!(info.unconstrained_ratio(), Some(unconstrained as f64 / info.len() as f64));
+
+    let mut empty = info.clone();
+    empty.pin_to_pin_routing.clear();
+    assert_eq!(empty.unconstrained_ratio(), None);
+}
+
+#[test]
+fn test_route_all_sorted() {
+    let msg = mux_chain_site(3).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let (sorted, timed_out) = router.route_all_sorted(true);
+    let map = router.route_all(true).pin_to_pin_routing;
+
+    assert!(timed_out.is_empty());
+    assert!(!sorted.is_empty());
+    assert!(sorted.windows(2).all(|pairs| pairs[0].0 < pairs[1].0));
+    assert_eq!(sorted.len(), map.len());
+    for (pair, ppri) in &sorted {
+        assert_eq!(ppri.requires, map[pair].requires);
+        assert_eq!(ppri.implies, map[pair].implies);
+    }
+}
+
+#[test]
+fn test_out_of_site_info_for_pin() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let info = router.route_all(true);
+
+    let d = pin(&router, &device, "FF", "D");
+    let a = pin(&router, &device, "A", "A");
+    let b = pin(&router, &device, "B", "B");
+    let ff_q = pin(&router, &device, "FF", "Q");
+    let q = pin(&router, &device, "Q", "Q");
+
+    let sorted = |mut pins: Vec<SitePinId>| { pins.sort(); pins };
+    assert_eq!(sorted(router.sources_for(d, true).unwrap()), sorted(vec![a, b]));
+    assert_eq!(router.sinks_for(ff_q, true), Ok(vec![q]));
+    assert_eq!(router.sinks_for(ff_q, true).unwrap(), info.out_of_site_sinks[&ff_q]);
+
+    for pin in (0 .. router.get_graph().node_count()).map(SitePinId) {
+        let sources = info.out_of_site_sources.get(&pin).cloned().unwrap_or_default();
+        let sinks = info.out_of_site_sinks.get(&pin).cloned().unwrap_or_default();
+        assert_eq!(sorted(router.sources_for(pin, true).unwrap()), sorted(sources));
+        assert_eq!(sorted(router.sinks_for(pin, true).unwrap()), sorted(sinks));
+    }
+
+    /* Input pins can't act as sources */
+    assert_eq!(router.sinks_for(q, true), Ok(Vec::new()));
+
+    let router = BruteRouter::<()>::new(&device, 0, false)
+        .with_pin_timeout(Some(std::time::Duration::ZERO));
+    assert_eq!(router.sources_for(d, true), Err(RoutingTimedOut));
+    assert_eq!(router.sinks_for(ff_q, true), Err(RoutingTimedOut));
+}
+
+#[test]
+fn test_routing_info_iter() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let info = router.route_all(true);
+
+    let a = pin(&router, &device, "A", "A");
+    let d = pin(&router, &device, "FF", "D");
+
+    let mut count = 0;
+    for (pair, ppri) in &info {
+        assert!(std::ptr::eq(ppri, &info.pin_to_pin_routing[&pair]));
+        count += 1;
+    }
+    assert_eq!(count, info.pin_to_pin_routing.len());
+    assert_eq!(info.iter().len(), info.len());
+    assert!(!info.is_empty());
+    assert_eq!(info.iter().filter(|((from, to), _)| (*from, *to) == (a, d)).count(), 1);
+}
+
+#[test]
+fn test_invalid_activators() {
+    let msg = mux_chain_site(3).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let mut info = router.route_all(true);
+
+    assert!(info.iter().any(|(_, ppri)| !ppri.implies.is_empty()));
+    assert!(router.find_invalid_activators(&info).is_empty());
+
+    let flattened = BruteRouter::<()>::new(&device, 0, false).with_flattened_inout(true);
+    assert!(flattened.find_invalid_activators(&flattened.route_all(true)).is_empty());
+
+    /* The input site port drives nothing, so it can't activate any route */
+    let q = pin(&router, &device, "Q", "Q");
+    let pair = *info.pin_to_pin_routing.keys().min().unwrap();
+    info.pin_to_pin_routing.get_mut(&pair).unwrap().implies
+        .push(DNFCube { terms: vec![FormulaTerm::Var(ConstrainingElement::Port(q.0 as u32))] });
+
+    assert_eq!(router.find_invalid_activators(&info), vec![(pair, q)]);
+
+    /* M1.I0 is reachable from P and drives M1.O, but not any pin of the route */
+    let p = pin(&router, &device, "P", "P");
+    let m0_i0 = pin(&router, &device, "M0", "I0");
+    let m0_o = pin(&router, &device, "M0", "O");
+    let m1_i0 = pin(&router, &device, "M1", "I0");
+    let port = |pin: SitePinId| FormulaTerm::Var(ConstrainingElement::Port(pin.0 as u32));
+    info.pin_to_pin_routing.get_mut(&(p, m0_o)).unwrap().implies
+        .push(DNFCube { terms: vec![port(p), port(m0_i0), port(m1_i0)] });
+
+    let invalid = router.find_invalid_activators(&info);
+    assert!(invalid.contains(&((p, m0_o), m1_i0)));
+    assert!(!invalid.contains(&((p, m0_o), m0_i0)));
+}
+
+#[test]
+fn test_route_bel() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let mux_o = pin(&router, &device, "MUX", "O");
+    let d = pin(&router, &device, "FF", "D");
+
+    let info = router.route_bel(&device, "MUX", true).unwrap();
+    assert!(!info.pin_to_pin_routing.is_empty());
+    for (from, _) in info.pin_to_pin_routing.keys() {
+        assert_eq!(*from, mux_o);
+    }
+    assert_eq!(
+        info.pin_to_pin_routing[&(mux_o, d)].requires,
+        router.route_all(true).pin_to_pin_routing[&(mux_o, d)].requires
+    );
+
+    assert!(router.route_bel(&device, "NO_SUCH_BEL", true).is_err());
+}
+
+#[test]
+fn test_query_pair_cache() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let mut router = BruteRouter::<()>::new(&device, 0, false);
+
+    let a = pin(&router, &device, "A", "A");
+    let d = pin(&router, &device, "FF", "D");
+    let i0 = pin(&router, &device, "MUX", "I0");
+    let info = router.route_all(true);
+
+    let to_d = router.query_pair(a, d, true).unwrap().unwrap();
+    assert_eq!(router.query_cache_misses(), 1);
+    let to_i0 = router.query_pair(a, i0, true).unwrap().unwrap();
+    assert_eq!(router.query_cache_misses(), 1);
+
+    assert_eq!(to_d.requires, info.pin_to_pin_routing[&(a, d)].requires);
+    assert_eq!(to_i0.requires, info.pin_to_pin_routing[&(a, i0)].requires);
+
+    /* Unoptimized results are cached separately */
+    router.query_pair(a, d, false).unwrap();
+    assert_eq!(router.query_cache_misses(), 2);
+
+    router.clear_query_cache();
+    router.query_pair(a, d, true).unwrap();
+    assert_eq!(router.query_cache_misses(), 1);
+
+    /* Pins out of range of the site type are not routed at all */
+    let past_last = SitePinId(router.get_graph().node_count());
+    assert!(router.query_pair(a, past_last, true).unwrap().is_none());
+    assert!(router.query_pair(past_last, d, true).unwrap().is_none());
+    assert!(router.query_routed_pair(past_last, d, true).unwrap().is_none());
+    assert_eq!(router.query_cache_misses(), 1);
+}
+
+#[test]
+fn test_sorted_dot_is_stable() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+
+    let export = || {
+        let brouter = BruteRouter::<()>::new(&device, 0, false);
+        brouter.create_dot_exporter()
+            .with_sorted_clusters(true)
+            .export_dot(&device, "MUX_SITE")
+    };
+
+    let dot = export();
+    assert_eq!(dot, export());
+
+    let a = dot.find("cluster_A ").unwrap();
+    let ff = dot.find("cluster_FF ").unwrap();
+    let mux = dot.find("cluster_MUX ").unwrap();
+    assert!(a < ff && ff < mux);
+}
+
+#[test]
+fn test_dot_edge_kind_colors() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let brouter = BruteRouter::<()>::new(&device, 0, false);
+    let (a, i0, o) = (
+        pin(&brouter, &device, "A", "A"),
+        pin(&brouter, &device, "MUX", "I0"),
+        pin(&brouter, &device, "MUX", "O"),
+    );
+
+    let edge_attrs = |dot: &str, from: SitePinId, to: SitePinId| {
+        let edge = format!("    {} -> {}", from.0, to.0);
+        dot.lines()
+            .filter_map(|line| line.strip_prefix(&edge))
+            .find(|attrs| attrs.starts_with([';', ' ']))
+            .unwrap()
+            .to_string()
+    };
+
+    let plain = brouter.create_dot_exporter().export_dot(&device, "MUX_SITE");
+    assert_eq!(edge_attrs(&plain, a, i0), ";");
+
+    let colored = brouter.create_dot_exporter()
+        .with_edge_kind_colors(true)
+        .export_dot(&device, "MUX_SITE");
+    let (wire, pip) = (edge_attrs(&colored, a, i0), edge_attrs(&colored, i0, o));
+    assert!(wire.contains("color="), "{}", wire);
+    assert!(pip.contains("color="), "{}", pip);
+    assert_ne!(wire, pip);
+}
+
+#[test]
+fn test_unresolved_site_wire_pin() {
+    use crate::ic_loader::OpenWriteError;
+
+    let msg = DeviceFixture::new("test_device").site_type(
+        SiteTypeFixture::new("BROKEN_SITE")
+            .port("A", Dir::Output)
+            .bel("FF", BELCategory::Logic, &[("D", Dir::Input)])
+            .dangling_pin("FF", "CE", Dir::Input)
+            .wire("A_W", &[("A", "A"), ("FF", "D"), ("FF", "CE")])
+    ).build();
+    let device = fixture_root(&msg);
+
+    match BruteRouter::<()>::try_new(&device, 0, false) {
+        Err(OpenWriteError::MalformedDevice(msg)) => {
+            assert!(msg.contains("A_W"), "{}", msg);
+            assert!(msg.contains("BROKEN_SITE"), "{}", msg);
+            assert!(msg.contains("FF.CE"), "{}", msg);
+        },
+        Err(e) => panic!("Unexpected error: {:?}", e),
+        Ok(_) => panic!("Unresolved site wire pin was accepted"),
+    }
+
+    /* The fixture is fine otherwise */
+    assert!(BruteRouter::<()>::try_new(&fixture_root(&mux_site().build()), 0, false).is_ok());
+}
+
+#[test]
+#[cfg(feature = "serialize")]
+fn test_site_port_tile_wires() {
+    use super::serialize::IntoRoutingInfoWithExtras;
+    use std::sync::Arc;
+
+    let msg = mux_site()
+        .tile_type("MUX_TILE", &["MUX_SITE"])
+        .site_pin_wires("MUX_TILE", 0, &[("A", "TILE_A"), ("Q", "TILE_Q")])
+        .build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let info = router.route_all(true);
+    let json = serde_json::to_value(
+        info.with_extras(Arc::new(router), &device).with_site_port_tile_wires(true)
+    ).unwrap();
+
+    let tile_wires = json["site_port_tile_wires"].as_object().unwrap();
+    assert_eq!(tile_wires["A.A"], serde_json::json!([
+        { "tile_type": "MUX_TILE", "site": 0, "wire": "TILE_A" }
+    ]));
+    assert_eq!(tile_wires["Q.Q"][0]["wire"], "TILE_Q");
+    /* B is not connected to any tile wire in the fixture */
+    assert!(tile_wires.get("B.B").is_none());
+}
+
+#[test]
+fn test_collapse_aliases() {
+    /* X.IO and Y.IO share a bidirectional site wire, so they're always connected */
+    let msg = DeviceFixture::new("test_device").site_type(
+        SiteTypeFixture::new("ALIAS_SITE")
+            .port("A", Dir::Output)
+            .bel("X", BELCategory::Logic, &[("IO", Dir::Inout)])
+            .bel("Y", BELCategory::Logic, &[("IO", Dir::Inout)])
+            .bel("BUF", BELCategory::Routing, &[("I", Dir::Input), ("O", Dir::Output)])
+            .wire("A_W", &[("A", "A"), ("BUF", "I")])
+            .wire("W", &[("BUF", "O"), ("X", "IO"), ("Y", "IO")])
+            .pip("BUF", "I", "O")
+    ).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let a = pin(&router, &device, "A", "A");
+    let x = pin(&router, &device, "X", "IO");
+    let y = pin(&router, &device, "Y", "IO");
+    let (representative, alias) = (x.min(y), x.max(y));
+
+    let aliases = router.find_pin_aliases();
+    assert_eq!(aliases.len(), 1);
+    assert_eq!(aliases[&alias], representative);
+
+    let mut info = router.route_all(true);
+    assert!(info.pin_to_pin_routing.contains_key(&(a, alias)));
+    let pairs_before = info.pin_to_pin_routing.len();
+
+    info.collapse_aliases(aliases);
+    assert!(info.pin_to_pin_routing.len() < pairs_before);
+    assert!(info.pin_to_pin_routing.contains_key(&(a, representative)));
+    assert!(info.pin_to_pin_routing.keys().all(|(from, to)| *from != alias && *to != alias));
+    assert_eq!(info.aliases[&alias], representative);
+}
+
+#[test]
+fn test_memory_estimate() {
+    use super::memory::*;
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let estimate = estimate_site_type(&device, 0);
+    let observed = router.get_graph().allocated_bytes();
+    assert!(
+        (estimate.graph * 2 >= observed) && (estimate.graph <= observed * 2),
+        "Estimated {} bytes for a graph taking {} bytes", estimate.graph, observed
+    );
+
+    let info = router.route_all(true);
+    assert!(estimate.routing > 0);
+    assert!(info.pin_to_pin_routing.len() <= router.get_graph().node_count().pow(2));
+
+    let device_estimate = MemoryEstimate::new(&device, [0]);
+    assert!(device_estimate.peak(true, 1) <= device_estimate.peak(false, 1));
+    assert!(device_estimate.peak(true, 1) < device_estimate.peak(true, 4));
+
+    /* Only as many site types as there are can be in flight */
+    let device_estimate = MemoryEstimate::new(&device, [0, 0]);
+    assert_eq!(device_estimate.peak_parallel(true, 1, 2), 2 * device_estimate.peak(true, 1));
+    assert_eq!(
+        device_estimate.peak_parallel(true, 1, 3),
+        device_estimate.peak_parallel(true, 1, 2)
+    );
+}
+
+#[test]
+fn test_pin_dir_round_trip() {
+    for dir in [PinDir::Inout, PinDir::Input, PinDir::Output] {
+        assert_eq!(dir.to_string().parse::<PinDir>(), Ok(dir));
+        assert_eq!(PinDir::try_from(dir.to_string().as_str()), Ok(dir));
+    }
+    assert_eq!("Input".parse::<PinDir>(), Ok(PinDir::Input));
+    assert_eq!("in".parse::<PinDir>(), Err(UnknownVariant("in".into())));
+}
+
+#[test]
+fn test_bel_category_round_trip() {
+    use super::BELCategory as Category;
+
+    for category in [Category::LogicOrRouting, Category::SitePort] {
+        assert_eq!(category.to_string().parse::<Category>(), Ok(category));
+        assert_eq!(Category::try_from(category.to_string().as_str()), Ok(category));
+    }
+    assert_eq!("routing".parse::<Category>(), Ok(Category::LogicOrRouting));
+    assert_eq!("SitePort".parse::<Category>(), Ok(Category::SitePort));
+    assert!("port".parse::<Category>().is_err());
+}
+
+#[test]
+fn test_excluded_bel_kinds() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let bel_idx = |name: &str| {
+        let gsctx = GlobalStringsCtx::hold();
+        router.get_bels().iter()
+            .position(|bel| &*bel.name.get(&device, &gsctx) == name)
+            .unwrap()
+    };
+    assert_eq!(router.bel_kind(bel_idx("MUX")), BELKind::Routing);
+    assert_eq!(router.bel_kind(bel_idx("FF")), BELKind::Logic);
+    assert_eq!(router.bel_kind(bel_idx("A")), BELKind::SitePort);
+
+    let a = pin(&router, &device, "A", "A");
+    let mux_o = pin(&router, &device, "MUX", "O");
+    let d = pin(&router, &device, "FF", "D");
+    let ff_q = pin(&router, &device, "FF", "Q");
+    let q = pin(&router, &device, "Q", "Q");
+
+    let info = router.route_all(true);
+    assert!(info.pin_to_pin_routing.contains_key(&(a, d)));
+    assert!(info.pin_to_pin_routing.contains_key(&(ff_q, q)));
+
+    let router = router.with_excluded_bel_kinds(&[BELKind::Logic]);
+    let info = router.route_all(true);
+    assert!(info.pin_to_pin_routing.contains_key(&(a, mux_o)));
+    for ((from, to), _) in &info.pin_to_pin_routing {
+        assert!(![d, ff_q].contains(from) && ![d, ff_q].contains(to));
+    }
+
+    assert_eq!("Routing".parse::<BELKind>(), Ok(BELKind::Routing));
+    assert_eq!(BELKind::Logic.to_string().parse::<BELKind>(), Ok(BELKind::Logic));
+}
+
+#[test]
+fn test_self_loop_site_wire() {
+    let msg = DeviceFixture::new("test_device").site_type(
+        SiteTypeFixture::new("LOOP_SITE")
+            .port("P", Dir::Inout)
+            .wire("P_W", &[("P", "P")])
+    ).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let p = pin(&router, &device, "P", "P");
+
+    assert!(!router.get_graph().has_edge(p.0, p.0));
+    assert_eq!(router.get_graph().self_loops().count(), 0);
+
+    /* The lone inout pin is the wire's only driver and sink */
+    assert_eq!(wire_self_loops(&[p.0], &[p.0]).collect::<Vec<_>>(), vec![p.0]);
+    /* Inout pins sharing a wire with other pins are fine */
+    assert_eq!(wire_self_loops(&[p.0], &[p.0, p.0 + 1]).count(), 0);
+
+    let mut graph = RoutingGraph::new(2);
+    assert!(graph.connect(1, 1, RoutingGraphEdge::SiteWire).is_none());
+    assert!(!graph.has_edge(1, 1));
+}
+
+#[test]
+fn test_edge_usage() {
+    use std::sync::Arc;
+
+    /* Both ports funnel into the MUX_O wire, which is the only way to reach BUF */
+    let msg = DeviceFixture::new("test_device").site_type(
+        SiteTypeFixture::new("BOTTLENECK_SITE")
+            .port("A", Dir::Output)
+            .port("B", Dir::Output)
+            .bel("MUX", BELCategory::Routing, &[
+                ("I0", Dir::Input),
+                ("I1", Dir::Input),
+                ("O", Dir::Output),
+            ])
+            .bel("BUF", BELCategory::Routing, &[("I", Dir::Input), ("O", Dir::Output)])
+            .bel("FF0", BELCategory::Logic, &[("D", Dir::Input)])
+            .bel("FF1", BELCategory::Logic, &[("D", Dir::Input)])
+            .wire("A_W", &[("A", "A"), ("MUX", "I0")])
+            .wire("B_W", &[("B", "B"), ("MUX", "I1")])
+            .wire("MUX_O", &[("MUX", "O"), ("BUF", "I")])
+            .wire("BUF_O", &[("BUF", "O"), ("FF0", "D"), ("FF1", "D")])
+            .pip("MUX", "I0", "O")
+            .pip("MUX", "I1", "O")
+            .pip("BUF", "I", "O")
+    ).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    assert!(router.route_all(true).edge_usage.is_empty());
+
+    let router = router.with_edge_usage(true);
+    let info = router.route_all(true);
+    let a = pin(&router, &device, "A", "A");
+    let mux_i0 = pin(&router, &device, "MUX", "I0");
+    let mux_o = pin(&router, &device, "MUX", "O");
+    let buf_i = pin(&router, &device, "BUF", "I");
+    let buf_o = pin(&router, &device, "BUF", "O");
+    let ff0_d = pin(&router, &device, "FF0", "D");
+
+    /* Routes from A, B and MUX.O to FF0.D and FF1.D each. Routes ending at pins of
+     * routing BELs are not counted. */
+    assert_eq!(info.edge_usage[&(mux_o, buf_i)], 6);
+    assert_eq!(info.edge_usage[&(buf_i, buf_o)], 6);
+    /* Routes from A, B, MUX.O and BUF.O */
+    assert_eq!(info.edge_usage[&(buf_o, ff0_d)], 4);
+    assert_eq!(info.edge_usage[&(a, mux_i0)], 2);
+    assert_eq!(info.edge_usage.values().max(), Some(&6));
+    for (from, to) in info.edge_usage.keys() {
+        assert!(router.get_graph().has_edge(from.0, to.0));
+    }
+
+    let multithreaded = Arc::new(router).route_all_multithreaded(2, true, &CancellationToken::default());
+    assert_eq!(multithreaded.edge_usage, info.edge_usage);
+}
+
+#[test]
+fn test_site_pips_in_tile() {
+    use super::tile_routing::*;
+
+    let msg = mux_site()
+        .site_type(SiteTypeFixture::new("EMPTY"))
+        .tile_type("MUX_TILE", &["MUX_SITE", "EMPTY", "MUX_SITE"])
+        .tile_pip("MUX_TILE", "W0", "W1")
+        .tile_pip("MUX_TILE", "W1", "W2")
+        .tile_pip("MUX_TILE", "W2", "W0")
+        .build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let a = pin(&router, &device, "A", "A");
+    let b = pin(&router, &device, "B", "B");
+    let o = pin(&router, &device, "MUX", "O");
+    let d = pin(&router, &device, "FF", "D");
+
+    let pairs = routed_site_pips(&device, &router, &router.route_all(true)).unwrap();
+    let routes = |from, to| pairs.iter()
+        .find(|pair| (pair.from, pair.to) == (from, to))
+        .map(|pair| pair.routes.clone());
+    /* Site PIPs of MUX are I0 -> O and I1 -> O */
+    assert_eq!(routes(a, d), Some(vec![vec![0]]));
+    assert_eq!(routes(a, o), Some(vec![vec![0]]));
+    assert_eq!(routes(b, d), Some(vec![vec![1]]));
+    assert_eq!(routes(o, d), None);
+
+    /* 3 tile PIPs come first, then 2 site PIPs of site 0, none of site 1 */
+    let tile_pairs = site_pips_in_tile(&device, 0, &pairs, 0, 2).unwrap();
+    assert_eq!(tile_pairs.len(), pairs.len());
+    let a_to_d = tile_pairs.iter()
+        .find(|pair| pair.from == TileSitePinId { site: 2, pin: a } && pair.to.pin == d)
+        .unwrap();
+    assert_eq!(a_to_d.to, TileSitePinId { site: 2, pin: d });
+    assert_eq!(a_to_d.routes, vec![vec![5]]);
+    let b_to_d = site_pips_in_tile(&device, 0, &pairs, 0, 0).unwrap().into_iter()
+        .find(|pair| (pair.from.pin, pair.to.pin) == (b, d))
+        .unwrap();
+    assert_eq!(b_to_d.routes, vec![vec![4]]);
+
+    assert!(site_pips_in_tile(&device, 0, &pairs, 0, 1).is_err());
+    assert!(site_pips_in_tile(&device, 0, &pairs, 0, 3).is_err());
+
+    /* Routes through disabled PIPs are gone */
+    let router = BruteRouter::<()>::new(&device, 0, false).with_disabled_pips(&device, &["MUX"]);
+    assert!(routed_site_pips(&device, &router, &router.route_all(true)).unwrap().is_empty());
+}
+
+#[test]
+fn test_fast_path_matches_full_routing() {
+    let msg = DeviceFixture::new("test_device")
+        .site_type(
+            SiteTypeFixture::new("LOGIC_SITE")
+                .port("A", Dir::Output)
+                .port("Q", Dir::Input)
+                .bel("D", BELCategory::Logic, &[("I", Dir::Input), ("O", Dir::Output)])
+                .bel("S", BELCategory::Logic, &[("I", Dir::Input)])
+                .wire("A_W", &[("A", "A"), ("D", "I")])
+                .wire("O_W", &[("D", "O"), ("S", "I"), ("Q", "Q")])
+        )
+        .build();
+    let device = fixture_root(&msg);
+    let fast = BruteRouter::<()>::new(&device, 0, false);
+    /* Callbacks rule the fast path out */
+    let full = BruteRouter::<()>::new(&device, 0, false)
+        .with_callback(|_| (None, None, ()));
+
+    let fast_info = fast.route_all(true);
+    let full_info = full.route_all(true);
+    assert!(fast.fast_path_count() > 0);
+    assert_eq!(full.fast_path_count(), 0);
+
+    assert!(!full_info.pin_to_pin_routing.is_empty());
+    assert_eq!(fast_info.pin_to_pin_routing.len(), full_info.pin_to_pin_routing.len());
+    for (pair, ppri) in &full_info.pin_to_pin_routing {
+        let fast_ppri = &fast_info.pin_to_pin_routing[pair];
+        assert_eq!(fast_ppri.requires, ppri.requires);
+        assert_eq!(fast_ppri.implies, ppri.implies);
+    }
+
+    /* The MUX can be driven by either of its inputs */
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    router.route_all(true);
+    assert_eq!(router.fast_path_count(), 0);
+}
+
+#[test]
+fn test_merged_pin_slices() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let full = router.route_all(true);
+    let pin_count = router.get_graph().node_count();
+    let mid = pin_count / 2;
+    let first = router.route_pin_range(0 .. mid, true);
+    let second = router.route_pin_range(mid .. usize::MAX, true);
+    assert_eq!(first.pin_range, Some(0 .. mid));
+    assert_eq!(second.pin_range, Some(mid .. pin_count));
+    assert_eq!(router.merge_routing([first.clone()]).pin_range, Some(0 .. mid));
+    let merged = router.merge_routing([first, second]);
+    assert_eq!(full.pin_range, None);
+    assert_eq!(merged.pin_range, None);
+
+    assert_eq!(merged.pin_to_pin_routing.len(), full.pin_to_pin_routing.len());
+    for (pair, ppri) in &full.pin_to_pin_routing {
+        let merged_ppri = &merged.pin_to_pin_routing[pair];
+        assert_eq!(merged_ppri.requires, ppri.requires);
+        assert_eq!(merged_ppri.implies, ppri.implies);
+    }
+    assert_eq!(merged.out_of_site_sources.len(), full.out_of_site_sources.len());
+    assert_eq!(merged.out_of_site_sinks.len(), full.out_of_site_sinks.len());
+
+    /* Slices are clamped to the pins of the site */
+    assert!(router.route_pin_range(mid + 100 .. mid, true).pin_to_pin_routing.is_empty());
+}
+
+#[test]
+fn test_degree_statistics() {
+    /* Node 0 drives nodes 1-4, node 4 also drives node 3 */
+    let mut graph = RoutingGraph::new(5);
+    for spoke in 1 .. 5 {
+        graph.connect(0, spoke, RoutingGraphEdge::SiteWire);
+    }
+    graph.connect(4, 3, RoutingGraphEdge::SiteWire);
+
+    assert_eq!(graph.out_degree(0), 4);
+    assert_eq!(graph.in_degree(0), 0);
+    assert_eq!(graph.in_degree(3), 2);
+    assert_eq!(graph.out_degree(3), 0);
+    assert_eq!(graph.degree_histogram(), vec![0, 2, 2, 0, 1]);
+}
+
+#[test]
+fn test_graph_reachability() {
+    /* 0 -> 1 -> 2, 3 -> 2, node 4 is disconnected */
+    let mut graph = RoutingGraph::new(5);
+    graph.connect(0, 1, RoutingGraphEdge::SiteWire);
+    graph.connect(1, 2, RoutingGraphEdge::PseudoPip);
+    graph.connect(3, 2, RoutingGraphEdge::SiteWire);
+
+    assert_eq!(graph.reachable([0], true), vec![true, true, true, false, false]);
+    assert_eq!(graph.reachable([2], false), vec![true, true, true, true, false]);
+    assert_eq!(graph.reachable([1, 3], true), vec![false, true, true, true, false]);
+    assert_eq!(graph.reachable([], true), vec![false; 5]);
+}
+
+#[test]
+fn test_ignore_ports() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let a = pin(&router, &device, "A", "A");
+    let b = pin(&router, &device, "B", "B");
+    let d = pin(&router, &device, "FF", "D");
+    let i0 = pin(&router, &device, "MUX", "I0");
+    let i1 = pin(&router, &device, "MUX", "I1");
+
+    let mut info = router.route_all(true);
+    let mentions = |info: &RoutingInfo, pair, port: SitePinId| {
+        info.pin_to_pin_routing[&pair].requires.iter()
+            .flat_map(|cube| cube.terms.iter())
+            .any(|term| *term == FormulaTerm::NegVar(ConstrainingElement::Port(port.0 as u32)))
+    };
+    assert!(mentions(&info, (a, d), i1));
+    assert!(mentions(&info, (b, d), i0));
+
+    info.ignore_ports(&[i1].into_iter().collect());
+    assert!(!mentions(&info, (a, d), i1));
+    assert_eq!(info.pin_to_pin_routing[&(a, d)].requires, vec![DNFCube::new()]);
+    assert!(mentions(&info, (b, d), i0));
+
+    /* Cubes requiring an ignored port to be driven are not dropped */
+    let driven = FormulaTerm::Var(ConstrainingElement::Port(i0.0 as u32));
+    info.pin_to_pin_routing.get_mut(&(b, d)).unwrap().requires
+        .push(DNFCube { terms: vec![driven.clone()] });
+    info.ignore_ports(&[i0].into_iter().collect());
+    assert!(!mentions(&info, (b, d), i0));
+    assert!(info.pin_to_pin_routing[&(b, d)].requires.iter()
+        .any(|cube| cube.terms == vec![driven.clone()]));
+}
+
+#[test]
+fn test_cancel_multithreaded_routing() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let full_info = BruteRouter::<()>::new(&device, 0, false).route_all(true);
+
+    /* Cancel once the second source pin starts being routed */
+    let cancel = CancellationToken::default();
+    let token = Arc::clone(&cancel);
+    let mut sources = 0;
+    let router = BruteRouter::<()>::new(&device, 0, false)
+        .with_callback(move |frame| {
+            if frame.prev_node.is_none() {
+                sources += 1;
+                if sources == 2 {
+                    token.store(true, Ordering::Relaxed);
+                }
+            }
+            (None, None, ())
+        });
+
+    let info = Arc::new(router).route_all_multithreaded(1, true, &cancel);
+    assert!(info.cancelled);
+    assert!(!full_info.cancelled);
+    assert!(!info.pin_to_pin_routing.is_empty());
+    assert!(info.pin_to_pin_routing.len() < full_info.pin_to_pin_routing.len());
+    for (pair, ppri) in &info.pin_to_pin_routing {
+        assert_eq!(&full_info.pin_to_pin_routing[pair], ppri);
+    }
+    /* Cancelled pins are not reported as timed out */
+    assert!(info.timed_out.is_empty());
+
+    let uncancelled = Arc::new(BruteRouter::<()>::new(&device, 0, false))
+        .route_all_multithreaded(2, true, &Arc::new(AtomicBool::new(false)));
+    assert!(!uncancelled.cancelled);
+    assert_eq!(uncancelled.pin_to_pin_routing.len(), full_info.pin_to_pin_routing.len());
+}
+
+#[test]
+fn test_isolated_from_ports() {
+    let msg = mux_site().build();
+    let router = BruteRouter::<()>::new(&fixture_root(&msg), 0, false);
+    assert!(router.isolated_from_ports().is_empty());
+
+    /* X is wired only to itself, so no site port can reach it */
+    let msg = DeviceFixture::new("test_device")
+        .site_type(
+            SiteTypeFixture::new("STRANDED_SITE")
+                .port("A", Dir::Output)
+                .port("Q", Dir::Input)
+                .bel("D", BELCategory::Logic, &[("I", Dir::Input), ("O", Dir::Output)])
+                .bel("X", BELCategory::Logic, &[("I", Dir::Input), ("O", Dir::Output)])
+                .wire("A_W", &[("A", "A"), ("D", "I")])
+                .wire("O_W", &[("D", "O"), ("Q", "Q")])
+                .wire("X_W", &[("X", "O"), ("X", "I")])
+        )
+        .build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+
+    let mut expected = vec![pin(&router, &device, "X", "I"), pin(&router, &device, "X", "O")];
+    expected.sort();
+    assert_eq!(router.isolated_from_ports(), expected);
+}
+
+#[test]
+fn test_successors_discovered_in_pin_order() {
+    use std::sync::{Arc, Mutex};
+
+    /* The wire lists the flip-flops in a different order than their pins are numbered */
+    let msg = DeviceFixture::new("test_device").site_type(
+        SiteTypeFixture::new("FANOUT_SITE")
+            .port("A", Dir::Output)
+            .bel("FF0", BELCategory::Logic, &[("D", Dir::Input)])
+            .bel("FF1", BELCategory::Logic, &[("D", Dir::Input)])
+            .bel("FF2", BELCategory::Logic, &[("D", Dir::Input)])
+            .wire("A_W", &[("A", "A"), ("FF2", "D"), ("FF0", "D"), ("FF1", "D")])
+    ).build();
+    let device = fixture_root(&msg);
+    let frames = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&frames);
+    let router = BruteRouter::<()>::new(&device, 0, false)
+        .with_callback(move |frame| {
+            recorded.lock().unwrap().push((frame.prev_node, frame.node));
+            (None, None, ())
+        });
+    let a = pin(&router, &device, "A", "A");
+
+    let successors: Vec<_> = router.get_graph().successors(a.0).collect();
+    assert_eq!(successors.len(), 3);
+    assert!(successors.windows(2).all(|w| w[0] < w[1]));
+
+    router.route_pins(a, true).unwrap().for_each(drop);
+    let discovered: Vec<_> = frames.lock().unwrap().iter()
+        .filter(|(prev, _)| *prev == Some(a))
+        .map(|(_, node)| node.0)
+        .collect();
+    assert_eq!(discovered, successors);
+}
+
+#[test]
+fn test_pin_table_covers_referenced_ids() {
+    use super::serialize::IntoRoutingInfoWithExtras;
+    use std::sync::Arc;
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = Arc::new(BruteRouter::<()>::new(&device, 0, false));
+    let info = router.route_all(true);
+
+    let json = serde_json::to_value(
+        info.with_extras(Arc::clone(&router), &device)
+            .with_numeric_port_ids(true)
+            .with_pin_table(true)
+    ).unwrap();
+
+    let pin_table = json["pin_table"].as_array().unwrap();
+    assert_eq!(pin_table.len(), router.get_graph().node_count());
+    for (id, entry) in pin_table.iter().enumerate() {
+        let (bel, pin) = (entry["bel"].as_str().unwrap(), entry["pin"].as_str().unwrap());
+        let name = format!("{}.{}", bel, pin);
+        assert_eq!(json["port_names"][id], name.as_str());
+        assert_eq!(
+            serde_json::to_value(router.get_graph().get_node(id).dir).unwrap(),
+            entry["dir"]
+        );
+    }
+
+    fn port_ids(value: &serde_json::Value, ids: &mut Vec<u64>) {
+        match value {
+            serde_json::Value::Object(map) => for (key, value) in map {
+                match (key.as_str(), value.as_u64()) {
+                    ("Port", Some(id)) => ids.push(id),
+                    _ => port_ids(value, ids),
+                }
+            },
+            serde_json::Value::Array(values) =>
+                values.iter().for_each(|value| port_ids(value, ids)),
+            _ => (),
+        }
+    }
+    let mut ids = Vec::new();
+    port_ids(&json["pin_to_pin_routing"], &mut ids);
+    assert!(!ids.is_empty());
+    assert!(ids.iter().all(|id| (*id as usize) < pin_table.len()));
+}
+
+#[test]
+fn test_port_pairs_only() {
+    use super::BELCategory as Category;
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let is_port = |pin: SitePinId| router.bel_of_pin(pin).category == Category::SitePort;
+
+    /* MUX.O -> FF.D is internal to the site */
+    let all = router.route_all(true).pin_to_pin_routing;
+    assert!(all.keys().any(|(from, to)| !is_port(*from) && !is_port(*to)));
+
+    let ports_only = BruteRouter::<()>::new(&device, 0, false)
+        .with_port_pairs_only(true)
+        .route_all(true)
+        .pin_to_pin_routing;
+    assert!(!ports_only.is_empty());
+    assert!(ports_only.keys().all(|(from, to)| is_port(*from) || is_port(*to)));
+    assert_eq!(
+        ports_only.len(),
+        all.keys().filter(|(from, to)| is_port(*from) || is_port(*to)).count()
+    );
+
+    let ports_router = BruteRouter::<()>::new(&device, 0, false).with_port_pairs_only(true);
+
+    let (sorted, _) = ports_router.route_all_sorted(true);
+    assert_eq!(sorted.len(), ports_only.len());
+    assert!(sorted.iter().all(|((from, to), _)| ports_only.contains_key(&(*from, *to))));
+
+    let mut streamed = 0;
+    ports_router.route_all_streaming(true, |from, results| {
+        assert!(results.keys().all(|to| is_port(from) || is_port(*to)));
+        streamed += results.len();
+    });
+    assert_eq!(streamed, ports_only.len());
+
+    /* MUX.O -> FF.D is the only pair routed from the MUX which doesn't involve a port */
+    let mux_o = pin(&router, &device, "MUX", "O");
+    let ff_d = pin(&router, &device, "FF", "D");
+    assert!(router.route_bel(&device, "MUX", true).unwrap()
+        .pin_to_pin_routing.contains_key(&(mux_o, ff_d)));
+    let bel_routing = ports_router.route_bel(&device, "MUX", true).unwrap().pin_to_pin_routing;
+    assert!(bel_routing.keys().all(|(from, to)| is_port(*from) || is_port(*to)));
+    assert!(!bel_routing.contains_key(&(mux_o, ff_d)));
+}
+
+#[test]
+fn test_bel_level_disjoins_pins_of_bel() {
+    use super::BELCategory as Category;
+
+    /* Both outputs of SRC reach FF.D, each through its own input of the MUX */
+    let msg = DeviceFixture::new("test_device").site_type(
+        SiteTypeFixture::new("TWO_OUTPUT_SITE")
+            .bel("SRC", BELCategory::Logic, &[("O0", Dir::Output), ("O1", Dir::Output)])
+            .bel("MUX", BELCategory::Routing, &[
+                ("I0", Dir::Input),
+                ("I1", Dir::Input),
+                ("O", Dir::Output),
+            ])
+            .bel("FF", BELCategory::Logic, &[("D", Dir::Input)])
+            .wire("O0_W", &[("SRC", "O0"), ("MUX", "I0")])
+            .wire("O1_W", &[("SRC", "O1"), ("MUX", "I1")])
+            .wire("MUX_O", &[("MUX", "O"), ("FF", "D")])
+            .pip("MUX", "I0", "O")
+            .pip("MUX", "I1", "O")
+    ).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let info = router.route_all(true);
+    let o0 = pin(&router, &device, "SRC", "O0");
+    let o1 = pin(&router, &device, "SRC", "O1");
+    let d = pin(&router, &device, "FF", "D");
+
+    let (o0_d, o1_d) = (&info.pin_to_pin_routing[&(o0, d)], &info.pin_to_pin_routing[&(o1, d)]);
+    assert_ne!(o0_d.requires, o1_d.requires);
+
+    let (src, _) = router.pin_to_bel_pin(o0);
+    assert_eq!(router.get_bels()[src].category, Category::LogicOrRouting);
+    let bel_level = info.bel_level(&router);
+    let bel_d = &bel_level[&(src, d)];
+
+    let expected = DNFForm { cubes: o0_d.requires.clone() }
+        .disjunct(DNFForm { cubes: o1_d.requires.clone() });
+    assert_eq!(
+        CanonicalDNFForm::from(DNFForm { cubes: bel_d.requires.clone() }),
+        CanonicalDNFForm::from(expected)
+    );
+    for cube in o0_d.implies.iter().chain(&o1_d.implies) {
+        assert!(bel_d.implies.contains(cube));
+    }
+}
+
+#[test]
+fn test_redundant_pip_detected() {
+    /* The wire already carries R.I to R.O, so the PIP between them adds nothing */
+    let msg = DeviceFixture::new("test_device").site_type(
+        SiteTypeFixture::new("REDUNDANT_PIP_SITE")
+            .port("A", Dir::Output)
+            .bel("R", BELCategory::Routing, &[("I", Dir::Inout), ("O", Dir::Output)])
+            .wire("A_W", &[("A", "A"), ("R", "I"), ("R", "O")])
+            .pip("R", "I", "O")
+    ).build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    let (r_i, r_o) = (pin(&router, &device, "R", "I"), pin(&router, &device, "R", "O"));
+
+    assert_eq!(router.redundant_pips(), [(r_i, r_o)]);
+    assert_eq!(*router.get_graph().get_edge(r_i.0, r_o.0), RoutingGraphEdge::SiteWire);
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    assert!(BruteRouter::<()>::new(&device, 0, false).redundant_pips().is_empty());
+}
+
+#[test]
+fn test_inconsistent_directions() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let router = BruteRouter::<()>::new(&device, 0, false);
+    assert!(router.find_inconsistent_directions().is_empty());
+
+    let flattened = BruteRouter::<()>::new(&device, 0, false).with_flattened_inout(true);
+    assert!(flattened.find_inconsistent_directions().is_empty());
+
+    /* A site wire driven by an input pin, and a PIP leading into an input pin */
+    let (d, q) = (pin(&router, &device, "FF", "D"), pin(&router, &device, "Q", "Q"));
+    let (a, i0) = (pin(&router, &device, "A", "A"), pin(&router, &device, "MUX", "I0"));
+    let mut graph = router.get_graph().clone();
+    graph.connect(d.0, q.0, RoutingGraphEdge::SiteWire).unwrap();
+    graph.disconnect(a.0, i0.0);
+    graph.connect(a.0, i0.0, RoutingGraphEdge::PseudoPip).unwrap();
+
+    let mut expected = vec![(a.0, i0.0), (d.0, q.0)];
+    expected.sort();
+    assert_eq!(graph.inconsistent_directions(), expected);
+}
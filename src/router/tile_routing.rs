@@ -0,0 +1,238 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Aggregation of site routing information up to tile types.
+//!
+//! Tile types instantiate site types, so routing information computed for a site type
+//! applies to each of its instances within a tile type. nextpnr-fpga-interchange expects
+//! site routing data organized per tile type, with sites identified by their index
+//! within the tile type.
+
+use std::collections::HashMap;
+#[cfg(feature = "serialize")]
+use serde::Serialize;
+
+use crate::common::IcStr;
+use crate::ic_loader::archdef::Root as Device;
+use super::SitePinId;
+use crate::logic_formula::FormulaTerm;
+use super::site_brute_router::{BruteRouter, RoutingInfo, PinPairRoutingInfo, ConstrainingElement};
+
+/// Identifies a site pin within a tile type.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct TileSitePinId {
+    /// Index of the site within the tile type
+    pub site: usize,
+    /// Pin within the site's type
+    pub pin: SitePinId,
+}
+
+/// Routing information for a pair of pins of a site within a tile type.
+/// Constraining elements found in formulas refer to pins of the same site.
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct TilePinPairRoutingInfo<'r> {
+    pub from: TileSitePinId,
+    pub to: TileSitePinId,
+    pub routing: &'r PinPairRoutingInfo,
+}
+
+/// Site routing information of all sites within a tile type.
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct TileTypeRoutingInfo<'r> {
+    /// Index of the tile type
+    pub tile_type: u32,
+    /// Site types of sites in the tile type, indexed by site index
+    pub site_types: Vec<u32>,
+    /// Routing information of pin pairs, sorted by pins
+    pub pin_to_pin_routing: Vec<TilePinPairRoutingInfo<'r>>,
+}
+
+/// Aggregates routing information of site types to the tile types containing them.
+/// Sites of types missing from `site_routing` contribute no pairs.
+///
+/// # Arguments
+/// * `device` - device resources
+/// * `site_routing` - routing information for site types, indexed by site type
+pub fn aggregate_tile_types<'r, 'd>(
+    device: &Device<'d>,
+    site_routing: &'r HashMap<u32, RoutingInfo>,
+)
+    -> Vec<TileTypeRoutingInfo<'r>>
+{
+    device.get_tile_type_list().unwrap().iter()
+        .enumerate()
+        .map(|(tt_idx, tt)| {
+            let site_types: Vec<u32> = tt.get_site_types().unwrap().iter()
+                .map(|st| st.get_primary_type())
+                .collect();
+
+            let mut pin_to_pin_routing: Vec<_> = site_types.iter()
+                .enumerate()
+                .filter_map(|(site, st_idx)| {
+                    site_routing.get(st_idx).map(|routing_info| (site, routing_info))
+                })
+                .flat_map(|(site, routing_info)| {
+                    routing_info.pin_to_pin_routing.iter()
+                        .map(move |((from, to), routing)| TilePinPairRoutingInfo {
+                            from: TileSitePinId { site, pin: *from },
+                            to: TileSitePinId { site, pin: *to },
+                            routing,
+                        })
+                })
+                .collect();
+
+            pin_to_pin_routing.sort_by_key(|ppri| (ppri.from, ppri.to));
+
+            TileTypeRoutingInfo {
+                tile_type: tt_idx as u32,
+                site_types,
+                pin_to_pin_routing,
+            }
+        })
+        .collect()
+}
+
+/// Routes of a pin pair of a site type through site PIPs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PinPairSitePips {
+    pub from: SitePinId,
+    pub to: SitePinId,
+    /// Indices of site PIPs within the site type, used by each route of the pair, in order
+    /// of cubes of the pair's _implies_ formula
+    pub routes: Vec<Vec<u32>>,
+}
+
+/// Finds site PIPs used by routes of pin pairs of a site type. Each cube of the _implies_
+/// formula of a pair lists pins driving along one of its routes, so a route uses a site PIP
+/// if the PIP's input pin is listed and its output pin is either listed or the sink.
+///
+/// # Arguments
+/// * `device` - device the router was created for
+/// * `router` - router of the site type
+/// * `routing_info` - results of routing the site type with `router`
+///
+/// # Return
+/// Pin pairs with routes through site PIPs, sorted by pins
+pub fn routed_site_pips<'d, A>(
+    device: &Device<'d>,
+    router: &BruteRouter<A>,
+    routing_info: &RoutingInfo
+)
+    -> Result<Vec<PinPairSitePips>, String>
+where
+    A: Default + Clone + std::fmt::Debug + 'static
+{
+    let st = device.get_site_type_list().unwrap().get(router.get_st_id());
+    let bel_pins = st.get_bel_pins().unwrap();
+    let pin_id = |bel_pin_idx: u32| {
+        let bel_pin = bel_pins.get(bel_pin_idx);
+        let bel_name = device.ic_str(bel_pin.get_bel());
+        let pin_name = device.ic_str(bel_pin.get_name());
+        router.get_pin_id(device, bel_name, pin_name)
+    };
+    let site_pips = st.get_site_p_i_ps().unwrap().iter()
+        .map(|spip| Ok((pin_id(spip.get_inpin())?, pin_id(spip.get_outpin())?)))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut pairs: Vec<_> = routing_info.pin_to_pin_routing.iter()
+        .filter_map(|((from, to), ppri)| {
+            let routes: Vec<Vec<u32>> = ppri.implies.iter()
+                .map(|cube| {
+                    let drives = |pin: SitePinId| cube.terms.iter().any(|term| {
+                        *term == FormulaTerm::Var(ConstrainingElement::Port(pin.0 as u32))
+                    });
+                    site_pips.iter()
+                        .enumerate()
+                        .filter(|(_, (inpin, outpin))| {
+                            drives(*inpin) && ((outpin == to) || drives(*outpin))
+                        })
+                        .map(|(idx, _)| idx as u32)
+                        .collect()
+                })
+                .filter(|pips: &Vec<u32>| !pips.is_empty())
+                .collect();
+            (!routes.is_empty()).then(|| PinPairSitePips { from: *from, to: *to, routes })
+        })
+        .collect();
+    pairs.sort_by_key(|pair| (pair.from, pair.to));
+
+    Ok(pairs)
+}
+
+/// Routes of a pin pair of a site within a tile type, through PIPs of the tile type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct TilePinPairPips {
+    pub from: TileSitePinId,
+    pub to: TileSitePinId,
+    /// Indices of PIPs within the tile type, used by each route of the pair
+    pub routes: Vec<Vec<u32>>,
+}
+
+/// Translates site PIPs used by routes of a site type (see `routed_site_pips`) into PIP
+/// indices of a tile type containing a site of that type. Site PIPs are flattened into
+/// PIPs of tile types the way chip databases of nextpnr-fpga-interchange are built by
+/// `fpga_interchange/populate_chip_info.py` of python-fpga-interchange: the tile type's
+/// own PIPs go first, followed by site PIPs of each site, in order of sites within the
+/// tile type and of site PIPs within their site types.
+///
+/// # Arguments
+/// * `device` - device resources
+/// * `site_type` - index of the site type `pairs` were found for
+/// * `pairs` - routes of the site type through site PIPs
+/// * `tile_type` - index of the tile type
+/// * `site` - index of the site within the tile type
+pub fn site_pips_in_tile<'d>(
+    device: &Device<'d>,
+    site_type: u32,
+    pairs: &[PinPairSitePips],
+    tile_type: u32,
+    site: usize
+)
+    -> Result<Vec<TilePinPairPips>, String>
+{
+    let tt = device.get_tile_type_list().unwrap().get(tile_type);
+    let tt_name = device.ic_str(tt.get_name());
+    let site_types: Vec<u32> = tt.get_site_types().unwrap().iter()
+        .map(|st| st.get_primary_type())
+        .collect();
+    let st_idx = *site_types.get(site)
+        .ok_or_else(|| format!("Tile type {} has no site {}", tt_name, site))?;
+    if st_idx != site_type {
+        return Err(format!(
+            "Site {} of tile type {} is not of site type {}",
+            site,
+            tt_name,
+            site_type
+        ));
+    }
+
+    let st_list = device.get_site_type_list().unwrap();
+    let preceding_site_pips: u32 = site_types[.. site].iter()
+        .map(|st_idx| st_list.get(*st_idx).get_site_p_i_ps().unwrap().len())
+        .sum();
+    let first_pip = tt.get_pips().unwrap().len() + preceding_site_pips;
+
+    Ok(pairs.iter()
+        .map(|pair| TilePinPairPips {
+            from: TileSitePinId { site, pin: pair.from },
+            to: TileSitePinId { site, pin: pair.to },
+            routes: pair.routes.iter()
+                .map(|route| route.iter().map(|pip| first_pip + pip).collect())
+                .collect(),
+        })
+        .collect())
+}
@@ -19,6 +19,9 @@ use std::borrow::Borrow;
 
 use lazy_static::__Deref;
 
+#[cfg(test)]
+mod tests;
+
 lazy_static!{
     static ref GLOBAL_STRINGS: RwLock<Vec<String>> = RwLock::new(Vec::new());
     static ref GLOBAL_STRINGS_REVMAP: Mutex<HashMap<String, usize>> =
@@ -66,6 +69,32 @@ impl GlobalStringsCtx {
         GlobalStringId(id)
     }
 
+    /// Get global identifiers for multiple strings, like `create_global_string` does
+    /// for a single one. The locks are acquired only once for the whole batch, so new
+    /// strings get contiguous identifiers.
+    /// 
+    /// The iterator is consumed while holding the locks, so it must not access global
+    /// strings by itself.
+    pub fn create_global_strings<I>(&mut self, iter: I) -> Vec<GlobalStringId> where
+        I: IntoIterator<Item = String>
+    {
+        /* Same locking order as in `Self::create_global_string` */
+        let mut revmap = GLOBAL_STRINGS_REVMAP.lock().unwrap();
+        let mut strings = GLOBAL_STRINGS.write().unwrap();
+
+        iter.into_iter()
+            .map(|s| {
+                if let Some(id) = revmap.get(&s) {
+                    return GlobalStringId(*id);
+                }
+                let id = strings.len();
+                revmap.insert(s.clone(), id);
+                strings.push(s);
+                GlobalStringId(id)
+            })
+            .collect()
+    }
+
     pub fn get_global_string<'s>(&'s self, id: GlobalStringId) -> GlobalStringRef<'s> {
         GlobalStringRef {
             guard: GLOBAL_STRINGS.read().unwrap(),
@@ -0,0 +1,38 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+use super::*;
+
+#[test]
+fn test_create_global_strings_batch() {
+    let mut gsctx = GlobalStringsCtx::hold();
+    let names: Vec<_> = (0 .. 100).map(|i| format!("test_batch_string_{}", i)).collect();
+
+    /* Other tests may create strings concurrently, but not within the batch */
+    let ids = gsctx.create_global_strings(names.clone());
+    assert_eq!(ids.len(), 100);
+    for pair in ids.windows(2) {
+        assert_eq!(pair[1].0, pair[0].0 + 1);
+    }
+    for (id, name) in ids.iter().zip(&names) {
+        assert_eq!(&*gsctx.get_global_string(*id), name.as_str());
+    }
+
+    /* Registered strings keep their ids */
+    assert_eq!(gsctx.create_global_string(names[42].as_str()), ids[42]);
+    let again = gsctx.create_global_strings(vec![names[7].clone(), names[7].clone()]);
+    assert_eq!(again, vec![ids[7], ids[7]]);
+}
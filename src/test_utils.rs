@@ -0,0 +1,445 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Utilities for building small, synthetic fpga-interchange devices in tests.
+//!
+//! The fixtures are described in terms of names (BELs, pins, wires) and get converted
+//! into a capnp message holding a `DeviceResources::Device` root, so that the code under
+//! test can consume them the same way as it consumes real device files.
+
+use std::collections::HashMap;
+
+use crate::ic_loader::archdef::Root as Device;
+use crate::ic_loader::DeviceResources_capnp::device;
+use crate::ic_loader::LogicalNetlist_capnp::netlist::Direction;
+
+pub use crate::ic_loader::DeviceResources_capnp::device::{BELCategory, ConstantType};
+pub use crate::ic_loader::LogicalNetlist_capnp::netlist::Direction as Dir;
+
+pub type FixtureMessage = capnp::message::Builder<capnp::message::HeapAllocator>;
+
+struct BELFixture {
+    name: String,
+    category: BELCategory,
+    pins: Vec<(String, Direction)>,
+}
+
+/// Describes a single site type of a synthetic device.
+pub struct SiteTypeFixture {
+    name: String,
+    bels: Vec<BELFixture>,
+    pins: Vec<(String, Direction)>,
+    wires: Vec<(String, Vec<(String, String)>)>,
+    pips: Vec<(String, String, String)>,
+    const_sources: Vec<(String, String, ConstantType)>,
+    dangling_pins: Vec<(String, String, Direction)>,
+}
+
+impl SiteTypeFixture {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            bels: Vec::new(),
+            pins: Vec::new(),
+            wires: Vec::new(),
+            pips: Vec::new(),
+            const_sources: Vec::new(),
+            dangling_pins: Vec::new(),
+        }
+    }
+
+    /// Adds a BEL with given pins.
+    pub fn bel(mut self, name: &str, category: BELCategory, pins: &[(&str, Direction)])
+        -> Self
+    {
+        self.bels.push(BELFixture {
+            name: name.into(),
+            category,
+            pins: pins.iter().map(|(pin, dir)| (pin.to_string(), *dir)).collect(),
+        });
+        self
+    }
+
+    /// Adds a site port. Site ports are BELs of `SitePort` category with a single pin
+    /// that has the same name as the BEL. A site pin of the same name is added as well.
+    pub fn port(mut self, name: &str, dir: Direction) -> Self {
+        self.pins.push((name.into(), dir));
+        self.bel(name, BELCategory::SitePort, &[(name, dir)])
+    }
+
+    /// Adds a site wire connecting given `(bel, pin)` pairs.
+    pub fn wire(mut self, name: &str, pins: &[(&str, &str)]) -> Self {
+        self.wires.push((
+            name.into(),
+            pins.iter().map(|(bel, pin)| (bel.to_string(), pin.to_string())).collect()
+        ));
+        self
+    }
+
+    /// Adds a site PIP going from `input` to `output` pin of a routing BEL `bel`.
+    pub fn pip(mut self, bel: &str, input: &str, output: &str) -> Self {
+        self.pips.push((bel.into(), input.into(), output.into()));
+        self
+    }
+
+    /// Removes all the site PIPs added so far.
+    pub fn without_pips(mut self) -> Self {
+        self.pips.clear();
+        self
+    }
+
+    /// Marks `bel.pin` as a constant source.
+    pub fn const_source(mut self, bel: &str, pin: &str, constant: ConstantType) -> Self {
+        self.const_sources.push((bel.into(), pin.into(), constant));
+        self
+    }
+
+    /// Adds a BEL pin entry `bel.pin` which isn't listed among pins of any BEL, so that
+    /// site wires referring to it can't be resolved. Used to craft malformed devices.
+    pub fn dangling_pin(mut self, bel: &str, pin: &str, dir: Direction) -> Self {
+        self.dangling_pins.push((bel.into(), pin.into(), dir));
+        self
+    }
+}
+
+/// Describes a synthetic device.
+pub struct DeviceFixture {
+    name: String,
+    site_types: Vec<SiteTypeFixture>,
+    tile_types: Vec<(String, Vec<String>)>,
+    site_pin_wires: HashMap<(String, usize), Vec<(String, String)>>,
+    tile_pips: HashMap<String, Vec<(String, String)>>,
+}
+
+#[derive(Default)]
+struct StringPool {
+    strings: Vec<String>,
+    ids: HashMap<String, u32>,
+}
+
+impl StringPool {
+    fn id(&mut self, s: &str) -> u32 {
+        if let Some(id) = self.ids.get(s) {
+            return *id;
+        }
+        let id = self.strings.len() as u32;
+        self.strings.push(s.into());
+        self.ids.insert(s.into(), id);
+        id
+    }
+}
+
+impl DeviceFixture {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            site_types: Vec::new(),
+            tile_types: Vec::new(),
+            site_pin_wires: HashMap::new(),
+            tile_pips: HashMap::new(),
+        }
+    }
+
+    pub fn site_type(mut self, st: SiteTypeFixture) -> Self {
+        self.site_types.push(st);
+        self
+    }
+
+    /// Adds a tile type instantiating given site types (referenced by names).
+    pub fn tile_type(mut self, name: &str, site_types: &[&str]) -> Self {
+        self.tile_types.push((
+            name.into(),
+            site_types.iter().map(|st| st.to_string()).collect()
+        ));
+        self
+    }
+
+    /// Connects pins of `site`-th site of a tile type to tile wires, given as
+    /// `(site pin, tile wire)` pairs.
+    pub fn site_pin_wires(mut self, tile_type: &str, site: usize, wires: &[(&str, &str)])
+        -> Self
+    {
+        self.site_pin_wires.insert(
+            (tile_type.into(), site),
+            wires.iter().map(|(pin, wire)| (pin.to_string(), wire.to_string())).collect()
+        );
+        self
+    }
+
+    /// Adds a PIP between two tile wires of a tile type. The wires get added to the tile
+    /// type as needed.
+    pub fn tile_pip(mut self, tile_type: &str, wire0: &str, wire1: &str) -> Self {
+        self.tile_pips.entry(tile_type.into())
+            .or_default()
+            .push((wire0.into(), wire1.into()));
+        self
+    }
+
+    /// Builds a capnp message holding the device described by the fixture.
+    pub fn build(&self) -> FixtureMessage {
+        let mut strings = StringPool::default();
+        let mut message = capnp::message::Builder::new_default();
+
+        {
+            let mut root = message.init_root::<device::Builder>();
+            root.set_name(&self.name);
+
+            let mut st_list = root.reborrow().init_site_type_list(self.site_types.len() as u32);
+            for (st_idx, st_fixture) in self.site_types.iter().enumerate() {
+                let mut st = st_list.reborrow().get(st_idx as u32);
+                st.set_name(strings.id(&st_fixture.name));
+
+                /* BEL pins are stored in a flat per-site-type list */
+                let mut belpin_idx = HashMap::new();
+                let belpin_cnt: usize = st_fixture.bels.iter().map(|b| b.pins.len()).sum::<usize>()
+                    + st_fixture.dangling_pins.len();
+                {
+                    let mut belpins = st.reborrow().init_bel_pins(belpin_cnt as u32);
+                    let mut idx = 0;
+                    for bel in &st_fixture.bels {
+                        for (pin, dir) in &bel.pins {
+                            let mut belpin = belpins.reborrow().get(idx);
+                            belpin.set_name(strings.id(pin));
+                            belpin.set_dir(*dir);
+                            belpin.set_bel(strings.id(&bel.name));
+                            belpin_idx.insert((bel.name.clone(), pin.clone()), idx);
+                            idx += 1;
+                        }
+                    }
+                    for (bel, pin, dir) in &st_fixture.dangling_pins {
+                        let mut belpin = belpins.reborrow().get(idx);
+                        belpin.set_name(strings.id(pin));
+                        belpin.set_dir(*dir);
+                        belpin.set_bel(strings.id(bel));
+                        belpin_idx.insert((bel.clone(), pin.clone()), idx);
+                        idx += 1;
+                    }
+                }
+
+                let lookup = |bel: &str, pin: &str| -> u32 {
+                    *belpin_idx.get(&(bel.to_string(), pin.to_string()))
+                        .unwrap_or_else(|| panic!("No BEL pin {}.{} in fixture", bel, pin))
+                };
+
+                {
+                    let mut pins = st.reborrow().init_pins(st_fixture.pins.len() as u32);
+                    for (pin_idx, (name, dir)) in st_fixture.pins.iter().enumerate() {
+                        let mut pin = pins.reborrow().get(pin_idx as u32);
+                        pin.set_name(strings.id(name));
+                        /* Site port BEL pins face the inside of the site */
+                        pin.set_dir(match dir {
+                            Direction::Input => Direction::Output,
+                            Direction::Output => Direction::Input,
+                            Direction::Inout => Direction::Inout,
+                        });
+                        pin.set_belpin(lookup(name, name));
+                    }
+                }
+
+                {
+                    let mut bels = st.reborrow().init_bels(st_fixture.bels.len() as u32);
+                    for (bel_idx, bel_fixture) in st_fixture.bels.iter().enumerate() {
+                        let mut bel = bels.reborrow().get(bel_idx as u32);
+                        bel.set_name(strings.id(&bel_fixture.name));
+                        bel.set_type(strings.id(&bel_fixture.name));
+                        bel.set_category(bel_fixture.category);
+                        let mut pins = bel.init_pins(bel_fixture.pins.len() as u32);
+                        for (pin_idx, (pin, _)) in bel_fixture.pins.iter().enumerate() {
+                            pins.set(pin_idx as u32, lookup(&bel_fixture.name, pin));
+                        }
+                    }
+                }
+
+                {
+                    let mut wires = st.reborrow().init_site_wires(st_fixture.wires.len() as u32);
+                    for (wire_idx, (name, wire_pins)) in st_fixture.wires.iter().enumerate() {
+                        let mut wire = wires.reborrow().get(wire_idx as u32);
+                        wire.set_name(strings.id(name));
+                        let mut pins = wire.init_pins(wire_pins.len() as u32);
+                        for (pin_idx, (bel, pin)) in wire_pins.iter().enumerate() {
+                            pins.set(pin_idx as u32, lookup(bel, pin));
+                        }
+                    }
+                }
+
+                {
+                    let mut pips = st.reborrow().init_site_p_i_ps(st_fixture.pips.len() as u32);
+                    for (pip_idx, (bel, input, output)) in st_fixture.pips.iter().enumerate() {
+                        let mut pip = pips.reborrow().get(pip_idx as u32);
+                        pip.set_inpin(lookup(bel, input));
+                        pip.set_outpin(lookup(bel, output));
+                    }
+                }
+            }
+
+            let mut tt_list = root.reborrow().init_tile_type_list(self.tile_types.len() as u32);
+            for (tt_idx, (name, site_types)) in self.tile_types.iter().enumerate() {
+                let mut tt = tt_list.reborrow().get(tt_idx as u32);
+                tt.set_name(strings.id(name));
+
+                let tile_pips = self.tile_pips.get(name).map(Vec::as_slice).unwrap_or_default();
+                let mut tile_wires: Vec<&str> = Vec::new();
+                let mut wire_idx = |wire: &str| {
+                    let idx = tile_wires.iter().position(|w| *w == wire).unwrap_or_else(|| {
+                        tile_wires.push(wire);
+                        tile_wires.len() - 1
+                    });
+                    idx as u32
+                };
+                let pip_wires: Vec<_> = tile_pips.iter()
+                    .map(|(wire0, wire1)| (wire_idx(wire0), wire_idx(wire1)))
+                    .collect();
+                {
+                    let mut pips = tt.reborrow().init_pips(pip_wires.len() as u32);
+                    for (pip_idx, (wire0, wire1)) in pip_wires.iter().enumerate() {
+                        let mut pip = pips.reborrow().get(pip_idx as u32);
+                        pip.set_wire0(*wire0);
+                        pip.set_wire1(*wire1);
+                        pip.set_directional(true);
+                    }
+                }
+                let mut wires = tt.reborrow().init_wires(tile_wires.len() as u32);
+                for (idx, wire) in tile_wires.iter().enumerate() {
+                    wires.set(idx as u32, strings.id(wire));
+                }
+
+                let mut sts = tt.init_site_types(site_types.len() as u32);
+                for (idx, st_name) in site_types.iter().enumerate() {
+                    let st_idx = self.site_types.iter()
+                        .position(|st| &st.name == st_name)
+                        .unwrap_or_else(|| panic!("No site type {} in fixture", st_name));
+                    let mut stitt = sts.reborrow().get(idx as u32);
+                    stitt.set_primary_type(st_idx as u32);
+
+                    if let Some(wires) = self.site_pin_wires.get(&(name.clone(), idx)) {
+                        let site_pins = &self.site_types[st_idx].pins;
+                        let mut pins_to_wires = stitt
+                            .init_primary_pins_to_tile_wires(site_pins.len() as u32);
+                        for (pin_idx, (pin, _)) in site_pins.iter().enumerate() {
+                            let wire = wires.iter()
+                                .find(|(wire_pin, _)| wire_pin == pin)
+                                .map(|(_, wire)| wire.as_str())
+                                .unwrap_or("");
+                            pins_to_wires.set(pin_idx as u32, strings.id(wire));
+                        }
+                    }
+                }
+            }
+
+            let const_source_cnt: usize = self.site_types.iter()
+                .map(|st| st.const_sources.len())
+                .sum();
+            {
+                let constants = root.reborrow().init_constants();
+                let mut site_sources = constants.init_site_sources(const_source_cnt as u32);
+                let mut idx = 0;
+                for st_fixture in &self.site_types {
+                    for (bel, pin, constant) in &st_fixture.const_sources {
+                        let mut source = site_sources.reborrow().get(idx);
+                        source.set_site_type(strings.id(&st_fixture.name));
+                        source.set_bel(strings.id(bel));
+                        source.set_bel_pin(strings.id(pin));
+                        source.set_constant(*constant);
+                        idx += 1;
+                    }
+                }
+            }
+
+            let mut str_list = root.init_str_list(strings.strings.len() as u32);
+            for (idx, s) in strings.strings.iter().enumerate() {
+                str_list.set(idx as u32, s);
+            }
+        }
+
+        message
+    }
+}
+
+/* A site with two inputs going through a mux into a flip-flop:
+ *
+ *        ┏━━━━━┓
+ *   A━━━━┃I0   ┃   ┏━━━━━┓
+ *        ┃  MUX┃O──┃D  FF┃Q━━━━Q
+ *   B━━━━┃I1   ┃   ┗━━━━━┛
+ *        ┗━━━━━┛
+ */
+pub fn mux_site() -> DeviceFixture {
+    DeviceFixture::new("test_device").site_type(mux_site_type())
+}
+
+/// The `MUX_SITE` site type of `mux_site`, for use in other devices.
+pub fn mux_site_type() -> SiteTypeFixture {
+    SiteTypeFixture::new("MUX_SITE")
+        .port("A", Dir::Output)
+        .port("B", Dir::Output)
+        .port("Q", Dir::Input)
+        .bel("MUX", BELCategory::Routing, &[
+            ("I0", Dir::Input),
+            ("I1", Dir::Input),
+            ("O", Dir::Output),
+        ])
+        .bel("FF", BELCategory::Logic, &[("D", Dir::Input), ("Q", Dir::Output)])
+        .wire("A_W", &[("A", "A"), ("MUX", "I0")])
+        .wire("B_W", &[("B", "B"), ("MUX", "I1")])
+        .wire("MUX_O", &[("MUX", "O"), ("FF", "D")])
+        .wire("FF_Q", &[("FF", "Q"), ("Q", "Q")])
+        .pip("MUX", "I0", "O")
+        .pip("MUX", "I1", "O")
+}
+
+/* A site with a single buffer between two ports:
+ *
+ *        ┏━━━━━━┓
+ *   A━━━━┃I BUF O┃━━━━Q
+ *        ┗━━━━━━┛
+ */
+pub fn buf_site_type() -> SiteTypeFixture {
+    SiteTypeFixture::new("BUF_SITE")
+        .port("A", Dir::Output)
+        .port("Q", Dir::Input)
+        .bel("BUF", BELCategory::Routing, &[("I", Dir::Input), ("O", Dir::Output)])
+        .pip("BUF", "I", "O")
+        .wire("A_W", &[("A", "A"), ("BUF", "I")])
+        .wire("Q_W", &[("BUF", "O"), ("Q", "Q")])
+}
+
+/* A site with a single port driving both inputs of a mux, so that there are two routes
+ * from A to the flip-flop:
+ *
+ *        ┏━━━━━┓
+ *   A━━┳━┃I0   ┃   ┏━━━━━┓
+ *      ┃ ┃  MUX┃O──┃D  FF┃
+ *      ┗━┃I1   ┃   ┗━━━━━┛
+ *        ┗━━━━━┛
+ */
+pub fn dual_mux_site_type() -> SiteTypeFixture {
+    SiteTypeFixture::new("DUAL_MUX_SITE")
+        .port("A", Dir::Output)
+        .bel("MUX", BELCategory::Routing, &[
+            ("I0", Dir::Input),
+            ("I1", Dir::Input),
+            ("O", Dir::Output),
+        ])
+        .bel("FF", BELCategory::Logic, &[("D", Dir::Input)])
+        .wire("A_W", &[("A", "A"), ("MUX", "I0"), ("MUX", "I1")])
+        .wire("MUX_O", &[("MUX", "O"), ("FF", "D")])
+        .pip("MUX", "I0", "O")
+        .pip("MUX", "I1", "O")
+}
+
+/// Gets the device root out of a message created by `DeviceFixture::build`.
+pub fn fixture_root<'a>(message: &'a FixtureMessage) -> Device<'a> {
+    message.get_root_as_reader::<Device<'a>>().unwrap()
+}
@@ -0,0 +1,1103 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+use super::*;
+use crate::test_utils::*;
+
+fn scratch_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("nisp-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_summary_only() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("summary-only");
+    let summary_path = dir.join("summary.json");
+
+    let args = PreprocessCmd::parse_from([
+        "preprocess",
+        "--json", ":all",
+        "--json-prefix", dir.to_str().unwrap(),
+        "--summary-only",
+        "--summary-json", summary_path.to_str().unwrap(),
+    ]);
+
+    let mut out = Vec::new();
+    let hash = Some("0123abcd".to_string());
+    preprocess(args, device, hash, &AtomicBool::new(false), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(out.contains("Site Type MUX_SITE:"));
+    assert!(out.contains("No. of intra-site routing pairs"));
+
+    let files: Vec<_> = std::fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(files, vec![std::ffi::OsString::from("summary.json")]);
+
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&summary_path).unwrap()).unwrap();
+    assert!(summary["total"]["pairs"].as_u64().unwrap() > 0);
+    assert_eq!(summary["site_types"]["MUX_SITE"], summary["total"]);
+    assert_eq!(summary["device_hash"], "0123abcd");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/* Restores the log level on drop, so that it doesn't leak into other tests */
+struct LogLevelGuard(usize);
+
+impl Drop for LogLevelGuard {
+    fn drop(&mut self) {
+        crate::log::set_log_level(self.0);
+    }
+}
+
+#[test]
+fn test_json_device_hash() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("json-device-hash");
+
+    let args = PreprocessCmd::parse_from([
+        "preprocess",
+        "--json", ":all",
+        "--json-prefix", dir.to_str().unwrap(),
+    ]);
+    assert!(args.records_device_hash());
+    let hash = Some("0123abcd".to_string());
+    preprocess(args, device, hash, &AtomicBool::new(false), &mut std::io::sink()).unwrap();
+
+    let json = std::fs::read_to_string(dir.join("test_device_site_routability.json")).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(json["MUX_SITE"]["device_hash"], "0123abcd");
+
+    /* Subcommands without such outputs don't need the device file to be hashed */
+    let command = SubCommands::try_parse_from(["nisp", "device-info"]).unwrap();
+    assert!(!command.records_device_hash());
+    let command = SubCommands::try_parse_from(["nisp", "preprocess"]).unwrap();
+    assert!(!command.records_device_hash());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_verbosity_flags() {
+    let _guard = LogLevelGuard(crate::log::log_level());
+
+    let args = Args::parse_from(["nisp", "-vv", "device.device", "out.bba", "preprocess"]);
+    args.apply_log_level();
+    assert_eq!(crate::log::log_level(), DBG_EXTRA2);
+    assert!(crate::log::log_enabled(DBG_EXTRA2));
+
+    let args = Args::parse_from(["nisp", "-v", "device.device", "out.bba", "preprocess"]);
+    args.apply_log_level();
+    assert!(crate::log::log_enabled(DBG_INFO));
+    assert!(!crate::log::log_enabled(DBG_EXTRA1));
+
+    let args = Args::parse_from(["nisp", "-q", "device.device", "out.bba", "preprocess"]);
+    assert_eq!(args.log_level(), Some(DBG_CRITICAL));
+    args.apply_log_level();
+    assert!(!crate::log::log_enabled(DBG_WARN));
+
+    assert!(
+        Args::try_parse_from(["nisp", "-q", "-v", "device.device", "out.bba", "preprocess"])
+            .is_err()
+    );
+}
+
+#[test]
+fn test_emit_both_forms_requires_formula_opt() {
+    assert!(
+        PreprocessCmd::try_parse_from(["preprocess", "--emit-both-forms", "--no-formula-opt"])
+            .is_err()
+    );
+}
+
+#[test]
+fn test_disable_pips_unknown_bel() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+
+    let args = PreprocessCmd::parse_from(["preprocess", "--disable-pips", "NO_SUCH_BEL"]);
+    let err = preprocess(args, device, None, &AtomicBool::new(false), &mut Vec::new())
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("NO_SUCH_BEL"));
+}
+
+#[test]
+fn test_max_memory_saturates() {
+    let args = PreprocessCmd::parse_from(["preprocess", "--max-memory", "2"]);
+    assert_eq!(args.max_memory_bytes(), Some(2 * MIB));
+
+    let huge = usize::MAX.to_string();
+    let args = PreprocessCmd::parse_from(["preprocess", "--max-memory", huge.as_str()]);
+    assert_eq!(args.max_memory_bytes(), Some(usize::MAX));
+    assert_eq!(mib(usize::MAX), usize::MAX / MIB + 1);
+}
+
+#[test]
+fn test_list_out_of_site_ports() {
+    use crate::router::site_brute_router::RoutingGraphNodeKind;
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+
+    let args = ListOutOfSitePortsCmd::parse_from(["list-out-of-site-ports", "MUX_SITE"]);
+    let mut out = Vec::new();
+    list_out_of_site_ports(args, device, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    let printed: Vec<_> = out.lines()
+        .filter(|line| line.starts_with("  ") && !line.starts_with("    "))
+        .map(|line| line.trim().split(' ').next().unwrap().to_string())
+        .collect();
+
+    let brouter = BruteRouter::<()>::new(&device, 0, false);
+    let graph = brouter.get_graph();
+    let gsctx = GlobalStringsCtx::hold();
+    let site_ports: Vec<_> = (0 .. graph.node_count())
+        .filter(|node| matches!(graph.get_node(*node).kind, RoutingGraphNodeKind::SitePort(_)))
+        .map(|node| brouter.get_pin_name(&device, &gsctx, SitePinId::from(node)).to_string())
+        .collect();
+
+    assert_eq!(printed, site_ports);
+    let a_drives = out.lines()
+        .skip_while(|line| *line != "  A.A (in)")
+        .nth(1)
+        .unwrap();
+    assert!(a_drives.starts_with("    drives: "));
+    for pin in ["MUX.I0", "MUX.O", "FF.D"] {
+        assert!(a_drives.contains(pin), "A.A should drive {}", pin);
+    }
+    assert!(!a_drives.contains("MUX.I1"));
+}
+
+#[test]
+fn test_device_info() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let name = device.get_name().unwrap().to_string();
+
+    let mut out = Vec::new();
+    device_info(device, Some(ic_loader::FileFormat::Gzip), &mut out);
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(out.lines().any(|line| line == format!("Device: {}", name)), "{}", out);
+    assert!(out.contains("File format: gzip"));
+    assert!(out.contains("Site types: 1"));
+}
+
+#[test]
+fn test_parallel_dot_export() {
+    let msg = mux_site().site_type(buf_site_type()).build();
+    let device = fixture_root(&msg);
+
+    /* Unsorted clusters follow the order of a HashMap, which differs between runs, so
+     * outputs are comparable only with --dot-sorted */
+    let export = |threads: &str| {
+        let dir = scratch_dir(&format!("dot-{}-threads", threads));
+        let args = PreprocessCmd::parse_from([
+            "preprocess",
+            "--dot", ":all",
+            "--dot-prefix", dir.to_str().unwrap(),
+            "--dot-sorted",
+            "--threads", threads,
+        ]);
+        preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink()).unwrap();
+        dir
+    };
+
+    let serial = export("1");
+    let parallel = export("2");
+
+    for st_name in ["MUX_SITE", "BUF_SITE"] {
+        let file_name = format!("{}.dot", st_name);
+        let serial_dot = std::fs::read(serial.join(&file_name)).unwrap();
+        let parallel_dot = std::fs::read(parallel.join(&file_name)).unwrap();
+        assert_eq!(serial_dot, parallel_dot, "{} differs", file_name);
+    }
+
+    std::fs::remove_dir_all(&serial).unwrap();
+    std::fs::remove_dir_all(&parallel).unwrap();
+}
+
+/* Raises the interrupt flag as soon as anything gets written, ie. after routing
+ * the first site type */
+struct InterruptingWriter<'a>(&'a AtomicBool);
+
+impl<'a> std::io::Write for InterruptingWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.store(true, Ordering::SeqCst);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_interrupted_preprocess() {
+    let msg = mux_site().site_type(buf_site_type()).build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("interrupted");
+
+    /* Zero memory limit forces streaming JSON export */
+    let args = PreprocessCmd::parse_from([
+        "preprocess",
+        "--json", ":all",
+        "--json-prefix", dir.to_str().unwrap(),
+        "--bundle", ":all",
+        "--bundle-prefix", dir.to_str().unwrap(),
+        "--max-memory", "0",
+    ]);
+
+    let interrupted = AtomicBool::new(false);
+    preprocess(args, device, None, &interrupted, &mut InterruptingWriter(&interrupted))
+        .unwrap();
+
+    let json = std::fs::read_to_string(dir.join("test_device_site_routability.json")).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let site_types: Vec<_> = json.as_object().unwrap().keys().collect();
+    assert_eq!(site_types, vec!["MUX_SITE"]);
+
+    let bundle = std::fs::File::open(dir.join("MUX_SITE.nisp.tar.gz")).unwrap();
+    let bundle = crate::router::bundle::read_bundle(bundle).unwrap();
+    assert_eq!(bundle.manifest.site_type, "MUX_SITE");
+    assert!(!dir.join("BUF_SITE.nisp.tar.gz").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_empty_output_is_denied() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let run = |extra: &[&str]| {
+        let args = PreprocessCmd::parse_from(
+            ["preprocess", "--site-types", "MUX_STIE"].iter().chain(extra)
+        );
+        preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink())
+    };
+
+    let err = run(&[]).unwrap_err().to_string();
+    assert!(err.contains("No site types match"), "{}", err);
+    assert!(err.contains("MUX_STIE"), "{}", err);
+    assert!(run(&["--allow-empty"]).is_ok());
+
+    /* A site type without any pins has nothing to route */
+    let msg = DeviceFixture::new("test_device")
+        .site_type(SiteTypeFixture::new("EMPTY"))
+        .build();
+    let device = fixture_root(&msg);
+    let args = PreprocessCmd::parse_from(["preprocess"]);
+    let err = preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink())
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("No routing information"), "{}", err);
+}
+
+#[test]
+fn test_bba_split() {
+    let msg = mux_site()
+        .site_type(SiteTypeFixture::new("EMPTY"))
+        .build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("bba-split");
+
+    let args = PreprocessCmd::parse_from([
+        "preprocess",
+        "--bba-split", ":all",
+        "--bba-prefix", dir.to_str().unwrap(),
+    ]);
+    preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink()).unwrap();
+
+    for st_name in ["MUX_SITE", "EMPTY"] {
+        let bba = std::fs::read_to_string(dir.join(format!("{}.bba", st_name))).unwrap();
+        assert!(bba.contains(&format!("str |{}|", st_name)), "{}", bba);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_resume_from_per_site_files() {
+    let msg = mux_site().site_type(buf_site_type()).build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("resume");
+    let checkpoint = "pre // Device hash: 0123abcd\n";
+    std::fs::write(dir.join("MUX_SITE.bba"), checkpoint).unwrap();
+
+    let run = |extra: &[&str]| {
+        let args = PreprocessCmd::parse_from(
+            ["preprocess", "--bba-split", ":all", "--bba-prefix", dir.to_str().unwrap()]
+                .iter()
+                .chain(extra)
+        );
+        let hash = Some("0123abcd".to_string());
+        let mut out = Vec::new();
+        preprocess(args, device, hash, &AtomicBool::new(false), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    let out = run(&["--resume", "--resume-verify-hash"]);
+    assert!(out.contains("Site Type MUX_SITE: skipped"), "{}", out);
+    assert!(out.contains("Site Type BUF_SITE:\n"), "{}", out);
+    assert_eq!(std::fs::read_to_string(dir.join("MUX_SITE.bba")).unwrap(), checkpoint);
+    let buf_bba = std::fs::read_to_string(dir.join("BUF_SITE.bba")).unwrap();
+    assert!(buf_bba.contains("str |BUF_SITE|"));
+    /* Files are written to temporary files first, which replace them once complete */
+    assert!(!dir.join("BUF_SITE.bba.tmp").exists());
+
+    /* Files made from a different device file get recomputed */
+    std::fs::write(dir.join("MUX_SITE.bba"), "pre // Device hash: 4567ef\n").unwrap();
+    let out = run(&["--resume", "--resume-verify-hash"]);
+    assert!(out.contains("Site Type MUX_SITE:\n"), "{}", out);
+    assert!(out.contains("Site Type BUF_SITE: skipped"), "{}", out);
+    let mux_bba = std::fs::read_to_string(dir.join("MUX_SITE.bba")).unwrap();
+    assert!(mux_bba.contains("str |MUX_SITE|"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_formula_budget_warning() {
+    /* Both MUX inputs are driven by A, so routes from A through the MUX have two
+     * alternatives */
+    let msg = DeviceFixture::new("test_device").site_type(dual_mux_site_type()).build();
+    let device = fixture_root(&msg);
+    let brouter = BruteRouter::<()>::new(&device, 0, false);
+    let routing_info = brouter.route_all(true);
+
+    let warning = formula_budget_warning(&device, &brouter, &routing_info, "DUAL_MUX_SITE", 1)
+        .unwrap();
+    assert!(warning.contains("DUAL_MUX_SITE: 2 pin pairs"), "{}", warning);
+    assert!(warning.contains("A.A -> FF.D (2 cubes)"), "{}", warning);
+    assert!(warning.contains("A.A -> MUX.O (2 cubes)"), "{}", warning);
+    assert!(!warning.contains("MUX.I0"), "{}", warning);
+
+    assert!(formula_budget_warning(&device, &brouter, &routing_info, "DUAL_MUX_SITE", 2)
+        .is_none());
+
+    let dir = scratch_dir("formula_budget");
+    let run = |budget: &str| {
+        let args = PreprocessCmd::parse_from([
+            "preprocess",
+            "--bba-split", ":all",
+            "--bba-prefix", dir.to_str().unwrap(),
+            "--warn-formula-budget", budget,
+        ]);
+        let mut out = Vec::new();
+        preprocess(args, device, None, &AtomicBool::new(false), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    let out = run("1");
+    assert!(out.contains("Warning: Site type DUAL_MUX_SITE: 2 pin pairs"), "{}", out);
+    let out = run("2");
+    assert!(!out.contains("Warning:"), "{}", out);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_site_scheduler() {
+    use std::sync::atomic::AtomicUsize;
+
+    let args = PreprocessCmd::parse_from(
+        ["preprocess", "--site-parallelism", "3", "--threads-per-site", "2"]
+    );
+    assert_eq!((args.site_parallelism, args.threads_per_site), (3, 2));
+    let args = PreprocessCmd::parse_from(["preprocess", "--threads", "4"]);
+    assert_eq!((args.site_parallelism, args.threads_per_site), (1, 4));
+
+    let scheduler = SiteScheduler { site_parallelism: 3, threads_per_site: 2 };
+    let (live, max_live, spawned) = (AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0));
+    let done_count = AtomicUsize::new(0);
+    let site_types: Vec<usize> = (0 .. 7).collect();
+
+    let route = |st: &usize, threads| {
+        /* The first site type is the slowest one, it's done only after all the others
+         * are. Without starting site types as soon as any of them is done, this would
+         * never happen. */
+        if *st == 0 {
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+            while done_count.load(Ordering::SeqCst) < site_types.len() - 1 {
+                assert!(std::time::Instant::now() < deadline, "Site types wait for each other");
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+        }
+        /* Stand-in for routing pin ranges of a site type */
+        std::thread::scope(|scope| {
+            for _ in 0 .. threads {
+                scope.spawn(|| {
+                    spawned.fetch_add(1, Ordering::SeqCst);
+                    let now_live = live.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_live.fetch_max(now_live, Ordering::SeqCst);
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    live.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        done_count.fetch_add(1, Ordering::SeqCst);
+        *st * 10
+    };
+
+    let mut results = Vec::new();
+    std::thread::scope(|scope| {
+        let mut pool = scheduler.pool(scope, &route);
+        let mut pending = site_types.iter().copied();
+        loop {
+            while !pool.is_full() {
+                match pending.next() {
+                    Some(st) => pool.start(st),
+                    None => break,
+                }
+            }
+            match pool.next_done() {
+                Some(done) => results.push(done),
+                None => break,
+            }
+        }
+    });
+
+    assert_eq!(results.last(), Some(&(0, 0)));
+    results.sort();
+    assert_eq!(results, site_types.iter().map(|st| (*st, *st * 10)).collect::<Vec<_>>());
+    assert_eq!(spawned.load(Ordering::SeqCst), site_types.len() * 2);
+    assert!(max_live.load(Ordering::SeqCst) <= scheduler.max_workers());
+}
+
+#[test]
+fn test_nextpnr_pips() {
+    let msg = mux_site()
+        .tile_type("MUX_TILE", &["MUX_SITE", "MUX_SITE"])
+        .tile_pip("MUX_TILE", "W0", "W1")
+        .build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("nextpnr-pips");
+    let pips_path = dir.join("pips.json");
+
+    let args = PreprocessCmd::parse_from([
+        "preprocess",
+        "--nextpnr-pips", pips_path.to_str().unwrap(),
+    ]);
+    preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink()).unwrap();
+
+    let brouter = BruteRouter::<()>::new(&device, 0, false);
+    let a = brouter.get_pin_id(&device, "A", "A").unwrap().index();
+    let d = brouter.get_pin_id(&device, "FF", "D").unwrap().index();
+
+    let pips: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&pips_path).unwrap()).unwrap();
+    let a_to_d = |site: usize| pips["MUX_TILE"].as_array().unwrap().iter()
+        .find(|pair| {
+            (pair["from"]["site"] == site)
+                && (pair["from"]["pin"] == a)
+                && (pair["to"]["pin"] == d)
+        })
+        .map(|pair| pair["routes"].clone())
+        .unwrap();
+    /* The tile PIP goes first, then the I0 -> O and I1 -> O site PIPs of each site */
+    assert_eq!(a_to_d(0), serde_json::json!([[1]]));
+    assert_eq!(a_to_d(1), serde_json::json!([[3]]));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_merge_slices() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("merge-slices");
+    let mut slices = Vec::new();
+    for (name, bounds) in [("first", ["--pin-end", "2"]), ("second", ["--pin-start", "2"])] {
+        let prefix = dir.join(name);
+        let args = PreprocessCmd::parse_from([
+            "preprocess",
+            "--bundle", ":all",
+            "--bundle-prefix", prefix.to_str().unwrap(),
+            bounds[0], bounds[1],
+            "--allow-empty",
+        ]);
+        preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink()).unwrap();
+        slices.push(prefix.join("MUX_SITE.nisp.tar.gz").to_str().unwrap().to_string());
+    }
+
+    let output = dir.join("merged.nisp.tar.gz");
+    let bba = dir.join("merged.bba");
+    let args = MergeSlicesCmd::parse_from([
+        "merge-slices",
+        output.to_str().unwrap(),
+        &slices[0],
+        &slices[1],
+        "--bba", bba.to_str().unwrap(),
+    ]);
+    merge_slices(args, device).unwrap();
+
+    let merged = read_bundle(std::fs::File::open(&output).unwrap()).unwrap();
+    let full = BruteRouter::<()>::new(&device, 0, false).route_all(true);
+    assert_eq!(merged.routing_info.pin_range, None);
+    assert_eq!(merged.routing_info.pin_to_pin_routing, full.pin_to_pin_routing);
+    let bba = std::fs::read_to_string(&bba).unwrap();
+    assert_eq!(crate::bba_exporter::read_pin_range(&bba), None);
+
+    /* A slice can't be merged with itself */
+    let args = MergeSlicesCmd::parse_from([
+        "merge-slices",
+        output.to_str().unwrap(),
+        &slices[0],
+        &slices[0],
+    ]);
+    assert_eq!(merge_slices(args, device).unwrap_err().kind(), "merge");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_site_parallelism() {
+    let msg = mux_site().site_type(buf_site_type()).build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("site-parallelism");
+    let summary_path = dir.join("summary.json");
+
+    let args = PreprocessCmd::parse_from([
+        "preprocess",
+        "--site-parallelism", "2",
+        "--threads-per-site", "2",
+        "--summary-json", summary_path.to_str().unwrap(),
+    ]);
+    preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink()).unwrap();
+
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&summary_path).unwrap()).unwrap();
+    let site_types: Vec<_> = summary["site_types"].as_object().unwrap().keys().collect();
+    assert_eq!(site_types, vec!["BUF_SITE", "MUX_SITE"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_json_error_format() {
+    let msg = mux_site()
+        .tile_type("MUX_SITE", &["MUX_SITE"])
+        .build();
+    let device = fixture_root(&msg);
+
+    let args = Args::try_parse_from([
+        "nisp", "--error-format", "json", "device.device", "out.bba",
+        "route-pair", "MUX_SITE", "MUX.I0", "FF.X",
+    ]).unwrap();
+    let error_format = args.error_format;
+    let sargs = match args.command {
+        SubCommands::RoutePair(sargs) => sargs,
+        _ => unreachable!(),
+    };
+
+    let e = route_pair(sargs, device).unwrap_err();
+    assert_eq!(e.exit_code(), 8);
+    let json: serde_json::Value = serde_json::from_str(&e.report(error_format)).unwrap();
+    assert_eq!(json["error"], "unknown_pin");
+    assert_eq!(json["context"]["bel"], "FF");
+    assert_eq!(json["context"]["pin"], "X");
+    assert!(json["message"].as_str().unwrap().contains("FF.X"));
+
+    let args = RoutePairCmd::parse_from(["route-pair", "MUX_SITE", "MUX", "FF.D"]);
+    let e = route_pair(args, device).unwrap_err();
+    assert_eq!(e.kind(), "bad_pin_path");
+}
+
+#[test]
+fn test_route_pair_site_type() {
+    /* Site types are looked up by name, regardless of tile types */
+    let msg = DeviceFixture::new("test_device")
+        .site_type(SiteTypeFixture::new("PORT_SITE").port("A", Dir::Output))
+        .site_type(mux_site_type())
+        .build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("route-pair");
+    let physnet = dir.join("routes.phys");
+
+    let args = RoutePairCmd::parse_from([
+        "route-pair", "MUX_SITE", "A.A", "FF.D", "--physnet", physnet.to_str().unwrap(),
+    ]);
+    route_pair(args, device).unwrap();
+    assert!(!std::fs::read(&physnet).unwrap().is_empty());
+
+    let args = RoutePairCmd::parse_from(["route-pair", "NO_SITE", "A.A", "FF.D"]);
+    assert_eq!(route_pair(args, device).unwrap_err().kind(), "unknown_site_type");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_preprocess_errors() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("preprocess-errors");
+
+    for option in ["--threads-per-site", "--site-parallelism"] {
+        let args = PreprocessCmd::parse_from(["preprocess", option, "0"]);
+        let e = preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink())
+            .unwrap_err();
+        assert_eq!(e.exit_code(), 13);
+        assert!(e.to_string().contains(option), "{}", e);
+    }
+
+    /* Pins found in no site type are most likely typos */
+    let args = PreprocessCmd::parse_from([
+        "preprocess", "--summary-only", "--ignore-ports", "MUX.I1", "--ignore-ports", "MUX.I7",
+    ]);
+    let e = preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink())
+        .unwrap_err()
+        .to_string();
+    assert!(e.contains("MUX.I7") && !e.contains("MUX.I1"), "{}", e);
+
+    /* Failing writes are reported instead of panicking */
+    let summary_path = dir.join("missing/summary.json");
+    let args = PreprocessCmd::parse_from([
+        "preprocess",
+        "--summary-json", summary_path.to_str().unwrap(),
+    ]);
+    let e = preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink())
+        .unwrap_err();
+    assert_eq!((e.kind(), e.exit_code()), ("io", 14));
+    assert!(e.to_string().contains("Couldn't write summary file"), "{}", e);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_missing_prefix_directories() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("missing-prefix");
+    let json_prefix = dir.join("json/nested");
+    let dot_prefix = dir.join("dot/nested");
+
+    let args = PreprocessCmd::parse_from([
+        "preprocess",
+        "--json", ":all",
+        "--json-prefix", json_prefix.to_str().unwrap(),
+        "--dot", "MUX_SITE",
+        "--dot-prefix", dot_prefix.to_str().unwrap(),
+    ]);
+    preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink()).unwrap();
+
+    assert!(json_prefix.join("test_device_site_routability.json").is_file());
+    assert!(dot_prefix.join("MUX_SITE.dot").is_file());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_out_dir() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("out-dir");
+    let out_dir = dir.join("out");
+    let dot_prefix = dir.join("dot");
+
+    let mut args = PreprocessCmd::parse_from([
+        "preprocess",
+        "--out-dir", out_dir.to_str().unwrap(),
+        "--json", ":all",
+        "--dot", "MUX_SITE",
+        "--bba-split", "MUX_SITE",
+    ]);
+    args.bba = Some("test_device.bba".into());
+    preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink()).unwrap();
+
+    assert!(out_dir.join("test_device_site_routability.json").is_file());
+    assert!(out_dir.join("MUX_SITE.dot").is_file());
+    assert!(out_dir.join("MUX_SITE.bba").is_file());
+    let combined = std::fs::read_to_string(out_dir.join("test_device.bba")).unwrap();
+    assert!(combined.contains("label st0_site_type_routing"));
+    assert!(combined.contains("label site_types"));
+
+    /* Explicit prefixes take precedence */
+    let device = fixture_root(&msg);
+    let args = PreprocessCmd::parse_from([
+        "preprocess",
+        "--out-dir", out_dir.to_str().unwrap(),
+        "--dot", "MUX_SITE",
+        "--dot-prefix", dot_prefix.to_str().unwrap(),
+    ]);
+    preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink()).unwrap();
+
+    assert!(dot_prefix.join("MUX_SITE.dot").is_file());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_json_lines() {
+    let msg = DeviceFixture::new("test_device")
+        .site_type(mux_site_type())
+        .site_type(SiteTypeFixture::new("EMPTY"))
+        .build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("json-lines");
+
+    let args = PreprocessCmd::parse_from([
+        "preprocess",
+        "--out-dir", dir.to_str().unwrap(),
+        "--json", ":all",
+        "--json-lines",
+    ]);
+    preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink()).unwrap();
+
+    let output = std::fs::read_to_string(dir.join("test_device_site_routability.jsonl")).unwrap();
+    let mut site_types: Vec<_> = output.lines()
+        .map(|line| {
+            let record: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert!(record.get("pin_to_pin_routing").is_some());
+            record["site_type"].as_str().unwrap().to_string()
+        })
+        .collect();
+    site_types.sort();
+    assert_eq!(site_types, ["EMPTY", "MUX_SITE"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[cfg(feature = "profiling")]
+fn test_profile_trace() {
+    use crate::profiling::Profiler;
+
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("profile");
+    let trace_path = dir.join("trace.folded");
+
+    let profiler = Profiler::start(&trace_path).unwrap();
+    let args = PreprocessCmd::parse_from(["preprocess"]);
+    preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink()).unwrap();
+    profiler.finish().unwrap();
+
+    let trace = std::fs::read_to_string(&trace_path).unwrap();
+    assert!(!trace.is_empty());
+    assert!(trace.lines().any(|line| line.contains("route_pin")));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_changed_since_baseline() {
+    let baseline_msg = mux_site().site_type(buf_site_type()).build();
+    let changed_msg = mux_site().site_type(buf_site_type().without_pips()).build();
+    let dir = scratch_dir("changed-since");
+    let baseline_dir = dir.join("baseline");
+
+    let args = PreprocessCmd::parse_from([
+        "preprocess",
+        "--bundle", ":all",
+        "--bundle-prefix", baseline_dir.to_str().unwrap(),
+    ]);
+    let device = fixture_root(&baseline_msg);
+    preprocess(args, device, None, &AtomicBool::new(false), &mut std::io::sink()).unwrap();
+
+    let args = PreprocessCmd::parse_from([
+        "preprocess",
+        "--json", ":all",
+        "--json-prefix", dir.to_str().unwrap(),
+        "--changed-since", baseline_dir.to_str().unwrap(),
+    ]);
+    let device = fixture_root(&changed_msg);
+    let mut out = Vec::new();
+    preprocess(args, device, None, &AtomicBool::new(false), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("Site Type MUX_SITE: skipped, routing unchanged"), "{}", out);
+    assert!(out.contains("Site Type BUF_SITE:\n"), "{}", out);
+
+    let json: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(dir.join("test_device_site_routability.json")).unwrap()
+    ).unwrap();
+    let site_types: Vec<_> = json.as_object().unwrap().keys().collect();
+    assert_eq!(site_types, vec!["BUF_SITE"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_site_types_file() {
+    let site = |name: &str| {
+        SiteTypeFixture::new(name)
+            .port("A", Dir::Output)
+            .bel("S", BELCategory::Logic, &[("I", Dir::Input)])
+            .wire("A_W", &[("A", "A"), ("S", "I")])
+    };
+    let msg = mux_site()
+        .site_type(site("BUF_SITE"))
+        .site_type(site("OTHER_SITE"))
+        .build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("site-types-file");
+    let list_path = dir.join("site_types.txt");
+    std::fs::write(&list_path, "# Site types to route\nMUX_SITE\n\nBUF_*\n").unwrap();
+
+    let args = PreprocessCmd::parse_from([
+        "preprocess",
+        "--site-types-file", list_path.to_str().unwrap(),
+    ]);
+    let mut out = Vec::new();
+    preprocess(args, device, None, &AtomicBool::new(false), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    let processed: Vec<_> = out.lines()
+        .filter_map(|line| line.strip_prefix("Site Type "))
+        .collect();
+    assert_eq!(processed, vec!["MUX_SITE:", "BUF_SITE:"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_batch() {
+    let msg = mux_site()
+        .site_type(SiteTypeFixture::new("EMPTY"))
+        .build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("batch");
+    let script = dir.join("script.txt");
+    let run_batch = |interrupted: bool, out: &mut dyn std::io::Write| {
+        let args = BatchCmd::parse_from(["batch", script.to_str().unwrap()]);
+        let interrupted = AtomicBool::new(interrupted);
+        batch(args, device, None, None, false, false, &interrupted, out)
+    };
+
+    std::fs::write(&script, concat!(
+        "# List ports, then route a single site type\n",
+        "list-out-of-site-ports MUX_SITE\n",
+        "\n",
+        "preprocess --site-types MUX_SITE\n",
+    )).unwrap();
+
+    /* Both steps share the device reader opened once by the caller */
+    let mut out = Vec::new();
+    run_batch(false, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    let mut listing = Vec::new();
+    let args = ListOutOfSitePortsCmd::parse_from(["list-out-of-site-ports", "MUX_SITE"]);
+    list_out_of_site_ports(args, device, &mut listing).unwrap();
+    let listing = String::from_utf8(listing).unwrap();
+
+    assert!(out.starts_with(&listing));
+    assert!(out[listing.len() ..].contains("Site Type MUX_SITE:"));
+    assert!(!out.contains("Site Type EMPTY:"));
+
+    std::fs::write(&script, "list-out-of-site-ports NO_SUCH_SITE\n").unwrap();
+    let e = run_batch(false, &mut std::io::sink()).unwrap_err();
+    assert_eq!(e.kind(), "unknown_site_type");
+
+    std::fs::write(&script, format!("batch {}\n", script.display())).unwrap();
+    let e = run_batch(false, &mut std::io::sink()).unwrap_err();
+    assert_eq!(e.kind(), "batch");
+
+    /* Invalid options fail instead of leaving the scheduler with no workers */
+    std::fs::write(&script, "preprocess --site-parallelism 0\n").unwrap();
+    let e = run_batch(false, &mut std::io::sink()).unwrap_err();
+    assert_eq!(e.kind(), "preprocess");
+
+    /* An interrupted subcommand writes what it has, the following ones don't run */
+    let first = dir.join("first.json");
+    let second = dir.join("second.json");
+    std::fs::write(&script, format!(
+        "preprocess --summary-json {}\npreprocess --summary-json {}\n",
+        first.display(),
+        second.display()
+    )).unwrap();
+    run_batch(true, &mut std::io::sink()).unwrap();
+    assert!(first.is_file());
+    assert!(!second.exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_show_pair() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+
+    /* Routes from A and B converge at MUX.O, so routing from A excludes MUX.I1 */
+    let args = ShowPairCmd::parse_from(["show-pair", "MUX_SITE", "A.A", "FF.D"]);
+    let mut out = Vec::new();
+    show_pair(args, device, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    let requires = out.lines().find(|line| line.trim().starts_with("requires:")).unwrap();
+    assert!(requires.contains("¬MUX.I1"), "{}", out);
+    assert!(!requires.contains("¬MUX.I0"), "{}", out);
+    assert!(out.lines().any(|line| line.trim().starts_with("implies:")));
+
+    let args = ShowPairCmd::parse_from(["show-pair", "MUX_SITE", "FF.D", "A.A"]);
+    let mut out = Vec::new();
+    show_pair(args, device, &mut out).unwrap();
+    assert!(String::from_utf8(out).unwrap().contains("not routable"));
+
+    let args = ShowPairCmd::parse_from(["show-pair", "MUX_SITE", "A.A", "FF.X"]);
+    let e = show_pair(args, device, &mut std::io::sink()).unwrap_err();
+    assert_eq!(e.kind(), "unknown_pin");
+}
+
+#[test]
+fn test_family_shares_identical_site_types() {
+    let msg_a = DeviceFixture::new("device_a")
+        .site_type(mux_site_type())
+        .site_type(SiteTypeFixture::new("PORTS").port("A", Dir::Output))
+        .build();
+    let msg_b = DeviceFixture::new("device_b")
+        .site_type(mux_site_type())
+        .site_type(SiteTypeFixture::new("PORTS").port("B", Dir::Output))
+        .build();
+    let devices = [fixture_root(&msg_a), fixture_root(&msg_b)];
+
+    let opts = |args: &[&str]| {
+        FamilyCmd::parse_from(["family", "--output", "family.json"].iter().chain(args).copied())
+            .routing
+    };
+    let family = route_family(&devices, &opts(&[])).unwrap();
+
+    /* MUX_SITE is stored once, PORTS differs between the devices */
+    assert_eq!(family.site_types.len(), 3);
+    let mux_idx = family.index["device_a"]["MUX_SITE"];
+    assert_eq!(family.index["device_b"]["MUX_SITE"], mux_idx);
+    assert_eq!(family.site_types[mux_idx].devices, ["device_a", "device_b"]);
+    assert_ne!(family.index["device_a"]["PORTS"], family.index["device_b"]["PORTS"]);
+
+    let only_mux = route_family(&devices, &opts(&["--site-types", "MUX*"])).unwrap();
+    assert_eq!(only_mux.site_types.len(), 1);
+
+    /* Site types are selected and routers configured the way preprocess does it */
+    let dir = scratch_dir("family");
+    let list = dir.join("site_types.txt");
+    std::fs::write(&list, "# Only the mux\nMUX_SITE\n").unwrap();
+    let from_file = route_family(&devices, &opts(&["--site-types-file", list.to_str().unwrap()]))
+        .unwrap();
+    assert_eq!(from_file.site_types.len(), 1);
+
+    let ports_only = route_family(&devices, &opts(&["--port-pairs-only", "--site-types", "MUX*"]))
+        .unwrap();
+    assert_ne!(ports_only.site_types[0].routing, only_mux.site_types[0].routing);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_report_unconstrained_ratio() {
+    let msg = mux_site().build();
+    let device = fixture_root(&msg);
+    let dir = scratch_dir("unconstrained-ratio");
+    let summary_path = dir.join("summary.json");
+
+    let args = PreprocessCmd::parse_from([
+        "preprocess",
+        "--report-unconstrained-ratio",
+        "--summary-json", summary_path.to_str().unwrap(),
+    ]);
+
+    let mut out = Vec::new();
+    preprocess(args, device, None, &AtomicBool::new(false), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("Ratio of unconstrained pairs:"), "{}", out);
+
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&summary_path).unwrap()).unwrap();
+    let site_type = &summary["site_types"]["MUX_SITE"];
+    let pairs = site_type["pairs"].as_u64().unwrap();
+    let unconstrained = site_type["unconstrained_pairs"].as_u64().unwrap();
+    assert!(unconstrained > 0 && unconstrained < pairs);
+    assert_eq!(
+        site_type["unconstrained_ratio"].as_f64().unwrap(),
+        unconstrained as f64 / pairs as f64
+    );
+    assert_eq!(summary["total"]["unconstrained_ratio"], site_type["unconstrained_ratio"]);
+}
+
+#[test]
+fn test_max_routes_per_pair() {
+    let inputs = ["I0", "I1", "I2", "I3", "I4", "I5", "I6", "I7"];
+    let mut mux_pins: Vec<_> = inputs.iter().map(|pin| (*pin, Dir::Input)).collect();
+    mux_pins.push(("O", Dir::Output));
+    let mut wire_pins = vec![("A", "A")];
+    wire_pins.extend(inputs.iter().map(|pin| ("MUX", *pin)));
+
+    /* A reaches D through any of the mux inputs */
+    let mut st = SiteTypeFixture::new("WIDE_MUX_SITE")
+        .port("A", Dir::Output)
+        .bel("MUX", BELCategory::Routing, &mux_pins)
+        .bel("FF", BELCategory::Logic, &[("D", Dir::Input)])
+        .wire("A_W", &wire_pins)
+        .wire("MUX_O", &[("MUX", "O"), ("FF", "D")]);
+    for pin in inputs {
+        st = st.pip("MUX", pin, "O");
+    }
+    let msg = DeviceFixture::new("test_device").site_type(st).build();
+    let device = fixture_root(&msg);
+
+    let new_router = || BruteRouter::<Vec<SitePinId>>::new(&device, 0, false);
+    let router = new_router();
+    let a = router.get_pin_id(&device, "A", "A").unwrap();
+    let d = router.get_pin_id(&device, "FF", "D").unwrap();
+
+    let (_, all) = explore_routes(new_router(), a, d, None);
+    assert!(all.len() > 3, "{} routes", all.len());
+    assert!(all.iter().all(|route| (route[0] == a) && (*route.last().unwrap() == d)));
+
+    let (_, capped) = explore_routes(new_router(), a, d, Some(3));
+    assert_eq!(capped.len(), 3);
+    assert_eq!(capped[..], all[.. 3]);
+}
+
+#[test]
+fn test_diff_devices() {
+    let msg_full = DeviceFixture::new("full")
+        .site_type(mux_site_type())
+        .site_type(SiteTypeFixture::new("PORTS").port("A", Dir::Output))
+        .build();
+    let msg_trimmed = DeviceFixture::new("trimmed")
+        .site_type(mux_site_type())
+        .build();
+    let full = fixture_root(&msg_full);
+    let trimmed = fixture_root(&msg_trimmed);
+
+    let diff = diff_site_types(&full, &trimmed);
+    assert_eq!(diff.removed_site_types, ["PORTS"]);
+    assert!(diff.added_site_types.is_empty());
+    assert!(diff.changed_site_types.is_empty());
+    assert_eq!(diff.unchanged_site_types, 1);
+
+    let diff = diff_site_types(&trimmed, &full);
+    assert_eq!(diff.added_site_types, ["PORTS"]);
+
+    let msg_renamed = DeviceFixture::new("renamed")
+        .site_type(SiteTypeFixture::new("PORTS").port("B", Dir::Output))
+        .build();
+    let diff = diff_site_types(&full, &fixture_root(&msg_renamed));
+    assert_eq!(diff.removed_site_types, ["MUX_SITE"]);
+    assert_eq!(diff.changed_site_types.len(), 1);
+    assert_eq!(diff.changed_site_types[0].name, "PORTS");
+    assert_eq!(diff.changed_site_types[0].removed_pins, ["A.A"]);
+    assert_eq!(diff.changed_site_types[0].added_pins, ["B.B"]);
+}
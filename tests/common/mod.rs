@@ -0,0 +1,52 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Helpers shared by integration tests.
+//!
+//! The fixture device is described with `nisp::test_utils` and written to a gzipped
+//! `.device` file, which then gets opened the same way as real device files.
+
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use nisp::common::IcStr;
+use nisp::ic_loader::{self, MsgReader, OpenOpts};
+use nisp::router::site_brute_router::BruteRouter;
+use nisp::test_utils::*;
+
+/// Writes the fixture device (see `nisp::test_utils::mux_site`) to a `.device` file
+/// named `name` within the temporary directory of integration tests.
+pub fn fixture_device_file(name: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.device", name));
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    capnp::serialize::write_message(&mut encoder, &mux_site().build()).unwrap();
+    std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+    path
+}
+
+/// Writes the fixture device to a file and opens it back.
+pub fn open_fixture_device(name: &str) -> Box<dyn MsgReader> {
+    ic_loader::open(fixture_device_file(name), OpenOpts::default()).unwrap()
+}
+
+/// Builds a router for a site type of an opened device, found by its name.
+pub fn fixture_router(device: &ic_loader::archdef::Root, site_type: &str) -> BruteRouter<()> {
+    let st_list = device.get_site_type_list().unwrap();
+    let st_id = st_list.iter()
+        .position(|st| device.ic_str(st.get_name()) == site_type)
+        .unwrap_or_else(|| panic!("No site type {} in the fixture device", site_type));
+    BruteRouter::new(device, st_id as u32, false)
+}
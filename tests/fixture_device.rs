@@ -0,0 +1,41 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+mod common;
+
+use nisp::strings::GlobalStringsCtx;
+
+#[test]
+fn test_route_pair_in_fixture_device() {
+    let msg = common::open_fixture_device("route_pair");
+    let device = msg.get_archdef_root().unwrap();
+    assert_eq!(device.get_name().unwrap(), "test_device");
+
+    let router = common::fixture_router(&device, "MUX_SITE");
+    let from = router.get_pin_id(&device, "A", "A").unwrap();
+    let to = router.get_pin_id(&device, "FF", "D").unwrap();
+    let ppri = router.query_routed_pair(from, to, true).unwrap()
+        .expect("A.A should reach FF.D through the MUX pseudo-pip");
+
+    /* Routes from A and B converge at MUX.O, so the one from A excludes MUX.I1 */
+    let gsctx = GlobalStringsCtx::hold();
+    let rendered = ppri.render(&router, &device, &gsctx);
+    let requires = rendered.lines().next().unwrap();
+    assert!(requires.contains("¬MUX.I1"), "{}", rendered);
+    assert!(!requires.contains("¬MUX.I0"), "{}", rendered);
+
+    /* Input pins don't act as sources */
+    assert!(router.query_routed_pair(to, from, true).unwrap().is_none());
+}
@@ -0,0 +1,54 @@
+/* Copyright (C) 2022 Antmicro
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+mod common;
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn test_serve_answers_queries() {
+    let device = common::fixture_device_file("serve");
+    let bba = device.with_extension("bba");
+
+    let mut server = Command::new(env!("CARGO_BIN_EXE_nisp"))
+        .arg(&device)
+        .arg(&bba)
+        .arg("serve")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    server.stdin.take().unwrap()
+        .write_all(b"query MUX_SITE A.A FF.D\nquery MUX_SITE FF.D A.A\n")
+        .unwrap();
+    let output = server.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let responses: Vec<serde_json::Value> = String::from_utf8(output.stdout).unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(responses.len(), 2);
+
+    assert_eq!(responses[0]["from"], "A.A");
+    assert_eq!(responses[0]["routable"], true);
+    assert!(responses[0]["requires"].to_string().contains("MUX.I1"), "{}", responses[0]);
+
+    /* Input pins don't act as sources */
+    assert_eq!(responses[1]["from"], "FF.D");
+    assert_eq!(responses[1]["routable"], false);
+}